@@ -10,16 +10,21 @@ use {
     crate::{
         error::Error,
         format::{
-            BomBlock, BomBlockBomInfo, BomBlockFile, BomBlockPathInfoIndex, BomBlockPathRecord,
-            BomBlockPathRecordPointer, BomBlockPaths, BomBlockTree, BomBlockTreePointer,
-            BomBlockVIndex, BomBlocksEntry, BomBlocksIndex, BomHeader, BomInfoEntry, BomPathsEntry,
-            BomVar, BomVarsIndex,
+            BomBlock, BomBlockArchitecture, BomBlockBinaryInfo, BomBlockBomInfo, BomBlockFile,
+            BomBlockPathInfoIndex, BomBlockPathRecord, BomBlockPathRecordPointer, BomBlockPaths,
+            BomBlockSize64, BomBlockTree, BomBlockTreePointer, BomBlockVIndex, BomBlocksEntry,
+            BomBlocksIndex, BomHeader, BomInfoEntry, BomPathsEntry, BomVar, BomVarsIndex,
+            ARCHITECTURE_BINARY_INFO,
         },
         path::{BomPath, BomPathType},
     },
     chrono::{DateTime, Utc},
+    cpio_archive::{CpioHeader, CpioReader, OdcHeader},
     scroll::IOwrite,
-    simple_file_manifest::{S_IFDIR, S_IRGRP, S_IROTH, S_IRUSR, S_IWUSR, S_IXGRP, S_IXUSR},
+    simple_file_manifest::{
+        FileManifest, S_IFBLK, S_IFCHR, S_IFDIR, S_IFLNK, S_IFREG, S_IRGRP, S_IROTH, S_IRUSR,
+        S_IWUSR, S_IXGRP, S_IXOTH, S_IXUSR,
+    },
     std::{
         borrow::Cow,
         collections::{BTreeMap, HashMap},
@@ -29,40 +34,217 @@ use {
     },
 };
 
-fn crc32_path(path: &Path) -> std::io::Result<(u32, usize)> {
-    let mut h = crc32fast::Hasher::new();
+/// Lookup table for the POSIX `cksum` CRC (polynomial 0x04c11db7, MSB first).
+const CKSUM_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Computes checksums the way Apple's tooling does.
+///
+/// BOMs store the checksum computed by the POSIX `cksum` utility, not a zlib CRC-32.
+#[derive(Default)]
+struct Cksum {
+    crc: u32,
+    length: u64,
+}
+
+impl Cksum {
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.crc = (self.crc << 8) ^ CKSUM_TABLE[((self.crc >> 24) as u8 ^ byte) as usize];
+        }
+        self.length += data.len() as u64;
+    }
+
+    fn finalize(mut self) -> u32 {
+        // The length is appended in little-endian order, omitting trailing zero bytes.
+        let mut length = self.length;
+        while length != 0 {
+            let byte = length as u8;
+            self.crc = (self.crc << 8) ^ CKSUM_TABLE[((self.crc >> 24) as u8 ^ byte) as usize];
+            length >>= 8;
+        }
 
-    let mut fh = std::fs::File::open(path)?;
+        !self.crc
+    }
+}
+
+fn cksum_data(data: &[u8]) -> u32 {
+    let mut h = Cksum::default();
+    h.update(data);
+    h.finalize()
+}
+
+/// Resolve the checksum, size and Mach-O architectures of file content.
+fn content_info(
+    reader: &mut (impl Read + ?Sized),
+) -> std::io::Result<(u32, usize, Vec<BomBlockArchitecture>)> {
+    let mut h = Cksum::default();
     let mut buffer = [0u8; 32768];
     let mut file_size = 0;
 
+    // Content is only buffered while it could be a Mach-O binary.
+    let mut content = vec![];
+    let mut buffering = true;
+
     loop {
-        let bytes_read = fh.read(&mut buffer)?;
+        let bytes_read = reader.read(&mut buffer)?;
         if bytes_read == 0 {
             break;
         }
         file_size += bytes_read;
         h.update(&buffer[0..bytes_read]);
+
+        if buffering {
+            content.extend_from_slice(&buffer[0..bytes_read]);
+
+            if content.len() >= 4 && !is_macho_magic(&content) {
+                buffering = false;
+                content = vec![];
+            }
+        }
     }
 
-    Ok((h.finalize(), file_size))
+    let architectures = if buffering {
+        macho_architectures(&content)
+    } else {
+        vec![]
+    };
+
+    Ok((h.finalize(), file_size, architectures))
 }
 
-fn crc32_data(data: &[u8]) -> u32 {
-    let mut h = crc32fast::Hasher::new();
-    h.update(data);
-    h.finalize()
+const MH_MAGIC: u32 = 0xfeedface;
+const MH_MAGIC_64: u32 = 0xfeedfacf;
+const FAT_MAGIC: u32 = 0xcafebabe;
+const FAT_MAGIC_64: u32 = 0xcafebabf;
+
+/// Whether data starts with the magic of a Mach-O or universal binary.
+fn is_macho_magic(data: &[u8]) -> bool {
+    data.len() >= 4
+        && [MH_MAGIC, MH_MAGIC_64, FAT_MAGIC, FAT_MAGIC_64]
+            .iter()
+            .any(|magic| data[0..4] == magic.to_be_bytes() || data[0..4] == magic.to_le_bytes())
 }
 
-fn validate_bom_path(s: &str) -> Result<(), Error> {
-    if s.starts_with('.') {
-        Err(Error::BadPath(s.to_string(), "path cannot start with ."))
-    } else if s.starts_with('/') {
+/// Resolve the architectures of a Mach-O binary.
+///
+/// Returns an empty vector if the data isn't a Mach-O binary.
+fn macho_architectures(data: &[u8]) -> Vec<BomBlockArchitecture> {
+    let u32_at = |offset: usize, be: bool| -> Option<u32> {
+        let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if be {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    let u64_at = |offset: usize| -> Option<u64> {
+        Some(u64::from_be_bytes(
+            data.get(offset..offset + 8)?.try_into().ok()?,
+        ))
+    };
+
+    let Some(magic) = u32_at(0, true) else {
+        return vec![];
+    };
+
+    let thin = |be: bool| -> Vec<BomBlockArchitecture> {
+        match (u32_at(4, be), u32_at(8, be)) {
+            (Some(cpu_type), Some(cpu_subtype)) => vec![BomBlockArchitecture {
+                cpu_type,
+                cpu_subtype,
+                size: data.len() as _,
+                checksum: cksum_data(data),
+            }],
+            _ => vec![],
+        }
+    };
+
+    match magic {
+        MH_MAGIC | MH_MAGIC_64 => thin(true),
+        _ if magic.swap_bytes() == MH_MAGIC || magic.swap_bytes() == MH_MAGIC_64 => thin(false),
+        FAT_MAGIC | FAT_MAGIC_64 => {
+            let is_64 = magic == FAT_MAGIC_64;
+            let count = u32_at(4, true).unwrap_or(0) as usize;
+
+            // Java class files share the magic of universal binaries. Their
+            // version field is never smaller than 45.
+            if count == 0 || count >= 45 {
+                return vec![];
+            }
+
+            let mut res = Vec::with_capacity(count);
+            for i in 0..count {
+                let offset = if is_64 { 8 + i * 32 } else { 8 + i * 20 };
+
+                let slice = (|| {
+                    let cpu_type = u32_at(offset, true)?;
+                    let cpu_subtype = u32_at(offset + 4, true)?;
+                    let (start, size) = if is_64 {
+                        (u64_at(offset + 8)?, u64_at(offset + 16)?)
+                    } else {
+                        (
+                            u32_at(offset + 8, true)? as u64,
+                            u32_at(offset + 12, true)? as u64,
+                        )
+                    };
+                    let slice = data.get(start as usize..start.checked_add(size)? as usize)?;
+
+                    Some(BomBlockArchitecture {
+                        cpu_type,
+                        cpu_subtype,
+                        size: slice.len() as _,
+                        checksum: cksum_data(slice),
+                    })
+                })();
+
+                match slice {
+                    Some(arch) => res.push(arch),
+                    None => return vec![],
+                }
+            }
+
+            res
+        }
+        _ => vec![],
+    }
+}
+
+/// Validate a path to add to a BOM, stripping trailing `/`.
+fn normalize_bom_path(s: &str) -> Result<String, Error> {
+    let path = s.trim_end_matches('/');
+
+    if s.starts_with('/') {
         Err(Error::BadPath(s.to_string(), "path cannot start with /"))
     } else if s.contains('\\') {
         Err(Error::BadPath(s.to_string(), "path cannot contain \\"))
+    } else if path
+        .split('/')
+        .any(|c| c.is_empty() || c == "." || c == "..")
+    {
+        Err(Error::BadPath(
+            s.to_string(),
+            "path cannot have empty, . or .. components",
+        ))
     } else {
-        Ok(())
+        Ok(path.to_string())
     }
 }
 
@@ -71,10 +253,16 @@ fn validate_bom_path(s: &str) -> Result<(), Error> {
 pub struct BomBuilder {
     /// Paths to materialize.
     ///
-    /// Directories are not tracked explicitly. Rather they are derived
-    /// at BOM generation time.
+    /// Directories don't need to be tracked explicitly. Missing parent
+    /// directories are derived at BOM generation time.
     paths: BTreeMap<String, BomPath>,
 
+    /// The root directory, if customized.
+    root: Option<BomPath>,
+
+    /// Groups of paths that are hard links to the same file.
+    hard_links: Vec<Vec<String>>,
+
     default_mtime: DateTime<Utc>,
 
     default_uid: u32,
@@ -88,6 +276,8 @@ impl Default for BomBuilder {
     fn default() -> Self {
         Self {
             paths: Default::default(),
+            root: None,
+            hard_links: vec![],
             default_mtime: Utc::now(),
             default_uid: 0,
             default_gid: 0,
@@ -101,7 +291,7 @@ impl Default for BomBuilder {
                 | S_IRGRP
                 | S_IXGRP
                 | S_IROTH
-                | S_IXGRP) as u16,
+                | S_IXOTH) as u16,
         }
     }
 }
@@ -118,9 +308,34 @@ impl BomBuilder {
             size: 0,
             crc32: None,
             link_name: None,
+            architecture: 15,
+            architectures: vec![],
+            device: None,
         }
     }
 
+    fn default_directory_path(&self) -> BomPath {
+        BomPath {
+            path_type: BomPathType::Directory,
+            file_mode: self.default_mode_dir,
+            ..self.default_file_path()
+        }
+    }
+
+    /// Register a path, returning a mutable reference to it.
+    fn insert_path(&mut self, bom_path: String, mut path: BomPath) -> &mut BomPath {
+        path.path = bom_path.clone();
+
+        // A replaced path is no longer part of a hard link group.
+        for group in self.hard_links.iter_mut() {
+            group.retain(|p| p != &bom_path);
+        }
+        self.hard_links.retain(|group| group.len() > 1);
+
+        self.paths.insert(bom_path.clone(), path);
+        self.paths.get_mut(&bom_path).unwrap()
+    }
+
     /// Set the default file mode to use for files.
     pub fn default_mode_file(&mut self, mode: u16) {
         self.default_mode_file = mode;
@@ -148,6 +363,8 @@ impl BomBuilder {
 
     /// Add a file to this BOM with file content derived from a filesystem path.
     ///
+    /// Architectures of Mach-O binaries are recorded automatically.
+    ///
     /// A mutable reference to the just-added entry is returned to allow
     /// for further customization.
     pub fn add_file_from_path(
@@ -155,24 +372,23 @@ impl BomBuilder {
         bom_path: impl ToString,
         path: impl AsRef<Path>,
     ) -> Result<&mut BomPath, Error> {
-        let bom_path = bom_path.to_string();
-        validate_bom_path(&bom_path)?;
+        let bom_path = normalize_bom_path(&bom_path.to_string())?;
         let path = path.as_ref();
 
-        let (crc32, file_size) = crc32_path(path)?;
+        let (crc32, file_size, architectures) = content_info(&mut std::fs::File::open(path)?)?;
 
-        let mut path = self.default_file_path();
-        path.path = bom_path.clone();
-        path.size = file_size;
-        path.crc32 = Some(crc32);
+        let mut entry = self.default_file_path();
+        entry.size = file_size;
+        entry.crc32 = Some(crc32);
+        entry.architectures = architectures;
 
-        self.paths.insert(bom_path.clone(), path);
-
-        Ok(self.paths.get_mut(&bom_path).unwrap())
+        Ok(self.insert_path(bom_path, entry))
     }
 
     /// Add a file to this BOM with content specified from a slice.
     ///
+    /// Architectures of Mach-O binaries are recorded automatically.
+    ///
     /// A mutable reference to the just-added entry is returned to allow
     /// for further customization.
     pub fn add_file_from_data(
@@ -180,21 +396,311 @@ impl BomBuilder {
         bom_path: impl ToString,
         data: impl AsRef<[u8]>,
     ) -> Result<&mut BomPath, Error> {
-        let bom_path = bom_path.to_string();
-        validate_bom_path(&bom_path)?;
+        let bom_path = normalize_bom_path(&bom_path.to_string())?;
 
         let data = data.as_ref();
 
-        let mut path = self.default_file_path();
-        path.path = bom_path.clone();
-        path.size = data.len();
-        path.crc32 = Some(crc32_data(data));
+        let mut entry = self.default_file_path();
+        entry.size = data.len();
+        entry.crc32 = Some(cksum_data(data));
+        entry.architectures = macho_architectures(data);
 
-        self.paths.insert(bom_path.clone(), path);
+        Ok(self.insert_path(bom_path, entry))
+    }
+
+    /// Add a directory to this BOM.
+    ///
+    /// Parent directories of added paths are added automatically. This only
+    /// needs to be called for empty directories or to customize a directory.
+    pub fn add_directory(&mut self, bom_path: impl ToString) -> Result<&mut BomPath, Error> {
+        let bom_path = normalize_bom_path(&bom_path.to_string())?;
+
+        let entry = self.default_directory_path();
+
+        Ok(self.insert_path(bom_path, entry))
+    }
+
+    /// Add a symbolic link pointing to `target`.
+    pub fn add_symlink(
+        &mut self,
+        bom_path: impl ToString,
+        target: impl ToString,
+    ) -> Result<&mut BomPath, Error> {
+        let bom_path = normalize_bom_path(&bom_path.to_string())?;
+        let target = target.to_string();
+
+        if target.is_empty() || target.contains('\0') {
+            return Err(Error::BadPath(bom_path, "invalid symlink target"));
+        }
+
+        let mut entry = self.default_file_path();
+        entry.path_type = BomPathType::Link;
+        entry.file_mode =
+            (S_IFLNK | S_IRUSR | S_IWUSR | S_IXUSR | S_IRGRP | S_IXGRP | S_IROTH | S_IXOTH) as u16;
+        entry.size = target.len();
+        entry.crc32 = Some(cksum_data(target.as_bytes()));
+        entry.link_name = Some(target);
+
+        Ok(self.insert_path(bom_path, entry))
+    }
+
+    /// Add a character device with the given device number.
+    pub fn add_character_device(
+        &mut self,
+        bom_path: impl ToString,
+        device: u32,
+    ) -> Result<&mut BomPath, Error> {
+        self.add_device(bom_path, S_IFCHR as u16, device)
+    }
+
+    /// Add a block device with the given device number.
+    pub fn add_block_device(
+        &mut self,
+        bom_path: impl ToString,
+        device: u32,
+    ) -> Result<&mut BomPath, Error> {
+        self.add_device(bom_path, S_IFBLK as u16, device)
+    }
+
+    fn add_device(
+        &mut self,
+        bom_path: impl ToString,
+        file_type: u16,
+        device: u32,
+    ) -> Result<&mut BomPath, Error> {
+        let bom_path = normalize_bom_path(&bom_path.to_string())?;
+
+        let mut entry = self.default_file_path();
+        entry.path_type = BomPathType::Dev;
+        entry.file_mode = file_type | (self.default_mode_file & 0o7777);
+        entry.device = Some(device);
+
+        Ok(self.insert_path(bom_path, entry))
+    }
+
+    /// Add a hard link to the already added file at `existing`.
+    ///
+    /// The new path receives a copy of the existing entry. The group is available
+    /// from [Self::hard_links] and is written to the `HLIndex` of the BOM.
+    pub fn add_hard_link(
+        &mut self,
+        bom_path: impl ToString,
+        existing: impl ToString,
+    ) -> Result<&mut BomPath, Error> {
+        let bom_path = normalize_bom_path(&bom_path.to_string())?;
+        let existing = existing.to_string();
+
+        if bom_path == existing {
+            return Err(Error::BadPath(bom_path, "hard link cannot point to itself"));
+        }
+
+        let entry = match self.paths.get(&existing) {
+            Some(entry) if entry.path_type() == BomPathType::File => entry.clone(),
+            Some(_) => return Err(Error::BadPath(existing, "hard link target is not a file")),
+            None => return Err(Error::BadPath(existing, "hard link target does not exist")),
+        };
+
+        // Detach from the group we may belong to before joining the new one.
+        self.insert_path(bom_path.clone(), entry);
+
+        if let Some(group) = self.hard_links.iter_mut().find(|g| g.contains(&existing)) {
+            group.push(bom_path.clone());
+        } else {
+            self.hard_links.push(vec![existing, bom_path.clone()]);
+        }
 
         Ok(self.paths.get_mut(&bom_path).unwrap())
     }
 
+    /// Groups of hard linked paths.
+    pub fn hard_links(&self) -> &[Vec<String>] {
+        &self.hard_links
+    }
+
+    /// Obtain the root directory (`.`) to customize its attributes.
+    ///
+    /// The root directory has default attributes unless customized.
+    pub fn root_directory(&mut self) -> &mut BomPath {
+        if self.root.is_none() {
+            let mut root = self.default_directory_path();
+            root.path = ".".to_string();
+            self.root = Some(root);
+        }
+
+        self.root.as_mut().expect("root is set")
+    }
+
+    /// Construct an instance from the members of a cpio archive.
+    ///
+    /// See [Self::add_cpio_reader].
+    pub fn from_cpio_reader<T: Read, R: CpioReader<T> + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, Error> {
+        let mut builder = Self::default();
+        builder.add_cpio_reader(reader)?;
+
+        Ok(builder)
+    }
+
+    /// Add all members of a cpio archive.
+    ///
+    /// Paths are added with the type, mode, ownership and modified time of their
+    /// header. Members with the same device and inode are recorded as hard links.
+    pub fn add_cpio_reader<T: Read, R: CpioReader<T> + ?Sized>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<(), Error> {
+        let mut inodes = BTreeMap::<(u32, u32), Vec<String>>::new();
+
+        while let Some(header) = reader.read_next()? {
+            let path = self.add_cpio_entry(header.as_ref(), reader)?.path.clone();
+
+            if header.nlink() > 1 && header.mode() & 0o170000 == S_IFREG {
+                inodes
+                    .entry((header.device(), header.inode()))
+                    .or_default()
+                    .push(path);
+            }
+        }
+
+        // Formats like newc only store the content of hard links once, so
+        // copy the entry with content to the other links.
+        for paths in inodes.into_values().filter(|paths| paths.len() > 1) {
+            // Prefer the first path among those with content.
+            let source = paths
+                .iter()
+                .rev()
+                .max_by_key(|path| self.paths[*path].size())
+                .expect("paths is not empty")
+                .clone();
+
+            for path in paths.iter().filter(|path| **path != source) {
+                self.add_hard_link(path, &source)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a path described by a cpio header.
+    ///
+    /// `data` is the content of the member. It is read to the end for regular files
+    /// and symlinks. The `.` member describes the root directory.
+    pub fn add_cpio_entry(
+        &mut self,
+        header: &dyn CpioHeader,
+        data: &mut (impl Read + ?Sized),
+    ) -> Result<&mut BomPath, Error> {
+        let name = header.name().trim_end_matches('/');
+        let name = name.strip_prefix("./").unwrap_or(name);
+
+        let entry = match header.mode() & 0o170000 {
+            _ if name.is_empty() || name == "." => self.root_directory(),
+            S_IFDIR => self.add_directory(name)?,
+            S_IFREG => {
+                let bom_path = normalize_bom_path(name)?;
+
+                let (crc32, file_size, architectures) = content_info(data)?;
+
+                let mut entry = self.default_file_path();
+                entry.size = file_size;
+                entry.crc32 = Some(crc32);
+                entry.architectures = architectures;
+
+                self.insert_path(bom_path, entry)
+            }
+            S_IFLNK => {
+                let mut target = vec![];
+                data.read_to_end(&mut target)?;
+                let target = String::from_utf8(target)
+                    .map_err(|_| Error::BadPath(name.to_string(), "symlink target is not UTF-8"))?;
+
+                self.add_symlink(name, target)?
+            }
+            S_IFCHR | S_IFBLK => {
+                self.add_device(name, (header.mode() & 0o170000) as u16, header.rdev())?
+            }
+            _ => {
+                return Err(Error::BadPath(
+                    name.to_string(),
+                    "unsupported cpio member type",
+                ))
+            }
+        };
+
+        entry.set_file_mode(header.mode() as u16);
+        entry.set_user_id(header.uid());
+        entry.set_group_id(header.gid());
+        entry.set_modified_time(header.modified_time());
+
+        Ok(entry)
+    }
+
+    /// Construct an instance from a [FileManifest].
+    ///
+    /// See [Self::add_file_manifest].
+    pub fn from_file_manifest(
+        manifest: &FileManifest,
+        template: &impl CpioHeader,
+    ) -> Result<Self, Error> {
+        let mut builder = Self::default();
+        builder.add_file_manifest(manifest, template)?;
+
+        Ok(builder)
+    }
+
+    /// Add all entries of a [FileManifest].
+    ///
+    /// Paths are recorded like the members `cpio_archive::OdcBuilder::append_file_manifest()`
+    /// writes. `template` provides their ownership and modified time. Pass a header
+    /// from `OdcBuilder::next_header()` of the builder writing the payload so the
+    /// BOM and payload agree. The root and parent directories have the default
+    /// directory mode of this builder.
+    pub fn add_file_manifest(
+        &mut self,
+        manifest: &FileManifest,
+        template: &impl CpioHeader,
+    ) -> Result<(), Error> {
+        let header = |name: &str, mode: u32, file_size: usize| OdcHeader {
+            dev: 0,
+            inode: 0,
+            mode,
+            uid: template.uid(),
+            gid: template.gid(),
+            nlink: 0,
+            rdev: 0,
+            mtime: template.mtime(),
+            file_size: file_size as _,
+            name: name.to_string(),
+        };
+        // The default directory mode may have been set without the file type.
+        let dir_mode = S_IFDIR | (self.default_mode_dir as u32 & 0o7777);
+
+        self.add_cpio_entry(&header(".", dir_mode, 0), &mut std::io::empty())?;
+
+        for (path, file) in manifest.iter_entries() {
+            let path = path.to_string_lossy().replace('\\', "/");
+
+            for (i, _) in path.match_indices('/') {
+                self.add_cpio_entry(&header(&path[..i], dir_mode, 0), &mut std::io::empty())?;
+            }
+
+            if let Some(target) = file.link_target() {
+                let target = target.to_string_lossy();
+                let mode = S_IFLNK | 0o755;
+
+                self.add_cpio_entry(&header(&path, mode, target.len()), &mut target.as_bytes())?;
+            } else {
+                let data = file.resolve_content()?;
+                let mode = S_IFREG | if file.is_executable() { 0o755 } else { 0o644 };
+
+                self.add_cpio_entry(&header(&path, mode, data.len()), &mut data.as_slice())?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Serialize the BOM data structure to bytes.
     pub fn build_bom(&self) -> Result<Vec<u8>, Error> {
         // Index is the path ID. Value is the filename as stored in the BOM.
@@ -204,26 +710,19 @@ impl BomBuilder {
         // Root directory is special.
         path_to_path_id.insert(".".to_string(), 1u32);
 
-        let path_record = BomBlockPathRecord {
-            path_type: BomPathType::Directory.into(),
-            a: 1,
-            architecture: 1,
-            mode: 0,
-            user: 0,
-            group: 0,
-            mtime: 0,
-            size: 0,
-            b: 1,
-            checksum_or_type: 0,
-            link_name_length: 0,
-            link_name: None,
-        };
         let file = BomBlockFile {
             parent_path_id: 0,
-            name: Cow::from(CString::new(b".\0".to_vec()).expect("string is null terminated")),
+            name: Cow::from(CString::new(".").expect("string has no NUL")),
         };
 
-        records.push((1u32, path_record, file));
+        records.push((
+            1u32,
+            path_record(self.root.as_ref().unwrap_or(&self.default_directory_path())),
+            file,
+            None,
+        ));
+
+        let default_directory = self.default_directory_path();
 
         for (index_path, entry) in &self.paths {
             // We need to emit parent paths before the file itself. So split
@@ -246,26 +745,9 @@ impl BomBuilder {
                     .get(parent_path)
                     .expect("parent path should always be present");
 
-                let path_record = BomBlockPathRecord {
-                    path_type: BomPathType::Directory.into(),
-                    a: 1,
-                    architecture: 15,
-                    mode: self.default_mode_dir,
-                    user: self.default_uid,
-                    group: self.default_gid,
-                    mtime: self.default_mtime.timestamp() as u32,
-                    size: 0,
-                    b: 1,
-                    checksum_or_type: 0,
-                    link_name_length: 0,
-                    link_name: None,
-                };
-
-                let mut path_cstring = Vec::<u8>::with_capacity(path.as_bytes().len());
-                path_cstring.extend(path.as_bytes());
-                path_cstring.push(0);
-                let path_cstring =
-                    CString::new(path_cstring).expect("C string should be well formed");
+                // Files only store their name. The full path is derived from parents.
+                let path_cstring = CString::new(parent_parts[i - 1].as_bytes())
+                    .expect("C string should be well formed");
 
                 let file = BomBlockFile {
                     parent_path_id,
@@ -273,7 +755,7 @@ impl BomBuilder {
                 };
 
                 path_to_path_id.insert(path, path_id);
-                records.push((path_id, path_record, file));
+                records.push((path_id, path_record(&default_directory), file, None));
             }
 
             // Now handle the file entry itself.
@@ -284,29 +766,8 @@ impl BomBuilder {
                 .expect("parent path should be present");
             let path_id = path_to_path_id.len() as u32 + 1;
 
-            let mut path_cstring = Vec::<u8>::with_capacity(path.as_bytes().len() + 1);
-            path_cstring.extend(path.as_bytes());
-            path_cstring.push(0);
-            let path_cstring = CString::new(path_cstring).expect("should be valid C string");
-
-            let path_record = BomBlockPathRecord {
-                path_type: entry.path_type().into(),
-                a: 1,
-                architecture: 15,
-                mode: entry.file_mode(),
-                user: entry.user_id(),
-                group: entry.group_id(),
-                mtime: entry.modified_time().timestamp() as _,
-                size: entry.size() as _,
-                b: 1,
-                checksum_or_type: entry.crc32().unwrap_or(0),
-                link_name_length: if let Some(link_name) = entry.link_name() {
-                    link_name.as_bytes().len() as u32 + 1
-                } else {
-                    0
-                },
-                link_name: entry.link_name_cstring().map(Cow::from),
-            };
+            let name = path_parts.last().expect("split yields an element");
+            let path_cstring = CString::new(name.as_bytes()).expect("should be valid C string");
 
             let file = BomBlockFile {
                 parent_path_id,
@@ -314,7 +775,7 @@ impl BomBuilder {
             };
 
             path_to_path_id.insert(path, path_id);
-            records.push((path_id, path_record, file));
+            records.push((path_id, path_record(entry), file, Some(index_path)));
         }
 
         // We now have all our paths assembled. It is now time to produce the blocks.
@@ -356,7 +817,7 @@ impl BomBuilder {
 
         let mut vars_index = BomVarsIndex {
             count: 1,
-            vars: vec![BomVar::new(blocks.len() as _, "BomInfo")?],
+            vars: vec![BomVar::new(1, "BomInfo")?],
         };
 
         // If we wanted to adhere to the order in Apple's tooling, we would emit
@@ -364,7 +825,11 @@ impl BomBuilder {
         // order doesn't appear to matter, we take the simpler approach and emit
         // them last, after all paths entries.
 
-        for (path_id, path_record, file) in records {
+        // Explicitly added paths to their entry in the Paths tree.
+        let mut path_entries = HashMap::with_capacity(self.paths.len());
+
+        for (path_id, path_record, file, index_path) in records {
+            let key = (file.parent_path_id, file.name.to_bytes().to_vec());
             let path_record_index = blocks.len() as u32;
             blocks.push(BomBlock::PathRecord(path_record));
             let file_index = blocks.len() as u32;
@@ -375,12 +840,24 @@ impl BomBuilder {
                 path_record_index,
             }));
 
-            paths_entries.push(BomPathsEntry {
+            let entry = BomPathsEntry {
                 block_index: path_info_index,
                 file_index,
-            });
+            };
+            if let Some(index_path) = index_path {
+                path_entries.insert(index_path, entry);
+            }
+            paths_entries.push((key, entry));
         }
 
+        // Like Apple's tooling, order the Paths tree by parent path ID and then
+        // by name. Index pages are keyed by the File block of their last entry.
+        paths_entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let paths_entries = paths_entries
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect::<Vec<_>>();
+
         // There are additional Tree, Paths, PathRecordPointer, and TreePointer
         // blocks for each tracked path. Why these exist, we're not sure. But we
         // provide them for parity with Apple tooling.
@@ -416,204 +893,828 @@ impl BomBuilder {
         }
 
         // The Paths variable points to a Tree + Paths.
-        //
-        // The Paths blocks are a bit complicated. The primary Paths is a pointer
-        // to another one. And, due to the 4096 block size limit, there can be multiple
-        // Paths blocks.
-        const PATHS_BLOCK_SIZE: u32 = 4096;
-
-        blocks.push(BomBlock::Tree(BomBlockTree {
-            block_paths_index: blocks.len() as u32 + 1,
-            block_size: PATHS_BLOCK_SIZE,
-            path_count: paths_entries.len() as u32,
-            ..Default::default()
-        }));
         vars_index.count += 1;
         vars_index
             .vars
             .push(BomVar::new(blocks.len() as _, "Paths")?);
+        push_tree(&mut blocks, paths_entries, PATHS_BLOCK_SIZE);
+
+        // HLIndex is a Tree + Paths with an entry per hard link group. Each entry
+        // points to a Tree + Paths listing the paths of the group.
+        let mut hl_index_entries = Vec::with_capacity(self.hard_links.len());
+        for group in &self.hard_links {
+            let members = group
+                .iter()
+                .filter_map(|path| path_entries.get(path).copied())
+                .collect::<Vec<_>>();
+            let Some(first) = members.first() else {
+                continue;
+            };
 
-        // Determine final set of Paths blocks holding meaningful records.
-        let mut paths_blocks = vec![];
-        let mut paths_block = BomBlockPaths {
-            is_path_info: 1,
-            ..Default::default()
-        };
-        for path_entry in paths_entries {
-            paths_block.count += 1;
-            paths_block.paths.push(path_entry);
-
-            let remaining_bytes = PATHS_BLOCK_SIZE - 12 - 8 * paths_block.count as u32;
-
-            // Running out of room. Flush block.
-            if remaining_bytes < 16 {
-                paths_blocks.push(paths_block.clone());
-                paths_block = BomBlockPaths {
-                    is_path_info: 1,
-                    ..Default::default()
-                };
-            }
-        }
-
-        if paths_block.count > 0 || paths_blocks.is_empty() {
-            paths_blocks.push(paths_block);
-        }
-
-        // 1st Paths block is a pointer to meaningful one.
-        blocks.push(BomBlock::Paths(BomBlockPaths {
-            is_path_info: 0,
-            count: 1,
-            paths: vec![BomPathsEntry {
-                block_index: blocks.len() as u32 + 1,
-                file_index: if let Some(entry) = paths_blocks[0].paths.get(0) {
-                    entry.file_index
-                } else {
-                    0
-                },
-            }],
-            ..Default::default()
-        }));
-
-        for (i, paths) in paths_blocks.iter().enumerate() {
-            blocks.push(BomBlock::Paths(BomBlockPaths {
-                is_path_info: paths.is_path_info,
-                count: paths.count,
-                next_paths_block_index: if i == paths_blocks.len() - 1 {
-                    0
-                } else {
-                    blocks.len() as u32 + 2
-                },
-                previous_paths_block_index: if i == 0 { 0 } else { blocks.len() as u32 },
-                paths: paths.paths.clone(),
-            }));
+            hl_index_entries.push(BomPathsEntry {
+                block_index: blocks.len() as u32,
+                file_index: first.file_index,
+            });
+            push_tree(&mut blocks, members, PATHS_BLOCK_SIZE);
         }
 
-        // Add records for other variables.
-
-        // HLIndex is Tree + Paths.
-        blocks.push(BomBlock::Tree(BomBlockTree {
-            block_paths_index: blocks.len() as u32 + 1,
-            block_size: PATHS_BLOCK_SIZE,
-            ..Default::default()
-        }));
         vars_index.count += 1;
         vars_index
             .vars
             .push(BomVar::new(blocks.len() as _, "HLIndex")?);
-        blocks.push(BomBlock::Paths(BomBlockPaths {
-            is_path_info: 1,
-            ..Default::default()
-        }));
+        push_tree(&mut blocks, hl_index_entries, PATHS_BLOCK_SIZE);
 
         // VIndex is VIndex + Tree + Paths.
+        vars_index.count += 1;
+        vars_index
+            .vars
+            .push(BomVar::new(blocks.len() as _, "VIndex")?);
         blocks.push(BomBlock::VIndex(BomBlockVIndex {
             a: 1,
             tree_block_index: blocks.len() as u32 + 1,
             b: 0,
             c: 0,
         }));
-        vars_index.count += 1;
-        vars_index
-            .vars
-            .push(BomVar::new(blocks.len() as _, "VIndex")?);
-        blocks.push(BomBlock::Tree(BomBlockTree {
-            block_paths_index: blocks.len() as u32 + 1,
-            block_size: PATHS_BLOCK_SIZE,
-            ..Default::default()
-        }));
-        blocks.push(BomBlock::Paths(BomBlockPaths {
-            is_path_info: 1,
-            ..Default::default()
-        }));
+        push_tree(&mut blocks, vec![], PATHS_BLOCK_SIZE);
+
+        // Size64 is Tree + Paths. Each entry points to the size of a path too
+        // large for its path record.
+        let mut size64_entries = vec![];
+        for (index_path, entry) in &self.paths {
+            if entry.size() as u64 <= u32::MAX as u64 {
+                continue;
+            }
+
+            let Some(path_entry) = path_entries.get(index_path) else {
+                continue;
+            };
+
+            size64_entries.push(BomPathsEntry {
+                block_index: blocks.len() as u32,
+                file_index: path_entry.file_index,
+            });
+            blocks.push(BomBlock::Size64(BomBlockSize64 {
+                size: entry.size() as u64,
+            }));
+        }
 
-        // Size64 is Tree + Paths.
-        blocks.push(BomBlock::Tree(BomBlockTree {
-            block_paths_index: blocks.len() as u32 + 1,
-            block_size: PATHS_BLOCK_SIZE,
-            ..Default::default()
-        }));
         vars_index.count += 1;
         vars_index
             .vars
             .push(BomVar::new(blocks.len() as _, "Size64")?);
+        push_tree(&mut blocks, size64_entries, PATHS_BLOCK_SIZE);
+
+        write_bom(&blocks, &vars_index)
+    }
+}
+
+/// Serialize blocks and variables to BOM data.
+pub(crate) fn write_bom(blocks: &[BomBlock], vars_index: &BomVarsIndex) -> Result<Vec<u8>, Error> {
+    // Now that we've assembled all the blocks as data structures, it is time to write
+    // them out.
+    //
+    // The header contains offsets and sizes of variable length data, which we won't
+    // know until we produced it. Furthermore, the blocks index refers to file level
+    // offsets. There's kind of a chicken and egg problem here. We side step it by
+    // starting blocks data at a fixed file offset, giving plenty of room for the
+    // file header.
+    const BLOCK_DATA_FILE_OFFSET: u32 = 512;
+
+    let mut blocks_index = BomBlocksIndex::default();
+    let mut blocks_writer = Cursor::new(Vec::<u8>::new());
+
+    for block in blocks {
+        let start_offset = blocks_writer.position();
+        block.write(&mut blocks_writer)?;
+        let end_offset = blocks_writer.position();
+
+        blocks_index.count += 1;
+        blocks_index.blocks.push(BomBlocksEntry {
+            file_offset: BLOCK_DATA_FILE_OFFSET + start_offset as u32,
+            length: (end_offset - start_offset) as _,
+        });
+    }
+
+    let blocks_data = blocks_writer.into_inner();
+    let vars_index_data = vars_index.to_vec()?;
+    let blocks_index_data = blocks_index.to_vec()?;
+
+    // The vars index is small. We can put it after the header.
+    const VARS_INDEX_OFFSET: u32 = 128;
+
+    // The blocks index goes after the blocks data. We align on 64 byte boundary
+    // because why not.
+    let blocks_index_offset =
+        BLOCK_DATA_FILE_OFFSET + blocks_data.len() as u32 + (64 - blocks_data.len() % 64) as u32;
+
+    let header = BomHeader {
+        magic: *b"BOMStore",
+        version: 1,
+        number_of_blocks: blocks.len() as _,
+        blocks_index_offset,
+        blocks_index_length: blocks_index_data.len() as _,
+        vars_index_offset: VARS_INDEX_OFFSET,
+        vars_index_length: vars_index_data.len() as _,
+    };
+
+    // We have all the requisite parts. Time to write it.
+    let mut writer = Cursor::new(Vec::<u8>::new());
+    writer.iowrite_with(header, scroll::BE)?;
+
+    // Pad to vars index.
+    for _ in 0..VARS_INDEX_OFFSET - writer.position() as u32 {
+        writer.write_all(b"\0")?;
+    }
+
+    writer.write_all(&vars_index_data)?;
+
+    // Pad to blocks data.
+    for _ in 0..BLOCK_DATA_FILE_OFFSET - writer.position() as u32 {
+        writer.write_all(b"\0")?;
+    }
+
+    writer.write_all(&blocks_data)?;
+
+    // Pad to blocks index.
+    for _ in 0..blocks_index_offset - writer.position() as u32 {
+        writer.write_all(b"\0")?;
+    }
+
+    writer.write_all(&blocks_index_data)?;
+
+    Ok(writer.into_inner())
+}
+
+/// Convert a [BomPath] to the record stored in the BOM.
+fn path_record(entry: &BomPath) -> BomBlockPathRecord<'static> {
+    let file_type = match entry.path_type() {
+        BomPathType::File => S_IFREG as u16,
+        BomPathType::Directory => S_IFDIR as u16,
+        BomPathType::Link => S_IFLNK as u16,
+        BomPathType::Dev | BomPathType::Other(_) => 0,
+    };
+
+    // Apple tooling records the file type in the mode.
+    let mode = if entry.file_mode() & 0o170000 == 0 {
+        entry.file_mode() | file_type
+    } else {
+        entry.file_mode()
+    };
+
+    let (architecture, binary_info) = if entry.architectures().is_empty() {
+        (entry.architecture(), None)
+    } else {
+        (
+            entry.architecture() | ARCHITECTURE_BINARY_INFO,
+            Some(BomBlockBinaryInfo {
+                a: 1,
+                architectures: entry.architectures().to_vec(),
+            }),
+        )
+    };
+
+    let checksum_or_type = match entry.path_type() {
+        BomPathType::Dev => entry.device().unwrap_or(0),
+        _ => entry.crc32().unwrap_or(0),
+    };
+
+    BomBlockPathRecord {
+        path_type: entry.path_type().into(),
+        a: 1,
+        architecture,
+        mode,
+        user: entry.user_id(),
+        group: entry.group_id(),
+        mtime: entry.modified_time().timestamp() as _,
+        // Sizes that don't fit are stored in the Size64 tree.
+        size: entry.size().min(u32::MAX as usize) as _,
+        b: 1,
+        checksum_or_type,
+        binary_info,
+        link_name_length: if let Some(link_name) = entry.link_name() {
+            link_name.len() as u32 + 1
+        } else {
+            0
+        },
+        link_name: entry.link_name_cstring().map(Cow::from),
+    }
+}
+
+/// Block size of trees written by Apple tooling.
+const PATHS_BLOCK_SIZE: u32 = 4096;
+
+/// Append blocks for a tree holding the given entries.
+///
+/// Returns the block index of the [BomBlockTree].
+///
+/// Entries are split into leaf pages of at most `block_size` bytes. Index pages
+/// are added until a single root page remains. The root is always an index page.
+/// Pages are emitted top-down, with the root directly after the [BomBlockTree].
+pub(crate) fn push_tree(
+    blocks: &mut Vec<BomBlock>,
+    entries: Vec<BomPathsEntry>,
+    block_size: u32,
+) -> u32 {
+    // Pages have a 12 byte header followed by 8 byte entries.
+    let page_capacity = ((block_size as usize).saturating_sub(12) / 8).max(2);
+
+    let tree_index = blocks.len() as u32;
+    blocks.push(BomBlock::Tree(BomBlockTree {
+        block_paths_index: tree_index + 1,
+        block_size,
+        path_count: entries.len() as u32,
+        ..Default::default()
+    }));
+
+    // Empty trees consist of a single empty Paths.
+    if entries.is_empty() {
         blocks.push(BomBlock::Paths(BomBlockPaths {
             is_path_info: 1,
             ..Default::default()
         }));
 
-        // Now that we've assembled all the blocks as data structures, it is time to write
-        // them out.
-        //
-        // The header contains offsets and sizes of variable length data, which we won't
-        // know until we produced it. Furthermore, the blocks index refers to file level
-        // offsets. There's kind of a chicken and egg problem here. We side step it by
-        // starting blocks data at a fixed file offset, giving plenty of room for the
-        // file header.
-        const BLOCK_DATA_FILE_OFFSET: u32 = 512;
-
-        let mut blocks_index = BomBlocksIndex::default();
-        let mut blocks_writer = Cursor::new(Vec::<u8>::new());
-
-        for block in &blocks {
-            let start_offset = blocks_writer.position();
-            block.write(&mut blocks_writer)?;
-            let end_offset = blocks_writer.position();
-
-            blocks_index.count += 1;
-            blocks_index.blocks.push(BomBlocksEntry {
-                file_offset: BLOCK_DATA_FILE_OFFSET + start_offset as u32,
-                length: (end_offset - start_offset) as _,
-            });
+        return tree_index;
+    }
+
+    // Number of pages in each level, leaves first.
+    let mut level_counts = vec![entries.len().div_ceil(page_capacity)];
+    loop {
+        let count = *level_counts.last().expect("levels is not empty");
+        if count == 1 && level_counts.len() > 1 {
+            break;
+        }
+        level_counts.push(count.div_ceil(page_capacity));
+    }
+
+    // Block index of the first page of each level.
+    let mut level_starts = vec![0u32; level_counts.len()];
+    let mut next_index = tree_index + 1;
+    for (start, count) in level_starts.iter_mut().zip(&level_counts).rev() {
+        *start = next_index;
+        next_index += *count as u32;
+    }
+
+    // Construct pages bottom-up. Index entries use the key of the last entry
+    // of their child.
+    let mut levels = vec![entries
+        .chunks(page_capacity)
+        .map(|chunk| chunk.to_vec())
+        .collect::<Vec<_>>()];
+    for level in 1..level_counts.len() {
+        let children = &levels[level - 1];
+        let pages = children
+            .iter()
+            .enumerate()
+            .map(|(i, child)| BomPathsEntry {
+                block_index: level_starts[level - 1] + i as u32,
+                file_index: child.last().expect("pages are not empty").file_index,
+            })
+            .collect::<Vec<_>>()
+            .chunks(page_capacity)
+            .map(|chunk| chunk.to_vec())
+            .collect::<Vec<_>>();
+        levels.push(pages);
+    }
+
+    for (level, pages) in levels.into_iter().enumerate().rev() {
+        let start = level_starts[level];
+        let count = pages.len() as u32;
+
+        for (i, paths) in pages.into_iter().enumerate() {
+            let i = i as u32;
+            debug_assert_eq!(blocks.len() as u32, start + i);
+
+            blocks.push(BomBlock::Paths(BomBlockPaths {
+                is_path_info: if level == 0 { 1 } else { 0 },
+                count: paths.len() as _,
+                next_paths_block_index: if i + 1 == count { 0 } else { start + i + 1 },
+                previous_paths_block_index: if i == 0 { 0 } else { start + i - 1 },
+                paths,
+            }));
         }
+    }
 
-        let blocks_data = blocks_writer.into_inner();
-        let vars_index_data = vars_index.to_vec()?;
-        let blocks_index_data = blocks_index.to_vec()?;
+    tree_index
+}
 
-        // The vars index is small. We can put it after the header.
-        const VARS_INDEX_OFFSET: u32 = 128;
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::format::ParsedBom, chrono::TimeZone};
+
+    #[test]
+    fn build_and_parse() -> Result<(), Error> {
+        let mut builder = BomBuilder::default();
+        builder
+            .add_file_from_data("bin/tool", b"tool")?
+            .set_file_mode(0o755);
+        builder.add_file_from_data("share/doc/README", b"readme")?;
+
+        let data = builder.build_bom()?;
+        let bom = ParsedBom::parse(&data)?;
+
+        bom.bom_info()?;
+        for _ in bom.hl_index()? {}
+        for _ in bom.size64()? {}
+        for _ in bom.vindex()? {}
+
+        let paths = bom.paths()?;
+        assert_eq!(
+            paths.iter().map(|p| p.path()).collect::<Vec<_>>(),
+            vec![
+                ".",
+                "./bin",
+                "./share",
+                "./bin/tool",
+                "./share/doc",
+                "./share/doc/README"
+            ]
+        );
+
+        let tool = &paths[3];
+        assert_eq!(tool.size(), 4);
+        assert_eq!(tool.crc32(), Some(2090409092));
+        assert_eq!(tool.symbolic_mode(), "-rwxr-xr-x");
+        assert_eq!(tool.architecture(), 15);
+
+        for dir in [&paths[0], &paths[1]] {
+            assert_eq!(dir.file_mode(), 0o40755);
+            assert_eq!(dir.architecture(), 15);
+        }
 
-        // The blocks index goes after the blocks data. We align on 64 byte boundary
-        // because why not.
-        let blocks_index_offset = BLOCK_DATA_FILE_OFFSET
-            + blocks_data.len() as u32
-            + (64 - blocks_data.len() % 64) as u32;
+        Ok(())
+    }
 
-        let header = BomHeader {
-            magic: *b"BOMStore",
-            version: 1,
-            number_of_blocks: blocks.len() as _,
-            blocks_index_offset,
-            blocks_index_length: blocks_index_data.len() as _,
-            vars_index_offset: VARS_INDEX_OFFSET,
-            vars_index_length: vars_index_data.len() as _,
+    #[test]
+    fn cksum() {
+        assert_eq!(cksum_data(b""), 0xffffffff);
+        assert_eq!(cksum_data(b"hello world\n"), 3733384285);
+    }
+
+    #[test]
+    fn special_paths() -> Result<(), Error> {
+        let mut builder = BomBuilder::default();
+        builder.add_directory("empty")?.set_file_mode(0o40700);
+        builder.add_symlink("lib/current", "../target")?;
+        builder.add_character_device("dev/null", 0x0300_0002)?;
+        builder.add_block_device("dev/disk0", 0x0100_0000)?;
+        builder.add_file_from_data("bin/a", b"data")?;
+        builder.add_hard_link("bin/b", "bin/a")?;
+        builder.add_hard_link("bin/c", "bin/b")?;
+        builder
+            .add_file_from_data("large", b"")?
+            .set_size(5 * 1024 * 1024 * 1024);
+
+        assert!(builder.add_hard_link("bin/d", "missing").is_err());
+        assert!(builder.add_hard_link("bin/d", "empty").is_err());
+
+        let data = builder.build_bom()?;
+        let bom = ParsedBom::parse(&data)?;
+
+        let paths = bom
+            .paths()?
+            .into_iter()
+            .map(|p| (p.path().to_string(), p))
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!(paths["./empty"].symbolic_mode(), "drwx------");
+
+        let link = &paths["./lib/current"];
+        assert_eq!(link.symbolic_mode(), "lrwxr-xr-x");
+        assert_eq!(link.link_name(), Some("../target"));
+        assert_eq!(link.size(), 9);
+        assert_eq!(link.crc32(), Some(1613057741));
+
+        assert_eq!(paths["./dev/null"].symbolic_mode(), "crw-r--r--");
+        assert_eq!(paths["./dev/null"].device(), Some(0x0300_0002));
+        assert_eq!(paths["./dev/disk0"].symbolic_mode(), "brw-r--r--");
+        assert_eq!(paths["./dev/disk0"].device(), Some(0x0100_0000));
+
+        assert_eq!(paths["./bin/c"].crc32(), paths["./bin/a"].crc32());
+        assert_eq!(
+            builder.hard_links(),
+            &[vec![
+                "bin/a".to_string(),
+                "bin/b".to_string(),
+                "bin/c".to_string()
+            ]]
+        );
+        let groups = bom.hl_index()?;
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            groups[0].iter().map(|p| p.path()).collect::<Vec<_>>(),
+            vec!["./bin/a", "./bin/b", "./bin/c"]
+        );
+        assert_eq!(groups[0][2].crc32(), paths["./bin/a"].crc32());
+
+        assert_eq!(paths["./large"].size(), 5 * 1024 * 1024 * 1024);
+        let size64 = bom.size64()?;
+        assert_eq!(size64.len(), 1);
+        assert_eq!(size64[0].path(), "./large");
+        assert_eq!(size64[0].size(), 5 * 1024 * 1024 * 1024);
+
+        // Replacing a path removes it from its hard link group.
+        builder.add_file_from_data("bin/b", b"other")?;
+        builder.add_file_from_data("bin/c", b"other")?;
+        assert!(builder.hard_links().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn macho_architectures() -> Result<(), Error> {
+        let slice = |cpu_type: u32, cpu_subtype: u32| {
+            let mut data = MH_MAGIC_64.to_le_bytes().to_vec();
+            data.extend(cpu_type.to_le_bytes());
+            data.extend(cpu_subtype.to_le_bytes());
+            data.resize(64, 0);
+            data
         };
 
-        // We have all the requisite parts. Time to write it.
-        let mut writer = Cursor::new(Vec::<u8>::new());
-        writer.iowrite_with(header, scroll::BE)?;
+        let x86_64 = slice(0x0100_0007, 3);
+        let arm64 = slice(0x0100_000c, 0);
+
+        let mut fat = FAT_MAGIC.to_be_bytes().to_vec();
+        fat.extend(2u32.to_be_bytes());
+        for (i, (cpu_type, cpu_subtype)) in [(0x0100_0007u32, 3u32), (0x0100_000c, 0)]
+            .into_iter()
+            .enumerate()
+        {
+            fat.extend(cpu_type.to_be_bytes());
+            fat.extend(cpu_subtype.to_be_bytes());
+            fat.extend((64 + i as u32 * 64).to_be_bytes());
+            fat.extend(64u32.to_be_bytes());
+            fat.extend(0u32.to_be_bytes());
+        }
+        fat.resize(64, 0);
+        fat.extend(&x86_64);
+        fat.extend(&arm64);
+
+        let mut builder = BomBuilder::default();
+        builder.add_file_from_data("universal", &fat)?;
+        builder.add_file_from_data("thin", &arm64)?;
+        // Java class files share the universal binary magic.
+        let mut class = FAT_MAGIC.to_be_bytes().to_vec();
+        class.extend(52u32.to_be_bytes());
+        builder.add_file_from_data("Class.class", &class)?;
+
+        let data = builder.build_bom()?;
+        let bom = ParsedBom::parse(&data)?;
+        let paths = bom.paths()?;
+
+        let universal = paths.iter().find(|p| p.path() == "./universal").unwrap();
+        assert_eq!(universal.architecture(), 15);
+        assert_eq!(
+            universal.architectures(),
+            &[
+                BomBlockArchitecture {
+                    cpu_type: 0x0100_0007,
+                    cpu_subtype: 3,
+                    size: 64,
+                    checksum: cksum_data(&x86_64),
+                },
+                BomBlockArchitecture {
+                    cpu_type: 0x0100_000c,
+                    cpu_subtype: 0,
+                    size: 64,
+                    checksum: cksum_data(&arm64),
+                }
+            ]
+        );
+
+        let thin = paths.iter().find(|p| p.path() == "./thin").unwrap();
+        assert_eq!(thin.architectures().len(), 1);
+        assert_eq!(thin.architectures()[0].cpu_type, 0x0100_000c);
+
+        let class = paths.iter().find(|p| p.path() == "./Class.class").unwrap();
+        assert!(class.architectures().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn large_tree() -> Result<(), Error> {
+        let mut builder = BomBuilder::default();
+        for i in 0..20000 {
+            builder.add_file_from_data(format!("dir{}/file{i}", i / 1000), b"")?;
+        }
 
-        // Pad to vars index.
-        for _ in 0..VARS_INDEX_OFFSET - writer.position() as u32 {
-            writer.write_all(b"\0")?;
+        let data = builder.build_bom()?;
+        let bom = ParsedBom::parse(&data)?;
+
+        let paths = bom.paths()?;
+        assert_eq!(paths.len(), 20000 + 20 + 1);
+        assert_eq!(paths[1].path(), "./dir0");
+        assert_eq!(paths[2].path(), "./dir1");
+        assert_eq!(paths[21].path(), "./dir0/file0");
+
+        let tree = bom.block_as_tree(bom.find_variable("Paths")?.block_index as _)?;
+        assert_eq!(tree.path_count, 20021);
+        let leaves = tree.leaves(&bom)?;
+        assert_eq!(leaves.len(), 20021usize.div_ceil(510));
+        for (i, leaf) in leaves.iter().enumerate() {
+            assert_eq!(leaf.is_path_info, 1);
+            assert!(BomBlock::Paths(leaf.clone()).to_vec()?.len() <= PATHS_BLOCK_SIZE as usize);
+            assert_eq!(leaf.next_paths_block_index == 0, i == leaves.len() - 1);
         }
 
-        writer.write_all(&vars_index_data)?;
+        let root = tree.paths(&bom)?;
+        assert_eq!(root.is_path_info, 0);
+        assert_eq!(root.count as usize, leaves.len());
 
-        // Pad to blocks data.
-        for _ in 0..BLOCK_DATA_FILE_OFFSET - writer.position() as u32 {
-            writer.write_all(b"\0")?;
+        Ok(())
+    }
+
+    #[test]
+    fn paths_order_matches_mkbom() -> Result<(), Error> {
+        // Written by mkbom, which orders the Paths tree by parent path ID and
+        // then by name instead of by full path. Path IDs follow the order of
+        // the payload, so only the ordering rule can be compared.
+        let keys = |bom: &ParsedBom| -> Result<Vec<(u32, Vec<u8>)>, Error> {
+            let tree = bom.block_as_tree(bom.find_variable("Paths")?.block_index as _)?;
+            tree.leaf_entries(bom)?
+                .iter()
+                .map(|entry| {
+                    let file = entry.file(bom)?;
+                    Ok((file.parent_path_id, file.name.to_bytes().to_vec()))
+                })
+                .collect()
+        };
+
+        let apple = ParsedBom::parse(include_bytes!("testdata/python-applications.bom"))?;
+        let apple_keys = keys(&apple)?;
+        assert!(apple_keys.windows(2).all(|w| w[0] < w[1]));
+
+        let mut builder = BomBuilder::default();
+        for path in apple.paths()? {
+            let Some(name) = path.path().strip_prefix("./") else {
+                continue;
+            };
+            if path.path_type() == BomPathType::Directory {
+                builder.add_directory(name)?;
+            } else {
+                builder.add_file_from_data(name, b"")?;
+            }
         }
 
-        writer.write_all(&blocks_data)?;
+        let data = builder.build_bom()?;
+        let bom = ParsedBom::parse(&data)?;
+        let built_keys = keys(&bom)?;
+        assert_eq!(built_keys.len(), apple_keys.len());
+        assert!(built_keys.windows(2).all(|w| w[0] < w[1]));
+
+        let mut apple_paths = apple
+            .paths()?
+            .iter()
+            .map(|p| p.path().to_string())
+            .collect::<Vec<_>>();
+        let mut built_paths = bom
+            .paths()?
+            .iter()
+            .map(|p| p.path().to_string())
+            .collect::<Vec<_>>();
+        apple_paths.sort();
+        built_paths.sort();
+        assert_eq!(built_paths, apple_paths);
+
+        Ok(())
+    }
+
+    #[test]
+    fn deep_tree() -> Result<(), Error> {
+        let entries = (1..=500)
+            .map(|i| BomPathsEntry {
+                block_index: i,
+                file_index: i,
+            })
+            .collect::<Vec<_>>();
+
+        let mut blocks = vec![BomBlock::Empty];
+        let tree_index = push_tree(&mut blocks, entries.clone(), 64);
+        let vars_index = BomVarsIndex {
+            count: 1,
+            vars: vec![BomVar::new(tree_index, "Paths")?],
+        };
+
+        let data = write_bom(&blocks, &vars_index)?;
+        let bom = ParsedBom::parse(&data)?;
+        let tree = bom.block_as_tree(tree_index as _)?;
+
+        // 6 entries per page: 84 leaves, 14 + 3 + 1 index pages.
+        assert_eq!(tree.leaves(&bom)?.len(), 84);
+        assert_eq!(blocks.len(), 1 + 1 + 84 + 14 + 3 + 1);
+        assert_eq!(tree.leaf_entries(&bom)?, entries);
+
+        let root = tree.paths(&bom)?;
+        assert_eq!(root.is_path_info, 0);
+        assert_eq!(root.paths.last().unwrap().file_index, 500);
+
+        Ok(())
+    }
+
+    #[test]
+    fn linked_leaves() -> Result<(), Error> {
+        let entry = |i| BomPathsEntry {
+            block_index: i,
+            file_index: i,
+        };
 
-        // Pad to blocks index.
-        for _ in 0..blocks_index_offset - writer.position() as u32 {
-            writer.write_all(b"\0")?;
+        // The root index only references the first of the linked leaves.
+        let blocks = vec![
+            BomBlock::Empty,
+            BomBlock::Tree(BomBlockTree {
+                block_paths_index: 2,
+                block_size: PATHS_BLOCK_SIZE,
+                path_count: 3,
+                ..Default::default()
+            }),
+            BomBlock::Paths(BomBlockPaths {
+                is_path_info: 0,
+                count: 1,
+                paths: vec![entry(3)],
+                ..Default::default()
+            }),
+            BomBlock::Paths(BomBlockPaths {
+                is_path_info: 1,
+                count: 2,
+                next_paths_block_index: 4,
+                paths: vec![entry(10), entry(11)],
+                ..Default::default()
+            }),
+            BomBlock::Paths(BomBlockPaths {
+                is_path_info: 1,
+                count: 1,
+                previous_paths_block_index: 3,
+                paths: vec![entry(12)],
+                ..Default::default()
+            }),
+        ];
+        let vars_index = BomVarsIndex {
+            count: 1,
+            vars: vec![BomVar::new(1, "Paths")?],
+        };
+
+        let data = write_bom(&blocks, &vars_index)?;
+        let bom = ParsedBom::parse(&data)?;
+        let tree = bom.block_as_tree(1)?;
+        assert_eq!(
+            tree.leaf_entries(&bom)?,
+            vec![entry(10), entry(11), entry(12)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_cpio() -> Result<(), Error> {
+        let mut cpio = cpio_archive::OdcBuilder::new(vec![]);
+        let mut append = |name: &str, mode: u32, inode: u32, nlink: u32, data: &[u8]| {
+            let mut header = cpio.next_header();
+            header.name = name.to_string();
+            header.mode = mode;
+            header.inode = inode;
+            header.nlink = nlink;
+            header.uid = 501;
+            header.gid = 20;
+            header.mtime = 1_600_000_000;
+            header.rdev = if mode & 0o170000 == S_IFCHR { 7 } else { 0 };
+            header.file_size = data.len() as _;
+            cpio.append_header_with_data(header, data)
+        };
+        append(".", 0o40700, 1, 2, b"")?;
+        append("./bin", 0o40755, 2, 2, b"")?;
+        append("./bin/tool", 0o100755, 3, 2, b"tool")?;
+        append("./bin/tool-link", 0o100755, 3, 2, b"tool")?;
+        append("./bin/current", 0o120755, 4, 1, b"tool")?;
+        append("./dev/tty", 0o20666, 5, 1, b"")?;
+        append("./.hidden", 0o100644, 6, 1, b"")?;
+        cpio.finish()?;
+        let data = cpio.into_inner()?;
+
+        let mut reader = cpio_archive::reader(std::io::Cursor::new(data))?;
+        let builder = BomBuilder::from_cpio_reader(reader.as_mut())?;
+        assert_eq!(
+            builder.hard_links(),
+            &[vec!["bin/tool".to_string(), "bin/tool-link".to_string()]]
+        );
+
+        let bom = builder.build_bom()?;
+        let bom = ParsedBom::parse(&bom)?;
+        let paths = bom
+            .paths()?
+            .into_iter()
+            .map(|p| (p.path().to_string(), p))
+            .collect::<BTreeMap<_, _>>();
+
+        assert_eq!(
+            paths.keys().map(|p| p.as_str()).collect::<Vec<_>>(),
+            vec![
+                ".",
+                "./.hidden",
+                "./bin",
+                "./bin/current",
+                "./bin/tool",
+                "./bin/tool-link",
+                "./dev",
+                "./dev/tty"
+            ]
+        );
+
+        assert_eq!(paths["."].file_mode(), 0o40700);
+        assert_eq!(paths["."].user_id(), 501);
+        let tool = &paths["./bin/tool"];
+        assert_eq!(tool.file_mode(), 0o100755);
+        assert_eq!((tool.user_id(), tool.group_id()), (501, 20));
+        assert_eq!(tool.modified_time().timestamp(), 1_600_000_000);
+        assert_eq!(tool.size(), 4);
+        assert_eq!(tool.crc32(), Some(2090409092));
+        assert_eq!(paths["./bin/current"].link_name(), Some("tool"));
+        assert_eq!(paths["./dev/tty"].symbolic_mode(), "crw-rw-rw-");
+        assert_eq!(paths["./dev/tty"].device(), Some(7));
+        let groups = bom.hl_index()?;
+        assert_eq!(
+            groups
+                .iter()
+                .map(|group| group.iter().map(|p| p.path()).collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            vec![vec!["./bin/tool", "./bin/tool-link"]]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_file_manifest() -> Result<(), Error> {
+        let mut manifest = FileManifest::new_with_links();
+        manifest
+            .add_file_entry(
+                "bin/tool",
+                simple_file_manifest::FileEntry::new_from_data(b"tool".to_vec(), true),
+            )
+            .unwrap();
+        manifest
+            .add_file_entry(
+                "share/README",
+                simple_file_manifest::FileEntry::new_from_data(b"readme".to_vec(), false),
+            )
+            .unwrap();
+        manifest.add_symlink("bin/link", "tool").unwrap();
+
+        let mut cpio = cpio_archive::OdcBuilder::new(vec![]);
+        cpio.default_user_id(501);
+        cpio.default_mtime(Utc.timestamp_opt(1_600_000_000, 0).unwrap());
+        let template = cpio.next_header();
+        cpio.append_file_manifest(&manifest)?;
+        let data = cpio.into_inner()?;
+
+        let builder = BomBuilder::from_file_manifest(&manifest, &template)?;
+        let bom = builder.build_bom()?;
+
+        // The BOM agrees with the one recorded from the payload.
+        let mut reader = cpio_archive::reader(std::io::Cursor::new(data))?;
+        assert_eq!(
+            bom,
+            BomBuilder::from_cpio_reader(reader.as_mut())?.build_bom()?
+        );
+
+        let bom = ParsedBom::parse(&bom)?;
+        let paths = bom
+            .paths()?
+            .into_iter()
+            .map(|p| (p.path().to_string(), p))
+            .collect::<BTreeMap<_, _>>();
+
+        assert_eq!(paths["./bin/tool"].file_mode(), 0o100755);
+        assert_eq!(paths["./bin/tool"].crc32(), Some(2090409092));
+        assert_eq!(paths["./share/README"].file_mode(), 0o100644);
+        assert_eq!(paths["./bin/link"].link_name(), Some("tool"));
+        assert_eq!(paths["./bin"].user_id(), 501);
+        assert_eq!(paths["./bin"].modified_time().timestamp(), 1_600_000_000);
+
+        // Directories are recorded as such when the default mode lacks the file type.
+        let mut builder = BomBuilder::default();
+        builder.default_mode_directory(0o700);
+        builder.add_file_manifest(&manifest, &template)?;
+        let bom = builder.build_bom()?;
+        let bom = ParsedBom::parse(&bom)?;
+        for path in bom.paths()? {
+            if matches!(path.path(), "." | "./bin" | "./share") {
+                assert_eq!(path.path_type(), BomPathType::Directory);
+                assert_eq!(path.file_mode(), 0o40700);
+            }
         }
 
-        writer.write_all(&blocks_index_data)?;
+        Ok(())
+    }
 
-        Ok(writer.into_inner())
+    #[test]
+    fn normalize_paths() {
+        assert_eq!(
+            normalize_bom_path(".hidden/._file").unwrap(),
+            ".hidden/._file"
+        );
+        assert_eq!(normalize_bom_path("a/b/").unwrap(), "a/b");
+        for path in ["/abs", "a\\b", "../a", "a/./b", "a//b", ".", "/"] {
+            assert!(normalize_bom_path(path).is_err(), "{path}");
+        }
     }
 }
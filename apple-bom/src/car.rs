@@ -0,0 +1,1090 @@
+// Copyright 2022 Gregory Szorc.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Compiled asset catalogs (`Assets.car`).
+//!
+//! Asset catalogs are produced by `actool` and live in app bundles. They use
+//! the BOM container: named variables point to blocks and trees. Apple hasn't
+//! documented the data structures stored inside the blocks, so everything here
+//! is based on reverse engineering. Unlike BOM structures, they are
+//! little-endian.
+//!
+//! The important variables are:
+//!
+//! * `CARHEADER`: a [CarHeader] with versions and the rendition count.
+//! * `EXTENDED_METADATA`: [ExtendedMetadata] describing how the catalog was
+//!   built.
+//! * `KEYFORMAT`: the [RenditionAttribute] of each token of rendition keys.
+//! * `FACETKEYS`: a tree mapping asset names to the attributes identifying
+//!   their renditions. See [Facet].
+//! * `RENDITIONS`: a tree mapping rendition keys to the rendition data.
+//!   See [Rendition].
+//! * `APPEARANCEKEYS`: a tree mapping appearance names to their ID.
+//!
+//! The entries of trees store the block of the key in
+//! [crate::format::BomPathsEntry::file_index] and the block of the value in
+//! [crate::format::BomPathsEntry::block_index].
+//!
+//! Only bitmaps stored uncompressed or with zlib are decoded. `actool` mostly
+//! stores bitmaps with LZFSE or LZVN, as palette images or as deepmaps, none of
+//! which are decoded: [Rendition::pixels] errors for them and
+//! [BitmapCompression::is_supported] tells them apart. So most bitmaps of
+//! catalogs found in app bundles can't be converted to images. Their renditions
+//! are still listed with their compression and size.
+//!
+//! The parser has been tested against synthetic catalogs following the layouts
+//! known from reverse engineering, not against catalogs produced by `actool`.
+
+use {
+    crate::{error::Error, format::ParsedBom},
+    scroll::Pread,
+    std::{
+        fmt::Display,
+        io::{Read, Write},
+    },
+};
+
+/// Magic of [CarHeader], `CTAR` as a little-endian u32.
+const CAR_HEADER_TAG: u32 = u32::from_be_bytes(*b"CTAR");
+/// Magic of [ExtendedMetadata].
+const EXTENDED_METADATA_TAG: u32 = u32::from_be_bytes(*b"META");
+/// Magic of the `KEYFORMAT` block.
+const KEY_FORMAT_TAG: u32 = u32::from_be_bytes(*b"kfmt");
+/// Magic of rendition headers.
+const RENDITION_TAG: u32 = u32::from_be_bytes(*b"CTSI");
+/// Magic of bitmap rendition data.
+const BITMAP_TAG: u32 = u32::from_be_bytes(*b"CELM");
+/// Magic of raw rendition data.
+const RAW_TAG: u32 = u32::from_be_bytes(*b"RAWD");
+/// Magic of color rendition data.
+const COLOR_TAG: u32 = u32::from_be_bytes(*b"COLR");
+
+/// Size of the fixed part of a rendition header.
+const RENDITION_HEADER_SIZE: usize = 184;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Format a four character code stored as a little-endian u32.
+fn fourcc(value: u32) -> String {
+    String::from_utf8_lossy(&value.to_be_bytes()).to_string()
+}
+
+/// Read a NUL padded string.
+fn c_string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+
+    String::from_utf8_lossy(&data[..end]).to_string()
+}
+
+/// Obtain a subslice, failing on truncated data.
+fn slice(data: &[u8], offset: usize, length: usize) -> Result<&[u8], Error> {
+    offset
+        .checked_add(length)
+        .and_then(|end| data.get(offset..end))
+        .ok_or(Error::BadAssetCatalog("truncated data"))
+}
+
+/// The `CARHEADER` of an asset catalog.
+#[derive(Clone, Debug)]
+pub struct CarHeader {
+    pub core_ui_version: u32,
+    pub storage_version: u32,
+    pub storage_timestamp: u32,
+    pub rendition_count: u32,
+    pub main_version: String,
+    pub version: String,
+    pub uuid: [u8; 16],
+    pub associated_checksum: u32,
+    pub schema_version: u32,
+    pub color_space_id: u32,
+    pub key_semantics: u32,
+}
+
+impl CarHeader {
+    /// Parse an instance from block data.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.pread_with::<u32>(0, scroll::LE)? != CAR_HEADER_TAG {
+            return Err(Error::BadAssetCatalog("bad CARHEADER magic"));
+        }
+
+        let mut uuid = [0u8; 16];
+        uuid.copy_from_slice(slice(data, 404, 16)?);
+
+        Ok(Self {
+            core_ui_version: data.pread_with(4, scroll::LE)?,
+            storage_version: data.pread_with(8, scroll::LE)?,
+            storage_timestamp: data.pread_with(12, scroll::LE)?,
+            rendition_count: data.pread_with(16, scroll::LE)?,
+            main_version: c_string(slice(data, 20, 128)?),
+            version: c_string(slice(data, 148, 256)?),
+            uuid,
+            associated_checksum: data.pread_with(420, scroll::LE)?,
+            schema_version: data.pread_with(424, scroll::LE)?,
+            color_space_id: data.pread_with(428, scroll::LE)?,
+            key_semantics: data.pread_with(432, scroll::LE)?,
+        })
+    }
+}
+
+/// The `EXTENDED_METADATA` of an asset catalog.
+#[derive(Clone, Debug)]
+pub struct ExtendedMetadata {
+    pub thinning_arguments: String,
+    pub deployment_platform_version: String,
+    pub deployment_platform: String,
+    pub authoring_tool: String,
+}
+
+impl ExtendedMetadata {
+    /// Parse an instance from block data.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.pread_with::<u32>(0, scroll::LE)? != EXTENDED_METADATA_TAG {
+            return Err(Error::BadAssetCatalog("bad EXTENDED_METADATA magic"));
+        }
+
+        Ok(Self {
+            thinning_arguments: c_string(slice(data, 4, 256)?),
+            deployment_platform_version: c_string(slice(data, 260, 256)?),
+            deployment_platform: c_string(slice(data, 516, 256)?),
+            authoring_tool: c_string(slice(data, 772, 256)?),
+        })
+    }
+}
+
+/// An attribute identifying renditions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RenditionAttribute {
+    ThemeLook,
+    Element,
+    Part,
+    Size,
+    Direction,
+    Placeholder,
+    Value,
+    Appearance,
+    Dimension1,
+    Dimension2,
+    State,
+    Layer,
+    Scale,
+    Localization,
+    PresentationState,
+    Idiom,
+    Subtype,
+    Identifier,
+    PreviousValue,
+    PreviousState,
+    HorizontalSizeClass,
+    VerticalSizeClass,
+    MemoryLevelClass,
+    GraphicsFeatureSetClass,
+    DisplayGamut,
+    DeploymentTarget,
+    Other(u16),
+}
+
+impl From<u16> for RenditionAttribute {
+    fn from(v: u16) -> Self {
+        match v {
+            0 => Self::ThemeLook,
+            1 => Self::Element,
+            2 => Self::Part,
+            3 => Self::Size,
+            4 => Self::Direction,
+            5 => Self::Placeholder,
+            6 => Self::Value,
+            7 => Self::Appearance,
+            8 => Self::Dimension1,
+            9 => Self::Dimension2,
+            10 => Self::State,
+            11 => Self::Layer,
+            12 => Self::Scale,
+            13 => Self::Localization,
+            14 => Self::PresentationState,
+            15 => Self::Idiom,
+            16 => Self::Subtype,
+            17 => Self::Identifier,
+            18 => Self::PreviousValue,
+            19 => Self::PreviousState,
+            20 => Self::HorizontalSizeClass,
+            21 => Self::VerticalSizeClass,
+            22 => Self::MemoryLevelClass,
+            23 => Self::GraphicsFeatureSetClass,
+            24 => Self::DisplayGamut,
+            25 => Self::DeploymentTarget,
+            _ => Self::Other(v),
+        }
+    }
+}
+
+impl Display for RenditionAttribute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::ThemeLook => "look",
+            Self::Element => "element",
+            Self::Part => "part",
+            Self::Size => "size",
+            Self::Direction => "direction",
+            Self::Placeholder => "placeholder",
+            Self::Value => "value",
+            Self::Appearance => "appearance",
+            Self::Dimension1 => "dimension1",
+            Self::Dimension2 => "dimension2",
+            Self::State => "state",
+            Self::Layer => "layer",
+            Self::Scale => "scale",
+            Self::Localization => "localization",
+            Self::PresentationState => "presentation-state",
+            Self::Idiom => "idiom",
+            Self::Subtype => "subtype",
+            Self::Identifier => "identifier",
+            Self::PreviousValue => "previous-value",
+            Self::PreviousState => "previous-state",
+            Self::HorizontalSizeClass => "horizontal-size-class",
+            Self::VerticalSizeClass => "vertical-size-class",
+            Self::MemoryLevelClass => "memory-class",
+            Self::GraphicsFeatureSetClass => "graphics-class",
+            Self::DisplayGamut => "gamut",
+            Self::DeploymentTarget => "deployment-target",
+            Self::Other(v) => return write!(f, "attribute-{v}"),
+        })
+    }
+}
+
+/// Find the value of an attribute in a list of attributes.
+fn find_attribute(
+    attributes: &[(RenditionAttribute, u16)],
+    attribute: RenditionAttribute,
+) -> Option<u16> {
+    attributes
+        .iter()
+        .find(|(a, _)| *a == attribute)
+        .map(|(_, v)| *v)
+}
+
+/// A named asset in the `FACETKEYS` tree.
+///
+/// Its attributes select the renditions of the asset.
+#[derive(Clone, Debug)]
+pub struct Facet {
+    pub name: String,
+    pub hot_spot: (u16, u16),
+    pub attributes: Vec<(RenditionAttribute, u16)>,
+}
+
+impl Facet {
+    /// Parse an instance from the key and value data of a tree entry.
+    pub fn parse(key: &[u8], value: &[u8]) -> Result<Self, Error> {
+        let count = value.pread_with::<u16>(4, scroll::LE)? as usize;
+
+        let attributes = (0..count)
+            .map(|i| {
+                let offset = 6 + i * 4;
+                Ok((
+                    value.pread_with::<u16>(offset, scroll::LE)?.into(),
+                    value.pread_with::<u16>(offset + 2, scroll::LE)?,
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(Self {
+            name: c_string(key),
+            hot_spot: (
+                value.pread_with(0, scroll::LE)?,
+                value.pread_with(2, scroll::LE)?,
+            ),
+            attributes,
+        })
+    }
+
+    /// Obtain the value of an attribute.
+    pub fn attribute(&self, attribute: RenditionAttribute) -> Option<u16> {
+        find_attribute(&self.attributes, attribute)
+    }
+
+    /// Whether a rendition belongs to this facet.
+    ///
+    /// This is the case if all attributes of the facet present in the rendition
+    /// key have the same value.
+    pub fn matches(&self, rendition: &Rendition) -> bool {
+        self.attributes
+            .iter()
+            .all(|(attribute, value)| match rendition.attribute(*attribute) {
+                Some(v) => v == *value,
+                None => true,
+            })
+    }
+}
+
+/// The compression of bitmap rendition data.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BitmapCompression {
+    Uncompressed,
+    Rle,
+    Zip,
+    Lzvn,
+    Lzfse,
+    JpegLzfse,
+    Blurred,
+    Astc,
+    PaletteImage,
+    Hevc,
+    DeepmapLzfse,
+    Deepmap2,
+    Other(u32),
+}
+
+impl BitmapCompression {
+    /// Whether [Rendition::pixels] can decode bitmaps with this compression.
+    pub fn is_supported(&self) -> bool {
+        matches!(self, Self::Uncompressed | Self::Zip)
+    }
+}
+
+impl From<u32> for BitmapCompression {
+    fn from(v: u32) -> Self {
+        match v {
+            0 => Self::Uncompressed,
+            1 => Self::Rle,
+            2 => Self::Zip,
+            3 => Self::Lzvn,
+            4 => Self::Lzfse,
+            5 => Self::JpegLzfse,
+            6 => Self::Blurred,
+            7 => Self::Astc,
+            8 => Self::PaletteImage,
+            9 => Self::Hevc,
+            10 => Self::DeepmapLzfse,
+            11 => Self::Deepmap2,
+            _ => Self::Other(v),
+        }
+    }
+}
+
+/// The content of a [Rendition].
+#[derive(Clone, Debug)]
+pub enum RenditionContent<'a> {
+    /// Pixels of a bitmap, in the stored compression.
+    Bitmap {
+        compression: BitmapCompression,
+        data: &'a [u8],
+    },
+    /// Data stored as is, such as PNG, JPEG, HEIF or PDF files or data assets.
+    Raw(&'a [u8]),
+    /// A color with a component per channel of its color space.
+    Color {
+        color_space: u32,
+        components: Vec<f64>,
+    },
+    /// Content of a type we don't know.
+    Unknown { tag: String, data: &'a [u8] },
+}
+
+/// A type-length-value record of a [Rendition].
+#[derive(Clone, Debug)]
+pub struct RenditionTlv<'a> {
+    pub tag: u32,
+    pub data: &'a [u8],
+}
+
+/// An entry in the `RENDITIONS` tree.
+///
+/// Renditions are the variants of assets, e.g. an image at a given scale.
+#[derive(Clone, Debug)]
+pub struct Rendition<'a> {
+    /// Attributes from the key of the entry.
+    pub key: Vec<(RenditionAttribute, u16)>,
+    pub version: u32,
+    pub flags: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Scale in percent. `200` for `@2x`.
+    pub scale_factor: u32,
+    /// Four character code of the pixel format, e.g. `ARGB`, `GA8 `, `PDF ` or `DATA`.
+    pub pixel_format: String,
+    pub color_space: u32,
+    pub modified_time: u32,
+    /// Layout describing how the rendition is drawn, e.g. `12` for a scaled image.
+    pub layout: u16,
+    /// File name the rendition was compiled from.
+    pub name: String,
+    pub tlvs: Vec<RenditionTlv<'a>>,
+    /// Rendition data following the header.
+    pub data: &'a [u8],
+}
+
+impl<'a> Rendition<'a> {
+    /// Parse an instance from the key and value data of a tree entry.
+    ///
+    /// `key_format` holds the attribute of each token in the key.
+    pub fn parse(
+        key_format: &[RenditionAttribute],
+        key: &[u8],
+        value: &'a [u8],
+    ) -> Result<Self, Error> {
+        if value.pread_with::<u32>(0, scroll::LE)? != RENDITION_TAG {
+            return Err(Error::BadAssetCatalog("bad rendition magic"));
+        }
+
+        let key = key_format
+            .iter()
+            .enumerate()
+            .map(|(i, attribute)| Ok((*attribute, key.pread_with::<u16>(i * 2, scroll::LE)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let tlv_length = value.pread_with::<u32>(168, scroll::LE)? as usize;
+        let data_length = value.pread_with::<u32>(180, scroll::LE)? as usize;
+
+        let mut tlv_data = slice(value, RENDITION_HEADER_SIZE, tlv_length)?;
+        let mut tlvs = vec![];
+        while tlv_data.len() >= 8 {
+            let tag = tlv_data.pread_with::<u32>(0, scroll::LE)?;
+            let length = tlv_data.pread_with::<u32>(4, scroll::LE)? as usize;
+            tlvs.push(RenditionTlv {
+                tag,
+                data: slice(tlv_data, 8, length)?,
+            });
+            tlv_data = &tlv_data[8 + length..];
+        }
+
+        Ok(Self {
+            key,
+            version: value.pread_with(4, scroll::LE)?,
+            flags: value.pread_with(8, scroll::LE)?,
+            width: value.pread_with(12, scroll::LE)?,
+            height: value.pread_with(16, scroll::LE)?,
+            scale_factor: value.pread_with(20, scroll::LE)?,
+            pixel_format: fourcc(value.pread_with(24, scroll::LE)?),
+            color_space: value.pread_with::<u32>(28, scroll::LE)? & 0xf,
+            modified_time: value.pread_with(32, scroll::LE)?,
+            layout: value.pread_with(36, scroll::LE)?,
+            name: c_string(slice(value, 40, 128)?),
+            tlvs,
+            data: slice(value, RENDITION_HEADER_SIZE + tlv_length, data_length)?,
+        })
+    }
+
+    /// Obtain the value of a key attribute.
+    pub fn attribute(&self, attribute: RenditionAttribute) -> Option<u16> {
+        find_attribute(&self.key, attribute)
+    }
+
+    /// Resolve the content of the rendition data.
+    pub fn content(&self) -> Result<RenditionContent<'a>, Error> {
+        let data = self.data;
+        if data.is_empty() {
+            return Ok(RenditionContent::Unknown {
+                tag: String::new(),
+                data,
+            });
+        }
+
+        let tag = data.pread_with::<u32>(0, scroll::LE)?;
+
+        Ok(match tag {
+            BITMAP_TAG => {
+                let length = data.pread_with::<u32>(12, scroll::LE)? as usize;
+
+                RenditionContent::Bitmap {
+                    compression: data.pread_with::<u32>(8, scroll::LE)?.into(),
+                    data: slice(data, 16, length)?,
+                }
+            }
+            RAW_TAG => {
+                let length = data.pread_with::<u32>(8, scroll::LE)? as usize;
+
+                RenditionContent::Raw(slice(data, 12, length)?)
+            }
+            COLOR_TAG => {
+                let count = data.pread_with::<u32>(12, scroll::LE)? as usize;
+                let components = (0..count)
+                    .map(|i| Ok(data.pread_with::<f64>(16 + i * 8, scroll::LE)?))
+                    .collect::<Result<Vec<_>, Error>>()?;
+
+                RenditionContent::Color {
+                    color_space: data.pread_with::<u32>(8, scroll::LE)? & 0xff,
+                    components,
+                }
+            }
+            _ => RenditionContent::Unknown {
+                tag: fourcc(tag),
+                data,
+            },
+        })
+    }
+
+    /// Resolve the decompressed pixels of a bitmap rendition.
+    ///
+    /// Rows may be padded beyond the width of the image. Errors with
+    /// [Error::UnsupportedAssetCatalog] for compressions other than uncompressed
+    /// and zlib. See [BitmapCompression::is_supported].
+    pub fn pixels(&self) -> Result<Vec<u8>, Error> {
+        self.pixels_with_limit(u64::MAX)
+    }
+
+    /// Resolve the pixels of a bitmap rendition, decompressing at most `limit` bytes.
+    fn pixels_with_limit(&self, limit: u64) -> Result<Vec<u8>, Error> {
+        let RenditionContent::Bitmap { compression, data } = self.content()? else {
+            return Err(Error::BadAssetCatalog("rendition is not a bitmap"));
+        };
+
+        match compression {
+            BitmapCompression::Uncompressed => Ok(data.to_vec()),
+            BitmapCompression::Zip => {
+                let mut pixels = vec![];
+                if flate2::read::ZlibDecoder::new(data)
+                    .take(limit)
+                    .read_to_end(&mut pixels)
+                    .is_err()
+                {
+                    pixels.clear();
+                    flate2::read::DeflateDecoder::new(data)
+                        .take(limit)
+                        .read_to_end(&mut pixels)?;
+                }
+
+                Ok(pixels)
+            }
+            _ => Err(Error::UnsupportedAssetCatalog(format!(
+                "{compression:?} compressed bitmap"
+            ))),
+        }
+    }
+
+    /// Convert a bitmap rendition to a PNG image.
+    ///
+    /// `ARGB` and `GA8 ` pixels are supported. They are stored premultiplied,
+    /// which is undone. Compressed pixels are only decompressed up to the size
+    /// given by the dimensions of the rendition.
+    pub fn to_png(&self) -> Result<Vec<u8>, Error> {
+        let (channels, color_type) = match self.pixel_format.as_str() {
+            "ARGB" => (4, 6),
+            "GA8 " => (2, 4),
+            format => {
+                return Err(Error::UnsupportedAssetCatalog(format!(
+                    "pixel format {format}"
+                )))
+            }
+        };
+
+        let height = self.height as usize;
+        let (row_size, size, png_size) = (self.width as usize)
+            .checked_mul(channels)
+            .and_then(|row_size| {
+                Some((
+                    row_size,
+                    row_size.checked_mul(height)?,
+                    (row_size.checked_add(1)?).checked_mul(height)?,
+                ))
+            })
+            .ok_or(Error::BadAssetCatalog("bitmap dimensions too large"))?;
+
+        // Compressed pixels beyond the dimensions of the image aren't needed.
+        let pixels = self.pixels_with_limit(size as u64)?;
+
+        if height == 0 || pixels.len() < size {
+            return Err(Error::BadAssetCatalog("bitmap smaller than its dimensions"));
+        }
+        let stride = pixels.len() / height;
+
+        let mut rows = Vec::with_capacity(png_size);
+        for row in pixels.chunks_exact(stride).take(height) {
+            // Filter type none.
+            rows.push(0);
+            for pixel in row[..row_size].chunks_exact(channels) {
+                if channels == 4 {
+                    // BGRA in memory.
+                    let alpha = pixel[3];
+                    rows.extend([
+                        unpremultiply(pixel[2], alpha),
+                        unpremultiply(pixel[1], alpha),
+                        unpremultiply(pixel[0], alpha),
+                        alpha,
+                    ]);
+                } else {
+                    rows.extend([unpremultiply(pixel[0], pixel[1]), pixel[1]]);
+                }
+            }
+        }
+
+        encode_png(self.width, self.height, color_type, &rows)
+    }
+
+    /// Resolve the rendition as a file.
+    ///
+    /// Returns the file extension and content. Bitmaps are converted to PNG and raw
+    /// data is identified by its magic. Colors and unknown content have no file.
+    pub fn to_file(&self) -> Result<Option<(&'static str, Vec<u8>)>, Error> {
+        Ok(match self.content()? {
+            RenditionContent::Bitmap { .. } => Some(("png", self.to_png()?)),
+            RenditionContent::Raw(data) => {
+                let extension = if data.starts_with(&PNG_SIGNATURE) {
+                    "png"
+                } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+                    "jpg"
+                } else if data.starts_with(b"%PDF") {
+                    "pdf"
+                } else if data.get(4..8) == Some(b"ftyp") {
+                    "heic"
+                } else {
+                    "data"
+                };
+
+                Some((extension, data.to_vec()))
+            }
+            RenditionContent::Color { .. } | RenditionContent::Unknown { .. } => None,
+        })
+    }
+}
+
+fn unpremultiply(value: u8, alpha: u8) -> u8 {
+    if alpha == 0 {
+        0
+    } else {
+        ((value as u32 * 255 + alpha as u32 / 2) / alpha as u32).min(255) as u8
+    }
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let mut crc = flate2::Crc::new();
+    crc.update(kind);
+    crc.update(data);
+
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    png.extend(crc.sum().to_be_bytes());
+}
+
+/// Encode 8-bit filtered rows as a PNG image.
+fn encode_png(width: u32, height: u32, color_type: u8, rows: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(rows)?;
+    let idat = encoder.finish()?;
+
+    let mut header = vec![];
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // Bit depth, color type, compression, filter and interlace methods.
+    header.extend([8, color_type, 0, 0, 0]);
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    png_chunk(&mut png, b"IHDR", &header);
+    png_chunk(&mut png, b"IDAT", &idat);
+    png_chunk(&mut png, b"IEND", &[]);
+
+    Ok(png)
+}
+
+/// The key and value data of a tree entry.
+type TreeEntry<'a> = (&'a [u8], &'a [u8]);
+
+/// A compiled asset catalog.
+pub struct AssetCatalog<'a> {
+    bom: ParsedBom<'a>,
+    header: CarHeader,
+}
+
+impl<'a> AssetCatalog<'a> {
+    /// Parse asset catalog data.
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        Self::from_bom(ParsedBom::parse(data)?)
+    }
+
+    /// Construct an instance from a BOM, validating its `CARHEADER`.
+    pub fn from_bom(bom: ParsedBom<'a>) -> Result<Self, Error> {
+        let var = bom.find_variable("CARHEADER")?;
+        let header = CarHeader::parse(bom.block_data(var.block_index as _)?)?;
+
+        Ok(Self { bom, header })
+    }
+
+    /// The underlying BOM.
+    pub fn bom(&self) -> &ParsedBom<'a> {
+        &self.bom
+    }
+
+    /// The `CARHEADER`.
+    pub fn header(&self) -> &CarHeader {
+        &self.header
+    }
+
+    /// Resolve the `EXTENDED_METADATA`, which older catalogs lack.
+    pub fn extended_metadata(&self) -> Result<Option<ExtendedMetadata>, Error> {
+        let Ok(var) = self.bom.find_variable("EXTENDED_METADATA") else {
+            return Ok(None);
+        };
+
+        Ok(Some(ExtendedMetadata::parse(
+            self.bom.block_data(var.block_index as _)?,
+        )?))
+    }
+
+    /// Resolve the attributes of the tokens of rendition keys.
+    pub fn key_format(&self) -> Result<Vec<RenditionAttribute>, Error> {
+        let var = self.bom.find_variable("KEYFORMAT")?;
+        let data = self.bom.block_data(var.block_index as _)?;
+
+        if data.pread_with::<u32>(0, scroll::LE)? != KEY_FORMAT_TAG {
+            return Err(Error::BadAssetCatalog("bad KEYFORMAT magic"));
+        }
+
+        let count = data.pread_with::<u32>(8, scroll::LE)? as usize;
+
+        (0..count)
+            .map(|i| Ok((data.pread_with::<u32>(12 + i * 4, scroll::LE)? as u16).into()))
+            .collect()
+    }
+
+    /// Resolve the key and value data of the entries of a tree variable.
+    ///
+    /// Missing variables have no entries.
+    fn tree_entries(&self, name: &str) -> Result<Vec<TreeEntry<'_>>, Error> {
+        let Ok(var) = self.bom.find_variable(name) else {
+            return Ok(vec![]);
+        };
+        let tree = self.bom.block_as_tree(var.block_index as _)?;
+
+        tree.leaf_entries(&self.bom)?
+            .into_iter()
+            .map(|entry| {
+                Ok((
+                    self.bom.block_data(entry.file_index as _)?,
+                    self.bom.block_data(entry.block_index as _)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Resolve the named assets.
+    pub fn facets(&self) -> Result<Vec<Facet>, Error> {
+        self.tree_entries("FACETKEYS")?
+            .into_iter()
+            .map(|(key, value)| Facet::parse(key, value))
+            .collect()
+    }
+
+    /// Resolve all renditions.
+    pub fn renditions(&self) -> Result<Vec<Rendition<'_>>, Error> {
+        let key_format = self.key_format()?;
+
+        self.tree_entries("RENDITIONS")?
+            .into_iter()
+            .map(|(key, value)| Rendition::parse(&key_format, key, value))
+            .collect()
+    }
+
+    /// Resolve the names and IDs of appearances, such as dark mode variants.
+    pub fn appearances(&self) -> Result<Vec<(String, u16)>, Error> {
+        self.tree_entries("APPEARANCEKEYS")?
+            .into_iter()
+            .map(|(key, value)| Ok((c_string(key), value.pread_with(0, scroll::LE)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            builder::{push_tree, write_bom},
+            format::{BomBlock, BomPathsEntry, BomVar, BomVarsIndex},
+        },
+        std::borrow::Cow,
+    };
+
+    fn le(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn padded(s: &str, size: usize) -> Vec<u8> {
+        let mut data = s.as_bytes().to_vec();
+        data.resize(size, 0);
+        data
+    }
+
+    fn rendition(
+        name: &str,
+        (width, height): (u32, u32),
+        pixel_format: &[u8; 4],
+        layout: u16,
+        content: Vec<u8>,
+    ) -> Vec<u8> {
+        let tlv = le(&[1001, 4, 7]);
+
+        let mut data = le(&[
+            RENDITION_TAG,
+            1,
+            0,
+            width,
+            height,
+            200,
+            u32::from_be_bytes(*pixel_format),
+            1,
+            1_600_000_000,
+        ]);
+        data.extend(layout.to_le_bytes());
+        data.extend([0, 0]);
+        data.extend(padded(name, 128));
+        data.extend(le(&[tlv.len() as u32, 0, 0, content.len() as u32]));
+        data.extend(tlv);
+        data.extend(content);
+        data
+    }
+
+    fn catalog() -> Vec<u8> {
+        fn push(blocks: &mut Vec<BomBlock>, data: Vec<u8>) -> u32 {
+            blocks.push(BomBlock::Raw(Cow::Owned(data)));
+            blocks.len() as u32 - 1
+        }
+
+        let mut blocks = vec![BomBlock::Empty];
+        let mut vars = vec![];
+
+        let mut header = le(&[CAR_HEADER_TAG, 800, 17, 1_600_000_000, 4]);
+        header.extend(padded("IBCocoaTouchImageCatalogTool-1", 128));
+        header.extend(padded("Xcode 15", 256));
+        header.extend([7; 16]);
+        header.extend(le(&[0, 2, 1, 2]));
+        vars.push(BomVar::new(push(&mut blocks, header), "CARHEADER").unwrap());
+
+        // Identifier, element, part, scale.
+        let key_format = le(&[KEY_FORMAT_TAG, 0, 4, 17, 1, 2, 12]);
+        vars.push(BomVar::new(push(&mut blocks, key_format), "KEYFORMAT").unwrap());
+
+        let facet = |identifier: u16| {
+            let mut data = vec![0, 0, 0, 0, 3, 0];
+            for (attribute, value) in [(1u16, 85u16), (2, 181), (17, identifier)] {
+                data.extend(attribute.to_le_bytes());
+                data.extend(value.to_le_bytes());
+            }
+            data
+        };
+        let mut facets = vec![];
+        for (name, identifier) in [("AccentColor", 2), ("AppIcon", 1)] {
+            facets.push(BomPathsEntry {
+                file_index: push(&mut blocks, name.as_bytes().to_vec()),
+                block_index: push(&mut blocks, facet(identifier)),
+            });
+        }
+
+        // BGRA premultiplied: transparent, opaque red, half transparent blue.
+        let pixels = vec![0, 0, 0, 0, 0, 0, 255, 255, 128, 0, 0, 128];
+        let mut zip = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        zip.write_all(&[0x80, 0x80, 0x40, 0x80]).unwrap();
+        let zip = zip.finish().unwrap();
+
+        let mut bitmap = le(&[BITMAP_TAG, 0, 0, pixels.len() as u32]);
+        bitmap.extend(&pixels);
+        let mut zip_bitmap = le(&[BITMAP_TAG, 0, 2, zip.len() as u32]);
+        zip_bitmap.extend(&zip);
+        let mut pdf = le(&[RAW_TAG, 0, 8]);
+        pdf.extend(b"%PDF-1.4");
+        let mut color = le(&[COLOR_TAG, 0, 1, 4]);
+        for component in [0.25f64, 0.5, 0.75, 1.0] {
+            color.extend(component.to_le_bytes());
+        }
+        let mut lzfse = le(&[BITMAP_TAG, 0, 4, 3]);
+        lzfse.extend(b"bvx");
+
+        let mut renditions = vec![];
+        for (key, value) in [
+            (
+                [1u16, 85, 181, 2],
+                rendition("icon.png", (3, 1), b"ARGB", 12, bitmap),
+            ),
+            (
+                [1, 85, 181, 1],
+                rendition("gray.png", (1, 1), b"GA8 ", 12, zip_bitmap),
+            ),
+            (
+                [1, 85, 181, 3],
+                rendition("icon.pdf", (0, 0), b"PDF ", 9, pdf),
+            ),
+            (
+                [1, 85, 181, 4],
+                rendition("big.png", (1, 1), b"ARGB", 12, lzfse),
+            ),
+            (
+                [2, 85, 181, 0],
+                rendition("", (0, 0), b"\0\0\0\0", 1009, color),
+            ),
+        ] {
+            let key = key.iter().flat_map(|v| v.to_le_bytes()).collect();
+            renditions.push(BomPathsEntry {
+                file_index: push(&mut blocks, key),
+                block_index: push(&mut blocks, value),
+            });
+        }
+
+        let appearances = vec![BomPathsEntry {
+            file_index: push(&mut blocks, b"NSAppearanceNameDarkAqua".to_vec()),
+            block_index: push(&mut blocks, 1u16.to_le_bytes().to_vec()),
+        }];
+
+        for (name, entries) in [
+            ("FACETKEYS", facets),
+            ("RENDITIONS", renditions),
+            ("APPEARANCEKEYS", appearances),
+        ] {
+            let index = push_tree(&mut blocks, entries, 4096);
+            vars.push(BomVar::new(index, name).unwrap());
+        }
+
+        let vars_index = BomVarsIndex {
+            count: vars.len() as _,
+            vars,
+        };
+
+        write_bom(&blocks, &vars_index).unwrap()
+    }
+
+    #[test]
+    fn bitmap_limits() -> Result<(), Error> {
+        let mut zip = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        zip.write_all(&[0x80; 1 << 20]).unwrap();
+        let zip = zip.finish().unwrap();
+        let mut content = le(&[BITMAP_TAG, 0, 2, zip.len() as u32]);
+        content.extend(&zip);
+
+        // Only the pixels covered by the dimensions are decompressed.
+        let value = rendition("gray.png", (1, 1), b"GA8 ", 12, content.clone());
+        let gray = Rendition::parse(&[], &[], &value)?;
+        assert_eq!(gray.pixels()?.len(), 1 << 20);
+        assert_eq!(gray.pixels_with_limit(2)?, vec![0x80, 0x80]);
+        assert_eq!(gray.to_png()?[25], 4);
+
+        let value = rendition("big.png", (u32::MAX, u32::MAX), b"ARGB", 12, content);
+        let big = Rendition::parse(&[], &[], &value)?;
+        assert!(matches!(big.to_png(), Err(Error::BadAssetCatalog(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_catalog() -> Result<(), Error> {
+        let data = catalog();
+        let catalog = AssetCatalog::parse(&data)?;
+
+        let header = catalog.header();
+        assert_eq!(header.core_ui_version, 800);
+        assert_eq!(header.rendition_count, 4);
+        assert_eq!(header.main_version, "IBCocoaTouchImageCatalogTool-1");
+        assert_eq!(header.version, "Xcode 15");
+        assert_eq!(header.uuid, [7; 16]);
+        assert_eq!(header.key_semantics, 2);
+        assert!(catalog.extended_metadata()?.is_none());
+
+        assert_eq!(
+            catalog.key_format()?,
+            vec![
+                RenditionAttribute::Identifier,
+                RenditionAttribute::Element,
+                RenditionAttribute::Part,
+                RenditionAttribute::Scale
+            ]
+        );
+        assert_eq!(
+            catalog.appearances()?,
+            vec![("NSAppearanceNameDarkAqua".to_string(), 1)]
+        );
+
+        let facets = catalog.facets()?;
+        assert_eq!(
+            facets.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(),
+            vec!["AccentColor", "AppIcon"]
+        );
+        assert_eq!(facets[1].attribute(RenditionAttribute::Identifier), Some(1));
+
+        let renditions = catalog.renditions()?;
+        assert_eq!(renditions.len(), 5);
+        assert_eq!(
+            renditions
+                .iter()
+                .filter(|r| facets[1].matches(r))
+                .map(|r| r.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["icon.png", "gray.png", "icon.pdf", "big.png"]
+        );
+
+        let icon = &renditions[0];
+        assert_eq!(icon.name, "icon.png");
+        assert_eq!((icon.width, icon.height, icon.scale_factor), (3, 1, 200));
+        assert_eq!(icon.pixel_format, "ARGB");
+        assert_eq!(icon.layout, 12);
+        assert_eq!(icon.attribute(RenditionAttribute::Scale), Some(2));
+        assert_eq!(icon.tlvs.len(), 1);
+        assert_eq!(icon.tlvs[0].tag, 1001);
+
+        let (extension, png) = icon.to_file()?.unwrap();
+        assert_eq!(extension, "png");
+        assert!(png.starts_with(&PNG_SIGNATURE));
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 0, 3, 0, 0, 0, 1]);
+        assert_eq!(png[25], 6);
+        let idat_length = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let mut rows = vec![];
+        flate2::read::ZlibDecoder::new(&png[41..41 + idat_length]).read_to_end(&mut rows)?;
+        assert_eq!(rows, vec![0, 0, 0, 0, 0, 255, 0, 0, 255, 0, 0, 255, 128]);
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]));
+
+        let gray = &renditions[1];
+        assert_eq!(gray.pixels()?, vec![0x80, 0x80, 0x40, 0x80]);
+        assert_eq!(gray.to_png()?[25], 4);
+
+        let pdf = &renditions[2];
+        assert_eq!(pdf.to_file()?, Some(("pdf", b"%PDF-1.4".to_vec())));
+
+        let big = &renditions[3];
+        assert!(matches!(
+            big.content()?,
+            RenditionContent::Bitmap {
+                compression: BitmapCompression::Lzfse,
+                data: b"bvx"
+            }
+        ));
+        assert!(!BitmapCompression::Lzfse.is_supported());
+        assert!(matches!(
+            big.to_file(),
+            Err(Error::UnsupportedAssetCatalog(_))
+        ));
+
+        let color = &renditions[4];
+        assert!(facets[0].matches(color));
+        assert!(!facets[1].matches(color));
+        match color.content()? {
+            RenditionContent::Color {
+                color_space,
+                components,
+            } => {
+                assert_eq!(color_space, 1);
+                assert_eq!(components, vec![0.25, 0.5, 0.75, 1.0]);
+            }
+            content => panic!("unexpected content: {content:?}"),
+        }
+        assert_eq!(color.to_file()?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn malformed() -> Result<(), Error> {
+        let data = rendition("x", (1, 1), b"ARGB", 12, le(&[BITMAP_TAG, 0, 0, 100]));
+        let rendition = Rendition::parse(&[], &[], &data)?;
+        assert!(matches!(
+            rendition.content(),
+            Err(Error::BadAssetCatalog(_))
+        ));
+        assert!(Rendition::parse(&[], &[], &data[0..100]).is_err());
+        assert!(CarHeader::parse(b"RATC").is_err());
+
+        Ok(())
+    }
+}
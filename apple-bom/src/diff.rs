@@ -0,0 +1,303 @@
+// Copyright 2022 Gregory Szorc.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Comparing the paths of BOMs.
+
+use {
+    crate::{
+        error::Error,
+        format::ParsedBom,
+        path::{BomPath, BomPathType},
+    },
+    std::{collections::BTreeMap, fmt::Display},
+};
+
+/// An attribute of a [BomPath] that can differ.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PathAttribute {
+    Type,
+    Mode,
+    UserId,
+    GroupId,
+    ModifiedTime,
+    Size,
+    Checksum,
+    LinkName,
+    Device,
+    Architectures,
+}
+
+impl Display for PathAttribute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Type => "type",
+            Self::Mode => "mode",
+            Self::UserId => "uid",
+            Self::GroupId => "gid",
+            Self::ModifiedTime => "mtime",
+            Self::Size => "size",
+            Self::Checksum => "checksum",
+            Self::LinkName => "link",
+            Self::Device => "device",
+            Self::Architectures => "architectures",
+        })
+    }
+}
+
+/// A changed attribute of a path, with its old and new values formatted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AttributeChange {
+    pub attribute: PathAttribute,
+    pub old: String,
+    pub new: String,
+}
+
+impl Display for AttributeChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} -> {}", self.attribute, self.old, self.new)
+    }
+}
+
+/// A difference between the paths of 2 BOMs.
+#[derive(Clone, Debug)]
+pub enum PathDifference {
+    /// The path only exists in the new BOM.
+    Added(BomPath),
+    /// The path only exists in the old BOM.
+    Removed(BomPath),
+    /// The path exists in both BOMs with different attributes.
+    Changed {
+        old: BomPath,
+        new: BomPath,
+        changes: Vec<AttributeChange>,
+    },
+}
+
+impl PathDifference {
+    /// The path this difference is about.
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Added(path) | Self::Removed(path) => path.path(),
+            Self::Changed { new, .. } => new.path(),
+        }
+    }
+}
+
+impl Display for PathDifference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Added(path) => write!(f, "+ {}", path.path()),
+            Self::Removed(path) => write!(f, "- {}", path.path()),
+            Self::Changed { new, changes, .. } => {
+                write!(f, "~ {}", new.path())?;
+                for (i, change) in changes.iter().enumerate() {
+                    f.write_str(if i == 0 { " (" } else { ", " })?;
+                    write!(f, "{change}")?;
+                }
+                if !changes.is_empty() {
+                    f.write_str(")")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+fn format_type(path_type: BomPathType) -> String {
+    match path_type {
+        BomPathType::File => "file".to_string(),
+        BomPathType::Directory => "directory".to_string(),
+        BomPathType::Link => "symlink".to_string(),
+        BomPathType::Dev => "device".to_string(),
+        BomPathType::Other(v) => format!("unknown ({v})"),
+    }
+}
+
+fn format_option<T: Display>(value: Option<T>) -> String {
+    value
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| "none".to_string())
+}
+
+/// Compare the attributes of 2 paths.
+///
+/// The paths themselves are not compared.
+pub fn compare_paths(old: &BomPath, new: &BomPath) -> Vec<AttributeChange> {
+    let mut changes = vec![];
+
+    let mut compare = |attribute, old: String, new: String| {
+        if old != new {
+            changes.push(AttributeChange {
+                attribute,
+                old,
+                new,
+            });
+        }
+    };
+
+    compare(
+        PathAttribute::Type,
+        format_type(old.path_type()),
+        format_type(new.path_type()),
+    );
+    compare(
+        PathAttribute::Mode,
+        format!("{:o}", old.file_mode()),
+        format!("{:o}", new.file_mode()),
+    );
+    compare(
+        PathAttribute::UserId,
+        old.user_id().to_string(),
+        new.user_id().to_string(),
+    );
+    compare(
+        PathAttribute::GroupId,
+        old.group_id().to_string(),
+        new.group_id().to_string(),
+    );
+    compare(
+        PathAttribute::ModifiedTime,
+        old.modified_time().to_rfc3339(),
+        new.modified_time().to_rfc3339(),
+    );
+    compare(
+        PathAttribute::Size,
+        old.size().to_string(),
+        new.size().to_string(),
+    );
+    compare(
+        PathAttribute::Checksum,
+        format_option(old.crc32()),
+        format_option(new.crc32()),
+    );
+    compare(
+        PathAttribute::LinkName,
+        format_option(old.link_name()),
+        format_option(new.link_name()),
+    );
+    compare(
+        PathAttribute::Device,
+        format_option(old.device()),
+        format_option(new.device()),
+    );
+
+    let format_architectures = |path: &BomPath| {
+        if path.architectures().is_empty() {
+            return "none".to_string();
+        }
+
+        path.architectures()
+            .iter()
+            .map(|arch| format!("{:#x}/{}", arch.cpu_type, arch.cpu_subtype))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    compare(
+        PathAttribute::Architectures,
+        format_architectures(old),
+        format_architectures(new),
+    );
+
+    changes
+}
+
+/// Compare 2 sets of paths.
+///
+/// Differences are returned sorted by path. Changes of attributes in `ignore`
+/// are not reported.
+pub fn diff_paths(
+    old: impl IntoIterator<Item = BomPath>,
+    new: impl IntoIterator<Item = BomPath>,
+    ignore: &[PathAttribute],
+) -> Vec<PathDifference> {
+    let mut old = old
+        .into_iter()
+        .map(|path| (path.path().to_string(), path))
+        .collect::<BTreeMap<_, _>>();
+    let new = new
+        .into_iter()
+        .map(|path| (path.path().to_string(), path))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut res = vec![];
+
+    for (name, new) in new {
+        match old.remove(&name) {
+            Some(old) => {
+                let changes = compare_paths(&old, &new)
+                    .into_iter()
+                    .filter(|change| !ignore.contains(&change.attribute))
+                    .collect::<Vec<_>>();
+
+                if !changes.is_empty() {
+                    res.push(PathDifference::Changed { old, new, changes });
+                }
+            }
+            None => {
+                res.push(PathDifference::Added(new));
+            }
+        }
+    }
+
+    res.extend(old.into_values().map(PathDifference::Removed));
+    res.sort_by(|a, b| a.path().cmp(b.path()));
+
+    res
+}
+
+/// Compare the paths of 2 BOMs.
+pub fn diff_boms(
+    old: &ParsedBom,
+    new: &ParsedBom,
+    ignore: &[PathAttribute],
+) -> Result<Vec<PathDifference>, Error> {
+    Ok(diff_paths(old.paths()?, new.paths()?, ignore))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::builder::BomBuilder};
+
+    #[test]
+    fn diff() -> Result<(), Error> {
+        let mut old = BomBuilder::default();
+        old.add_file_from_data("bin/tool", b"tool")?;
+        old.add_file_from_data("bin/removed", b"")?;
+        old.add_file_from_data("share/same", b"same")?;
+        let old = old.build_bom()?;
+
+        let mut new = BomBuilder::default();
+        new.add_file_from_data("bin/tool", b"tool 2")?
+            .set_file_mode(0o755);
+        new.add_symlink("bin/added", "tool")?;
+        new.add_file_from_data("share/same", b"same")?;
+        let new = new.build_bom()?;
+
+        let differences = diff_boms(
+            &ParsedBom::parse(&old)?,
+            &ParsedBom::parse(&new)?,
+            &[PathAttribute::ModifiedTime],
+        )?;
+
+        assert_eq!(
+            differences
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "+ ./bin/added".to_string(),
+                "- ./bin/removed".to_string(),
+                "~ ./bin/tool (mode: 100644 -> 100755, size: 4 -> 6, checksum: 2090409092 -> 3962679269)"
+                    .to_string(),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -3,30 +3,225 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+#[allow(unused)]
+mod builder;
+#[allow(unused)]
+mod car;
+#[allow(unused)]
+mod diff;
 #[allow(unused)]
 mod error;
 #[allow(unused)]
 mod format;
 #[allow(unused)]
+mod listing;
+#[allow(unused)]
 mod path;
 
 use {
     crate::{
+        car::{AssetCatalog, RenditionAttribute, RenditionContent},
+        diff::{diff_boms, PathAttribute},
         error::Error,
         format::{BomBlock, ParsedBom},
+        listing::{ListingField, ListingOptions},
     },
-    clap::{value_parser, Arg, ArgAction, Command},
+    clap::{value_parser, Arg, ArgAction, ArgMatches, Command},
     std::path::PathBuf,
 };
 
 type BomResult<T> = Result<T, error::Error>;
 
+const LS_ABOUT: &str = "\
+List paths like lsbom.
+
+By default, each path is printed with tab separated columns depending on its
+type. Files print their path, mode, UID/GID, size and checksum. Directories
+print their path, mode and UID/GID. Symlinks print the columns of files and
+their link name. Devices print their path, mode, UID/GID and device number.
+
+The -p argument selects explicit columns. It accepts a string of characters:
+
+c  checksum
+f  path
+F  path in double quotes
+g  group ID
+G  group name (printed as ID)
+m  mode in octal
+M  symbolic mode
+s  size
+S  size with unit
+t  modified time in seconds since UNIX epoch
+T  formatted modified time
+u  user ID
+U  user name (printed as ID)
+/  UID/GID
+";
+
+fn command_ls(args: &ArgMatches) -> BomResult<()> {
+    let path = args
+        .get_one::<PathBuf>("path")
+        .expect("path should be required");
+
+    let options = ListingOptions {
+        block_devices: args.get_flag("block_devices"),
+        character_devices: args.get_flag("character_devices"),
+        directories: args.get_flag("directories"),
+        files: args.get_flag("files"),
+        symlinks: args.get_flag("symlinks"),
+        modified_time: args.get_flag("modified_time"),
+        paths_only: args.get_flag("paths_only"),
+        suppress_modes: args.get_flag("suppress_modes"),
+        fields: args
+            .get_one::<String>("fields")
+            .map(|s| ListingField::parse_fields(s))
+            .transpose()?,
+    };
+
+    let bom_data = std::fs::read(path)?;
+    let bom = ParsedBom::parse(&bom_data)?;
+
+    for line in options.format_paths(&bom.paths()?) {
+        println!("{line}");
+    }
+
+    Ok(())
+}
+
+fn command_diff(args: &ArgMatches) -> BomResult<()> {
+    let old_path = args
+        .get_one::<PathBuf>("old")
+        .expect("old should be required");
+    let new_path = args
+        .get_one::<PathBuf>("new")
+        .expect("new should be required");
+
+    let ignore = if args.get_flag("mtime") {
+        vec![]
+    } else {
+        vec![PathAttribute::ModifiedTime]
+    };
+
+    let old_data = std::fs::read(old_path)?;
+    let new_data = std::fs::read(new_path)?;
+
+    for difference in diff_boms(
+        &ParsedBom::parse(&old_data)?,
+        &ParsedBom::parse(&new_data)?,
+        &ignore,
+    )? {
+        println!("{difference}");
+    }
+
+    Ok(())
+}
+
+fn command_car(args: &ArgMatches) -> BomResult<()> {
+    let path = args
+        .get_one::<PathBuf>("path")
+        .expect("path should be required");
+
+    let data = std::fs::read(path)?;
+    let catalog = AssetCatalog::parse(&data)?;
+
+    let header = catalog.header();
+    println!("CoreUI version: {}", header.core_ui_version);
+    println!("Version: {}", header.main_version);
+    println!("Renditions: {}", header.rendition_count);
+    if let Some(metadata) = catalog.extended_metadata()? {
+        println!(
+            "Platform: {} {}",
+            metadata.deployment_platform, metadata.deployment_platform_version
+        );
+        println!("Authoring tool: {}", metadata.authoring_tool);
+    }
+
+    let renditions = catalog.renditions()?;
+
+    println!();
+    println!("Facets:");
+    for facet in catalog.facets()? {
+        let size = renditions
+            .iter()
+            .filter(|r| facet.matches(r))
+            .map(|r| r.data.len())
+            .sum::<usize>();
+
+        println!(
+            "{}\t{}\t{}",
+            facet.name,
+            facet
+                .attribute(RenditionAttribute::Identifier)
+                .unwrap_or_default(),
+            size
+        );
+    }
+
+    println!();
+    println!("Renditions:");
+    for rendition in &renditions {
+        let content = match rendition.content()? {
+            RenditionContent::Bitmap { compression, .. } if compression.is_supported() => {
+                format!("bitmap {compression:?}")
+            }
+            RenditionContent::Bitmap { compression, .. } => {
+                format!("bitmap {compression:?} (not decodable)")
+            }
+            RenditionContent::Raw(_) => "raw".to_string(),
+            RenditionContent::Color {
+                color_space,
+                components,
+            } => format!("color {color_space} {components:?}"),
+            RenditionContent::Unknown { tag, .. } => format!("unknown {tag:?}"),
+        };
+
+        println!(
+            "{}\t{}x{}@{}x\t{}\t{}\t{}\t{}",
+            rendition.name,
+            rendition.width,
+            rendition.height,
+            rendition.scale_factor as f64 / 100.0,
+            rendition.pixel_format.trim(),
+            rendition.layout,
+            rendition.data.len(),
+            content
+        );
+    }
+
+    if let Some(dest) = args.get_one::<PathBuf>("extract") {
+        std::fs::create_dir_all(dest)?;
+
+        for (i, rendition) in renditions.iter().enumerate() {
+            match rendition.to_file() {
+                Ok(Some((extension, data))) => {
+                    // Names come from the catalog, so only keep their file name.
+                    let stem = std::path::Path::new(&rendition.name)
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    let file = dest.join(format!("{i:04}-{stem}.{extension}"));
+
+                    std::fs::write(&file, data)?;
+                    println!("wrote {}", file.display());
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    eprintln!("unable to extract {}: {err}", rendition.name);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn main_impl() -> BomResult<()> {
     let matches = Command::new("Apple BOM Dumper")
         .arg_required_else_help(true)
         .version("0.1")
         .author("Gregory Szorc <gregory.szorc@gmail.com>")
         .about("Show information about Apple BOM data structures")
+        .args_conflicts_with_subcommands(true)
         .arg(
             Arg::new("path")
                 .action(ArgAction::Set)
@@ -52,8 +247,125 @@ fn main_impl() -> BomResult<()> {
                 .default_value("header")
                 .help("Which content to show"),
         )
+        .subcommand(
+            Command::new("ls")
+                .about("List paths like lsbom")
+                .long_about(LS_ABOUT)
+                .arg(
+                    Arg::new("block_devices")
+                        .short('b')
+                        .action(ArgAction::SetTrue)
+                        .help("List block devices"),
+                )
+                .arg(
+                    Arg::new("character_devices")
+                        .short('c')
+                        .action(ArgAction::SetTrue)
+                        .help("List character devices"),
+                )
+                .arg(
+                    Arg::new("directories")
+                        .short('d')
+                        .action(ArgAction::SetTrue)
+                        .help("List directories"),
+                )
+                .arg(
+                    Arg::new("files")
+                        .short('f')
+                        .action(ArgAction::SetTrue)
+                        .help("List files"),
+                )
+                .arg(
+                    Arg::new("symlinks")
+                        .short('l')
+                        .action(ArgAction::SetTrue)
+                        .help("List symlinks"),
+                )
+                .arg(
+                    Arg::new("modified_time")
+                        .short('m')
+                        .action(ArgAction::SetTrue)
+                        .help("Print the modified time of files"),
+                )
+                .arg(
+                    Arg::new("paths_only")
+                        .short('s')
+                        .action(ArgAction::SetTrue)
+                        .help("Only print paths"),
+                )
+                .arg(
+                    Arg::new("suppress_modes")
+                        .short('x')
+                        .action(ArgAction::SetTrue)
+                        .help("Don't print the mode of directories and symlinks"),
+                )
+                .arg(
+                    Arg::new("fields")
+                        .short('p')
+                        .action(ArgAction::Set)
+                        .value_name("PARAMETERS")
+                        .help("Columns to print"),
+                )
+                .arg(
+                    Arg::new("path")
+                        .action(ArgAction::Set)
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("Path to BOM file"),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show added, removed and changed paths between 2 BOMs")
+                .arg(
+                    Arg::new("mtime")
+                        .long("mtime")
+                        .action(ArgAction::SetTrue)
+                        .help("Also report changed modified times"),
+                )
+                .arg(
+                    Arg::new("old")
+                        .action(ArgAction::Set)
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("Path to old BOM file"),
+                )
+                .arg(
+                    Arg::new("new")
+                        .action(ArgAction::Set)
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("Path to new BOM file"),
+                ),
+        )
+        .subcommand(
+            Command::new("car")
+                .about("Show facets and renditions of a compiled asset catalog")
+                .arg(
+                    Arg::new("extract")
+                        .long("extract")
+                        .action(ArgAction::Set)
+                        .value_parser(value_parser!(PathBuf))
+                        .value_name("DIR")
+                        .help("Write raw data and decodable bitmaps of renditions to a directory"),
+                )
+                .arg(
+                    Arg::new("path")
+                        .action(ArgAction::Set)
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("Path to Assets.car file"),
+                ),
+        )
         .get_matches();
 
+    match matches.subcommand() {
+        Some(("ls", args)) => return command_ls(args),
+        Some(("diff", args)) => return command_diff(args),
+        Some(("car", args)) => return command_car(args),
+        _ => {}
+    }
+
     let path = matches
         .get_one::<PathBuf>("path")
         .expect("path should be required");
@@ -26,6 +26,9 @@ pub enum Error {
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 
+    #[error("cpio error: {0}")]
+    Cpio(#[from] cpio_archive::Error),
+
     #[error("bad arguments: {0}")]
     CliBadArgs(String),
 
@@ -34,4 +37,10 @@ pub enum Error {
 
     #[error("invalid time value")]
     BadTime,
+
+    #[error("malformed asset catalog: {0}")]
+    BadAssetCatalog(&'static str),
+
+    #[error("unsupported asset catalog content: {0}")]
+    UnsupportedAssetCatalog(String),
 }
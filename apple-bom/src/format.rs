@@ -43,6 +43,7 @@
 //! * [BomBlockPathRecord]
 //! * [BomBlockPathRecordPointer]
 //! * [BomBlockPaths]
+//! * [BomBlockSize64]
 //! * [BomBlockTree]
 //! * [BomBlockTreePointer]
 //! * [BomBlockVIndex]
@@ -65,13 +66,23 @@
 //!
 //! Defines hard links. Its block data is [BomBlockTree].
 //!
+//! Each leaf entry describes a group of hard linked paths. Its block index
+//! points to a [BomBlockTree] whose leaf entries are the [BomPathsEntry] of
+//! each path in the group. Its file index is the [BomBlockFile] of the first
+//! path. We have not seen Apple tooling populate this tree, so this layout
+//! is a best guess. `BomBuilder` writes this tree empty.
+//!
 //! ## VIndex
 //!
 //! Unknown. Its block data is [BomBlockVIndex].
 //!
 //! ## Size64
 //!
-//! Unknown. Its block data is [BomBlockTree].
+//! Sizes of files larger than 4 GiB, which don't fit in
+//! [BomBlockPathRecord::size]. Its block data is [BomBlockTree].
+//!
+//! Each leaf entry's block index points to a [BomBlockSize64] and its file
+//! index is the [BomBlockFile] of the path.
 //!
 //! # Layout
 //!
@@ -135,6 +146,9 @@
 //! is buffered and then written out at the end, once the state of the world
 //! is fully known.
 
+// The scroll derives emit unit expressions.
+#![allow(clippy::unused_unit)]
+
 use {
     crate::{
         error::Error,
@@ -143,7 +157,7 @@ use {
     scroll::{IOwrite, Pread, Pwrite, SizeWith},
     std::{
         borrow::Cow,
-        collections::HashMap,
+        collections::{BTreeMap, HashMap, HashSet},
         ffi::CStr,
         io::{Cursor, Write},
     },
@@ -293,13 +307,13 @@ impl BomVar {
     pub fn new(block_index: u32, name: impl ToString) -> Result<Self, Error> {
         let name = name.to_string();
 
-        if name.as_bytes().len() > 254 {
+        if name.len() > 254 {
             return Err(Error::BadVariableString);
         }
 
         Ok(Self {
             block_index,
-            name_length: name.as_bytes().len() as u8 + 1,
+            name_length: name.len() as u8,
             name,
         })
     }
@@ -309,7 +323,6 @@ impl BomVar {
         writer.iowrite_with(self.block_index, scroll::BE)?;
         writer.iowrite_with(self.name_length, scroll::BE)?;
         writer.write_all(self.name.as_bytes())?;
-        writer.write_all(b"\0")?;
 
         Ok(())
     }
@@ -619,7 +632,7 @@ impl<'a> scroll::ctx::TryFromCtx<'a, scroll::Endian> for BomBlockPaths {
 ///
 /// This type is contained within [BomBlockPaths].
 #[repr(C)]
-#[derive(Clone, Copy, Default, Debug, IOwrite, Pread, Pwrite, SizeWith)]
+#[derive(Clone, Copy, Default, Debug, Eq, PartialEq, IOwrite, Pread, Pwrite, SizeWith)]
 pub struct BomPathsEntry {
     /// Block index of associated data structure.
     ///
@@ -712,6 +725,11 @@ pub struct BomBlockPathRecord<'a> {
     /// CRC32 checksum or device type.
     pub checksum_or_type: u32,
 
+    /// Per architecture information of Mach-O binaries.
+    ///
+    /// Present if [Self::architecture] has [ARCHITECTURE_BINARY_INFO] set.
+    pub binary_info: Option<BomBlockBinaryInfo>,
+
     /// Length of link name.
     ///
     /// May be non-0 for non-link path records.
@@ -723,6 +741,69 @@ pub struct BomBlockPathRecord<'a> {
     pub link_name: Option<Cow<'a, CStr>>,
 }
 
+/// Flag in [BomBlockPathRecord::architecture] denoting a Mach-O binary.
+///
+/// Records with this flag carry a [BomBlockBinaryInfo].
+pub const ARCHITECTURE_BINARY_INFO: u16 = 0x2000;
+
+/// Mach-O architectures of a file in a [BomBlockPathRecord].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BomBlockBinaryInfo {
+    /// Unknown. Always appears to be 1.
+    pub a: u8,
+
+    /// The architectures in the binary.
+    pub architectures: Vec<BomBlockArchitecture>,
+}
+
+impl BomBlockBinaryInfo {
+    /// Write this data structure to a writer.
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
+        writer.iowrite_with(self.a, scroll::BE)?;
+        writer.iowrite_with(self.architectures.len() as u32, scroll::BE)?;
+        for arch in &self.architectures {
+            writer.iowrite_with(*arch, scroll::BE)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> scroll::ctx::TryFromCtx<'a, scroll::Endian> for BomBlockBinaryInfo {
+    type Error = Error;
+
+    fn try_from_ctx(data: &'a [u8], le: scroll::Endian) -> Result<(Self, usize), Self::Error> {
+        let offset = &mut 0;
+
+        let a = data.gread_with(offset, le)?;
+        let count = data.gread_with::<u32>(offset, le)?;
+
+        let mut architectures = Vec::with_capacity(count.min(64) as usize);
+        for _ in 0..count {
+            architectures.push(data.gread_with(offset, le)?);
+        }
+
+        Ok((Self { a, architectures }, *offset))
+    }
+}
+
+/// A single architecture of a Mach-O binary.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, IOwrite, Pread, Pwrite, SizeWith)]
+pub struct BomBlockArchitecture {
+    /// Mach-O CPU type.
+    pub cpu_type: u32,
+
+    /// Mach-O CPU subtype.
+    pub cpu_subtype: u32,
+
+    /// Size in bytes of the code for this architecture.
+    pub size: u32,
+
+    /// Checksum of the code for this architecture.
+    pub checksum: u32,
+}
+
 impl<'a> BomBlockPathRecord<'a> {
     /// Write this data structure to a writer.
     pub fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
@@ -736,6 +817,9 @@ impl<'a> BomBlockPathRecord<'a> {
         writer.iowrite_with(self.size, scroll::BE)?;
         writer.iowrite_with(self.b, scroll::BE)?;
         writer.iowrite_with(self.checksum_or_type, scroll::BE)?;
+        if let Some(info) = &self.binary_info {
+            info.write(writer)?;
+        }
         writer.iowrite_with(self.link_name_length, scroll::BE)?;
         if let Some(link_name) = &self.link_name {
             writer.write_all(link_name.to_bytes_with_nul())?;
@@ -760,7 +844,7 @@ impl<'a> scroll::ctx::TryFromCtx<'a, scroll::Endian> for BomBlockPathRecord<'a>
 
         let path_type = data.gread_with(offset, le)?;
         let a = data.gread_with(offset, le)?;
-        let architecture = data.gread_with(offset, le)?;
+        let architecture: u16 = data.gread_with(offset, le)?;
         let mode = data.gread_with(offset, le)?;
         let user = data.gread_with(offset, le)?;
         let group = data.gread_with(offset, le)?;
@@ -768,6 +852,11 @@ impl<'a> scroll::ctx::TryFromCtx<'a, scroll::Endian> for BomBlockPathRecord<'a>
         let size = data.gread_with(offset, le)?;
         let b = data.gread_with(offset, le)?;
         let checksum_or_type = data.gread_with(offset, le)?;
+        let binary_info = if architecture & ARCHITECTURE_BINARY_INFO != 0 {
+            Some(data.gread_with(offset, le)?)
+        } else {
+            None
+        };
         let link_name_length = data.gread_with(offset, le)?;
 
         let link_name = if path_type == BomPathType::Link.into() && link_name_length > 0 {
@@ -791,6 +880,7 @@ impl<'a> scroll::ctx::TryFromCtx<'a, scroll::Endian> for BomBlockPathRecord<'a>
                 size,
                 b,
                 checksum_or_type,
+                binary_info,
                 link_name_length,
                 link_name,
             },
@@ -800,6 +890,13 @@ impl<'a> scroll::ctx::TryFromCtx<'a, scroll::Endian> for BomBlockPathRecord<'a>
 }
 
 /// Block type for various variables describing a collection/tree of paths.
+///
+/// The tree is a B+ tree whose pages are [BomBlockPaths] of at most
+/// [Self::block_size] bytes. Index pages have [BomBlockPaths::is_path_info]
+/// set to `0` and point to child pages. Their keys are the keys of the last
+/// entry in each child. Leaf pages hold the entries of the tree and are linked
+/// via [BomBlockPaths::next_paths_block_index] and
+/// [BomBlockPaths::previous_paths_block_index].
 #[repr(C)]
 #[derive(Clone, Copy, Debug, IOwrite, Pwrite, SizeWith)]
 pub struct BomBlockTree {
@@ -879,54 +976,138 @@ impl BomBlockTree {
         let mut paths = self.paths(bom)?;
 
         while paths.is_path_info == 0 {
-            let entry = paths.paths.get(0).ok_or(Error::BadIndex)?;
+            let entry = paths.paths.first().ok_or(Error::BadIndex)?;
             paths = entry.paths(bom)?;
         }
 
         Ok(paths)
     }
 
-    /// Resolve all [BomPath] in this tree.
+    /// Resolve all leaf [BomBlockPaths] in this tree, in order.
     ///
-    /// This contains the logic for iterating over multiple [BomBlockPaths] instances.
-    pub fn bom_paths(&self, bom: &ParsedBom) -> Result<Vec<BomPath>, Error> {
-        let mut res = Vec::with_capacity(self.path_count as _);
+    /// Index pages are descended recursively. Leaves linked from the last leaf
+    /// via [BomBlockPaths::next_paths_block_index] but not referenced by an
+    /// index page are also included, as some writers only reference the first
+    /// leaf.
+    pub fn leaves(&self, bom: &ParsedBom) -> Result<Vec<BomBlockPaths>, Error> {
+        // Deeper trees would hold more entries than can be addressed.
+        const MAX_DEPTH: usize = 16;
+
+        fn descend(
+            bom: &ParsedBom,
+            index: u32,
+            depth: usize,
+            visited: &mut HashSet<u32>,
+            leaves: &mut Vec<BomBlockPaths>,
+        ) -> Result<(), Error> {
+            if depth > MAX_DEPTH || !visited.insert(index) {
+                return Err(Error::BadIndex);
+            }
 
-        let mut paths = self.root_paths(bom)?;
-        let mut files_by_id = HashMap::with_capacity(res.capacity());
+            let paths = bom.block_as_paths(index as _)?;
 
-        loop {
-            for entry in paths.iter_path_entries(bom) {
-                let (path_id, file, record) = entry?;
-
-                // The full filename is resolved by traversing the file's parent path ID until
-                // we get to a root.
-                let mut resolve_file = &file;
-                let mut filename = file.string_file_name();
-
-                while resolve_file.parent_path_id != 0 {
-                    resolve_file = files_by_id
-                        .get(&resolve_file.parent_path_id)
-                        .ok_or(Error::BadIndex)?;
-                    filename = format!("{}/{}", resolve_file.string_file_name(), filename);
+            if paths.is_path_info == 0 {
+                for entry in &paths.paths {
+                    descend(bom, entry.block_index, depth + 1, visited, leaves)?;
                 }
+            } else {
+                leaves.push(paths);
+            }
 
-                res.push(BomPath::from_record(filename, &record)?);
+            Ok(())
+        }
 
-                files_by_id.insert(path_id, file);
-            }
+        let mut visited = HashSet::new();
+        let mut leaves = vec![];
+        descend(bom, self.block_paths_index, 0, &mut visited, &mut leaves)?;
 
-            if paths.next_paths_block_index != 0 {
-                paths = bom.block_as_paths(paths.next_paths_block_index as _)?;
-            } else {
+        loop {
+            let next = match leaves.last() {
+                Some(paths) if paths.next_paths_block_index != 0 => paths.next_paths_block_index,
+                _ => break,
+            };
+
+            // The link points to a leaf we already have.
+            if !visited.insert(next) {
                 break;
             }
+
+            let paths = bom.block_as_paths(next as _)?;
+            if paths.is_path_info == 0 {
+                return Err(Error::BadIndex);
+            }
+            leaves.push(paths);
+        }
+
+        Ok(leaves)
+    }
+
+    /// Resolve the entries of all leaf [BomBlockPaths] in this tree, in order.
+    pub fn leaf_entries(&self, bom: &ParsedBom) -> Result<Vec<BomPathsEntry>, Error> {
+        Ok(self
+            .leaves(bom)?
+            .into_iter()
+            .flat_map(|paths| paths.paths)
+            .collect())
+    }
+
+    /// Resolve all [BomPath] in this tree.
+    ///
+    /// This contains the logic for iterating over multiple [BomBlockPaths] instances.
+    pub fn bom_paths(&self, bom: &ParsedBom) -> Result<Vec<BomPath>, Error> {
+        Ok(self
+            .bom_paths_with_entries(bom)?
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect())
+    }
+
+    /// Resolve all [BomPath] in this tree along with their [BomPathsEntry].
+    pub(crate) fn bom_paths_with_entries(
+        &self,
+        bom: &ParsedBom,
+    ) -> Result<Vec<(BomPathsEntry, BomPath)>, Error> {
+        let entries = self.leaf_entries(bom)?;
+        let mut res = Vec::with_capacity(entries.len());
+        let mut files_by_id = HashMap::with_capacity(entries.len());
+
+        for entry in entries {
+            let path_info = entry.path_info(bom)?;
+            let file = entry.file(bom)?;
+            let record = path_info.path_record(bom)?;
+
+            // The full filename is resolved by traversing the file's parent path ID until
+            // we get to a root.
+            let mut resolve_file = &file;
+            let mut filename = file.string_file_name();
+
+            while resolve_file.parent_path_id != 0 {
+                resolve_file = files_by_id
+                    .get(&resolve_file.parent_path_id)
+                    .ok_or(Error::BadIndex)?;
+                filename = format!("{}/{}", resolve_file.string_file_name(), filename);
+            }
+
+            res.push((entry, BomPath::from_record(filename, &record)?));
+
+            files_by_id.insert(path_info.path_id, file);
         }
 
         Ok(res)
     }
 }
 
+/// Block type holding the 64-bit size of a path.
+///
+/// Entries in the `Size64` tree point to instances for files larger than
+/// what [BomBlockPathRecord::size] can hold.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, IOwrite, Pread, Pwrite, SizeWith)]
+pub struct BomBlockSize64 {
+    /// Size in bytes.
+    pub size: u64,
+}
+
 /// A pointer to a block index holding a [BomBlockTree].
 ///
 /// We're unsure what this block type is used for. But instances appear to
@@ -1030,6 +1211,11 @@ pub enum BomBlock<'a> {
     PathRecord(BomBlockPathRecord<'a>),
     PathRecordPointer(BomBlockPathRecordPointer),
     Paths(BomBlockPaths),
+    /// Data whose structure isn't known to the BOM format.
+    ///
+    /// Asset catalogs store their own data structures in blocks.
+    Raw(Cow<'a, [u8]>),
+    Size64(BomBlockSize64),
     Tree(BomBlockTree),
     TreePointer(BomBlockTreePointer),
     VIndex(BomBlockVIndex),
@@ -1121,6 +1307,12 @@ impl<'a> BomBlock<'a> {
             Self::Paths(b) => {
                 b.write(writer)?;
             }
+            Self::Raw(b) => {
+                writer.write_all(b)?;
+            }
+            Self::Size64(b) => {
+                writer.iowrite_with(*b, scroll::BE)?;
+            }
             Self::Tree(b) => {
                 writer.iowrite_with(*b, scroll::BE)?;
             }
@@ -1206,26 +1398,108 @@ impl<'a> ParsedBom<'a> {
         self.block_as_bom_info(var.block_index as _)
     }
 
-    pub fn hl_index(&self) -> Result<Vec<BomPath>, Error> {
+    /// Resolve the groups of hard linked paths.
+    ///
+    /// Each entry of the `HLIndex` tree points to a tree listing the paths of a group.
+    pub fn hl_index(&self) -> Result<Vec<Vec<BomPath>>, Error> {
         let var = self.find_variable("HLIndex")?;
         let tree = self.block_as_tree(var.block_index as _)?;
 
-        tree.bom_paths(self)
+        let entries = tree.leaf_entries(self)?;
+        if entries.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let paths = self.paths_by_file_index()?;
+
+        entries
+            .into_iter()
+            .map(|entry| {
+                self.block_as_tree(entry.block_index as _)?
+                    .leaf_entries(self)?
+                    .into_iter()
+                    .map(|member| {
+                        paths
+                            .get(&member.file_index)
+                            .cloned()
+                            .ok_or(Error::BadIndex)
+                    })
+                    .collect()
+            })
+            .collect()
     }
 
+    /// Resolve all paths.
+    ///
+    /// Sizes of files larger than 4 GiB are resolved from the `Size64` tree.
     pub fn paths(&self) -> Result<Vec<BomPath>, Error> {
         let index = self.find_variable("Paths")?;
         let tree = self.block_as_tree(index.block_index as _)?;
 
-        tree.bom_paths(self)
+        let sizes = self.size64_by_file_index()?;
+
+        Ok(tree
+            .bom_paths_with_entries(self)?
+            .into_iter()
+            .map(|(entry, mut path)| {
+                if let Some(size) = sizes.get(&entry.file_index) {
+                    path.size = *size as _;
+                }
+                path
+            })
+            .collect())
     }
 
-    /// Resolve the Size64 tree.
+    /// Resolve the paths in the Size64 tree.
+    ///
+    /// These are files larger than 4 GiB. The returned paths have their full size.
     pub fn size64(&self) -> Result<Vec<BomPath>, Error> {
-        let var = self.find_variable("Size64")?;
+        let sizes = self.size64_by_file_index()?;
+        if sizes.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let paths = self.paths_by_file_index()?;
+
+        sizes
+            .into_iter()
+            .map(|(file_index, size)| {
+                let mut path = paths.get(&file_index).cloned().ok_or(Error::BadIndex)?;
+                path.size = size as _;
+                Ok(path)
+            })
+            .collect()
+    }
+
+    /// Resolve the sizes in the Size64 tree, keyed by [BomBlockFile] block index.
+    fn size64_by_file_index(&self) -> Result<BTreeMap<u32, u64>, Error> {
+        // Not all BOMs have this variable.
+        let Ok(var) = self.find_variable("Size64") else {
+            return Ok(BTreeMap::new());
+        };
         let tree = self.block_as_tree(var.block_index as _)?;
 
-        tree.bom_paths(self)
+        tree.leaf_entries(self)?
+            .into_iter()
+            .map(|entry| {
+                Ok((
+                    entry.file_index,
+                    self.block_as_size64(entry.block_index as _)?.size,
+                ))
+            })
+            .collect()
+    }
+
+    /// Resolve paths from the Paths tree, keyed by [BomBlockFile] block index.
+    fn paths_by_file_index(&self) -> Result<HashMap<u32, BomPath>, Error> {
+        let index = self.find_variable("Paths")?;
+        let tree = self.block_as_tree(index.block_index as _)?;
+
+        Ok(tree
+            .bom_paths_with_entries(self)?
+            .into_iter()
+            .map(|(entry, path)| (entry.file_index, path))
+            .collect())
     }
 
     /// Resolve the V Index.
@@ -1241,7 +1515,9 @@ impl<'a> ParsedBom<'a> {
     pub fn block_data(&self, index: usize) -> Result<&[u8], Error> {
         let entry = self.blocks.blocks.get(index).ok_or(Error::BadIndex)?;
 
-        Ok(&self.data[entry.file_offset as usize..(entry.file_offset + entry.length) as usize])
+        self.data
+            .get(entry.file_offset as usize..entry.file_offset as usize + entry.length as usize)
+            .ok_or(Error::BadIndex)
     }
 
     /// Attempt to resolve a block at an index as a [BomBlockBomInfo].
@@ -1260,7 +1536,7 @@ impl<'a> ParsedBom<'a> {
     }
 
     /// Attempt to resolve a block at an index as a [BomBlockPathRecord].
-    pub fn block_as_path_record(&self, index: usize) -> Result<BomBlockPathRecord, Error> {
+    pub fn block_as_path_record(&self, index: usize) -> Result<BomBlockPathRecord<'_>, Error> {
         self.block_data(index)?.pread_with(0, scroll::BE)
     }
 
@@ -1284,6 +1560,11 @@ impl<'a> ParsedBom<'a> {
         data.pread_with(0, scroll::BE)
     }
 
+    /// Attempt to resolve a block at an index as a [BomBlockSize64].
+    pub fn block_as_size64(&self, index: usize) -> Result<BomBlockSize64, Error> {
+        Ok(self.block_data(index)?.pread_with(0, scroll::BE)?)
+    }
+
     /// Attempt to resolve a block at an index as a [BomBlockTreePointer].
     pub fn block_as_tree_pointer(&self, index: usize) -> Result<BomBlockTreePointer, Error> {
         Ok(self.block_data(index)?.pread_with(0, scroll::BE)?)
@@ -1323,6 +1604,21 @@ mod tests {
             .unwrap();
         assert_eq!(readme.symbolic_mode(), "-rw-r--r--");
 
+        let universal = bom
+            .paths()?
+            .into_iter()
+            .find(|p| p.architectures().len() > 1)
+            .unwrap();
+        assert_eq!(universal.architecture(), 15);
+        assert_eq!(
+            universal
+                .architectures()
+                .iter()
+                .map(|a| a.cpu_type)
+                .collect::<Vec<_>>(),
+            vec![0x0100_0007, 0x0100_000c]
+        );
+
         Ok(())
     }
 }
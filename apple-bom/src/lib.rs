@@ -21,10 +21,13 @@
 //! Writing support is still a work in progress.
 
 pub mod builder;
+pub mod car;
+pub mod diff;
 pub mod error;
 pub use error::Error;
 pub mod format;
 pub use format::ParsedBom;
+pub mod listing;
 pub mod path;
 
 pub use path::{BomPath, BomPathType};
@@ -0,0 +1,306 @@
+// Copyright 2022 Gregory Szorc.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Listing BOM paths like Apple's `lsbom`.
+//!
+//! By default, each path is printed as tab separated columns depending on
+//! its type:
+//!
+//! * Files: path, mode, UID/GID, size, checksum.
+//! * Directories: path, mode, UID/GID.
+//! * Symlinks: path, mode, UID/GID, size, checksum, link name.
+//! * Devices: path, mode, UID/GID, device number.
+//!
+//! Modes are printed in octal. [ListingOptions::fields] selects explicit
+//! columns instead, like `lsbom -p`.
+
+use crate::{
+    error::Error,
+    path::{BomPath, BomPathType},
+};
+
+/// A column in a listing, as selected by `lsbom -p`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ListingField {
+    /// `c`: the checksum.
+    Checksum,
+    /// `f`: the path.
+    FileName,
+    /// `F`: the path in double quotes.
+    QuotedFileName,
+    /// `g`: the group ID.
+    GroupId,
+    /// `G`: the group name.
+    ///
+    /// Names aren't resolved, so this prints the group ID.
+    GroupName,
+    /// `m`: the mode in octal.
+    Mode,
+    /// `M`: the symbolic mode.
+    SymbolicMode,
+    /// `s`: the size in bytes.
+    Size,
+    /// `S`: the size with a unit suffix.
+    FormattedSize,
+    /// `t`: the modified time in seconds since UNIX epoch.
+    ModifiedTime,
+    /// `T`: the formatted modified time.
+    FormattedModifiedTime,
+    /// `u`: the user ID.
+    UserId,
+    /// `U`: the user name.
+    ///
+    /// Names aren't resolved, so this prints the user ID.
+    UserName,
+    /// `/`: the user ID and group ID separated by `/`.
+    UserGroupId,
+}
+
+impl ListingField {
+    /// Resolve a field from its `lsbom -p` character.
+    pub fn from_char(c: char) -> Option<Self> {
+        Some(match c {
+            'c' => Self::Checksum,
+            'f' => Self::FileName,
+            'F' => Self::QuotedFileName,
+            'g' => Self::GroupId,
+            'G' => Self::GroupName,
+            'm' => Self::Mode,
+            'M' => Self::SymbolicMode,
+            's' => Self::Size,
+            'S' => Self::FormattedSize,
+            't' => Self::ModifiedTime,
+            'T' => Self::FormattedModifiedTime,
+            'u' => Self::UserId,
+            'U' => Self::UserName,
+            '/' => Self::UserGroupId,
+            _ => return None,
+        })
+    }
+
+    /// Parse a `lsbom -p` parameter string into fields.
+    pub fn parse_fields(s: &str) -> Result<Vec<Self>, Error> {
+        s.chars()
+            .map(|c| {
+                Self::from_char(c)
+                    .ok_or_else(|| Error::CliBadArgs(format!("unknown listing field: {c}")))
+            })
+            .collect()
+    }
+
+    /// Format the value of this field for a path.
+    ///
+    /// Fields that don't apply to the path are empty.
+    pub fn format(&self, path: &BomPath) -> String {
+        match self {
+            Self::Checksum => path.crc32().map(|v| v.to_string()).unwrap_or_default(),
+            Self::FileName => path.path().to_string(),
+            Self::QuotedFileName => format!("\"{}\"", path.path()),
+            Self::GroupId | Self::GroupName => path.group_id().to_string(),
+            Self::Mode => format!("{:o}", path.file_mode()),
+            Self::SymbolicMode => path.symbolic_mode(),
+            Self::Size => path.size().to_string(),
+            Self::FormattedSize => format_size(path.size() as u64),
+            Self::ModifiedTime => path.modified_time().timestamp().to_string(),
+            Self::FormattedModifiedTime => path
+                .modified_time()
+                .format("%a %b %e %H:%M:%S %Y")
+                .to_string(),
+            Self::UserId | Self::UserName => path.user_id().to_string(),
+            Self::UserGroupId => format!("{}/{}", path.user_id(), path.group_id()),
+        }
+    }
+}
+
+/// Format a size with a unit suffix.
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+
+    if size < 1024 {
+        return format!("{size}B");
+    }
+
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{value:.1}{}", UNITS[unit])
+}
+
+/// Options controlling an `lsbom` style listing.
+///
+/// If none of the path type filters are set, all paths are listed.
+#[derive(Clone, Debug, Default)]
+pub struct ListingOptions {
+    /// List block devices (`-b`).
+    pub block_devices: bool,
+    /// List character devices (`-c`).
+    pub character_devices: bool,
+    /// List directories (`-d`).
+    pub directories: bool,
+    /// List files (`-f`).
+    pub files: bool,
+    /// List symlinks (`-l`).
+    pub symlinks: bool,
+    /// Print the modified time of files (`-m`).
+    pub modified_time: bool,
+    /// Only print paths (`-s`).
+    pub paths_only: bool,
+    /// Don't print the mode of directories and symlinks (`-x`).
+    pub suppress_modes: bool,
+    /// Explicit columns to print (`-p`).
+    pub fields: Option<Vec<ListingField>>,
+}
+
+impl ListingOptions {
+    /// Whether a path should be listed.
+    pub fn includes(&self, path: &BomPath) -> bool {
+        if !(self.block_devices
+            || self.character_devices
+            || self.directories
+            || self.files
+            || self.symlinks)
+        {
+            return true;
+        }
+
+        match path.path_type() {
+            BomPathType::File => self.files,
+            BomPathType::Directory => self.directories,
+            BomPathType::Link => self.symlinks,
+            BomPathType::Dev if path.file_mode() & 0o170000 == 0o060000 => self.block_devices,
+            BomPathType::Dev => self.character_devices,
+            BomPathType::Other(_) => false,
+        }
+    }
+
+    /// Format the listing line of a path.
+    pub fn format_path(&self, path: &BomPath) -> String {
+        if self.paths_only {
+            return path.path().to_string();
+        }
+
+        if let Some(fields) = &self.fields {
+            return fields
+                .iter()
+                .map(|field| field.format(path))
+                .collect::<Vec<_>>()
+                .join("\t");
+        }
+
+        let mut columns = vec![path.path().to_string()];
+
+        let suppress_mode = self.suppress_modes
+            && matches!(path.path_type(), BomPathType::Directory | BomPathType::Link);
+        if !suppress_mode {
+            columns.push(ListingField::Mode.format(path));
+        }
+        columns.push(ListingField::UserGroupId.format(path));
+
+        match path.path_type() {
+            BomPathType::File => {
+                if self.modified_time {
+                    columns.push(ListingField::ModifiedTime.format(path));
+                }
+                columns.push(ListingField::Size.format(path));
+                columns.push(ListingField::Checksum.format(path));
+            }
+            BomPathType::Link => {
+                columns.push(ListingField::Size.format(path));
+                columns.push(ListingField::Checksum.format(path));
+                columns.push(path.link_name().unwrap_or_default().to_string());
+            }
+            BomPathType::Dev => {
+                columns.push(path.device().unwrap_or_default().to_string());
+            }
+            BomPathType::Directory | BomPathType::Other(_) => {}
+        }
+
+        columns.join("\t")
+    }
+
+    /// Format the listing of paths.
+    pub fn format_paths<'a>(
+        &'a self,
+        paths: impl IntoIterator<Item = &'a BomPath> + 'a,
+    ) -> impl Iterator<Item = String> + 'a {
+        paths
+            .into_iter()
+            .filter(|path| self.includes(path))
+            .map(|path| self.format_path(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{builder::BomBuilder, format::ParsedBom},
+        chrono::TimeZone,
+    };
+
+    #[test]
+    fn listing() -> Result<(), Error> {
+        let mut builder = BomBuilder::default();
+        builder.default_mtime(chrono::Utc.timestamp_opt(1_600_000_000, 0).unwrap());
+        builder.default_user_id(501);
+        builder.default_group_id(20);
+        builder.add_file_from_data("bin/tool", b"tool")?;
+        builder.add_symlink("bin/link", "tool")?;
+        builder.add_character_device("dev/null", 3)?;
+
+        let data = builder.build_bom()?;
+        let bom = ParsedBom::parse(&data)?;
+        let paths = bom.paths()?;
+
+        let options = ListingOptions::default();
+        assert_eq!(
+            options.format_paths(&paths).collect::<Vec<_>>(),
+            vec![
+                ".\t40755\t501/20",
+                "./bin\t40755\t501/20",
+                "./dev\t40755\t501/20",
+                "./bin/link\t120755\t501/20\t4\t2090409092\ttool",
+                "./bin/tool\t100644\t501/20\t4\t2090409092",
+                "./dev/null\t20644\t501/20\t3",
+            ]
+        );
+
+        let options = ListingOptions {
+            files: true,
+            symlinks: true,
+            fields: Some(ListingField::parse_fields("MFsS")?),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.format_paths(&paths).collect::<Vec<_>>(),
+            vec![
+                "lrwxr-xr-x\t\"./bin/link\"\t4\t4B",
+                "-rw-r--r--\t\"./bin/tool\"\t4\t4B",
+            ]
+        );
+
+        let options = ListingOptions {
+            directories: true,
+            suppress_modes: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.format_paths(&paths).collect::<Vec<_>>(),
+            vec![".\t501/20", "./bin\t501/20", "./dev\t501/20"]
+        );
+
+        assert!(ListingField::parse_fields("fz").is_err());
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0G");
+
+        Ok(())
+    }
+}
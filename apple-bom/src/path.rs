@@ -6,7 +6,10 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 use {
-    crate::error::Error,
+    crate::{
+        error::Error,
+        format::{BomBlockArchitecture, ARCHITECTURE_BINARY_INFO},
+    },
     chrono::{DateTime, TimeZone, Utc},
     simple_file_manifest::{
         S_IRGRP, S_IROTH, S_IRUSR, S_IWGRP, S_IWOTH, S_IWUSR, S_IXGRP, S_IXOTH, S_IXUSR,
@@ -15,7 +18,7 @@ use {
 };
 
 /// The type of path in a BOM.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum BomPathType {
     /// A regular file.
     File,
@@ -76,6 +79,9 @@ pub struct BomPath {
     pub(crate) size: usize,
     pub(crate) crc32: Option<u32>,
     pub(crate) link_name: Option<String>,
+    pub(crate) architecture: u16,
+    pub(crate) architectures: Vec<BomBlockArchitecture>,
+    pub(crate) device: Option<u32>,
 }
 
 impl BomPath {
@@ -95,6 +101,10 @@ impl BomPath {
             BomPathType::File | BomPathType::Link => Some(record.checksum_or_type),
             BomPathType::Directory | BomPathType::Dev | BomPathType::Other(_) => None,
         };
+        let device = match path_type {
+            BomPathType::Dev => Some(record.checksum_or_type),
+            _ => None,
+        };
 
         Ok(Self {
             path_type,
@@ -106,6 +116,13 @@ impl BomPath {
             size: record.size as _,
             crc32,
             link_name: record.string_link_name(),
+            architecture: record.architecture & !ARCHITECTURE_BINARY_INFO,
+            architectures: record
+                .binary_info
+                .as_ref()
+                .map(|info| info.architectures.clone())
+                .unwrap_or_default(),
+            device,
         })
     }
 
@@ -141,7 +158,8 @@ impl BomPath {
             BomPathType::Directory => 'd',
             BomPathType::File => '-',
             BomPathType::Link => 'l',
-            BomPathType::Dev => '?',
+            BomPathType::Dev if self.file_mode & 0o170000 == 0o060000 => 'b',
+            BomPathType::Dev => 'c',
             BomPathType::Other(_) => '?',
         });
 
@@ -263,15 +281,9 @@ impl BomPath {
 
     /// The path that this link refers to, as a [CString].
     pub fn link_name_cstring(&self) -> Option<CString> {
-        if let Some(link_name) = &self.link_name {
-            let mut data = Vec::<u8>::with_capacity(link_name.as_bytes().len() + 1);
-            data.extend(link_name.as_bytes());
-            data.push(0);
-
-            Some(CString::new(data).expect("should be valid C string"))
-        } else {
-            None
-        }
+        self.link_name
+            .as_ref()
+            .map(|link_name| CString::new(link_name.as_bytes()).expect("should be valid C string"))
     }
 
     /// Set the link name for this path.
@@ -280,4 +292,45 @@ impl BomPath {
         self.link_name = value;
         old
     }
+
+    /// Bitmask of architectures this path applies to.
+    ///
+    /// Apple tooling writes `0xf` for most paths.
+    pub fn architecture(&self) -> u16 {
+        self.architecture
+    }
+
+    /// Set the bitmask of architectures this path applies to.
+    pub fn set_architecture(&mut self, value: u16) -> u16 {
+        let old = self.architecture;
+        self.architecture = value & !ARCHITECTURE_BINARY_INFO;
+        old
+    }
+
+    /// The architectures of a Mach-O binary.
+    ///
+    /// Empty for other paths.
+    pub fn architectures(&self) -> &[BomBlockArchitecture] {
+        &self.architectures
+    }
+
+    /// Set the architectures of a Mach-O binary.
+    pub fn set_architectures(
+        &mut self,
+        value: Vec<BomBlockArchitecture>,
+    ) -> Vec<BomBlockArchitecture> {
+        std::mem::replace(&mut self.architectures, value)
+    }
+
+    /// The device number of a device.
+    pub fn device(&self) -> Option<u32> {
+        self.device
+    }
+
+    /// Set the device number of a device.
+    pub fn set_device(&mut self, value: Option<u32>) -> Option<u32> {
+        let old = self.device;
+        self.device = value;
+        old
+    }
 }
@@ -479,6 +479,14 @@ impl KnownCertificate {
     pub fn all_roots() -> &'static [&'static CapturedX509Certificate] {
         KNOWN_ROOTS.deref()
     }
+
+    /// Obtain a policy for evaluating the trust of signed XAR archives against Apple's roots.
+    ///
+    /// This can be used to verify flat packages (`.pkg` installers) are signed with a
+    /// Developer ID Installer certificate.
+    pub fn xar_trust_policy() -> apple_xar::trust::TrustPolicy {
+        apple_xar::trust::TrustPolicy::new(Self::all_roots().iter().map(|cert| (*cert).clone()))
+    }
 }
 
 #[cfg(test)]
@@ -496,6 +504,13 @@ mod test {
         }
     }
 
+    #[test]
+    fn xar_trust_policy() {
+        let policy = KnownCertificate::xar_trust_policy();
+        assert_eq!(policy.roots.len(), KnownCertificate::all_roots().len());
+        assert!(policy.require_developer_id_installer);
+    }
+
     #[test]
     fn apple_root_ca() {
         assert!(APPLE_INC_ROOT_CERTIFICATE.is_apple_root_ca());
@@ -565,6 +565,7 @@ impl TryFrom<XarChecksumType> for DigestType {
             XarChecksumType::None => Ok(Self::None),
             XarChecksumType::Sha1 => Ok(Self::Sha1),
             XarChecksumType::Sha256 => Ok(Self::Sha256),
+            XarChecksumType::Sha384 => Ok(Self::Sha384),
             XarChecksumType::Sha512 => Ok(Self::Sha512),
             XarChecksumType::Md5 => Err(AppleCodesignError::DigestUnsupportedAlgorithm),
         }
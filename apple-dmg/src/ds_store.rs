@@ -0,0 +1,823 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Finder `.DS_Store` files.
+//!
+//! A `.DS_Store` file is a B-tree of records stored in a buddy allocated file.
+//! Each record attaches a typed value to a file name in the directory, with `.`
+//! referring to the directory itself. Finder stores the window bounds, view
+//! settings and icon positions of a directory this way.
+
+use {
+    crate::hfs::hfs_time,
+    anyhow::Result,
+    byteorder::{ReadBytesExt, WriteBytesExt, BE},
+    plist::{Dictionary, Value},
+    std::{
+        cmp::Ordering,
+        collections::{BTreeMap, HashSet},
+        io::Read,
+        path::PathBuf,
+    },
+};
+
+const BUD1_MAGIC: u32 = u32::from_be_bytes(*b"Bud1");
+const PAGE_SIZE: usize = 0x1000;
+const MIN_BLOCK_SHIFT: u32 = 5;
+const FREE_LIST_COUNT: usize = 32;
+/// Size of the smallest record: an empty file name, code, type and a `bool`.
+const MIN_RECORD_SIZE: usize = 13;
+/// Maximum depth of the B-tree. Finder never needs more than a few levels.
+const MAX_LEVELS: u32 = 32;
+
+/// Value of a `.DS_Store` record.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DsValue {
+    /// `long`: a 32-bit integer.
+    Long(u32),
+    /// `shor`: a 16-bit integer stored in 4 bytes.
+    Short(u16),
+    /// `bool`: a boolean.
+    Bool(bool),
+    /// `blob`: opaque data, usually a binary plist.
+    Blob(Vec<u8>),
+    /// `type`: a four character code.
+    Type([u8; 4]),
+    /// `ustr`: a UTF-16 string.
+    Ustr(String),
+    /// `comp`: a 64-bit integer.
+    Comp(u64),
+    /// `dutc`: a timestamp in 1/65536 seconds since 1904.
+    Dutc(u64),
+}
+
+impl DsValue {
+    fn type_code(&self) -> &'static [u8; 4] {
+        match self {
+            Self::Long(_) => b"long",
+            Self::Short(_) => b"shor",
+            Self::Bool(_) => b"bool",
+            Self::Blob(_) => b"blob",
+            Self::Type(_) => b"type",
+            Self::Ustr(_) => b"ustr",
+            Self::Comp(_) => b"comp",
+            Self::Dutc(_) => b"dutc",
+        }
+    }
+}
+
+/// A record attaching a value to a file name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DsRecord {
+    pub filename: String,
+    /// Four character code of the property, e.g. `Iloc` for icon positions.
+    pub code: [u8; 4],
+    pub value: DsValue,
+}
+
+impl DsRecord {
+    pub fn new(filename: &str, code: &[u8; 4], value: DsValue) -> Self {
+        Self {
+            filename: filename.to_string(),
+            code: *code,
+            value,
+        }
+    }
+
+    /// Records are sorted case insensitively by file name, then by code.
+    fn compare(&self, other: &Self) -> Ordering {
+        let a = self.filename.to_lowercase();
+        let b = other.filename.to_lowercase();
+        a.encode_utf16()
+            .cmp(b.encode_utf16())
+            .then(self.code.cmp(&other.code))
+    }
+
+    fn read_from(r: &mut &[u8]) -> Result<Self> {
+        let filename = read_utf16(r)?;
+        let mut code = [0; 4];
+        r.read_exact(&mut code)?;
+        let mut ty = [0; 4];
+        r.read_exact(&mut ty)?;
+        let value = match &ty {
+            b"long" => DsValue::Long(r.read_u32::<BE>()?),
+            b"shor" => DsValue::Short(r.read_u32::<BE>()? as u16),
+            b"bool" => DsValue::Bool(r.read_u8()? != 0),
+            b"blob" => {
+                let len = r.read_u32::<BE>()? as usize;
+                anyhow::ensure!(len <= r.len(), ".DS_Store blob is out of bounds");
+                let mut data = vec![0; len];
+                r.read_exact(&mut data)?;
+                DsValue::Blob(data)
+            }
+            b"type" => {
+                let mut code = [0; 4];
+                r.read_exact(&mut code)?;
+                DsValue::Type(code)
+            }
+            b"ustr" => DsValue::Ustr(read_utf16(r)?),
+            b"comp" => DsValue::Comp(r.read_u64::<BE>()?),
+            b"dutc" => DsValue::Dutc(r.read_u64::<BE>()?),
+            _ => anyhow::bail!(
+                "unknown .DS_Store value type {:?}",
+                String::from_utf8_lossy(&ty)
+            ),
+        };
+        Ok(Self {
+            filename,
+            code,
+            value,
+        })
+    }
+
+    fn write_to(&self, w: &mut Vec<u8>) -> Result<()> {
+        write_utf16(w, &self.filename)?;
+        w.extend_from_slice(&self.code);
+        w.extend_from_slice(self.value.type_code());
+        match &self.value {
+            DsValue::Long(v) => w.write_u32::<BE>(*v)?,
+            DsValue::Short(v) => w.write_u32::<BE>(*v as u32)?,
+            DsValue::Bool(v) => w.write_u8(*v as u8)?,
+            DsValue::Blob(data) => {
+                w.write_u32::<BE>(data.len() as u32)?;
+                w.extend_from_slice(data);
+            }
+            DsValue::Type(code) => w.extend_from_slice(code),
+            DsValue::Ustr(s) => write_utf16(w, s)?,
+            DsValue::Comp(v) | DsValue::Dutc(v) => w.write_u64::<BE>(*v)?,
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> usize {
+        let mut data = vec![];
+        self.write_to(&mut data).unwrap();
+        data.len()
+    }
+}
+
+fn read_utf16(r: &mut &[u8]) -> Result<String> {
+    let len = r.read_u32::<BE>()? as usize;
+    anyhow::ensure!(len <= r.len() / 2, ".DS_Store string is out of bounds");
+    let mut units = Vec::with_capacity(len);
+    for _ in 0..len {
+        units.push(r.read_u16::<BE>()?);
+    }
+    Ok(String::from_utf16(&units)?)
+}
+
+fn write_utf16(w: &mut Vec<u8>, s: &str) -> Result<()> {
+    let units = s.encode_utf16().collect::<Vec<_>>();
+    w.write_u32::<BE>(units.len() as u32)?;
+    for unit in units {
+        w.write_u16::<BE>(unit)?;
+    }
+    Ok(())
+}
+
+/// Buddy allocator managing the blocks of a `Bud1` file.
+///
+/// Offsets are relative to the fourth byte of the file.
+struct BuddyAllocator {
+    free: Vec<Vec<u32>>,
+}
+
+impl BuddyAllocator {
+    fn new() -> Self {
+        let mut free = vec![vec![]; FREE_LIST_COUNT];
+        free[31].push(0);
+        Self { free }
+    }
+
+    /// Allocate a block of at least `size` bytes, returning its address.
+    ///
+    /// The address is the offset of the block ORed with the log2 of its size.
+    fn allocate(&mut self, size: usize) -> Result<u32> {
+        let shift = (size.max(1).next_power_of_two().trailing_zeros()).max(MIN_BLOCK_SHIFT);
+        let mut i = shift as usize;
+        while i < FREE_LIST_COUNT && self.free[i].is_empty() {
+            i += 1;
+        }
+        anyhow::ensure!(i < FREE_LIST_COUNT, ".DS_Store allocator is full");
+        let offset = self.free[i].remove(0);
+        while i > shift as usize {
+            i -= 1;
+            self.free[i].push(offset + (1 << i));
+        }
+        Ok(offset | shift)
+    }
+}
+
+fn block_range(address: u32) -> (usize, usize) {
+    let offset = (address & !0x1f) as usize + 4;
+    (offset, offset + (1usize << (address & 0x1f)))
+}
+
+/// The records of a `.DS_Store` file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DsStore {
+    pub records: Vec<DsRecord>,
+}
+
+impl DsStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a record, replacing an existing record with the same file name and code.
+    pub fn insert(&mut self, record: DsRecord) {
+        match self.records.binary_search_by(|r| r.compare(&record)) {
+            Ok(i) => self.records[i] = record,
+            Err(i) => self.records.insert(i, record),
+        }
+    }
+
+    /// Look up the value of `code` for `filename`.
+    pub fn get(&self, filename: &str, code: &[u8; 4]) -> Option<&DsValue> {
+        self.records
+            .iter()
+            .find(|r| r.filename == filename && &r.code == code)
+            .map(|r| &r.value)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut r = bytes;
+        anyhow::ensure!(r.read_u32::<BE>()? == 1, "invalid .DS_Store header");
+        anyhow::ensure!(r.read_u32::<BE>()? == BUD1_MAGIC, "invalid .DS_Store magic");
+        let info_offset = r.read_u32::<BE>()? as usize + 4;
+        let info_size = r.read_u32::<BE>()? as usize;
+        let info = bytes
+            .get(info_offset..info_offset + info_size)
+            .ok_or_else(|| anyhow::anyhow!(".DS_Store info block is out of bounds"))?;
+
+        let mut r = info;
+        let count = r.read_u32::<BE>()? as usize;
+        let _unknown = r.read_u32::<BE>()?;
+        anyhow::ensure!(
+            count <= r.len() / 4,
+            ".DS_Store block count is out of bounds"
+        );
+        let mut addresses = vec![];
+        for _ in 0..count.div_ceil(256) * 256 {
+            addresses.push(r.read_u32::<BE>()?);
+        }
+        addresses.truncate(count);
+        let mut dsdb = None;
+        for _ in 0..r.read_u32::<BE>()? {
+            let len = r.read_u8()?;
+            let mut name = vec![0; len as usize];
+            r.read_exact(&mut name)?;
+            let id = r.read_u32::<BE>()?;
+            if name == b"DSDB" {
+                dsdb = Some(id);
+            }
+        }
+        let dsdb = dsdb.ok_or_else(|| anyhow::anyhow!(".DS_Store has no DSDB entry"))?;
+
+        let block = |id: u32| -> Result<&[u8]> {
+            let address = addresses
+                .get(id as usize)
+                .ok_or_else(|| anyhow::anyhow!("invalid .DS_Store block id {}", id))?;
+            let (start, end) = block_range(*address);
+            bytes
+                .get(start..end.min(bytes.len()))
+                .ok_or_else(|| anyhow::anyhow!(".DS_Store block {} is out of bounds", id))
+        };
+        let mut r = block(dsdb)?;
+        let root = r.read_u32::<BE>()?;
+        let levels = r.read_u32::<BE>()?;
+        let record_count = r.read_u32::<BE>()?;
+        anyhow::ensure!(levels <= MAX_LEVELS, ".DS_Store tree is too deep");
+        anyhow::ensure!(
+            record_count as usize <= bytes.len() / MIN_RECORD_SIZE,
+            ".DS_Store record count is out of bounds"
+        );
+
+        // Children are visited in order so records come out sorted.
+        fn walk<'a>(
+            block: &dyn Fn(u32) -> Result<&'a [u8]>,
+            id: u32,
+            depth: u32,
+            visited: &mut HashSet<u32>,
+            records: &mut Vec<DsRecord>,
+        ) -> Result<()> {
+            anyhow::ensure!(visited.insert(id), ".DS_Store tree has a cycle");
+            let mut r = block(id)?;
+            let p = r.read_u32::<BE>()?;
+            let count = r.read_u32::<BE>()?;
+            anyhow::ensure!(
+                count as usize <= r.len() / MIN_RECORD_SIZE,
+                ".DS_Store node record count is out of bounds"
+            );
+            if p == 0 {
+                for _ in 0..count {
+                    records.push(DsRecord::read_from(&mut r)?);
+                }
+            } else {
+                anyhow::ensure!(depth > 0, ".DS_Store tree is deeper than declared");
+                for _ in 0..count {
+                    let child = r.read_u32::<BE>()?;
+                    walk(block, child, depth - 1, visited, records)?;
+                    records.push(DsRecord::read_from(&mut r)?);
+                }
+                walk(block, p, depth - 1, visited, records)?;
+            }
+            Ok(())
+        }
+        let mut store = Self::new();
+        walk(
+            &block,
+            root,
+            levels,
+            &mut HashSet::new(),
+            &mut store.records,
+        )?;
+        anyhow::ensure!(
+            store.records.len() == record_count as usize,
+            ".DS_Store record count mismatch"
+        );
+        Ok(store)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut records = self.records.clone();
+        records.sort_by(|a, b| a.compare(b));
+
+        // Block 0 is the info block and block 1 the DSDB header, nodes follow.
+        let mut nodes: Vec<Vec<u8>> = vec![];
+        let mut push_node = |node: Vec<u8>| {
+            nodes.push(node);
+            nodes.len() as u32 + 1
+        };
+
+        let budget = PAGE_SIZE - 8;
+        let (groups, mut separators) = split(records.clone(), budget, |r| r.size());
+        let mut children = vec![];
+        for group in groups {
+            let mut node = vec![];
+            node.write_u32::<BE>(0)?;
+            node.write_u32::<BE>(group.len() as u32)?;
+            for record in &group {
+                record.write_to(&mut node)?;
+            }
+            children.push(push_node(node));
+        }
+        let mut levels = 0;
+        while children.len() > 1 {
+            levels += 1;
+            // Pair each separator with the child to its left.
+            let items = separators
+                .into_iter()
+                .zip(children.iter().copied())
+                .collect::<Vec<_>>();
+            let last_child = *children.last().unwrap();
+            let (groups, seps) = split(items, budget - 4, |(r, _)| 4 + r.size());
+            let mut next = vec![];
+            let group_count = groups.len();
+            let mut seps_iter = seps.iter();
+            for (i, group) in groups.into_iter().enumerate() {
+                // The rightmost child of a node is the left child of the
+                // separator promoted after it.
+                let p = if i + 1 == group_count {
+                    last_child
+                } else {
+                    seps_iter.next().unwrap().1
+                };
+                let mut node = vec![];
+                node.write_u32::<BE>(p)?;
+                node.write_u32::<BE>(group.len() as u32)?;
+                for (record, child) in &group {
+                    node.write_u32::<BE>(*child)?;
+                    record.write_to(&mut node)?;
+                }
+                next.push(push_node(node));
+            }
+            separators = seps.into_iter().map(|(r, _)| r).collect();
+            children = next;
+        }
+        let root = children[0];
+
+        let mut dsdb = vec![];
+        dsdb.write_u32::<BE>(root)?;
+        dsdb.write_u32::<BE>(levels)?;
+        dsdb.write_u32::<BE>(records.len() as u32)?;
+        dsdb.write_u32::<BE>(nodes.len() as u32)?;
+        dsdb.write_u32::<BE>(PAGE_SIZE as u32)?;
+
+        let block_count = nodes.len() + 2;
+        let info_size = 8 + 4 * block_count.div_ceil(256) * 256 + 4 + 9 + 4 * FREE_LIST_COUNT * 2;
+        let mut allocator = BuddyAllocator::new();
+        // The file header occupies the first block.
+        allocator.allocate(32)?;
+        let mut addresses = vec![
+            allocator.allocate(info_size)?,
+            allocator.allocate(dsdb.len())?,
+        ];
+        for node in &nodes {
+            addresses.push(allocator.allocate(node.len().max(PAGE_SIZE))?);
+        }
+
+        let mut info = vec![];
+        info.write_u32::<BE>(block_count as u32)?;
+        info.write_u32::<BE>(0)?;
+        for i in 0..block_count.div_ceil(256) * 256 {
+            info.write_u32::<BE>(addresses.get(i).copied().unwrap_or(0))?;
+        }
+        info.write_u32::<BE>(1)?;
+        info.write_u8(4)?;
+        info.extend_from_slice(b"DSDB");
+        info.write_u32::<BE>(1)?;
+        for list in &allocator.free {
+            info.write_u32::<BE>(list.len() as u32)?;
+            for offset in list {
+                info.write_u32::<BE>(*offset)?;
+            }
+        }
+        anyhow::ensure!(
+            info.len() <= block_range(addresses[0]).1 - block_range(addresses[0]).0,
+            ".DS_Store info block overflow"
+        );
+
+        let end = addresses.iter().map(|a| block_range(*a).1).max().unwrap();
+        let mut bytes = vec![0; end];
+        let (info_start, info_end) = block_range(addresses[0]);
+        let mut header = &mut bytes[..36];
+        header.write_u32::<BE>(1)?;
+        header.write_u32::<BE>(BUD1_MAGIC)?;
+        header.write_u32::<BE>(addresses[0] & !0x1f)?;
+        header.write_u32::<BE>((info_end - info_start) as u32)?;
+        header.write_u32::<BE>(addresses[0] & !0x1f)?;
+        let blocks = [info, dsdb].into_iter().chain(nodes);
+        for (address, block) in addresses.iter().zip(blocks) {
+            let start = block_range(*address).0;
+            bytes[start..start + block.len()].copy_from_slice(&block);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Split sorted items into groups fitting `budget` bytes, separated by single items.
+///
+/// Returns the groups and the separators between them, which move up a level.
+fn split<T>(items: Vec<T>, budget: usize, size: impl Fn(&T) -> usize) -> (Vec<Vec<T>>, Vec<T>) {
+    let mut groups = vec![vec![]];
+    let mut separators = vec![];
+    let mut used = 0;
+    for item in items {
+        let len = size(&item);
+        if used + len > budget && !groups.last().unwrap().is_empty() {
+            separators.push(item);
+            groups.push(vec![]);
+            used = 0;
+        } else {
+            used += len;
+            groups.last_mut().unwrap().push(item);
+        }
+    }
+    // A trailing separator needs a right child, so it takes the place of the
+    // last item of the previous group.
+    if groups.len() > 1 && groups.last().unwrap().is_empty() {
+        let last = separators.pop().unwrap();
+        let previous = groups.len() - 2;
+        separators.push(groups[previous].pop().unwrap());
+        groups.last_mut().unwrap().push(last);
+    }
+    (groups, separators)
+}
+
+/// A Mac OS alias record (version 2) pointing to a file on an HFS+ volume.
+///
+/// Finder uses aliases to locate the background picture of a window.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Alias {
+    pub volume_name: String,
+    /// Creation time of the volume as a Unix timestamp.
+    pub volume_created: i64,
+    /// Path of the target relative to the volume root, e.g. `.background/image.png`.
+    pub path: String,
+    /// Catalog node ids of the parent folders of the target, innermost first.
+    pub parent_ids: Vec<u32>,
+    /// Catalog node id of the target.
+    pub file_id: u32,
+    /// Creation time of the target as a Unix timestamp.
+    pub file_created: i64,
+}
+
+impl Alias {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let components = self.path.split('/').collect::<Vec<_>>();
+        let file_name = *components.last().unwrap();
+        anyhow::ensure!(!file_name.is_empty(), "alias target has no file name");
+
+        let mut data = vec![];
+        data.write_u32::<BE>(0)?;
+        // Record size, filled in at the end.
+        data.write_u16::<BE>(0)?;
+        data.write_u16::<BE>(2)?;
+        // Kind: file.
+        data.write_u16::<BE>(0)?;
+        write_pascal(&mut data, &self.volume_name, 28);
+        data.write_u32::<BE>(hfs_time(self.volume_created))?;
+        data.extend_from_slice(b"H+");
+        // Disk type: ejectable.
+        data.write_u16::<BE>(5)?;
+        data.write_u32::<BE>(self.parent_ids.first().copied().unwrap_or(2))?;
+        write_pascal(&mut data, file_name, 64);
+        data.write_u32::<BE>(self.file_id)?;
+        data.write_u32::<BE>(hfs_time(self.file_created))?;
+        // File type and creator.
+        data.extend_from_slice(&[0; 8]);
+        // Levels from and to a common parent are unknown.
+        data.write_i16::<BE>(-1)?;
+        data.write_i16::<BE>(-1)?;
+        // Volume attributes, volume filesystem id and reserved.
+        data.extend_from_slice(&[0; 16]);
+        debug_assert_eq!(data.len(), 150);
+
+        if components.len() > 1 {
+            let folder = components[components.len() - 2];
+            write_tag(&mut data, 0, folder.as_bytes())?;
+        }
+        let mut ids = vec![];
+        for id in &self.parent_ids {
+            ids.write_u32::<BE>(*id)?;
+        }
+        write_tag(&mut data, 1, &ids)?;
+        let carbon_path = std::iter::once(self.volume_name.as_str())
+            .chain(components.iter().copied())
+            .collect::<Vec<_>>()
+            .join(":");
+        write_tag(&mut data, 2, carbon_path.as_bytes())?;
+        write_tag(&mut data, 14, &unicode_tag(file_name)?)?;
+        write_tag(&mut data, 15, &unicode_tag(&self.volume_name)?)?;
+        write_tag(&mut data, 18, format!("/{}", self.path).as_bytes())?;
+        write_tag(
+            &mut data,
+            19,
+            format!("/Volumes/{}", self.volume_name).as_bytes(),
+        )?;
+        data.write_i16::<BE>(-1)?;
+        data.write_u16::<BE>(0)?;
+
+        let len = data.len() as u16;
+        data[4..6].copy_from_slice(&len.to_be_bytes());
+        Ok(data)
+    }
+}
+
+fn write_pascal(w: &mut Vec<u8>, s: &str, size: usize) {
+    let bytes = &s.as_bytes()[..s.len().min(size - 1)];
+    w.push(bytes.len() as u8);
+    w.extend_from_slice(bytes);
+    w.resize(w.len() + size - 1 - bytes.len(), 0);
+}
+
+fn unicode_tag(s: &str) -> Result<Vec<u8>> {
+    let units = s.encode_utf16().collect::<Vec<_>>();
+    let mut data = vec![];
+    data.write_u16::<BE>(units.len() as u16)?;
+    for unit in units {
+        data.write_u16::<BE>(unit)?;
+    }
+    Ok(data)
+}
+
+fn write_tag(w: &mut Vec<u8>, tag: i16, data: &[u8]) -> Result<()> {
+    w.write_i16::<BE>(tag)?;
+    w.write_u16::<BE>(data.len() as u16)?;
+    w.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        w.push(0);
+    }
+    Ok(())
+}
+
+/// Background of a Finder window.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FinderBackground {
+    /// A solid color with components between 0 and 1.
+    Color { red: f64, green: f64, blue: f64 },
+    /// A picture copied into the hidden `.background` folder of the volume.
+    Image(PathBuf),
+}
+
+/// Layout of the Finder window opened for a directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FinderWindow {
+    /// Position of the top left corner of the window on screen.
+    pub origin: (i32, i32),
+    /// Width and height of the window.
+    pub size: (u32, u32),
+    pub icon_size: u32,
+    pub text_size: u32,
+    pub background: Option<FinderBackground>,
+    /// Center of the icon of each top level entry, relative to the window.
+    pub icon_positions: BTreeMap<String, (u32, u32)>,
+}
+
+impl Default for FinderWindow {
+    fn default() -> Self {
+        Self {
+            origin: (100, 100),
+            size: (640, 480),
+            icon_size: 128,
+            text_size: 16,
+            background: None,
+            icon_positions: BTreeMap::new(),
+        }
+    }
+}
+
+impl FinderWindow {
+    /// Create the `.DS_Store` of the directory shown in the window.
+    ///
+    /// `background_alias` must point to the background image if the background
+    /// is a picture.
+    pub fn to_ds_store(&self, background_alias: Option<&Alias>) -> Result<DsStore> {
+        let mut bwsp = Dictionary::new();
+        for key in [
+            "ContainerShowSidebar",
+            "ShowPathbar",
+            "ShowSidebar",
+            "ShowStatusBar",
+            "ShowTabView",
+            "ShowToolbar",
+        ] {
+            bwsp.insert(key.to_string(), Value::Boolean(false));
+        }
+        bwsp.insert(
+            "WindowBounds".to_string(),
+            Value::String(format!(
+                "{{{{{}, {}}}, {{{}, {}}}}}",
+                self.origin.0, self.origin.1, self.size.0, self.size.1
+            )),
+        );
+
+        let mut icvp = Dictionary::new();
+        icvp.insert("arrangeBy".to_string(), Value::String("none".to_string()));
+        let (background_type, (red, green, blue)) = match &self.background {
+            None => (0, (1.0, 1.0, 1.0)),
+            Some(FinderBackground::Color { red, green, blue }) => (1, (*red, *green, *blue)),
+            Some(FinderBackground::Image(_)) => {
+                let alias = background_alias
+                    .ok_or_else(|| anyhow::anyhow!("background image requires an alias record"))?;
+                icvp.insert(
+                    "backgroundImageAlias".to_string(),
+                    Value::Data(alias.to_bytes()?),
+                );
+                (2, (1.0, 1.0, 1.0))
+            }
+        };
+        icvp.insert("backgroundColorRed".to_string(), Value::Real(red));
+        icvp.insert("backgroundColorGreen".to_string(), Value::Real(green));
+        icvp.insert("backgroundColorBlue".to_string(), Value::Real(blue));
+        icvp.insert(
+            "backgroundType".to_string(),
+            Value::Integer(background_type.into()),
+        );
+        icvp.insert("gridOffsetX".to_string(), Value::Real(0.0));
+        icvp.insert("gridOffsetY".to_string(), Value::Real(0.0));
+        icvp.insert("gridSpacing".to_string(), Value::Real(100.0));
+        icvp.insert("iconSize".to_string(), Value::Real(self.icon_size as f64));
+        icvp.insert("labelOnBottom".to_string(), Value::Boolean(true));
+        icvp.insert("showIconPreview".to_string(), Value::Boolean(true));
+        icvp.insert("showItemInfo".to_string(), Value::Boolean(false));
+        icvp.insert("textSize".to_string(), Value::Real(self.text_size as f64));
+        icvp.insert("viewOptionsVersion".to_string(), Value::Integer(1.into()));
+
+        let mut store = DsStore::new();
+        store.insert(DsRecord::new(
+            ".",
+            b"bwsp",
+            DsValue::Blob(binary_plist(bwsp)?),
+        ));
+        store.insert(DsRecord::new(
+            ".",
+            b"icvp",
+            DsValue::Blob(binary_plist(icvp)?),
+        ));
+        store.insert(DsRecord::new(".", b"vSrn", DsValue::Long(1)));
+        store.insert(DsRecord::new(".", b"vstl", DsValue::Type(*b"icnv")));
+        for (name, (x, y)) in &self.icon_positions {
+            let mut iloc = vec![];
+            iloc.write_u32::<BE>(*x)?;
+            iloc.write_u32::<BE>(*y)?;
+            iloc.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0]);
+            store.insert(DsRecord::new(name, b"Iloc", DsValue::Blob(iloc)));
+        }
+        Ok(store)
+    }
+}
+
+fn binary_plist(dict: Dictionary) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    plist::to_writer_binary(&mut data, &Value::Dictionary(dict))?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ds_store_round_trip() -> Result<()> {
+        let mut store = DsStore::new();
+        store.insert(DsRecord::new("b", b"cmmt", DsValue::Ustr("comment".into())));
+        store.insert(DsRecord::new("A", b"Iloc", DsValue::Blob(vec![1; 16])));
+        store.insert(DsRecord::new(".", b"vSrn", DsValue::Long(1)));
+        store.insert(DsRecord::new(".", b"vstl", DsValue::Type(*b"icnv")));
+        store.insert(DsRecord::new("a", b"dilc", DsValue::Bool(true)));
+        let bytes = store.to_bytes()?;
+        assert_eq!(&bytes[4..8], b"Bud1");
+        let read = DsStore::from_bytes(&bytes)?;
+        assert_eq!(read, store);
+        assert_eq!(read.records[0].filename, ".");
+        assert_eq!(
+            read.get("b", b"cmmt"),
+            Some(&DsValue::Ustr("comment".into()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ds_store_multiple_nodes() -> Result<()> {
+        let mut store = DsStore::new();
+        for i in 0..2000 {
+            store.insert(DsRecord::new(
+                &format!("file {:04}", i),
+                b"Iloc",
+                DsValue::Blob(vec![0; 16]),
+            ));
+        }
+        let read = DsStore::from_bytes(&store.to_bytes()?)?;
+        assert_eq!(read, store);
+        Ok(())
+    }
+
+    #[test]
+    fn ds_store_corrupted() -> Result<()> {
+        let mut store = DsStore::new();
+        store.insert(DsRecord::new("a", b"Iloc", DsValue::Blob(vec![1; 16])));
+        let bytes = store.to_bytes()?;
+
+        // A blob longer than the remaining data.
+        let blob = bytes.windows(4).position(|w| w == b"blob").unwrap() + 4;
+        let mut corrupted = bytes.clone();
+        corrupted[blob..blob + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(DsStore::from_bytes(&corrupted).is_err());
+
+        // A tree deeper than allowed. The DSDB block holds the root node, the
+        // number of levels, records and nodes and the page size.
+        let dsdb = [0u32, 1, 1, PAGE_SIZE as u32]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect::<Vec<_>>();
+        let levels = bytes.windows(16).position(|w| w == dsdb).unwrap();
+        let mut corrupted = bytes.clone();
+        corrupted[levels..levels + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(DsStore::from_bytes(&corrupted).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn window_records() -> Result<()> {
+        let mut window = FinderWindow {
+            background: Some(FinderBackground::Image("bg.png".into())),
+            ..Default::default()
+        };
+        window
+            .icon_positions
+            .insert("Example.app".into(), (160, 200));
+        assert!(window.to_ds_store(None).is_err());
+
+        let alias = Alias {
+            volume_name: "Example".into(),
+            volume_created: 0,
+            path: ".background/bg.png".into(),
+            parent_ids: vec![17],
+            file_id: 18,
+            file_created: 0,
+        };
+        let record = alias.to_bytes()?;
+        assert_eq!(
+            u16::from_be_bytes([record[4], record[5]]) as usize,
+            record.len()
+        );
+        let store = window.to_ds_store(Some(&alias))?;
+        let Some(DsValue::Blob(iloc)) = store.get("Example.app", b"Iloc") else {
+            panic!("missing icon location");
+        };
+        assert_eq!(&iloc[..8], &[0, 0, 0, 160, 0, 0, 0, 200]);
+        let Some(DsValue::Blob(icvp)) = store.get(".", b"icvp") else {
+            panic!("missing view settings");
+        };
+        let icvp: Value = plist::from_bytes(icvp)?;
+        let icvp = icvp.as_dictionary().unwrap();
+        assert_eq!(icvp["backgroundType"].as_signed_integer(), Some(2));
+        assert_eq!(icvp["backgroundImageAlias"].as_data(), Some(&record[..]));
+        Ok(())
+    }
+}
@@ -0,0 +1,424 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Encrypted images (`encrcdsa`, version 2).
+//!
+//! The image is split into fixed size chunks encrypted with AES-CBC. The IV of
+//! a chunk is the truncated HMAC-SHA1 of its big endian number. The AES and HMAC
+//! keys are stored in a key blob wrapped with 3DES-CBC, using a key derived from
+//! the passphrase with PBKDF2-HMAC-SHA1.
+
+use {
+    anyhow::Result,
+    byteorder::{ReadBytesExt, BE},
+    cbc::cipher::{
+        block_padding::{NoPadding, Pkcs7},
+        BlockDecryptMut, KeyIvInit,
+    },
+    hmac::{Hmac, Mac},
+    sha1::Sha1,
+    std::{
+        fs::File,
+        io::{BufReader, Read, Seek, SeekFrom},
+        path::Path,
+    },
+};
+
+pub const ENCRYPTED_IMAGE_SIGNATURE: &[u8; 8] = b"encrcdsa";
+const KEY_HEADER_PASSWORD: u32 = 1;
+const KDF_PBKDF2: u32 = 103;
+const HMAC_KEY_SIZE: usize = 20;
+/// Marker following the keys in an unwrapped key blob.
+const KEY_BLOB_MARKER: &[u8; 4] = b"CKIE";
+
+/// Location of a key header.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct KeyHeaderPointer {
+    /// 1 for passphrase protected keys.
+    pub ty: u32,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Header of an encrypted image.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EncryptedHeader {
+    pub version: u32,
+    pub iv_size: u32,
+    pub mode: u32,
+    pub algorithm: u32,
+    /// Size of the AES key, 128 or 256.
+    pub key_bits: u32,
+    pub prng_algorithm: u32,
+    pub prng_key_bits: u32,
+    pub uuid: [u8; 16],
+    /// Size of an encrypted chunk.
+    pub block_size: u32,
+    /// Size of the decrypted image.
+    pub data_size: u64,
+    pub data_offset: u64,
+    pub key_headers: Vec<KeyHeaderPointer>,
+}
+
+impl EncryptedHeader {
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let mut signature = [0; 8];
+        r.read_exact(&mut signature)?;
+        anyhow::ensure!(
+            &signature == ENCRYPTED_IMAGE_SIGNATURE,
+            "invalid encrypted image signature"
+        );
+        let version = r.read_u32::<BE>()?;
+        anyhow::ensure!(
+            version == 2,
+            "unsupported encrypted image version {}",
+            version
+        );
+        let iv_size = r.read_u32::<BE>()?;
+        let mode = r.read_u32::<BE>()?;
+        let algorithm = r.read_u32::<BE>()?;
+        let key_bits = r.read_u32::<BE>()?;
+        let prng_algorithm = r.read_u32::<BE>()?;
+        let prng_key_bits = r.read_u32::<BE>()?;
+        let mut uuid = [0; 16];
+        r.read_exact(&mut uuid)?;
+        let block_size = r.read_u32::<BE>()?;
+        let data_size = r.read_u64::<BE>()?;
+        let data_offset = r.read_u64::<BE>()?;
+        let key_count = r.read_u32::<BE>()?;
+        let mut key_headers = vec![];
+        for _ in 0..key_count {
+            key_headers.push(KeyHeaderPointer {
+                ty: r.read_u32::<BE>()?,
+                offset: r.read_u64::<BE>()?,
+                size: r.read_u64::<BE>()?,
+            });
+        }
+        Ok(Self {
+            version,
+            iv_size,
+            mode,
+            algorithm,
+            key_bits,
+            prng_algorithm,
+            prng_key_bits,
+            uuid,
+            block_size,
+            data_size,
+            data_offset,
+            key_headers,
+        })
+    }
+}
+
+/// Key header protecting the image keys with a passphrase.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PasswordKeyHeader {
+    pub kdf_algorithm: u32,
+    pub kdf_prng_algorithm: u32,
+    pub iteration_count: u32,
+    pub salt: Vec<u8>,
+    pub blob_iv: Vec<u8>,
+    pub blob_key_bits: u32,
+    pub blob_algorithm: u32,
+    pub blob_padding: u32,
+    pub blob_mode: u32,
+    pub encrypted_key_blob: Vec<u8>,
+}
+
+impl PasswordKeyHeader {
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let kdf_algorithm = r.read_u32::<BE>()?;
+        let kdf_prng_algorithm = r.read_u32::<BE>()?;
+        let iteration_count = r.read_u32::<BE>()?;
+        let salt_size = r.read_u32::<BE>()? as usize;
+        let mut salt = [0; 32];
+        r.read_exact(&mut salt)?;
+        let blob_iv_size = r.read_u32::<BE>()? as usize;
+        let mut blob_iv = [0; 32];
+        r.read_exact(&mut blob_iv)?;
+        anyhow::ensure!(
+            salt_size <= 32 && blob_iv_size <= 32,
+            "invalid encrypted image key header"
+        );
+        let blob_key_bits = r.read_u32::<BE>()?;
+        let blob_algorithm = r.read_u32::<BE>()?;
+        let blob_padding = r.read_u32::<BE>()?;
+        let blob_mode = r.read_u32::<BE>()?;
+        let blob_size = r.read_u32::<BE>()?;
+        let mut encrypted_key_blob = vec![0; blob_size as usize];
+        r.read_exact(&mut encrypted_key_blob)?;
+        Ok(Self {
+            kdf_algorithm,
+            kdf_prng_algorithm,
+            iteration_count,
+            salt: salt[..salt_size].to_vec(),
+            blob_iv: blob_iv[..blob_iv_size].to_vec(),
+            blob_key_bits,
+            blob_algorithm,
+            blob_padding,
+            blob_mode,
+            encrypted_key_blob,
+        })
+    }
+
+    /// Derive the wrapping key from `passphrase` and unwrap the key blob.
+    fn unwrap_key_blob(&self, passphrase: &str) -> Result<Vec<u8>> {
+        anyhow::ensure!(
+            self.kdf_algorithm == KDF_PBKDF2,
+            "unsupported key derivation algorithm {}",
+            self.kdf_algorithm
+        );
+        anyhow::ensure!(self.blob_iv.len() >= 8, "invalid key blob IV");
+        let mut key = [0; 24];
+        pbkdf2::pbkdf2_hmac::<Sha1>(
+            passphrase.as_bytes(),
+            &self.salt,
+            self.iteration_count,
+            &mut key,
+        );
+        let mut blob = self.encrypted_key_blob.clone();
+        let len = cbc::Decryptor::<des::TdesEde3>::new_from_slices(&key, &self.blob_iv[..8])?
+            .decrypt_padded_mut::<Pkcs7>(&mut blob)
+            .map_err(|_| anyhow::anyhow!("invalid passphrase"))?
+            .len();
+        blob.truncate(len);
+        Ok(blob)
+    }
+}
+
+/// `Read + Seek` view of the decrypted content of an encrypted image.
+///
+/// The content is usually a UDIF image, so the reader can be passed to
+/// [crate::DmgReader::new].
+pub struct EncryptedReader<R: Read + Seek> {
+    header: EncryptedHeader,
+    aes_key: Vec<u8>,
+    hmac_key: Vec<u8>,
+    r: R,
+    position: u64,
+    chunk: Option<(u64, Vec<u8>)>,
+}
+
+impl EncryptedReader<BufReader<File>> {
+    pub fn open(path: &Path, passphrase: &str) -> Result<Self> {
+        Self::new(BufReader::new(File::open(path)?), passphrase)
+    }
+}
+
+impl<R: Read + Seek> EncryptedReader<R> {
+    pub fn new(mut r: R, passphrase: &str) -> Result<Self> {
+        r.seek(SeekFrom::Start(0))?;
+        let header = EncryptedHeader::read_from(&mut r)?;
+        anyhow::ensure!(
+            matches!(header.key_bits, 128 | 256),
+            "unsupported AES key size {}",
+            header.key_bits
+        );
+        anyhow::ensure!(
+            header.block_size > 0 && header.block_size % 16 == 0,
+            "invalid encrypted image chunk size"
+        );
+        let key_size = header.key_bits as usize / 8;
+        let mut error = anyhow::anyhow!("encrypted image has no passphrase key header");
+        for pointer in &header.key_headers {
+            if pointer.ty != KEY_HEADER_PASSWORD {
+                continue;
+            }
+            r.seek(SeekFrom::Start(pointer.offset))?;
+            let key_header = PasswordKeyHeader::read_from(&mut r)?;
+            match key_header.unwrap_key_blob(passphrase) {
+                Ok(blob)
+                    if blob.len() == key_size + HMAC_KEY_SIZE + KEY_BLOB_MARKER.len()
+                        && blob.ends_with(KEY_BLOB_MARKER) =>
+                {
+                    return Ok(Self {
+                        aes_key: blob[..key_size].to_vec(),
+                        hmac_key: blob[key_size..key_size + HMAC_KEY_SIZE].to_vec(),
+                        header,
+                        r,
+                        position: 0,
+                        chunk: None,
+                    });
+                }
+                Ok(_) => error = anyhow::anyhow!("invalid passphrase"),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    pub fn header(&self) -> &EncryptedHeader {
+        &self.header
+    }
+
+    /// Size of the decrypted content in bytes.
+    pub fn len(&self) -> u64 {
+        self.header.data_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn chunk_iv(&self, chunk: u64) -> Vec<u8> {
+        let mut mac = Hmac::<Sha1>::new_from_slice(&self.hmac_key).unwrap();
+        mac.update(&(chunk as u32).to_be_bytes());
+        mac.finalize().into_bytes()[..16].to_vec()
+    }
+
+    fn decrypt_chunk(&mut self, chunk: u64) -> Result<()> {
+        if matches!(&self.chunk, Some((c, _)) if *c == chunk) {
+            return Ok(());
+        }
+        let block_size = self.header.block_size as u64;
+        let mut data = vec![0; block_size as usize];
+        self.r.seek(SeekFrom::Start(
+            self.header.data_offset + chunk * block_size,
+        ))?;
+        self.r.read_exact(&mut data)?;
+        let iv = self.chunk_iv(chunk);
+        let result = if self.aes_key.len() == 16 {
+            cbc::Decryptor::<aes::Aes128>::new_from_slices(&self.aes_key, &iv)?
+                .decrypt_padded_mut::<NoPadding>(&mut data)
+                .map(|_| ())
+        } else {
+            cbc::Decryptor::<aes::Aes256>::new_from_slices(&self.aes_key, &iv)?
+                .decrypt_padded_mut::<NoPadding>(&mut data)
+                .map(|_| ())
+        };
+        result.map_err(|_| anyhow::anyhow!("failed to decrypt chunk {}", chunk))?;
+        self.chunk = Some((chunk, data));
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for EncryptedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let block_size = self.header.block_size as u64;
+        let remaining = self.len().saturating_sub(self.position);
+        let (chunk, offset) = (self.position / block_size, self.position % block_size);
+        let len = (buf.len() as u64).min(remaining).min(block_size - offset) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        self.decrypt_chunk(chunk)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let data = &self.chunk.as_ref().unwrap().1;
+        buf[..len].copy_from_slice(&data[offset as usize..offset as usize + len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for EncryptedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+        };
+        self.position = new.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seek to a negative position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        byteorder::WriteBytesExt,
+        cbc::cipher::BlockEncryptMut,
+        std::io::{Cursor, Write},
+    };
+
+    /// Encrypt `data` the way `hdiutil` does, with AES-128 and 4096 byte chunks.
+    pub(crate) fn encrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        let aes_key = [7; 16];
+        let hmac_key = [9; HMAC_KEY_SIZE];
+        let salt = [1; 20];
+        let blob_iv = [2; 8];
+        let mut wrapping_key = [0; 24];
+        pbkdf2::pbkdf2_hmac::<Sha1>(passphrase.as_bytes(), &salt, 1000, &mut wrapping_key);
+        let mut blob = [&aes_key[..], &hmac_key[..], KEY_BLOB_MARKER].concat();
+        let len = blob.len();
+        blob.resize(len + 8, 0);
+        let blob = cbc::Encryptor::<des::TdesEde3>::new_from_slices(&wrapping_key, &blob_iv)?
+            .encrypt_padded_mut::<Pkcs7>(&mut blob, len)
+            .unwrap()
+            .to_vec();
+
+        let mut key_header = vec![];
+        key_header.write_u32::<BE>(KDF_PBKDF2)?;
+        key_header.write_u32::<BE>(0)?;
+        key_header.write_u32::<BE>(1000)?;
+        key_header.write_u32::<BE>(salt.len() as u32)?;
+        key_header.write_all(&salt)?;
+        key_header.write_all(&[0; 12])?;
+        key_header.write_u32::<BE>(blob_iv.len() as u32)?;
+        key_header.write_all(&blob_iv)?;
+        key_header.write_all(&[0; 24])?;
+        for value in [192, 17, 7, 6, blob.len() as u32] {
+            key_header.write_u32::<BE>(value)?;
+        }
+        key_header.write_all(&blob)?;
+
+        let key_header_offset = 76 + 20;
+        let data_offset = 4096;
+        let mut image = vec![];
+        image.write_all(ENCRYPTED_IMAGE_SIGNATURE)?;
+        for value in [2, 16, 2, 0x8000_0001, 128, 0x8000_0011, 160] {
+            image.write_u32::<BE>(value)?;
+        }
+        image.write_all(&[3; 16])?;
+        image.write_u32::<BE>(4096)?;
+        image.write_u64::<BE>(data.len() as u64)?;
+        image.write_u64::<BE>(data_offset)?;
+        image.write_u32::<BE>(1)?;
+        image.write_u32::<BE>(KEY_HEADER_PASSWORD)?;
+        image.write_u64::<BE>(key_header_offset)?;
+        image.write_u64::<BE>(key_header.len() as u64)?;
+        image.write_all(&key_header)?;
+        image.resize(data_offset as usize, 0);
+
+        for (i, chunk) in data.chunks(4096).enumerate() {
+            let mut chunk = chunk.to_vec();
+            chunk.resize(4096, 0);
+            let mut mac = Hmac::<Sha1>::new_from_slice(&hmac_key)?;
+            mac.update(&(i as u32).to_be_bytes());
+            let iv = &mac.finalize().into_bytes()[..16];
+            cbc::Encryptor::<aes::Aes128>::new_from_slices(&aes_key, iv)?
+                .encrypt_padded_mut::<NoPadding>(&mut chunk, 4096)
+                .unwrap();
+            image.extend_from_slice(&chunk);
+        }
+        Ok(image)
+    }
+
+    #[test]
+    fn decrypt() -> Result<()> {
+        let data = (0..10000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let image = encrypt(&data, "secret")?;
+        assert!(EncryptedReader::new(Cursor::new(&image), "wrong").is_err());
+
+        let mut reader = EncryptedReader::new(Cursor::new(&image), "secret")?;
+        assert_eq!(reader.header().key_bits, 128);
+        let mut decrypted = vec![];
+        reader.read_to_end(&mut decrypted)?;
+        assert_eq!(decrypted, data);
+
+        reader.seek(SeekFrom::Start(4094))?;
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf)?;
+        assert_eq!(buf, data[4094..4098]);
+        Ok(())
+    }
+}
@@ -0,0 +1,1885 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! HFS+ volume formatting and reading.
+//!
+//! The on-disk structures follow Apple's Technical Note TN1150. Volumes written
+//! by [HfsBuilder] are non-journaled HFSX (case-sensitive HFS+) volumes: HFSX
+//! catalog keys compare with binary ordering, which avoids having to carry
+//! Apple's Unicode case folding tables.
+
+use {
+    crate::reproducible::{now, Reproducible},
+    anyhow::Result,
+    byteorder::{ReadBytesExt, WriteBytesExt, BE},
+    std::{
+        collections::{BTreeMap, HashMap},
+        io::{Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+    unicode_normalization::UnicodeNormalization,
+};
+
+/// Seconds between the HFS epoch (1904-01-01) and the Unix epoch.
+const HFS_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+const VOLUME_HEADER_OFFSET: u64 = 1024;
+const BLOCK_SIZE: u32 = 4096;
+const CATALOG_NODE_SIZE: usize = 8192;
+const EXTENTS_NODE_SIZE: usize = 4096;
+
+const SIGNATURE_HFS_PLUS: u16 = 0x482b;
+const SIGNATURE_HFSX: u16 = 0x4858;
+/// `10.0`, the last mounted version of volumes not written by a journaling driver.
+const LAST_MOUNTED_VERSION: u32 = 0x3130_2e30;
+const VOLUME_UNMOUNTED: u32 = 1 << 8;
+
+const ROOT_PARENT_ID: u32 = 1;
+const ROOT_FOLDER_ID: u32 = 2;
+const EXTENTS_FILE_ID: u32 = 3;
+const CATALOG_FILE_ID: u32 = 4;
+const FIRST_USER_CATALOG_NODE_ID: u32 = 16;
+
+const FOLDER_RECORD: i16 = 1;
+const FILE_RECORD: i16 = 2;
+const FOLDER_THREAD_RECORD: i16 = 3;
+const FILE_THREAD_RECORD: i16 = 4;
+
+const FILE_THREAD_EXISTS: u16 = 0x0002;
+
+const BTREE_LEAF_NODE: i8 = -1;
+const BTREE_INDEX_NODE: i8 = 0;
+const BTREE_HEADER_NODE: i8 = 1;
+const BTREE_BIG_KEYS: u32 = 0x2;
+const BTREE_VARIABLE_INDEX_KEYS: u32 = 0x4;
+const BTREE_BINARY_COMPARE: u8 = 0xbc;
+
+const S_IFMT: u16 = 0o170000;
+const S_IFDIR: u16 = 0o040000;
+const S_IFREG: u16 = 0o100000;
+const S_IFLNK: u16 = 0o120000;
+
+/// Owner and group id that macOS maps to the user mounting the volume.
+const UNKNOWN_ID: u32 = 99;
+
+/// Finder file type and creator for symlinks.
+const SYMLINK_FILE_TYPE: u32 = u32::from_be_bytes(*b"slnk");
+const SYMLINK_CREATOR: u32 = u32::from_be_bytes(*b"rhap");
+
+/// Finder file type and creator for file hard links.
+const HARD_LINK_FILE_TYPE: u32 = u32::from_be_bytes(*b"hlnk");
+const HARD_LINK_CREATOR: u32 = u32::from_be_bytes(*b"hfs+");
+/// Finder file type and creator for directory hard links.
+const DIRECTORY_LINK_FILE_TYPE: u32 = u32::from_be_bytes(*b"fdrp");
+const DIRECTORY_LINK_CREATOR: u32 = u32::from_be_bytes(*b"MACS");
+
+/// BSD owner flag of files compressed by decmpfs.
+const UF_COMPRESSED: u8 = 0x20;
+
+/// Finder flag of entries with a custom icon. On the root folder, it makes the
+/// Finder show `.VolumeIcon.icns` as the volume icon.
+pub const FINDER_HAS_CUSTOM_ICON: u16 = 0x0400;
+/// Finder flag of entries hidden by the Finder.
+pub const FINDER_IS_INVISIBLE: u16 = 0x4000;
+
+/// Name of the file holding the icon of a volume.
+const VOLUME_ICON_NAME: &str = ".VolumeIcon.icns";
+
+/// Names of the hidden folders holding hard link targets.
+const PRIVATE_FOLDER_NAMES: [&str; 2] = [
+    "\0\0\0\0HFS+ Private Data",
+    ".HFS+ Private Directory Data\r",
+];
+
+/// Convert a Unix timestamp to an HFS timestamp.
+pub(crate) fn hfs_time(unix: i64) -> u32 {
+    (unix + HFS_EPOCH_OFFSET).clamp(0, u32::MAX as i64) as u32
+}
+
+/// Convert an HFS timestamp to a Unix timestamp.
+fn unix_time(hfs: u32) -> i64 {
+    hfs as i64 - HFS_EPOCH_OFFSET
+}
+
+fn system_time_to_unix(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+/// Convert a POSIX file name to the UTF-16 form stored in the catalog.
+///
+/// Names are stored decomposed and with `:` and `/` swapped, as the Carbon path
+/// separator is `:`.
+fn encode_name(name: &str) -> Result<Vec<u16>> {
+    let name = name
+        .nfd()
+        .map(|c| match c {
+            ':' => '/',
+            c => c,
+        })
+        .collect::<String>()
+        .encode_utf16()
+        .collect::<Vec<_>>();
+    anyhow::ensure!(name.len() <= 255, "file name is too long for HFS+");
+    Ok(name)
+}
+
+fn decode_name(name: &[u16]) -> String {
+    String::from_utf16_lossy(name)
+        .nfc()
+        .map(|c| match c {
+            '/' => ':',
+            c => c,
+        })
+        .collect()
+}
+
+/// A contiguous range of allocation blocks.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct HfsExtentDescriptor {
+    pub start_block: u32,
+    pub block_count: u32,
+}
+
+impl HfsExtentDescriptor {
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        Ok(Self {
+            start_block: r.read_u32::<BE>()?,
+            block_count: r.read_u32::<BE>()?,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_u32::<BE>(self.start_block)?;
+        w.write_u32::<BE>(self.block_count)?;
+        Ok(())
+    }
+}
+
+/// Location and size of a fork (the data or resource stream of a file).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct HfsForkData {
+    pub logical_size: u64,
+    pub clump_size: u32,
+    pub total_blocks: u32,
+    pub extents: [HfsExtentDescriptor; 8],
+}
+
+impl HfsForkData {
+    fn contiguous(start_block: u32, block_count: u32, logical_size: u64) -> Self {
+        let mut extents = [HfsExtentDescriptor::default(); 8];
+        if block_count > 0 {
+            extents[0] = HfsExtentDescriptor {
+                start_block,
+                block_count,
+            };
+        }
+        Self {
+            logical_size,
+            clump_size: 0,
+            total_blocks: block_count,
+            extents,
+        }
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let logical_size = r.read_u64::<BE>()?;
+        let clump_size = r.read_u32::<BE>()?;
+        let total_blocks = r.read_u32::<BE>()?;
+        let mut extents = [HfsExtentDescriptor::default(); 8];
+        for extent in &mut extents {
+            *extent = HfsExtentDescriptor::read_from(r)?;
+        }
+        Ok(Self {
+            logical_size,
+            clump_size,
+            total_blocks,
+            extents,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_u64::<BE>(self.logical_size)?;
+        w.write_u32::<BE>(self.clump_size)?;
+        w.write_u32::<BE>(self.total_blocks)?;
+        for extent in &self.extents {
+            extent.write_to(w)?;
+        }
+        Ok(())
+    }
+}
+
+/// HFS+ volume header, stored 1024 bytes from the start of the volume.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HfsVolumeHeader {
+    pub signature: u16,
+    pub version: u16,
+    pub attributes: u32,
+    pub last_mounted_version: u32,
+    pub journal_info_block: u32,
+    pub create_date: u32,
+    pub modify_date: u32,
+    pub backup_date: u32,
+    pub checked_date: u32,
+    pub file_count: u32,
+    pub folder_count: u32,
+    pub block_size: u32,
+    pub total_blocks: u32,
+    pub free_blocks: u32,
+    pub next_allocation: u32,
+    pub rsrc_clump_size: u32,
+    pub data_clump_size: u32,
+    pub next_catalog_id: u32,
+    pub write_count: u32,
+    pub encodings_bitmap: u64,
+    pub finder_info: [u32; 8],
+    pub allocation_file: HfsForkData,
+    pub extents_file: HfsForkData,
+    pub catalog_file: HfsForkData,
+    pub attributes_file: HfsForkData,
+    pub startup_file: HfsForkData,
+}
+
+impl Default for HfsVolumeHeader {
+    fn default() -> Self {
+        Self {
+            signature: SIGNATURE_HFSX,
+            version: 5,
+            attributes: VOLUME_UNMOUNTED,
+            last_mounted_version: LAST_MOUNTED_VERSION,
+            journal_info_block: 0,
+            create_date: 0,
+            modify_date: 0,
+            backup_date: 0,
+            checked_date: 0,
+            file_count: 0,
+            folder_count: 0,
+            block_size: BLOCK_SIZE,
+            total_blocks: 0,
+            free_blocks: 0,
+            next_allocation: 0,
+            rsrc_clump_size: 65536,
+            data_clump_size: 65536,
+            next_catalog_id: FIRST_USER_CATALOG_NODE_ID,
+            write_count: 0,
+            // MacRoman
+            encodings_bitmap: 1,
+            finder_info: [0; 8],
+            allocation_file: HfsForkData::default(),
+            extents_file: HfsForkData::default(),
+            catalog_file: HfsForkData::default(),
+            attributes_file: HfsForkData::default(),
+            startup_file: HfsForkData::default(),
+        }
+    }
+}
+
+impl HfsVolumeHeader {
+    /// Construct an instance by reading from a seekable reader over the volume.
+    pub fn read_from<R: Read + Seek>(r: &mut R) -> Result<Self> {
+        r.seek(SeekFrom::Start(VOLUME_HEADER_OFFSET))?;
+        let signature = r.read_u16::<BE>()?;
+        anyhow::ensure!(
+            signature == SIGNATURE_HFS_PLUS || signature == SIGNATURE_HFSX,
+            "not an HFS+ volume"
+        );
+        let version = r.read_u16::<BE>()?;
+        let attributes = r.read_u32::<BE>()?;
+        let last_mounted_version = r.read_u32::<BE>()?;
+        let journal_info_block = r.read_u32::<BE>()?;
+        let create_date = r.read_u32::<BE>()?;
+        let modify_date = r.read_u32::<BE>()?;
+        let backup_date = r.read_u32::<BE>()?;
+        let checked_date = r.read_u32::<BE>()?;
+        let file_count = r.read_u32::<BE>()?;
+        let folder_count = r.read_u32::<BE>()?;
+        let block_size = r.read_u32::<BE>()?;
+        let total_blocks = r.read_u32::<BE>()?;
+        let free_blocks = r.read_u32::<BE>()?;
+        let next_allocation = r.read_u32::<BE>()?;
+        let rsrc_clump_size = r.read_u32::<BE>()?;
+        let data_clump_size = r.read_u32::<BE>()?;
+        let next_catalog_id = r.read_u32::<BE>()?;
+        let write_count = r.read_u32::<BE>()?;
+        let encodings_bitmap = r.read_u64::<BE>()?;
+        let mut finder_info = [0; 8];
+        r.read_u32_into::<BE>(&mut finder_info)?;
+        let allocation_file = HfsForkData::read_from(r)?;
+        let extents_file = HfsForkData::read_from(r)?;
+        let catalog_file = HfsForkData::read_from(r)?;
+        let attributes_file = HfsForkData::read_from(r)?;
+        let startup_file = HfsForkData::read_from(r)?;
+        Ok(Self {
+            signature,
+            version,
+            attributes,
+            last_mounted_version,
+            journal_info_block,
+            create_date,
+            modify_date,
+            backup_date,
+            checked_date,
+            file_count,
+            folder_count,
+            block_size,
+            total_blocks,
+            free_blocks,
+            next_allocation,
+            rsrc_clump_size,
+            data_clump_size,
+            next_catalog_id,
+            write_count,
+            encodings_bitmap,
+            finder_info,
+            allocation_file,
+            extents_file,
+            catalog_file,
+            attributes_file,
+            startup_file,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_u16::<BE>(self.signature)?;
+        w.write_u16::<BE>(self.version)?;
+        w.write_u32::<BE>(self.attributes)?;
+        w.write_u32::<BE>(self.last_mounted_version)?;
+        w.write_u32::<BE>(self.journal_info_block)?;
+        w.write_u32::<BE>(self.create_date)?;
+        w.write_u32::<BE>(self.modify_date)?;
+        w.write_u32::<BE>(self.backup_date)?;
+        w.write_u32::<BE>(self.checked_date)?;
+        w.write_u32::<BE>(self.file_count)?;
+        w.write_u32::<BE>(self.folder_count)?;
+        w.write_u32::<BE>(self.block_size)?;
+        w.write_u32::<BE>(self.total_blocks)?;
+        w.write_u32::<BE>(self.free_blocks)?;
+        w.write_u32::<BE>(self.next_allocation)?;
+        w.write_u32::<BE>(self.rsrc_clump_size)?;
+        w.write_u32::<BE>(self.data_clump_size)?;
+        w.write_u32::<BE>(self.next_catalog_id)?;
+        w.write_u32::<BE>(self.write_count)?;
+        w.write_u64::<BE>(self.encodings_bitmap)?;
+        for info in &self.finder_info {
+            w.write_u32::<BE>(*info)?;
+        }
+        self.allocation_file.write_to(w)?;
+        self.extents_file.write_to(w)?;
+        self.catalog_file.write_to(w)?;
+        self.attributes_file.write_to(w)?;
+        self.startup_file.write_to(w)?;
+        Ok(())
+    }
+}
+
+/// BSD ownership and mode of a catalog entry.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct BsdInfo {
+    owner_id: u32,
+    group_id: u32,
+    admin_flags: u8,
+    owner_flags: u8,
+    file_mode: u16,
+    special: u32,
+}
+
+impl BsdInfo {
+    fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        Ok(Self {
+            owner_id: r.read_u32::<BE>()?,
+            group_id: r.read_u32::<BE>()?,
+            admin_flags: r.read_u8()?,
+            owner_flags: r.read_u8()?,
+            file_mode: r.read_u16::<BE>()?,
+            special: r.read_u32::<BE>()?,
+        })
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_u32::<BE>(self.owner_id)?;
+        w.write_u32::<BE>(self.group_id)?;
+        w.write_u8(self.admin_flags)?;
+        w.write_u8(self.owner_flags)?;
+        w.write_u16::<BE>(self.file_mode)?;
+        w.write_u32::<BE>(self.special)?;
+        Ok(())
+    }
+}
+
+fn write_catalog_key(parent_id: u32, name: &[u16]) -> Result<Vec<u8>> {
+    let mut key = vec![];
+    key.write_u16::<BE>(6 + 2 * name.len() as u16)?;
+    key.write_u32::<BE>(parent_id)?;
+    key.write_u16::<BE>(name.len() as u16)?;
+    for c in name {
+        key.write_u16::<BE>(*c)?;
+    }
+    Ok(key)
+}
+
+fn read_catalog_key(mut key: &[u8]) -> Result<(u32, Vec<u16>)> {
+    let _key_length = key.read_u16::<BE>()?;
+    let parent_id = key.read_u32::<BE>()?;
+    let length = key.read_u16::<BE>()?;
+    let mut name = vec![0; length as usize];
+    key.read_u16_into::<BE>(&mut name)?;
+    Ok((parent_id, name))
+}
+
+fn write_thread_record(record_type: i16, parent_id: u32, name: &[u16]) -> Result<Vec<u8>> {
+    let mut record = vec![];
+    record.write_i16::<BE>(record_type)?;
+    record.write_i16::<BE>(0)?;
+    record.write_u32::<BE>(parent_id)?;
+    record.write_u16::<BE>(name.len() as u16)?;
+    for c in name {
+        record.write_u16::<BE>(*c)?;
+    }
+    Ok(record)
+}
+
+/// Serialize a B-tree from records sorted by key.
+///
+/// Keys include their leading key length. Returns the tree file content.
+fn build_btree(
+    records: &[(Vec<u8>, Vec<u8>)],
+    node_size: usize,
+    max_key_length: u16,
+    attributes: u32,
+    key_compare_type: u8,
+) -> Result<Vec<u8>> {
+    // (first key, node content) for every node on the current level.
+    struct Node {
+        first_key: Vec<u8>,
+        records: Vec<Vec<u8>>,
+    }
+
+    fn pack(records: Vec<(Vec<u8>, Vec<u8>)>, node_size: usize) -> Result<Vec<Node>> {
+        let mut nodes: Vec<Node> = vec![];
+        let mut used = node_size;
+        for (key, data) in records {
+            let mut record = key.clone();
+            record.extend_from_slice(&data);
+            // descriptor, records and one offset per record plus the free space offset.
+            anyhow::ensure!(
+                14 + record.len() + 4 <= node_size,
+                "B-tree record too large"
+            );
+            if used + record.len() + 2 > node_size {
+                nodes.push(Node {
+                    first_key: key,
+                    records: vec![],
+                });
+                used = 14 + 2;
+            }
+            used += record.len() + 2;
+            nodes.last_mut().unwrap().records.push(record);
+        }
+        Ok(nodes)
+    }
+
+    fn write_node(
+        buf: &mut [u8],
+        f_link: u32,
+        b_link: u32,
+        kind: i8,
+        height: u8,
+        records: &[Vec<u8>],
+    ) -> Result<()> {
+        let node_size = buf.len();
+        let mut w = &mut buf[..];
+        w.write_u32::<BE>(f_link)?;
+        w.write_u32::<BE>(b_link)?;
+        w.write_i8(kind)?;
+        w.write_u8(height)?;
+        w.write_u16::<BE>(records.len() as u16)?;
+        w.write_u16::<BE>(0)?;
+        let mut offset = 14;
+        let mut offsets = vec![];
+        for record in records {
+            offsets.push(offset as u16);
+            buf[offset..offset + record.len()].copy_from_slice(record);
+            offset += record.len();
+        }
+        offsets.push(offset as u16);
+        for (i, offset) in offsets.iter().enumerate() {
+            let pos = node_size - 2 * (i + 1);
+            buf[pos..pos + 2].copy_from_slice(&offset.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    let variable_index_keys = attributes & BTREE_VARIABLE_INDEX_KEYS != 0;
+
+    // Levels from the leaves up to the root.
+    let mut levels = vec![pack(records.to_vec(), node_size)?];
+    while levels.last().unwrap().len() > 1 {
+        let mut first_node = 1 + levels.iter().map(|l| l.len()).sum::<usize>() as u32;
+        first_node -= levels.last().unwrap().len() as u32;
+        let index_records = levels
+            .last()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let mut key = node.first_key.clone();
+                if !variable_index_keys {
+                    key.resize(2 + max_key_length as usize, 0);
+                    key[..2].copy_from_slice(&max_key_length.to_be_bytes());
+                }
+                (key, (first_node + i as u32).to_be_bytes().to_vec())
+            })
+            .collect::<Vec<_>>();
+        levels.push(pack(index_records, node_size)?);
+    }
+
+    let total_nodes = 1 + levels.iter().map(|l| l.len()).sum::<usize>();
+    let map_bits = (node_size - 256) * 8;
+    anyhow::ensure!(
+        total_nodes <= map_bits,
+        "B-tree too large for its header node map"
+    );
+    let mut tree = vec![0; total_nodes * node_size];
+
+    let leaf_count = levels[0].len() as u32;
+    let leaf_records = records.len() as u32;
+    let tree_depth = if leaf_records == 0 {
+        0
+    } else {
+        levels.len() as u16
+    };
+    let root_node = if leaf_records == 0 {
+        0
+    } else {
+        total_nodes as u32 - 1
+    };
+
+    let mut first_node = 1;
+    for (height, level) in levels.iter().enumerate() {
+        let kind = if height == 0 {
+            BTREE_LEAF_NODE
+        } else {
+            BTREE_INDEX_NODE
+        };
+        for (i, node) in level.iter().enumerate() {
+            let number = first_node + i;
+            let f_link = if i + 1 < level.len() {
+                number as u32 + 1
+            } else {
+                0
+            };
+            let b_link = if i > 0 { number as u32 - 1 } else { 0 };
+            write_node(
+                &mut tree[number * node_size..(number + 1) * node_size],
+                f_link,
+                b_link,
+                kind,
+                height as u8 + 1,
+                &node.records,
+            )?;
+        }
+        first_node += level.len();
+    }
+
+    let mut header = vec![];
+    header.write_u16::<BE>(tree_depth)?;
+    header.write_u32::<BE>(root_node)?;
+    header.write_u32::<BE>(leaf_records)?;
+    header.write_u32::<BE>(if leaf_count > 0 { 1 } else { 0 })?;
+    header.write_u32::<BE>(leaf_count)?;
+    header.write_u16::<BE>(node_size as u16)?;
+    header.write_u16::<BE>(max_key_length)?;
+    header.write_u32::<BE>(total_nodes as u32)?;
+    header.write_u32::<BE>(0)?;
+    header.write_u16::<BE>(0)?;
+    header.write_u32::<BE>(node_size as u32)?;
+    header.write_u8(0)?;
+    header.write_u8(key_compare_type)?;
+    header.write_u32::<BE>(attributes)?;
+    header.extend_from_slice(&[0; 64]);
+    let mut map = vec![0u8; node_size - 256];
+    for i in 0..total_nodes {
+        map[i / 8] |= 0x80 >> (i % 8);
+    }
+    write_node(
+        &mut tree[..node_size],
+        0,
+        0,
+        BTREE_HEADER_NODE,
+        0,
+        &[header, vec![0; 128], map],
+    )?;
+
+    Ok(tree)
+}
+
+/// Where the content of a file added to an [HfsBuilder] comes from.
+#[derive(Clone, Debug)]
+enum HfsSource {
+    Data(Vec<u8>),
+    Path(PathBuf),
+}
+
+#[derive(Clone, Debug)]
+enum HfsBuilderEntryKind {
+    Directory,
+    File(HfsSource),
+    Symlink(String),
+}
+
+#[derive(Clone, Debug)]
+struct HfsBuilderEntry {
+    kind: HfsBuilderEntryKind,
+    mode: u16,
+    mtime: i64,
+    finder_flags: u16,
+}
+
+/// Builds an HFS+ volume image in memory.
+///
+/// Paths are `/` separated and relative to the volume root. Missing parent
+/// directories are created automatically.
+#[derive(Clone, Debug)]
+pub struct HfsBuilder {
+    volume_name: String,
+    entries: BTreeMap<Vec<String>, HfsBuilderEntry>,
+    free_space: u64,
+    create_time: i64,
+    clamp_time: Option<i64>,
+    root_finder_flags: u16,
+}
+
+impl HfsBuilder {
+    pub fn new(volume_name: &str) -> Self {
+        Self {
+            volume_name: volume_name.to_string(),
+            entries: BTreeMap::new(),
+            free_space: 1024 * 1024,
+            create_time: now(),
+            clamp_time: None,
+            root_finder_flags: 0,
+        }
+    }
+
+    /// Use the timestamp of `reproducible` as creation time, and clamp newer
+    /// modification times to it.
+    pub fn set_reproducible(&mut self, reproducible: &Reproducible) {
+        self.create_time = reproducible.timestamp;
+        self.clamp_time = Some(reproducible.timestamp);
+    }
+
+    pub fn volume_name(&self) -> &str {
+        &self.volume_name
+    }
+
+    /// Creation time of the volume as a Unix timestamp.
+    ///
+    /// Entries added from memory use it as their modification time.
+    pub fn create_time(&self) -> i64 {
+        self.create_time
+    }
+
+    /// Set the amount of free space to reserve beyond what the content needs.
+    pub fn free_space(&mut self, bytes: u64) {
+        self.free_space = bytes;
+    }
+
+    fn components(path: &str) -> Result<Vec<String>> {
+        let components = path
+            .split('/')
+            .filter(|c| !c.is_empty() && *c != ".")
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        anyhow::ensure!(!components.is_empty(), "empty path");
+        anyhow::ensure!(
+            components.iter().all(|c| c != ".."),
+            "path {} escapes the volume root",
+            path
+        );
+        Ok(components)
+    }
+
+    fn insert(&mut self, path: &str, entry: HfsBuilderEntry) -> Result<()> {
+        let components = Self::components(path)?;
+        for i in 1..components.len() {
+            let parent = &components[..i];
+            match self.entries.get(parent) {
+                Some(HfsBuilderEntry {
+                    kind: HfsBuilderEntryKind::Directory,
+                    ..
+                }) => {}
+                Some(_) => anyhow::bail!("parent of {} is not a directory", path),
+                None => {
+                    self.entries.insert(
+                        parent.to_vec(),
+                        HfsBuilderEntry {
+                            kind: HfsBuilderEntryKind::Directory,
+                            mode: 0o755,
+                            mtime: entry.mtime,
+                            finder_flags: 0,
+                        },
+                    );
+                }
+            }
+        }
+        self.entries.insert(components, entry);
+        Ok(())
+    }
+
+    /// Add a directory with the given permission bits.
+    pub fn add_directory(&mut self, path: &str, mode: u16) -> Result<()> {
+        self.insert(
+            path,
+            HfsBuilderEntry {
+                kind: HfsBuilderEntryKind::Directory,
+                mode: mode & !S_IFMT,
+                mtime: self.create_time,
+                finder_flags: 0,
+            },
+        )
+    }
+
+    /// Add a regular file with in-memory content.
+    pub fn add_file_from_data(&mut self, path: &str, data: Vec<u8>, mode: u16) -> Result<()> {
+        self.insert(
+            path,
+            HfsBuilderEntry {
+                kind: HfsBuilderEntryKind::File(HfsSource::Data(data)),
+                mode: mode & !S_IFMT,
+                mtime: self.create_time,
+                finder_flags: 0,
+            },
+        )
+    }
+
+    /// Add a symlink pointing to `target`.
+    pub fn add_symlink(&mut self, path: &str, target: &str) -> Result<()> {
+        self.insert(
+            path,
+            HfsBuilderEntry {
+                kind: HfsBuilderEntryKind::Symlink(target.to_string()),
+                mode: 0o755,
+                mtime: self.create_time,
+                finder_flags: 0,
+            },
+        )
+    }
+
+    /// Use `icns` as the icon of the volume.
+    ///
+    /// The icon is written to an invisible `.VolumeIcon.icns` file at the root and
+    /// the root folder is flagged with [FINDER_HAS_CUSTOM_ICON].
+    pub fn set_volume_icon(&mut self, icns: Vec<u8>) -> Result<()> {
+        self.insert(
+            VOLUME_ICON_NAME,
+            HfsBuilderEntry {
+                kind: HfsBuilderEntryKind::File(HfsSource::Data(icns)),
+                mode: 0o644,
+                mtime: self.create_time,
+                finder_flags: FINDER_IS_INVISIBLE,
+            },
+        )?;
+        self.root_finder_flags |= FINDER_HAS_CUSTOM_ICON;
+        Ok(())
+    }
+
+    /// Recursively add the content of the filesystem directory `src` under `dest`.
+    ///
+    /// Permission bits, modification times and symlinks are preserved. An empty
+    /// `dest` adds the content to the volume root.
+    pub fn add_dir_from_path(&mut self, src: &Path, dest: &str) -> Result<()> {
+        for entry in std::fs::read_dir(src)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let file_name = file_name
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("file name is not valid UTF-8"))?;
+            let source = src.join(file_name);
+            let path = if dest.is_empty() {
+                file_name.to_string()
+            } else {
+                format!("{}/{}", dest, file_name)
+            };
+            let metadata = std::fs::symlink_metadata(&source)?;
+            let mtime = system_time_to_unix(metadata.modified()?);
+            let mode = permissions(&metadata);
+            let kind = if metadata.is_dir() {
+                HfsBuilderEntryKind::Directory
+            } else if metadata.is_file() {
+                HfsBuilderEntryKind::File(HfsSource::Path(source.clone()))
+            } else if metadata.file_type().is_symlink() {
+                let target = std::fs::read_link(&source)?;
+                let target = target
+                    .to_str()
+                    .ok_or_else(|| anyhow::anyhow!("symlink target is not valid UTF-8"))?;
+                HfsBuilderEntryKind::Symlink(target.to_string())
+            } else {
+                continue;
+            };
+            let is_dir = matches!(kind, HfsBuilderEntryKind::Directory);
+            self.insert(
+                &path,
+                HfsBuilderEntry {
+                    kind,
+                    mode,
+                    mtime,
+                    finder_flags: 0,
+                },
+            )?;
+            if is_dir {
+                self.add_dir_from_path(&source, &path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Catalog node ids are assigned in path order when building.
+    fn catalog_ids(&self) -> HashMap<Vec<String>, u32> {
+        let mut ids = HashMap::new();
+        ids.insert(vec![], ROOT_FOLDER_ID);
+        for (i, path) in self.entries.keys().enumerate() {
+            ids.insert(path.clone(), FIRST_USER_CATALOG_NODE_ID + i as u32);
+        }
+        ids
+    }
+
+    /// The catalog node id `path` will have in the built volume.
+    ///
+    /// Ids change when entries are added or removed.
+    pub fn catalog_id(&self, path: &str) -> Option<u32> {
+        self.catalog_ids()
+            .get(&Self::components(path).ok()?)
+            .copied()
+    }
+
+    /// Write the volume image.
+    pub fn build(&self) -> Result<Vec<u8>> {
+        let now = hfs_time(self.create_time);
+        let block_size = BLOCK_SIZE as u64;
+        let blocks = |len: u64| len.div_ceil(block_size);
+
+        let ids = self.catalog_ids();
+        let next_id = FIRST_USER_CATALOG_NODE_ID + self.entries.len() as u32;
+
+        let mut valence = HashMap::<u32, u32>::new();
+        for path in self.entries.keys() {
+            *valence.entry(ids[&path[..path.len() - 1]]).or_default() += 1;
+        }
+
+        // Content sizes for regular files and symlinks.
+        let mut sizes = BTreeMap::new();
+        for (path, entry) in &self.entries {
+            let size = match &entry.kind {
+                HfsBuilderEntryKind::Directory => continue,
+                HfsBuilderEntryKind::File(HfsSource::Data(data)) => data.len() as u64,
+                HfsBuilderEntryKind::File(HfsSource::Path(path)) => std::fs::metadata(path)?.len(),
+                HfsBuilderEntryKind::Symlink(target) => target.len() as u64,
+            };
+            sizes.insert(path.clone(), size);
+        }
+
+        let extents_tree = build_btree(&[], EXTENTS_NODE_SIZE, 10, BTREE_BIG_KEYS, 0)?;
+
+        // The catalog content depends on where file data is placed, but its size
+        // does not. Lay out the volume with a placeholder catalog first.
+        let catalog_records =
+            |starts: &BTreeMap<Vec<String>, u32>| -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+                let mut records = vec![];
+                let volume_name = encode_name(&self.volume_name)?;
+
+                let mut root = vec![];
+                write_folder_record(
+                    &mut root,
+                    ROOT_FOLDER_ID,
+                    valence.get(&ROOT_FOLDER_ID).copied().unwrap_or_default(),
+                    now,
+                    0o755,
+                    self.root_finder_flags,
+                )?;
+                records.push((write_catalog_key(ROOT_PARENT_ID, &volume_name)?, root));
+                records.push((
+                    write_catalog_key(ROOT_FOLDER_ID, &[])?,
+                    write_thread_record(FOLDER_THREAD_RECORD, ROOT_PARENT_ID, &volume_name)?,
+                ));
+
+                for (path, entry) in &self.entries {
+                    let id = ids[path];
+                    let parent_id = ids[&path[..path.len() - 1]];
+                    let name = encode_name(path.last().unwrap())?;
+                    let mtime = match self.clamp_time {
+                        Some(clamp) => hfs_time(entry.mtime.min(clamp)),
+                        None => hfs_time(entry.mtime),
+                    };
+                    let mut record = vec![];
+                    let thread_type = match &entry.kind {
+                        HfsBuilderEntryKind::Directory => {
+                            write_folder_record(
+                                &mut record,
+                                id,
+                                valence.get(&id).copied().unwrap_or_default(),
+                                mtime,
+                                entry.mode,
+                                entry.finder_flags,
+                            )?;
+                            FOLDER_THREAD_RECORD
+                        }
+                        kind => {
+                            let size = sizes[path];
+                            let fork =
+                                HfsForkData::contiguous(starts[path], blocks(size) as u32, size);
+                            let symlink = matches!(kind, HfsBuilderEntryKind::Symlink(_));
+                            write_file_record(
+                                &mut record,
+                                id,
+                                mtime,
+                                entry.mode,
+                                entry.finder_flags,
+                                symlink,
+                                &fork,
+                            )?;
+                            FILE_THREAD_RECORD
+                        }
+                    };
+                    records.push((write_catalog_key(parent_id, &name)?, record));
+                    records.push((
+                        write_catalog_key(id, &[])?,
+                        write_thread_record(thread_type, parent_id, &name)?,
+                    ));
+                }
+
+                records.sort_by(|(a, _), (b, _)| {
+                    let a = read_catalog_key(a).unwrap();
+                    let b = read_catalog_key(b).unwrap();
+                    a.cmp(&b)
+                });
+                Ok(records)
+            };
+
+        let placeholder = sizes.keys().map(|path| (path.clone(), 0)).collect();
+        let catalog_len = build_btree(
+            &catalog_records(&placeholder)?,
+            CATALOG_NODE_SIZE,
+            516,
+            BTREE_BIG_KEYS | BTREE_VARIABLE_INDEX_KEYS,
+            BTREE_BINARY_COMPARE,
+        )?
+        .len() as u64;
+
+        let data_blocks = sizes.values().map(|size| blocks(*size)).sum::<u64>();
+        let used_blocks = 1
+            + blocks(extents_tree.len() as u64)
+            + blocks(catalog_len)
+            + data_blocks
+            + blocks(self.free_space)
+            // alternate volume header
+            + 1;
+
+        // The allocation bitmap needs to cover itself.
+        let mut bitmap_blocks = 1;
+        while bitmap_blocks * block_size * 8 < used_blocks + bitmap_blocks {
+            bitmap_blocks += 1;
+        }
+        let total_blocks = used_blocks + bitmap_blocks;
+        anyhow::ensure!(total_blocks <= u32::MAX as u64, "volume too large");
+
+        let mut next_block = 1;
+        let mut allocate = |count: u64| {
+            let start = next_block;
+            next_block += count;
+            start as u32
+        };
+        let allocation_start = allocate(bitmap_blocks);
+        let extents_start = allocate(blocks(extents_tree.len() as u64));
+        let catalog_start = allocate(blocks(catalog_len));
+        let mut starts = BTreeMap::new();
+        for (path, size) in &sizes {
+            starts.insert(path.clone(), allocate(blocks(*size)));
+        }
+        let allocated = next_block;
+
+        let catalog_tree = build_btree(
+            &catalog_records(&starts)?,
+            CATALOG_NODE_SIZE,
+            516,
+            BTREE_BIG_KEYS | BTREE_VARIABLE_INDEX_KEYS,
+            BTREE_BINARY_COMPARE,
+        )?;
+
+        let mut volume = vec![0; (total_blocks * block_size) as usize];
+        let mut write_at = |block: u32, data: &[u8]| {
+            let offset = block as usize * BLOCK_SIZE as usize;
+            volume[offset..offset + data.len()].copy_from_slice(data);
+        };
+        write_at(extents_start, &extents_tree);
+        write_at(catalog_start, &catalog_tree);
+        for (path, entry) in &self.entries {
+            match &entry.kind {
+                HfsBuilderEntryKind::Directory => {}
+                HfsBuilderEntryKind::File(HfsSource::Data(data)) => write_at(starts[path], data),
+                HfsBuilderEntryKind::File(HfsSource::Path(source)) => {
+                    // The extent was allocated for the size seen during layout.
+                    let data = std::fs::read(source)?;
+                    anyhow::ensure!(
+                        data.len() as u64 == sizes[path],
+                        "{} changed size while building the volume",
+                        source.display()
+                    );
+                    write_at(starts[path], &data)
+                }
+                HfsBuilderEntryKind::Symlink(target) => write_at(starts[path], target.as_bytes()),
+            }
+        }
+
+        let mut bitmap = vec![0u8; (bitmap_blocks * block_size) as usize];
+        let mut mark = |block: u64| bitmap[block as usize / 8] |= 0x80 >> (block % 8);
+        (0..allocated).for_each(&mut mark);
+        mark(total_blocks - 1);
+        write_at(allocation_start, &bitmap);
+
+        let file_count = self
+            .entries
+            .values()
+            .filter(|e| !matches!(e.kind, HfsBuilderEntryKind::Directory))
+            .count() as u32;
+        let header = HfsVolumeHeader {
+            create_date: now,
+            modify_date: now,
+            checked_date: now,
+            file_count,
+            folder_count: self.entries.len() as u32 - file_count,
+            total_blocks: total_blocks as u32,
+            free_blocks: (total_blocks - allocated - 1) as u32,
+            next_allocation: allocated as u32,
+            next_catalog_id: next_id,
+            allocation_file: HfsForkData::contiguous(
+                allocation_start,
+                bitmap_blocks as u32,
+                bitmap_blocks * block_size,
+            ),
+            extents_file: HfsForkData {
+                clump_size: EXTENTS_NODE_SIZE as u32,
+                ..HfsForkData::contiguous(
+                    extents_start,
+                    blocks(extents_tree.len() as u64) as u32,
+                    extents_tree.len() as u64,
+                )
+            },
+            catalog_file: HfsForkData {
+                clump_size: CATALOG_NODE_SIZE as u32,
+                ..HfsForkData::contiguous(
+                    catalog_start,
+                    blocks(catalog_tree.len() as u64) as u32,
+                    catalog_tree.len() as u64,
+                )
+            },
+            ..Default::default()
+        };
+        let mut header_bytes = vec![];
+        header.write_to(&mut header_bytes)?;
+        let offset = VOLUME_HEADER_OFFSET as usize;
+        volume[offset..offset + header_bytes.len()].copy_from_slice(&header_bytes);
+        let offset = volume.len() - VOLUME_HEADER_OFFSET as usize;
+        volume[offset..offset + header_bytes.len()].copy_from_slice(&header_bytes);
+
+        Ok(volume)
+    }
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &std::fs::Metadata) -> u16 {
+    use std::os::unix::fs::PermissionsExt;
+    (metadata.permissions().mode() as u16) & !S_IFMT
+}
+
+#[cfg(not(unix))]
+fn permissions(metadata: &std::fs::Metadata) -> u16 {
+    if metadata.is_dir() {
+        0o755
+    } else if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+fn write_folder_record(
+    w: &mut Vec<u8>,
+    id: u32,
+    valence: u32,
+    date: u32,
+    mode: u16,
+    finder_flags: u16,
+) -> Result<()> {
+    w.write_i16::<BE>(FOLDER_RECORD)?;
+    w.write_u16::<BE>(0)?;
+    w.write_u32::<BE>(valence)?;
+    w.write_u32::<BE>(id)?;
+    for _ in 0..4 {
+        w.write_u32::<BE>(date)?;
+    }
+    // backup date
+    w.write_u32::<BE>(0)?;
+    BsdInfo {
+        owner_id: UNKNOWN_ID,
+        group_id: UNKNOWN_ID,
+        file_mode: S_IFDIR | mode,
+        ..Default::default()
+    }
+    .write_to(w)?;
+    // window bounds
+    w.extend_from_slice(&[0; 8]);
+    w.write_u16::<BE>(finder_flags)?;
+    // location, reserved, finder info, text encoding and reserved
+    w.extend_from_slice(&[0; 30]);
+    Ok(())
+}
+
+fn write_file_record(
+    w: &mut Vec<u8>,
+    id: u32,
+    date: u32,
+    mode: u16,
+    finder_flags: u16,
+    symlink: bool,
+    data_fork: &HfsForkData,
+) -> Result<()> {
+    w.write_i16::<BE>(FILE_RECORD)?;
+    w.write_u16::<BE>(FILE_THREAD_EXISTS)?;
+    w.write_u32::<BE>(0)?;
+    w.write_u32::<BE>(id)?;
+    for _ in 0..4 {
+        w.write_u32::<BE>(date)?;
+    }
+    w.write_u32::<BE>(0)?;
+    let (file_mode, file_type, creator) = if symlink {
+        (S_IFLNK | mode, SYMLINK_FILE_TYPE, SYMLINK_CREATOR)
+    } else {
+        (S_IFREG | mode, 0, 0)
+    };
+    BsdInfo {
+        owner_id: UNKNOWN_ID,
+        group_id: UNKNOWN_ID,
+        file_mode,
+        special: 1,
+        ..Default::default()
+    }
+    .write_to(w)?;
+    w.write_u32::<BE>(file_type)?;
+    w.write_u32::<BE>(creator)?;
+    w.write_u16::<BE>(finder_flags)?;
+    // location, reserved, extended finder info, text encoding and reserved
+    w.extend_from_slice(&[0; 30]);
+    data_fork.write_to(w)?;
+    HfsForkData::default().write_to(w)?;
+    Ok(())
+}
+
+/// Kind of an entry in an HFS+ volume.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HfsEntryKind {
+    Directory,
+    File,
+    Symlink,
+}
+
+/// A file, directory or symlink in an HFS+ volume.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HfsEntry {
+    /// `/` separated path relative to the volume root.
+    pub path: String,
+    pub kind: HfsEntryKind,
+    /// Catalog node id. Hard links have the id of their target.
+    pub id: u32,
+    /// Permission bits, without the file type.
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    /// Modification time as a Unix timestamp.
+    pub mtime: i64,
+    /// Size of the data fork.
+    pub size: u64,
+    pub data_fork: HfsForkData,
+    /// Finder flags, like [FINDER_HAS_CUSTOM_ICON] and [FINDER_IS_INVISIBLE].
+    pub finder_flags: u16,
+    /// Whether the content is compressed by decmpfs and stored in the
+    /// attributes file, which isn't supported.
+    pub compressed: bool,
+}
+
+/// Reads HFS+ and HFSX volumes.
+pub struct HfsReader<R: Read + Seek> {
+    r: R,
+    header: HfsVolumeHeader,
+    overflow_extents: HashMap<u32, Vec<HfsExtentDescriptor>>,
+    volume_name: String,
+    root_finder_flags: u16,
+    entries: Vec<HfsEntry>,
+}
+
+impl<R: Read + Seek> HfsReader<R> {
+    pub fn new(mut r: R) -> Result<Self> {
+        let header = HfsVolumeHeader::read_from(&mut r)?;
+        let mut reader = Self {
+            r,
+            header,
+            overflow_extents: HashMap::new(),
+            volume_name: String::new(),
+            root_finder_flags: 0,
+            entries: vec![],
+        };
+
+        let extents_file = header.extents_file;
+        let extents = reader.read_fork(EXTENTS_FILE_ID, &extents_file)?;
+        for (key, data) in btree_leaf_records(&extents)? {
+            let mut key = &key[2..];
+            let mut data = &data[..];
+            let fork_type = key.read_u8()?;
+            let _pad = key.read_u8()?;
+            let file_id = key.read_u32::<BE>()?;
+            // Only data forks are of interest.
+            if fork_type != 0 {
+                continue;
+            }
+            let list = reader.overflow_extents.entry(file_id).or_default();
+            for _ in 0..8 {
+                let extent = HfsExtentDescriptor::read_from(&mut data)?;
+                if extent.block_count > 0 {
+                    list.push(extent);
+                }
+            }
+        }
+
+        let catalog_file = header.catalog_file;
+        let catalog = reader.read_fork(CATALOG_FILE_ID, &catalog_file)?;
+        reader.read_catalog(&catalog)?;
+        Ok(reader)
+    }
+
+    pub fn header(&self) -> &HfsVolumeHeader {
+        &self.header
+    }
+
+    pub fn volume_name(&self) -> &str {
+        &self.volume_name
+    }
+
+    /// Finder flags of the root folder.
+    ///
+    /// [FINDER_HAS_CUSTOM_ICON] is set for volumes with a `.VolumeIcon.icns` icon.
+    pub fn root_finder_flags(&self) -> u16 {
+        self.root_finder_flags
+    }
+
+    /// All entries in the volume in catalog order.
+    pub fn entries(&self) -> &[HfsEntry] {
+        &self.entries
+    }
+
+    pub fn entry(&self, path: &str) -> Option<&HfsEntry> {
+        let path = path.trim_matches('/');
+        self.entries.iter().find(|e| e.path == path)
+    }
+
+    fn read_catalog(&mut self, catalog: &[u8]) -> Result<()> {
+        // cnid -> (parent cnid, name, entry without path)
+        let mut nodes = HashMap::new();
+        // (link cnid, inode number)
+        let mut links = vec![];
+        for (key, data) in btree_leaf_records(catalog)? {
+            let (parent_id, name) = read_catalog_key(&key)?;
+            let mut data = &data[..];
+            let record_type = data.read_i16::<BE>()?;
+            let (kind, id, bsd, file_type, creator, finder_flags, data_fork, mtime) =
+                match record_type {
+                    FOLDER_RECORD => {
+                        let _flags = data.read_u16::<BE>()?;
+                        let _valence = data.read_u32::<BE>()?;
+                        let id = data.read_u32::<BE>()?;
+                        let _create_date = data.read_u32::<BE>()?;
+                        let mtime = data.read_u32::<BE>()?;
+                        let mut dates = [0; 3];
+                        data.read_u32_into::<BE>(&mut dates)?;
+                        let bsd = BsdInfo::read_from(&mut data)?;
+                        let mut window_bounds = [0; 8];
+                        data.read_exact(&mut window_bounds)?;
+                        let finder_flags = data.read_u16::<BE>()?;
+                        (
+                            HfsEntryKind::Directory,
+                            id,
+                            bsd,
+                            0,
+                            0,
+                            finder_flags,
+                            HfsForkData::default(),
+                            mtime,
+                        )
+                    }
+                    FILE_RECORD => {
+                        let _flags = data.read_u16::<BE>()?;
+                        let _reserved = data.read_u32::<BE>()?;
+                        let id = data.read_u32::<BE>()?;
+                        let _create_date = data.read_u32::<BE>()?;
+                        let mtime = data.read_u32::<BE>()?;
+                        let mut dates = [0; 3];
+                        data.read_u32_into::<BE>(&mut dates)?;
+                        let bsd = BsdInfo::read_from(&mut data)?;
+                        let file_type = data.read_u32::<BE>()?;
+                        let creator = data.read_u32::<BE>()?;
+                        let finder_flags = data.read_u16::<BE>()?;
+                        let mut rest = [0; 30];
+                        data.read_exact(&mut rest)?;
+                        let data_fork = HfsForkData::read_from(&mut data)?;
+                        (
+                            HfsEntryKind::File,
+                            id,
+                            bsd,
+                            file_type,
+                            creator,
+                            finder_flags,
+                            data_fork,
+                            mtime,
+                        )
+                    }
+                    // Threads only map ids back to names, which the file and folder
+                    // records already provide.
+                    FOLDER_THREAD_RECORD | FILE_THREAD_RECORD => continue,
+                    _ => anyhow::bail!("unknown catalog record type {}", record_type),
+                };
+            anyhow::ensure!(
+                (file_type, creator) != (DIRECTORY_LINK_FILE_TYPE, DIRECTORY_LINK_CREATOR),
+                "directory hard links are not supported"
+            );
+            if (file_type, creator) == (HARD_LINK_FILE_TYPE, HARD_LINK_CREATOR) {
+                // The target is the file named after the inode number in the
+                // private folder.
+                links.push((id, bsd.special));
+            }
+            let kind = if kind == HfsEntryKind::File
+                && (bsd.file_mode & S_IFMT == S_IFLNK || file_type == SYMLINK_FILE_TYPE)
+            {
+                HfsEntryKind::Symlink
+            } else {
+                kind
+            };
+            let mode = if bsd.file_mode == 0 {
+                // Volumes written by classic Mac OS have no BSD info.
+                if kind == HfsEntryKind::Directory {
+                    0o755
+                } else {
+                    0o644
+                }
+            } else {
+                bsd.file_mode & !S_IFMT
+            };
+            let entry = HfsEntry {
+                path: String::new(),
+                kind,
+                id,
+                mode,
+                uid: bsd.owner_id,
+                gid: bsd.group_id,
+                mtime: unix_time(mtime),
+                size: data_fork.logical_size,
+                data_fork,
+                finder_flags,
+                compressed: bsd.owner_flags & UF_COMPRESSED != 0,
+            };
+            nodes.insert(id, (parent_id, decode_name(&name), entry));
+        }
+
+        // Hard links take the content, metadata and id of their target.
+        let private_folder = nodes
+            .iter()
+            .find(|(_, (parent, name, _))| {
+                *parent == ROOT_FOLDER_ID && name.as_str() == PRIVATE_FOLDER_NAMES[0]
+            })
+            .map(|(id, _)| *id);
+        for (link, inode) in links {
+            let inode_name = format!("iNode{inode}");
+            let target = nodes
+                .values()
+                .find(|(parent, name, _)| Some(*parent) == private_folder && *name == inode_name)
+                .map(|(_, _, entry)| entry.clone())
+                .ok_or_else(|| anyhow::anyhow!("hard link target {} not found", inode_name))?;
+            let (_, _, entry) = nodes.get_mut(&link).expect("link is a catalog node");
+            *entry = target;
+        }
+
+        let root = nodes
+            .get(&ROOT_FOLDER_ID)
+            .ok_or_else(|| anyhow::anyhow!("catalog has no root folder"))?;
+        self.volume_name = root.1.clone();
+        self.root_finder_flags = root.2.finder_flags;
+
+        let mut entries = vec![];
+        for (id, (_, _, entry)) in &nodes {
+            if *id == ROOT_FOLDER_ID {
+                continue;
+            }
+            let mut components = vec![];
+            let mut current = *id;
+            let mut hidden = false;
+            while current != ROOT_FOLDER_ID {
+                let (parent, name, _) = nodes
+                    .get(&current)
+                    .ok_or_else(|| anyhow::anyhow!("catalog node {} has no parent", current))?;
+                anyhow::ensure!(components.len() < nodes.len(), "catalog contains a cycle");
+                hidden |=
+                    *parent == ROOT_FOLDER_ID && PRIVATE_FOLDER_NAMES.contains(&name.as_str());
+                components.push(name.clone());
+                current = *parent;
+            }
+            if hidden {
+                continue;
+            }
+            components.reverse();
+            let mut entry = entry.clone();
+            entry.path = components.join("/");
+            entries.push(entry);
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        self.entries = entries;
+        Ok(())
+    }
+
+    fn read_fork(&mut self, file_id: u32, fork: &HfsForkData) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        self.write_fork(file_id, fork, &mut data)?;
+        Ok(data)
+    }
+
+    fn write_fork(&mut self, file_id: u32, fork: &HfsForkData, w: &mut impl Write) -> Result<()> {
+        let block_size = self.header.block_size as u64;
+        let overflow = self
+            .overflow_extents
+            .get(&file_id)
+            .cloned()
+            .unwrap_or_default();
+        let mut remaining = fork.logical_size;
+        for extent in fork.extents.iter().chain(overflow.iter()) {
+            if remaining == 0 {
+                break;
+            }
+            if extent.block_count == 0 {
+                continue;
+            }
+            let len = std::cmp::min(remaining, extent.block_count as u64 * block_size);
+            self.r
+                .seek(SeekFrom::Start(extent.start_block as u64 * block_size))?;
+            let copied = std::io::copy(&mut (&mut self.r).take(len), w)?;
+            anyhow::ensure!(copied == len, "fork extends past the end of the volume");
+            remaining -= len;
+        }
+        anyhow::ensure!(
+            remaining == 0,
+            "fork of catalog node {} is truncated",
+            file_id
+        );
+        Ok(())
+    }
+
+    /// Read the data fork of a file or the target of a symlink.
+    ///
+    /// Hard links read as their target. Files compressed by decmpfs are rejected.
+    pub fn read_file(&mut self, entry: &HfsEntry) -> Result<Vec<u8>> {
+        anyhow::ensure!(
+            entry.kind != HfsEntryKind::Directory,
+            "{} is a directory",
+            entry.path
+        );
+        anyhow::ensure!(
+            !entry.compressed,
+            "{} is compressed by decmpfs, which isn't supported",
+            entry.path
+        );
+        self.read_fork(entry.id, &entry.data_fork)
+    }
+
+    /// Extract the volume content into the directory `dest`.
+    ///
+    /// Permission bits and symlinks are restored on Unix. Hard links are
+    /// extracted as copies of their target and files compressed by decmpfs are
+    /// rejected.
+    pub fn extract(&mut self, dest: &Path) -> Result<()> {
+        std::fs::create_dir_all(dest)?;
+        let entries = self.entries.clone();
+        let mut directories = vec![];
+        for entry in &entries {
+            let path = safe_destination(dest, &entry.path)?;
+            match entry.kind {
+                HfsEntryKind::Directory => {
+                    if let Err(e) = std::fs::create_dir(&path) {
+                        // Only reuse real directories, never symlinks to them.
+                        anyhow::ensure!(
+                            e.kind() == std::io::ErrorKind::AlreadyExists
+                                && std::fs::symlink_metadata(&path)?.is_dir(),
+                            "cannot create directory {}: {}",
+                            path.display(),
+                            e
+                        );
+                    }
+                    directories.push((path, entry.mode));
+                    continue;
+                }
+                HfsEntryKind::File => {
+                    anyhow::ensure!(
+                        !entry.compressed,
+                        "{} is compressed by decmpfs, which isn't supported",
+                        entry.path
+                    );
+                    let mut f = std::fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&path)?;
+                    self.write_fork(entry.id, &entry.data_fork, &mut f)?;
+                }
+                HfsEntryKind::Symlink => {
+                    let target = String::from_utf8(self.read_file(entry)?)?;
+                    create_symlink(&target, &path)?;
+                    continue;
+                }
+            }
+            set_permissions(&path, entry.mode)?;
+        }
+        // Restore directory modes last so read-only directories can be populated.
+        for (path, mode) in directories.iter().rev() {
+            set_permissions(path, *mode)?;
+        }
+        Ok(())
+    }
+}
+
+/// Resolve where a volume entry is extracted to below `dest`.
+///
+/// Names come from the catalog of an untrusted image. Components must not be
+/// empty, `.` or `..` or contain `\\` or NUL, and parents must be directories
+/// rather than symlinks.
+fn safe_destination(dest: &Path, path: &str) -> Result<PathBuf> {
+    let components = path.split('/').collect::<Vec<_>>();
+    let mut destination = dest.to_path_buf();
+    for (i, component) in components.iter().enumerate() {
+        anyhow::ensure!(
+            !matches!(*component, "" | "." | "..") && !component.contains(['\\', '\0']),
+            "volume entry {:?} escapes the destination",
+            path
+        );
+        destination.push(component);
+        if i + 1 < components.len() {
+            anyhow::ensure!(
+                std::fs::symlink_metadata(&destination)?.is_dir(),
+                "parent of volume entry {:?} is not a directory",
+                path
+            );
+        }
+    }
+    Ok(destination)
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, path)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &str, _path: &Path) -> Result<()> {
+    anyhow::bail!("extracting symlinks is only supported on Unix")
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: u16) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode as u32))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_path: &Path, _mode: u16) -> Result<()> {
+    Ok(())
+}
+
+/// All records stored in the leaf nodes of a B-tree, in key order.
+fn btree_leaf_records(tree: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    anyhow::ensure!(tree.len() >= 106 + 14, "B-tree header node is truncated");
+    let mut header = &tree[14..];
+    let _depth = header.read_u16::<BE>()?;
+    let _root = header.read_u32::<BE>()?;
+    let _leaf_records = header.read_u32::<BE>()?;
+    let first_leaf = header.read_u32::<BE>()?;
+    let _last_leaf = header.read_u32::<BE>()?;
+    let node_size = header.read_u16::<BE>()? as usize;
+    anyhow::ensure!(node_size >= 512, "invalid B-tree node size");
+
+    let mut records = vec![];
+    let mut node_number = first_leaf as usize;
+    let mut visited = 0;
+    while node_number != 0 {
+        visited += 1;
+        anyhow::ensure!(
+            visited <= tree.len() / node_size,
+            "B-tree leaf chain contains a cycle"
+        );
+        let node = tree
+            .get(node_number * node_size..(node_number + 1) * node_size)
+            .ok_or_else(|| anyhow::anyhow!("B-tree node {} out of bounds", node_number))?;
+        let mut descriptor = node;
+        let f_link = descriptor.read_u32::<BE>()?;
+        let _b_link = descriptor.read_u32::<BE>()?;
+        let kind = descriptor.read_i8()?;
+        anyhow::ensure!(
+            kind == BTREE_LEAF_NODE,
+            "B-tree node {} is not a leaf",
+            node_number
+        );
+        let _height = descriptor.read_u8()?;
+        let num_records = descriptor.read_u16::<BE>()? as usize;
+        // The descriptor is followed by records, the offsets of which are
+        // stored at the end of the node, plus the offset of the free space.
+        anyhow::ensure!(
+            14 + 2 * (num_records + 1) <= node_size,
+            "B-tree node {} has too many records",
+            node_number
+        );
+        let offset = |i: usize| {
+            let pos = node_size - 2 * (i + 1);
+            u16::from_be_bytes([node[pos], node[pos + 1]]) as usize
+        };
+        for i in 0..num_records {
+            let (start, end) = (offset(i), offset(i + 1));
+            anyhow::ensure!(
+                start + 2 <= end && end <= node_size,
+                "invalid B-tree record offset"
+            );
+            let record = &node[start..end];
+            let key_length = u16::from_be_bytes([record[0], record[1]]) as usize;
+            // Records are 2 byte aligned.
+            let data_start = (2 + key_length + 1) & !1;
+            anyhow::ensure!(data_start <= record.len(), "B-tree key exceeds its record");
+            records.push((
+                record[..2 + key_length].to_vec(),
+                record[data_start..].to_vec(),
+            ));
+        }
+        node_number = f_link as usize;
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::io::Cursor};
+
+    #[test]
+    fn build_and_read_volume() -> Result<()> {
+        let mut builder = HfsBuilder::new("Test");
+        builder.add_directory("Test.app/Contents/MacOS", 0o755)?;
+        builder.add_file_from_data(
+            "Test.app/Contents/MacOS/test",
+            b"#!/bin/sh\n".to_vec(),
+            0o755,
+        )?;
+        builder.add_file_from_data("Test.app/Contents/Info.plist", vec![b'x'; 10_000], 0o644)?;
+        builder.add_symlink("Applications", "/Applications")?;
+        builder.add_file_from_data("a:b", vec![], 0o600)?;
+        for i in 0..1000 {
+            builder.add_file_from_data(&format!("many/file-{i}"), vec![i as u8; 10], 0o644)?;
+        }
+        let volume = builder.build()?;
+
+        let mut reader = HfsReader::new(Cursor::new(volume))?;
+        assert_eq!(reader.volume_name(), "Test");
+        assert_eq!(reader.header().file_count, 1004);
+        assert_eq!(reader.header().folder_count, 4);
+        assert_eq!(reader.entries().len(), 1008);
+
+        let exe = reader
+            .entry("Test.app/Contents/MacOS/test")
+            .unwrap()
+            .clone();
+        assert_eq!(exe.kind, HfsEntryKind::File);
+        assert_eq!(exe.mode, 0o755);
+        assert_eq!(reader.read_file(&exe)?, b"#!/bin/sh\n");
+
+        let plist = reader
+            .entry("Test.app/Contents/Info.plist")
+            .unwrap()
+            .clone();
+        assert_eq!(reader.read_file(&plist)?, vec![b'x'; 10_000]);
+
+        let link = reader.entry("Applications").unwrap().clone();
+        assert_eq!(link.kind, HfsEntryKind::Symlink);
+        assert_eq!(reader.read_file(&link)?, b"/Applications");
+
+        assert_eq!(reader.entry("a:b").unwrap().mode, 0o600);
+        let file = reader.entry("many/file-999").unwrap().clone();
+        assert_eq!(reader.read_file(&file)?, vec![(999 % 256) as u8; 10]);
+
+        Ok(())
+    }
+
+    /// Replace all UTF-16 encodings of a name in a volume.
+    fn rename(volume: &mut [u8], from: &str, to: &str) {
+        let encode = |name: &str| {
+            name.encode_utf16()
+                .flat_map(|c| c.to_be_bytes())
+                .collect::<Vec<_>>()
+        };
+        let (from, to) = (encode(from), encode(to));
+        for i in 0..volume.len() - from.len() {
+            if volume[i..i + from.len()] == from[..] {
+                volume[i..i + to.len()].copy_from_slice(&to);
+            }
+        }
+    }
+
+    #[test]
+    fn volume_icon() -> Result<()> {
+        let mut builder = HfsBuilder::new("Test");
+        builder.add_file_from_data("file", b"data".to_vec(), 0o644)?;
+        builder.set_volume_icon(b"icns".to_vec())?;
+        let mut reader = HfsReader::new(Cursor::new(builder.build()?))?;
+
+        assert_eq!(reader.root_finder_flags(), FINDER_HAS_CUSTOM_ICON);
+        let icon = reader.entry(".VolumeIcon.icns").unwrap().clone();
+        assert_eq!(icon.finder_flags, FINDER_IS_INVISIBLE);
+        assert_eq!(reader.read_file(&icon)?, b"icns");
+        assert_eq!(reader.entry("file").unwrap().finder_flags, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn extract_rejects_escapes() -> Result<()> {
+        let tmp = std::env::temp_dir().join(format!("apple-dmg-extract-{}", std::process::id()));
+        let outside = tmp.join("outside");
+        std::fs::create_dir_all(&outside)?;
+
+        // A `..` directory.
+        let mut builder = HfsBuilder::new("Test");
+        builder.add_file_from_data("qq/escape", b"data".to_vec(), 0o644)?;
+        let mut volume = builder.build()?;
+        rename(&mut volume, "qq", "..");
+        let mut reader = HfsReader::new(Cursor::new(volume))?;
+        assert_eq!(reader.entries()[1].path, "../escape");
+        assert!(reader.extract(&tmp.join("dest")).is_err());
+        assert!(!tmp.join("escape").exists());
+
+        // A directory with the name of a symlink pointing outside.
+        let mut builder = HfsBuilder::new("Test");
+        builder.add_symlink("link", outside.to_str().unwrap())?;
+        builder.add_file_from_data("linq/file", b"data".to_vec(), 0o644)?;
+        let mut volume = builder.build()?;
+        rename(&mut volume, "linq", "link");
+        let mut reader = HfsReader::new(Cursor::new(volume))?;
+        assert!(reader.extract(&tmp.join("dest2")).is_err());
+        assert_eq!(std::fs::read_dir(&outside)?.count(), 0);
+
+        // Files are never written through existing paths.
+        let mut builder = HfsBuilder::new("Test");
+        builder.add_file_from_data("file", b"data".to_vec(), 0o644)?;
+        let mut reader = HfsReader::new(Cursor::new(builder.build()?))?;
+        let dest = tmp.join("dest3");
+        std::fs::create_dir(&dest)?;
+        std::fs::write(dest.join("file"), b"existing")?;
+        assert!(reader.extract(&dest).is_err());
+
+        for path in ["a/../b", "a//b", "a/\0", "."] {
+            assert!(safe_destination(&tmp, path).is_err(), "{path}");
+        }
+
+        std::fs::remove_dir_all(&tmp)?;
+        Ok(())
+    }
+
+    /// Turn the record of the symlink `target` into a regular file with the
+    /// given Finder type, creator and BSD info.
+    fn patch_symlink(volume: &mut [u8], file_type: &[u8; 4], owner_flags: u8, special: u32) {
+        let pos = volume
+            .windows(8)
+            .position(|w| w == b"slnkrhap")
+            .expect("volume has a symlink");
+        // BSD info is owner, group, admin and owner flags, mode and special.
+        volume[pos - 7] = owner_flags;
+        volume[pos - 6..pos - 4].copy_from_slice(&(S_IFREG | 0o644).to_be_bytes());
+        volume[pos - 4..pos].copy_from_slice(&special.to_be_bytes());
+        volume[pos..pos + 4].copy_from_slice(file_type);
+        volume[pos + 4..pos + 8].copy_from_slice(b"hfs+");
+    }
+
+    #[test]
+    fn hard_links_and_compressed_files() -> Result<()> {
+        let tmp = std::env::temp_dir().join(format!("apple-dmg-links-{}", std::process::id()));
+
+        let mut builder = HfsBuilder::new("Test");
+        builder.add_file_from_data("qqqqHFS+ Private Data/iNode42", b"data".to_vec(), 0o600)?;
+        builder.add_symlink("link", "target")?;
+        let mut volume = builder.build()?;
+        rename(&mut volume, "qqqq", "\0\0\0\0");
+        patch_symlink(&mut volume, b"hlnk", 0, 42);
+        let mut reader = HfsReader::new(Cursor::new(volume))?;
+        assert_eq!(reader.entries().len(), 1);
+        let link = reader.entry("link").unwrap().clone();
+        assert_eq!(link.kind, HfsEntryKind::File);
+        assert_eq!(link.mode, 0o600);
+        assert_eq!(reader.read_file(&link)?, b"data");
+        reader.extract(&tmp.join("links"))?;
+        assert_eq!(std::fs::read(tmp.join("links/link"))?, b"data");
+
+        // A link without target.
+        let mut builder = HfsBuilder::new("Test");
+        builder.add_symlink("link", "target")?;
+        let mut volume = builder.build()?;
+        patch_symlink(&mut volume, b"hlnk", 0, 42);
+        assert!(HfsReader::new(Cursor::new(volume)).is_err());
+
+        let mut builder = HfsBuilder::new("Test");
+        builder.add_symlink("file", "target")?;
+        let mut volume = builder.build()?;
+        patch_symlink(&mut volume, b"\0\0\0\0", UF_COMPRESSED, 0);
+        let mut reader = HfsReader::new(Cursor::new(volume))?;
+        let file = reader.entry("file").unwrap().clone();
+        assert!(file.compressed);
+        assert!(reader.read_file(&file).is_err());
+        assert!(reader.extract(&tmp.join("compressed")).is_err());
+        assert!(!tmp.join("compressed/file").exists());
+
+        std::fs::remove_dir_all(&tmp)?;
+        Ok(())
+    }
+
+    #[test]
+    fn btree_node_with_too_many_records() -> Result<()> {
+        let records = vec![(write_catalog_key(1, &[])?, vec![0; 4])];
+        let mut tree = build_btree(&records, 512, 516, BTREE_BIG_KEYS, BTREE_BINARY_COMPARE)?;
+        // Record count of the first leaf node.
+        tree[512 + 10..512 + 12].copy_from_slice(&300u16.to_be_bytes());
+        assert!(btree_leaf_records(&tree).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn btree_round_trip() -> Result<()> {
+        let records = (0..5000u32)
+            .map(|i| {
+                (
+                    write_catalog_key(i, &[]).unwrap(),
+                    i.to_be_bytes().repeat(10),
+                )
+            })
+            .collect::<Vec<_>>();
+        let tree = build_btree(
+            &records,
+            CATALOG_NODE_SIZE,
+            516,
+            BTREE_BIG_KEYS | BTREE_VARIABLE_INDEX_KEYS,
+            BTREE_BINARY_COMPARE,
+        )?;
+        assert_eq!(btree_leaf_records(&tree)?, records);
+        Ok(())
+    }
+}
@@ -0,0 +1,778 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Partition layouts of disk images.
+//!
+//! Every partition table structure (MBR, GPT headers and tables, the driver
+//! descriptor map and the Apple partition map) is stored as its own `blkx`
+//! entry, mirroring the layouts `hdiutil` produces.
+
+use {
+    crate::{reproducible::Reproducible, DmgWriter},
+    anyhow::Result,
+    byteorder::{ReadBytesExt, WriteBytesExt, BE, LE},
+    gpt::mbr::{PartRecord, ProtectiveMBR},
+    std::{
+        fmt,
+        io::{Read, Seek, Write},
+        str::FromStr,
+    },
+};
+
+const SECTOR_SIZE: u64 = 512;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_REVISION: u32 = 0x0001_0000;
+const GPT_HEADER_SIZE: u32 = 92;
+const GPT_ENTRY_COUNT: u32 = 128;
+const GPT_ENTRY_SIZE: u32 = 128;
+const GPT_TABLE_SECTORS: u64 = (GPT_ENTRY_COUNT * GPT_ENTRY_SIZE) as u64 / SECTOR_SIZE;
+/// Partitions are aligned to 4 KiB like `hdiutil` does.
+const GPT_ALIGNMENT: u64 = 8;
+
+const DDM_SIGNATURE: u16 = 0x4552;
+const APM_SIGNATURE: u16 = 0x504d;
+const APM_MAP_SECTORS: u64 = 63;
+/// Valid, allocated, in use, readable and writable.
+const APM_STATUS: u32 = 0x0000_0033;
+
+/// A GUID in its on-disk (mixed endian) representation.
+#[derive(Clone, Copy, Default, Eq, Hash, PartialEq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// Generate a random version 4 GUID.
+    pub fn random() -> Result<Self> {
+        let mut bytes = [0; 16];
+        getrandom::getrandom(&mut bytes)
+            .map_err(|e| anyhow::anyhow!("failed to generate a GUID: {e}"))?;
+        bytes[7] = (bytes[7] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        Ok(Self(bytes))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        self.0 == [0; 16]
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9]
+        )?;
+        for byte in &b[10..] {
+            write!(f, "{byte:02X}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl FromStr for Guid {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let hex = s.replace('-', "");
+        anyhow::ensure!(hex.len() == 32 && s.len() == 36, "invalid GUID {}", s);
+        let mut be = [0; 16];
+        for (i, byte) in be.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)?;
+        }
+        let mut bytes = be;
+        bytes[..4].reverse();
+        bytes[4..6].reverse();
+        bytes[6..8].reverse();
+        Ok(Self(bytes))
+    }
+}
+
+/// How the partitions of a disk image are described.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PartitionScheme {
+    /// A single partition without a partition table.
+    None,
+    /// GUID partition table with a protective MBR.
+    Gpt,
+    /// Apple partition map with a driver descriptor map.
+    Apm,
+}
+
+/// Type of a `blkx` partition of a disk image.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PartitionType {
+    Hfs,
+    Apfs,
+    Fat32,
+    EfiSystem,
+    Free,
+    ProtectiveMbr,
+    DriverDescriptorMap,
+    ApplePartitionMap,
+    PrimaryGptHeader,
+    PrimaryGptTable,
+    BackupGptTable,
+    BackupGptHeader,
+    Other(String),
+}
+
+impl PartitionType {
+    /// Parse the type hint in parentheses of a `blkx` partition name.
+    pub fn from_hint(hint: &str) -> Self {
+        match hint {
+            "Apple_HFS" | "Apple_HFSX" | "48465300-0000-11AA-AA11-00306543ECAC" => Self::Hfs,
+            "Apple_APFS" | "7C3457EF-0000-11AA-AA11-00306543ECAC" => Self::Apfs,
+            "FAT32" | "DOS_FAT_32" | "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7" => Self::Fat32,
+            "EFI" | "C12A7328-F81F-11D2-BA4B-00A0C93EC93B" => Self::EfiSystem,
+            "Apple_Free" => Self::Free,
+            "MBR" => Self::ProtectiveMbr,
+            "DDM" => Self::DriverDescriptorMap,
+            "Apple_partition_map" => Self::ApplePartitionMap,
+            "Primary GPT Header" => Self::PrimaryGptHeader,
+            "Primary GPT Table" => Self::PrimaryGptTable,
+            "Backup GPT Table" => Self::BackupGptTable,
+            "Backup GPT Header" => Self::BackupGptHeader,
+            hint => Self::Other(hint.to_string()),
+        }
+    }
+
+    /// The type hint used in `blkx` partition names.
+    pub fn hint(&self) -> &str {
+        match self {
+            Self::Hfs => "Apple_HFS",
+            Self::Apfs => "Apple_APFS",
+            Self::Fat32 => "FAT32",
+            Self::EfiSystem => "C12A7328-F81F-11D2-BA4B-00A0C93EC93B",
+            Self::Free => "Apple_Free",
+            Self::ProtectiveMbr => "MBR",
+            Self::DriverDescriptorMap => "DDM",
+            Self::ApplePartitionMap => "Apple_partition_map",
+            Self::PrimaryGptHeader => "Primary GPT Header",
+            Self::PrimaryGptTable => "Primary GPT Table",
+            Self::BackupGptTable => "Backup GPT Table",
+            Self::BackupGptHeader => "Backup GPT Header",
+            Self::Other(hint) => hint,
+        }
+    }
+
+    /// The partition type stored in Apple partition map entries.
+    pub fn apm_type(&self) -> &str {
+        match self {
+            Self::Fat32 => "DOS_FAT_32",
+            Self::EfiSystem => "EFI",
+            ty => ty.hint(),
+        }
+    }
+
+    /// The partition type GUID stored in GPT entries.
+    pub fn gpt_type(&self) -> Result<Guid> {
+        match self {
+            Self::Hfs => "48465300-0000-11AA-AA11-00306543ECAC".parse(),
+            Self::Apfs => "7C3457EF-0000-11AA-AA11-00306543ECAC".parse(),
+            Self::Fat32 => "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7".parse(),
+            Self::EfiSystem => "C12A7328-F81F-11D2-BA4B-00A0C93EC93B".parse(),
+            Self::Other(hint) => hint.parse(),
+            ty => anyhow::bail!("{:?} partitions have no GPT type", ty),
+        }
+    }
+
+    fn from_gpt_type(guid: &Guid) -> Self {
+        Self::from_hint(&guid.to_string())
+    }
+
+    /// Whether this partition holds a partition table structure rather than content.
+    pub fn is_table(&self) -> bool {
+        matches!(
+            self,
+            Self::ProtectiveMbr
+                | Self::DriverDescriptorMap
+                | Self::ApplePartitionMap
+                | Self::PrimaryGptHeader
+                | Self::PrimaryGptTable
+                | Self::BackupGptTable
+                | Self::BackupGptHeader
+        )
+    }
+}
+
+/// Split a `blkx` partition name like `disk image (Apple_HFS : 2)` into its
+/// label and type hint.
+pub fn parse_partition_name(name: &str) -> (&str, &str) {
+    if let Some(open) = name.rfind('(') {
+        if let Some(rest) = name[open + 1..].strip_suffix(')') {
+            let hint = match rest.rfind(" : ") {
+                Some(colon) => &rest[..colon],
+                None => rest,
+            };
+            return (name[..open].trim_end(), hint);
+        }
+    }
+    (name, "")
+}
+
+/// Format a `blkx` partition name from its label, type hint and index.
+pub fn partition_name(label: &str, hint: &str, index: usize) -> String {
+    if label.is_empty() {
+        format!("({hint} : {index})")
+    } else {
+        format!("{label} ({hint} : {index})")
+    }
+}
+
+/// A partition of a disk image as described by its `blkx` table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DmgPartition {
+    /// Index into [crate::Plist::partitions].
+    pub index: usize,
+    pub id: i32,
+    pub name: String,
+    pub ty: PartitionType,
+    pub sector_number: u64,
+    pub sector_count: u64,
+}
+
+/// Block 0 of disks using an Apple partition map.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DriverDescriptorMap {
+    pub block_size: u16,
+    pub block_count: u32,
+    pub device_type: u16,
+    pub device_id: u16,
+    pub data: u32,
+    pub driver_count: u16,
+}
+
+impl DriverDescriptorMap {
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let signature = r.read_u16::<BE>()?;
+        anyhow::ensure!(signature == DDM_SIGNATURE, "invalid driver descriptor map");
+        Ok(Self {
+            block_size: r.read_u16::<BE>()?,
+            block_count: r.read_u32::<BE>()?,
+            device_type: r.read_u16::<BE>()?,
+            device_id: r.read_u16::<BE>()?,
+            data: r.read_u32::<BE>()?,
+            driver_count: r.read_u16::<BE>()?,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_u16::<BE>(DDM_SIGNATURE)?;
+        w.write_u16::<BE>(self.block_size)?;
+        w.write_u32::<BE>(self.block_count)?;
+        w.write_u16::<BE>(self.device_type)?;
+        w.write_u16::<BE>(self.device_id)?;
+        w.write_u32::<BE>(self.data)?;
+        w.write_u16::<BE>(self.driver_count)?;
+        w.write_all(&[0; 494])?;
+        Ok(())
+    }
+}
+
+fn read_fixed_string<R: Read>(r: &mut R) -> Result<String> {
+    let mut bytes = [0; 32];
+    r.read_exact(&mut bytes)?;
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+fn write_fixed_string<W: Write>(w: &mut W, s: &str) -> Result<()> {
+    anyhow::ensure!(s.len() < 32, "{} is too long for a partition map entry", s);
+    let mut bytes = [0; 32];
+    bytes[..s.len()].copy_from_slice(s.as_bytes());
+    w.write_all(&bytes)?;
+    Ok(())
+}
+
+/// An entry of an Apple partition map.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ApmEntry {
+    /// Number of entries in the map.
+    pub map_entries: u32,
+    pub start_block: u32,
+    pub block_count: u32,
+    pub name: String,
+    pub partition_type: String,
+    pub data_start: u32,
+    pub data_count: u32,
+    pub status: u32,
+}
+
+impl ApmEntry {
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let signature = r.read_u16::<BE>()?;
+        anyhow::ensure!(signature == APM_SIGNATURE, "invalid partition map entry");
+        let _pad = r.read_u16::<BE>()?;
+        let map_entries = r.read_u32::<BE>()?;
+        let start_block = r.read_u32::<BE>()?;
+        let block_count = r.read_u32::<BE>()?;
+        let name = read_fixed_string(r)?;
+        let partition_type = read_fixed_string(r)?;
+        let data_start = r.read_u32::<BE>()?;
+        let data_count = r.read_u32::<BE>()?;
+        let status = r.read_u32::<BE>()?;
+        let mut rest = [0; 420];
+        r.read_exact(&mut rest)?;
+        Ok(Self {
+            map_entries,
+            start_block,
+            block_count,
+            name,
+            partition_type,
+            data_start,
+            data_count,
+            status,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_u16::<BE>(APM_SIGNATURE)?;
+        w.write_u16::<BE>(0)?;
+        w.write_u32::<BE>(self.map_entries)?;
+        w.write_u32::<BE>(self.start_block)?;
+        w.write_u32::<BE>(self.block_count)?;
+        write_fixed_string(w, &self.name)?;
+        write_fixed_string(w, &self.partition_type)?;
+        w.write_u32::<BE>(self.data_start)?;
+        w.write_u32::<BE>(self.data_count)?;
+        w.write_u32::<BE>(self.status)?;
+        // boot code location and processor type
+        w.write_all(&[0; 420])?;
+        Ok(())
+    }
+}
+
+/// A GUID partition table header.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct GptHeader {
+    pub revision: u32,
+    pub header_size: u32,
+    pub header_crc32: u32,
+    pub current_lba: u64,
+    pub backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    pub entries_lba: u64,
+    pub entry_count: u32,
+    pub entry_size: u32,
+    pub entries_crc32: u32,
+}
+
+impl GptHeader {
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let mut sector = [0; 512];
+        r.read_exact(&mut sector)?;
+        anyhow::ensure!(&sector[..8] == GPT_SIGNATURE, "invalid GPT header");
+        let mut r = &sector[8..];
+        let header = Self {
+            revision: r.read_u32::<LE>()?,
+            header_size: r.read_u32::<LE>()?,
+            header_crc32: r.read_u32::<LE>()?,
+            current_lba: {
+                let _reserved = r.read_u32::<LE>()?;
+                r.read_u64::<LE>()?
+            },
+            backup_lba: r.read_u64::<LE>()?,
+            first_usable_lba: r.read_u64::<LE>()?,
+            last_usable_lba: r.read_u64::<LE>()?,
+            disk_guid: {
+                let mut guid = [0; 16];
+                r.read_exact(&mut guid)?;
+                Guid(guid)
+            },
+            entries_lba: r.read_u64::<LE>()?,
+            entry_count: r.read_u32::<LE>()?,
+            entry_size: r.read_u32::<LE>()?,
+            entries_crc32: r.read_u32::<LE>()?,
+        };
+        anyhow::ensure!(
+            (GPT_HEADER_SIZE..=512).contains(&header.header_size),
+            "invalid GPT header size"
+        );
+        let mut check = sector[..header.header_size as usize].to_vec();
+        check[16..20].copy_from_slice(&[0; 4]);
+        anyhow::ensure!(
+            crc32fast::hash(&check) == header.header_crc32,
+            "GPT header checksum mismatch"
+        );
+        Ok(header)
+    }
+
+    fn write_fields<W: Write>(&self, w: &mut W, crc32: u32) -> Result<()> {
+        w.write_all(GPT_SIGNATURE)?;
+        w.write_u32::<LE>(self.revision)?;
+        w.write_u32::<LE>(self.header_size)?;
+        w.write_u32::<LE>(crc32)?;
+        w.write_u32::<LE>(0)?;
+        w.write_u64::<LE>(self.current_lba)?;
+        w.write_u64::<LE>(self.backup_lba)?;
+        w.write_u64::<LE>(self.first_usable_lba)?;
+        w.write_u64::<LE>(self.last_usable_lba)?;
+        w.write_all(&self.disk_guid.0)?;
+        w.write_u64::<LE>(self.entries_lba)?;
+        w.write_u32::<LE>(self.entry_count)?;
+        w.write_u32::<LE>(self.entry_size)?;
+        w.write_u32::<LE>(self.entries_crc32)?;
+        Ok(())
+    }
+
+    /// Write the header sector, computing the header checksum.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        let mut header = vec![];
+        self.write_fields(&mut header, 0)?;
+        let crc32 = crc32fast::hash(&header);
+        header.clear();
+        self.write_fields(&mut header, crc32)?;
+        header.resize(SECTOR_SIZE as usize, 0);
+        w.write_all(&header)?;
+        Ok(())
+    }
+}
+
+/// An entry of a GUID partition table.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GptEntry {
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+impl GptEntry {
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let mut type_guid = [0; 16];
+        r.read_exact(&mut type_guid)?;
+        let mut unique_guid = [0; 16];
+        r.read_exact(&mut unique_guid)?;
+        let first_lba = r.read_u64::<LE>()?;
+        let last_lba = r.read_u64::<LE>()?;
+        let attributes = r.read_u64::<LE>()?;
+        let mut name = [0; 36];
+        r.read_u16_into::<LE>(&mut name)?;
+        let end = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        Ok(Self {
+            type_guid: Guid(type_guid),
+            unique_guid: Guid(unique_guid),
+            first_lba,
+            last_lba,
+            attributes,
+            name: String::from_utf16_lossy(&name[..end]),
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&self.type_guid.0)?;
+        w.write_all(&self.unique_guid.0)?;
+        w.write_u64::<LE>(self.first_lba)?;
+        w.write_u64::<LE>(self.last_lba)?;
+        w.write_u64::<LE>(self.attributes)?;
+        let name = self.name.encode_utf16().collect::<Vec<_>>();
+        anyhow::ensure!(
+            name.len() <= 36,
+            "{} is too long for a GPT entry",
+            self.name
+        );
+        for i in 0..36 {
+            w.write_u16::<LE>(name.get(i).copied().unwrap_or_default())?;
+        }
+        Ok(())
+    }
+
+    /// The partition type described by the type GUID.
+    pub fn partition_type(&self) -> PartitionType {
+        PartitionType::from_gpt_type(&self.type_guid)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct LayoutPartition {
+    name: String,
+    ty: PartitionType,
+    data: Vec<u8>,
+}
+
+/// Describes the partitions of a disk image to write.
+#[derive(Clone, Debug)]
+pub struct DiskLayout {
+    scheme: PartitionScheme,
+    partitions: Vec<LayoutPartition>,
+    reproducible: Option<Reproducible>,
+}
+
+impl DiskLayout {
+    pub fn new(scheme: PartitionScheme) -> Self {
+        Self {
+            scheme,
+            partitions: vec![],
+            reproducible: None,
+        }
+    }
+
+    /// Derive GUIDs from the seed of `reproducible` instead of generating random ones.
+    pub fn set_reproducible(&mut self, reproducible: Reproducible) {
+        self.reproducible = Some(reproducible);
+    }
+
+    fn guid(&self, name: &str) -> Result<Guid> {
+        match &self.reproducible {
+            Some(reproducible) => Ok(reproducible.id(name)),
+            None => Guid::random(),
+        }
+    }
+
+    /// Add a partition with the given content.
+    ///
+    /// `data` has to be a non-empty multiple of 512 bytes.
+    pub fn add_partition(&mut self, name: &str, ty: PartitionType, data: Vec<u8>) -> Result<()> {
+        anyhow::ensure!(!data.is_empty(), "partition {} is empty", name);
+        anyhow::ensure!(data.len() as u64 % SECTOR_SIZE == 0);
+        anyhow::ensure!(!ty.is_table(), "{:?} partitions are generated", ty);
+        if self.scheme == PartitionScheme::None {
+            anyhow::ensure!(
+                self.partitions.is_empty(),
+                "layouts without a partition table have a single partition"
+            );
+        }
+        self.partitions.push(LayoutPartition {
+            name: name.to_string(),
+            ty,
+            data,
+        });
+        Ok(())
+    }
+
+    /// Write all partitions and finish the image.
+    pub fn write<W: Write + Seek>(&self, mut dmg: DmgWriter<W>) -> Result<()> {
+        match self.scheme {
+            PartitionScheme::None => {
+                for p in &self.partitions {
+                    dmg.add_partition(&partition_name(&p.name, p.ty.hint(), 0), &p.data)?;
+                }
+            }
+            PartitionScheme::Gpt => self.write_gpt(&mut dmg)?,
+            PartitionScheme::Apm => self.write_apm(&mut dmg)?,
+        }
+        dmg.finish()
+    }
+
+    fn write_gpt<W: Write + Seek>(&self, dmg: &mut DmgWriter<W>) -> Result<()> {
+        let sectors = |data: &[u8]| data.len() as u64 / SECTOR_SIZE;
+        let align = |sector: u64| sector.div_ceil(GPT_ALIGNMENT) * GPT_ALIGNMENT;
+        anyhow::ensure!(
+            self.partitions.len() <= GPT_ENTRY_COUNT as usize,
+            "too many partitions for a GUID partition table"
+        );
+
+        // (partition, start sector, free sectors preceding it)
+        let mut placed = vec![];
+        let mut next = 2 + GPT_TABLE_SECTORS;
+        for p in &self.partitions {
+            let start = align(next);
+            placed.push((p, start, start - next));
+            next = start + sectors(&p.data);
+        }
+        let trailing_free = align(next) - next;
+        let backup_table = next + trailing_free;
+        let total = backup_table + GPT_TABLE_SECTORS + 1;
+
+        let mut table = vec![];
+        for (i, (p, start, _)) in placed.iter().enumerate() {
+            GptEntry {
+                type_guid: p.ty.gpt_type()?,
+                unique_guid: self.guid(&format!("partition {}", i))?,
+                first_lba: *start,
+                last_lba: start + sectors(&p.data) - 1,
+                attributes: 0,
+                name: p.name.clone(),
+            }
+            .write_to(&mut table)?;
+        }
+        table.resize((GPT_ENTRY_COUNT * GPT_ENTRY_SIZE) as usize, 0);
+
+        let primary = GptHeader {
+            revision: GPT_REVISION,
+            header_size: GPT_HEADER_SIZE,
+            header_crc32: 0,
+            current_lba: 1,
+            backup_lba: total - 1,
+            first_usable_lba: 2 + GPT_TABLE_SECTORS,
+            last_usable_lba: backup_table - 1,
+            disk_guid: self.guid("disk")?,
+            entries_lba: 2,
+            entry_count: GPT_ENTRY_COUNT,
+            entry_size: GPT_ENTRY_SIZE,
+            entries_crc32: crc32fast::hash(&table),
+        };
+        let backup = GptHeader {
+            current_lba: total - 1,
+            backup_lba: 1,
+            entries_lba: backup_table,
+            ..primary
+        };
+
+        let mut mbr = ProtectiveMBR::new();
+        mbr.set_partition(0, PartRecord::new_protective(Some((total - 1).try_into()?)));
+
+        let mut index = 0;
+        let mut add = |dmg: &mut DmgWriter<W>, name: &str, ty: &PartitionType, data: &[u8]| {
+            let name = partition_name(name, ty.hint(), index);
+            index += 1;
+            dmg.add_partition(&name, data)
+        };
+        add(
+            dmg,
+            "Protective Master Boot Record",
+            &PartitionType::ProtectiveMbr,
+            &mbr.to_bytes(),
+        )?;
+        let mut header = vec![];
+        primary.write_to(&mut header)?;
+        add(dmg, "GPT Header", &PartitionType::PrimaryGptHeader, &header)?;
+        add(
+            dmg,
+            "GPT Partition Data",
+            &PartitionType::PrimaryGptTable,
+            &table,
+        )?;
+        for (p, _, free) in &placed {
+            if *free > 0 {
+                add(
+                    dmg,
+                    "",
+                    &PartitionType::Free,
+                    &vec![0; (free * SECTOR_SIZE) as usize],
+                )?;
+            }
+            add(dmg, &p.name, &p.ty, &p.data)?;
+        }
+        if trailing_free > 0 {
+            let free = vec![0; (trailing_free * SECTOR_SIZE) as usize];
+            add(dmg, "", &PartitionType::Free, &free)?;
+        }
+        add(
+            dmg,
+            "GPT Partition Data",
+            &PartitionType::BackupGptTable,
+            &table,
+        )?;
+        let mut header = vec![];
+        backup.write_to(&mut header)?;
+        add(dmg, "GPT Header", &PartitionType::BackupGptHeader, &header)?;
+        Ok(())
+    }
+
+    fn write_apm<W: Write + Seek>(&self, dmg: &mut DmgWriter<W>) -> Result<()> {
+        let map_entries = self.partitions.len() as u32 + 1;
+        anyhow::ensure!(
+            map_entries as u64 <= APM_MAP_SECTORS,
+            "too many partitions for an Apple partition map"
+        );
+
+        let mut entries = vec![ApmEntry {
+            map_entries,
+            start_block: 1,
+            block_count: APM_MAP_SECTORS as u32,
+            name: "Apple".to_string(),
+            partition_type: PartitionType::ApplePartitionMap.apm_type().to_string(),
+            data_start: 0,
+            data_count: APM_MAP_SECTORS as u32,
+            status: 0x3,
+        }];
+        let mut next = 1 + APM_MAP_SECTORS;
+        for p in &self.partitions {
+            let count = p.data.len() as u64 / SECTOR_SIZE;
+            entries.push(ApmEntry {
+                map_entries,
+                start_block: next.try_into()?,
+                block_count: count.try_into()?,
+                name: p.name.clone(),
+                partition_type: p.ty.apm_type().to_string(),
+                data_start: 0,
+                data_count: count.try_into()?,
+                status: APM_STATUS,
+            });
+            next += count;
+        }
+
+        let mut ddm = vec![];
+        DriverDescriptorMap {
+            block_size: SECTOR_SIZE as u16,
+            block_count: next.try_into()?,
+            ..Default::default()
+        }
+        .write_to(&mut ddm)?;
+        let mut map = vec![];
+        for entry in &entries {
+            entry.write_to(&mut map)?;
+        }
+        map.resize((APM_MAP_SECTORS * SECTOR_SIZE) as usize, 0);
+
+        dmg.add_partition("Driver Descriptor Map (DDM : 0)", &ddm)?;
+        dmg.add_partition("Apple (Apple_partition_map : 1)", &map)?;
+        for (i, p) in self.partitions.iter().enumerate() {
+            let name = partition_name(&p.name, p.ty.apm_type(), i + 2);
+            dmg.add_partition(&name, &p.data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guid_round_trip() -> Result<()> {
+        let s = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+        let guid = s.parse::<Guid>()?;
+        assert_eq!(guid.0[..4], [0x28, 0x73, 0x2a, 0xc1]);
+        assert_eq!(guid.to_string(), s);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_partitions() -> Result<()> {
+        let mut layout = DiskLayout::new(PartitionScheme::Gpt);
+        assert!(layout
+            .add_partition("empty", PartitionType::Hfs, vec![])
+            .is_err());
+
+        for i in 0..=GPT_ENTRY_COUNT {
+            layout.add_partition(&i.to_string(), PartitionType::Hfs, vec![0; 512])?;
+        }
+        let dmg = DmgWriter::new(std::io::Cursor::new(vec![]));
+        assert!(layout.write(dmg).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn partition_names() {
+        assert_eq!(
+            parse_partition_name("disk image (Apple_HFS : 2)"),
+            ("disk image", "Apple_HFS")
+        );
+        assert_eq!(parse_partition_name("(Apple_Free : 3)"), ("", "Apple_Free"));
+        assert_eq!(
+            parse_partition_name("GPT Header (Primary GPT Header : 1)"),
+            ("GPT Header", "Primary GPT Header")
+        );
+        assert_eq!(
+            PartitionType::from_hint(PartitionType::EfiSystem.hint()),
+            PartitionType::EfiSystem
+        );
+    }
+}
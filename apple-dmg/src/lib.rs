@@ -13,15 +13,26 @@ use {
     std::{
         fs::File,
         io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
-        path::Path,
+        path::{Path, PathBuf},
     },
 };
 
 mod blkx;
+mod ds_store;
+mod encrypted;
+mod hfs;
 mod koly;
+mod layout;
+mod license;
+mod reproducible;
+mod sparse;
+mod udif;
 mod xml;
 
-pub use crate::{blkx::*, koly::*, xml::*};
+pub use crate::{
+    blkx::*, ds_store::*, encrypted::*, hfs::*, koly::*, layout::*, license::*, reproducible::*,
+    sparse::*, udif::*, xml::*,
+};
 
 pub struct DmgReader<R: Read + Seek> {
     koly: KolyTrailer,
@@ -36,6 +47,13 @@ impl DmgReader<BufReader<File>> {
     }
 }
 
+impl DmgReader<EncryptedReader<BufReader<File>>> {
+    /// Open an image encrypted with a passphrase.
+    pub fn open_encrypted(path: &Path, passphrase: &str) -> Result<Self> {
+        Self::new(EncryptedReader::open(path, passphrase)?)
+    }
+}
+
 impl<R: Read + Seek> DmgReader<R> {
     pub fn new(mut r: R) -> Result<Self> {
         let koly = KolyTrailer::read_from(&mut r)?;
@@ -57,13 +75,18 @@ impl<R: Read + Seek> DmgReader<R> {
     pub fn sector(&mut self, chunk: &BlkxChunk) -> Result<impl Read + '_> {
         self.r.seek(SeekFrom::Start(chunk.compressed_offset))?;
         let compressed_chunk = (&mut self.r).take(chunk.compressed_length);
-        match chunk.ty().expect("unknown chunk type") {
+        let ty = chunk
+            .ty()
+            .ok_or_else(|| anyhow::anyhow!("unknown UDIF chunk type {:#x}", chunk.r#type))?;
+        match ty {
             ChunkType::Ignore | ChunkType::Zero | ChunkType::Comment => {
                 Ok(Box::new(std::io::repeat(0).take(chunk.compressed_length)) as Box<dyn Read>)
             }
             ChunkType::Raw => Ok(Box::new(compressed_chunk)),
             ChunkType::Zlib => Ok(Box::new(ZlibDecoder::new(compressed_chunk))),
-            ChunkType::Adc | ChunkType::Bzlib | ChunkType::Lzfse => unimplemented!(),
+            ChunkType::Adc | ChunkType::Bzlib | ChunkType::Lzfse => {
+                anyhow::bail!("unsupported UDIF chunk type {:?}", ty)
+            }
             ChunkType::Term => Ok(Box::new(std::io::empty())),
         }
     }
@@ -93,6 +116,198 @@ impl<R: Read + Seek> DmgReader<R> {
         }
         Ok(partition)
     }
+
+    /// Assemble the whole disk by placing every partition at its first sector.
+    ///
+    /// The disk is held in memory, [UdifReader] reads it chunk by chunk instead.
+    pub fn disk_data(&mut self) -> Result<Vec<u8>> {
+        let len = self
+            .koly
+            .sector_count
+            .checked_mul(512)
+            .and_then(|len| usize::try_from(len).ok())
+            .ok_or_else(|| anyhow::anyhow!("disk is too large"))?;
+        let mut disk = vec![];
+        disk.try_reserve_exact(len)?;
+        disk.resize(len, 0);
+        for i in 0..self.plist().partitions().len() {
+            let table = self.partition_table(i)?;
+            let start = table
+                .sector_number
+                .checked_mul(512)
+                .and_then(|start| usize::try_from(start).ok())
+                .filter(|start| *start <= len)
+                .ok_or_else(|| anyhow::anyhow!("partition {} is beyond the end of the disk", i))?;
+            // Data past the end of the disk is dropped.
+            let mut w = &mut disk[start..];
+            for chunk in &table.chunks {
+                let remaining = w.len() as u64;
+                std::io::copy(&mut self.sector(chunk)?.take(remaining), &mut w)?;
+            }
+        }
+        Ok(disk)
+    }
+
+    /// Describe all partitions of the image, including partition table structures.
+    pub fn partition_list(&self) -> Result<Vec<DmgPartition>> {
+        self.plist()
+            .partitions()
+            .iter()
+            .enumerate()
+            .map(|(index, partition)| {
+                let table = partition.table()?;
+                let (name, hint) = parse_partition_name(&partition.name);
+                Ok(DmgPartition {
+                    index,
+                    id: partition.id.parse()?,
+                    name: name.to_string(),
+                    ty: PartitionType::from_hint(hint),
+                    sector_number: table.sector_number,
+                    sector_count: table.sector_count,
+                })
+            })
+            .collect()
+    }
+
+    fn partition_data_of_type(&mut self, ty: &PartitionType) -> Result<Option<Vec<u8>>> {
+        match self.partition_list()?.into_iter().find(|p| &p.ty == ty) {
+            Some(partition) => Ok(Some(self.partition_data(partition.index)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Parse the driver descriptor map of images using an Apple partition map.
+    pub fn driver_descriptor_map(&mut self) -> Result<Option<DriverDescriptorMap>> {
+        self.partition_data_of_type(&PartitionType::DriverDescriptorMap)?
+            .map(|data| DriverDescriptorMap::read_from(&mut &data[..]))
+            .transpose()
+    }
+
+    /// Parse the Apple partition map, including the entry describing the map itself.
+    pub fn apple_partition_map(&mut self) -> Result<Option<Vec<ApmEntry>>> {
+        let Some(data) = self.partition_data_of_type(&PartitionType::ApplePartitionMap)? else {
+            return Ok(None);
+        };
+        let mut r = &data[..];
+        let first = ApmEntry::read_from(&mut r)?;
+        let mut entries = vec![first.clone()];
+        for _ in 1..first.map_entries {
+            entries.push(ApmEntry::read_from(&mut r)?);
+        }
+        Ok(Some(entries))
+    }
+
+    /// Parse the primary GUID partition table header and its used entries.
+    pub fn gpt(&mut self) -> Result<Option<(GptHeader, Vec<GptEntry>)>> {
+        let Some(header) = self.partition_data_of_type(&PartitionType::PrimaryGptHeader)? else {
+            return Ok(None);
+        };
+        let header = GptHeader::read_from(&mut &header[..])?;
+        let table = self
+            .partition_data_of_type(&PartitionType::PrimaryGptTable)?
+            .ok_or_else(|| anyhow::anyhow!("image has a GPT header but no partition table"))?;
+        let len = header.entry_count as usize * header.entry_size as usize;
+        anyhow::ensure!(
+            header.entry_size >= 128 && table.len() >= len,
+            "GPT partition table is truncated"
+        );
+        anyhow::ensure!(
+            crc32fast::hash(&table[..len]) == header.entries_crc32,
+            "GPT partition table checksum mismatch"
+        );
+        let mut entries = vec![];
+        for entry in table[..len].chunks(header.entry_size as usize) {
+            let entry = GptEntry::read_from(&mut &entry[..])?;
+            if !entry.type_guid.is_nil() {
+                entries.push(entry);
+            }
+        }
+        Ok(Some((header, entries)))
+    }
+
+    /// Open the first HFS+ or HFSX partition of the image.
+    pub fn hfs(self) -> Result<HfsReader<UdifReader<R>>> {
+        let partition = self
+            .partition_list()?
+            .into_iter()
+            .find(|p| p.ty == PartitionType::Hfs)
+            .ok_or_else(|| anyhow::anyhow!("image has no HFS+ partition"))?;
+        HfsReader::new(UdifReader::partition(self, partition.index)?)
+    }
+}
+
+/// A disk image of any supported type, read as a whole disk.
+///
+/// UDIF images are decompressed chunk by chunk, sparse images and sparse bundles
+/// are read band by band.
+pub enum DiskImage {
+    Udif(UdifReader<BufReader<File>>),
+    EncryptedUdif(UdifReader<EncryptedReader<BufReader<File>>>),
+    SparseImage(SparseImageReader<BufReader<File>>),
+    SparseBundle(SparseBundleReader),
+}
+
+impl DiskImage {
+    /// Open a disk image, detecting its type.
+    pub fn open(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            return Ok(Self::SparseBundle(SparseBundleReader::open(path)?));
+        }
+        let mut signature = [0; 8];
+        let mut file = File::open(path)?;
+        let len = file.read(&mut signature)?;
+        let signature = &signature[..len];
+        if signature.starts_with(&SPARSE_IMAGE_SIGNATURE.to_be_bytes()) {
+            Ok(Self::SparseImage(SparseImageReader::open(path)?))
+        } else if signature == ENCRYPTED_IMAGE_SIGNATURE {
+            anyhow::bail!("image is encrypted, use DiskImage::open_encrypted()")
+        } else {
+            Ok(Self::Udif(UdifReader::new(DmgReader::open(path)?)?))
+        }
+    }
+
+    /// Open an encrypted UDIF image.
+    pub fn open_encrypted(path: &Path, passphrase: &str) -> Result<Self> {
+        Ok(Self::EncryptedUdif(UdifReader::new(
+            DmgReader::open_encrypted(path, passphrase)?,
+        )?))
+    }
+
+    /// Size of the disk in bytes.
+    pub fn len(&self) -> u64 {
+        match self {
+            Self::Udif(reader) => reader.len(),
+            Self::EncryptedUdif(reader) => reader.len(),
+            Self::SparseImage(reader) => reader.len(),
+            Self::SparseBundle(reader) => reader.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Read for DiskImage {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Udif(reader) => reader.read(buf),
+            Self::EncryptedUdif(reader) => reader.read(buf),
+            Self::SparseImage(reader) => reader.read(buf),
+            Self::SparseBundle(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for DiskImage {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            Self::Udif(reader) => reader.seek(pos),
+            Self::EncryptedUdif(reader) => reader.seek(pos),
+            Self::SparseImage(reader) => reader.seek(pos),
+            Self::SparseBundle(reader) => reader.seek(pos),
+        }
+    }
 }
 
 pub struct DmgWriter<W: Write + Seek> {
@@ -102,6 +317,7 @@ pub struct DmgWriter<W: Write + Seek> {
     main_hasher: Hasher,
     sector_number: u64,
     compressed_offset: u64,
+    segment_id: Option<[u8; 16]>,
 }
 
 impl DmgWriter<BufWriter<File>> {
@@ -120,9 +336,16 @@ impl<W: Write + Seek> DmgWriter<W> {
             main_hasher: Hasher::new(),
             sector_number: 0,
             compressed_offset: 0,
+            segment_id: None,
         }
     }
 
+    /// Derive the segment id of the trailer from `reproducible` instead of
+    /// generating a random one.
+    pub fn set_reproducible(&mut self, reproducible: &Reproducible) {
+        self.segment_id = Some(reproducible.id("segment").0);
+    }
+
     pub fn create_fat32(mut self, fat32: &[u8]) -> Result<()> {
         anyhow::ensure!(fat32.len() % 512 == 0);
         let sector_count = fat32.len() as u64 / 512;
@@ -137,6 +360,22 @@ impl<W: Write + Seek> DmgWriter<W> {
         Ok(())
     }
 
+    /// Write the partitions of `layout` and finish the image.
+    pub fn create_layout(self, layout: &DiskLayout) -> Result<()> {
+        layout.write(self)
+    }
+
+    pub fn create_hfs(mut self, hfs: &[u8]) -> Result<()> {
+        self.add_partition("disk image (Apple_HFS : 0)", hfs)?;
+        self.finish()?;
+        Ok(())
+    }
+
+    /// The plist written by [Self::finish], e.g. to add license agreements.
+    pub fn plist_mut(&mut self) -> &mut Plist {
+        &mut self.xml
+    }
+
     pub fn add_partition(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
         anyhow::ensure!(bytes.len() % 512 == 0);
         let id = self.xml.partitions().len() as u32;
@@ -173,7 +412,7 @@ impl<W: Write + Seek> DmgWriter<W> {
         let pos = self.w.stream_position()?;
         let data_digest = self.data_hasher.finalize();
         let main_digest = self.main_hasher.finalize();
-        let koly = KolyTrailer::new(
+        let mut koly = KolyTrailer::new(
             pos,
             self.sector_number,
             pos,
@@ -181,17 +420,52 @@ impl<W: Write + Seek> DmgWriter<W> {
             data_digest,
             main_digest,
         );
+        if let Some(segment_id) = self.segment_id {
+            koly.segment_id = segment_id;
+        }
         self.w.write_all(&xml)?;
         koly.write_to(&mut self.w)?;
         Ok(())
     }
 }
 
+/// Modify the plist of an existing dmg in place, e.g. to add a license agreement.
+///
+/// The data fork is left untouched.
+pub fn update_plist(path: &Path, f: impl FnOnce(&mut Plist) -> Result<()>) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+    let (mut koly, mut plist) = {
+        let dmg = DmgReader::new(BufReader::new(&mut file))?;
+        (*dmg.koly(), dmg.plist().clone())
+    };
+    f(&mut plist)?;
+    let mut xml = vec![];
+    plist::to_writer_xml(&mut xml, &plist)?;
+
+    // The plist usually directly precedes the trailer. Otherwise append it.
+    let len = file.seek(SeekFrom::End(0))?;
+    let offset = if koly.plist_offset + koly.plist_length + 512 == len {
+        koly.plist_offset
+    } else {
+        len - 512
+    };
+    koly.plist_offset = offset;
+    koly.plist_length = xml.len() as _;
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(&xml)?;
+    koly.write_to(&mut file)?;
+    file.set_len(offset + xml.len() as u64 + 512)?;
+    Ok(())
+}
+
 // https://wiki.samba.org/index.php/UNIX_Extensions#Storing_symlinks_on_Windows_servers
 fn symlink(target: &str) -> Result<Vec<u8>> {
     let xsym = format!(
         "XSym\n{:04}\n{:x}\n{}\n",
-        target.as_bytes().len(),
+        target.len(),
         md5::compute(target.as_bytes()),
         target,
     );
@@ -202,8 +476,11 @@ fn symlink(target: &str) -> Result<Vec<u8>> {
 }
 
 fn add_dir<T: ReadWriteSeek>(src: &Path, dest: &Dir<'_, T>) -> Result<()> {
-    for entry in std::fs::read_dir(src)? {
-        let entry = entry?;
+    // read_dir() order depends on the host filesystem. Sorting makes the
+    // directory entries, and with them the image, reproducible.
+    let mut entries = std::fs::read_dir(src)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
         let file_name = entry.file_name();
         let file_name = file_name.to_str().unwrap();
         let source = src.join(file_name);
@@ -224,24 +501,176 @@ fn add_dir<T: ReadWriteSeek>(src: &Path, dest: &Dir<'_, T>) -> Result<()> {
     Ok(())
 }
 
+/// Filesystem of the volume created by [create_dmg_with_options].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DmgFilesystem {
+    /// A FAT32 volume of a fixed size. Symlinks are stored as `XSym` files.
+    Fat32 { total_sectors: u32 },
+    /// An HFS+ volume sized to fit the content.
+    Hfs,
+}
+
+/// Options of [create_dmg_with_options].
+#[derive(Clone, Debug, PartialEq)]
+pub struct DmgOptions {
+    pub volume_label: String,
+    pub filesystem: DmgFilesystem,
+    /// Add an `Applications` symlink to `/Applications` next to the content.
+    pub applications_symlink: bool,
+    /// Layout of the Finder window opened when the image is mounted.
+    pub window: Option<FinderWindow>,
+    /// `.icns` file to use as the volume icon. Requires an HFS+ volume.
+    pub volume_icon: Option<PathBuf>,
+    /// Produce identical images for identical input.
+    pub reproducible: Option<Reproducible>,
+}
+
+impl DmgOptions {
+    pub fn new(volume_label: &str, filesystem: DmgFilesystem) -> Self {
+        Self {
+            volume_label: volume_label.to_string(),
+            filesystem,
+            applications_symlink: false,
+            window: None,
+            volume_icon: None,
+            reproducible: None,
+        }
+    }
+}
+
 pub fn create_dmg(dir: &Path, dmg: &Path, volume_label: &str, total_sectors: u32) -> Result<()> {
+    create_dmg_with_options(
+        dir,
+        dmg,
+        &DmgOptions::new(volume_label, DmgFilesystem::Fat32 { total_sectors }),
+    )
+}
+
+/// Create a dmg containing `dir` on an HFS+ volume.
+///
+/// Unlike FAT32 volumes, permissions and symlinks are preserved. The volume is
+/// sized to fit the content.
+pub fn create_hfs_dmg(dir: &Path, dmg: &Path, volume_label: &str) -> Result<()> {
+    create_dmg_with_options(dir, dmg, &DmgOptions::new(volume_label, DmgFilesystem::Hfs))
+}
+
+/// Create a dmg containing `dir`, optionally laid out as an installer window.
+///
+/// `dir` is added as a top level entry named after it, so icon positions of the
+/// window refer to its file name and to `Applications`.
+pub fn create_dmg_with_options(dir: &Path, dmg: &Path, options: &DmgOptions) -> Result<()> {
+    let mut writer = DmgWriter::create(dmg)?;
+    if let Some(reproducible) = &options.reproducible {
+        writer.set_reproducible(reproducible);
+    }
+    match options.filesystem {
+        DmgFilesystem::Fat32 { total_sectors } => {
+            let fat32 = fat32_volume(dir, options, total_sectors)?;
+            writer.create_fat32(&fat32)
+        }
+        DmgFilesystem::Hfs => {
+            let hfs = hfs_volume(dir, options)?;
+            writer.create_hfs(&hfs)
+        }
+    }
+}
+
+fn fat32_volume(dir: &Path, options: &DmgOptions, total_sectors: u32) -> Result<Vec<u8>> {
+    anyhow::ensure!(
+        options.volume_icon.is_none(),
+        "volume icons require an HFS+ volume"
+    );
+    let ds_store = match &options.window {
+        Some(window) => {
+            anyhow::ensure!(
+                !matches!(window.background, Some(FinderBackground::Image(_))),
+                "background images require an HFS+ volume"
+            );
+            Some(window.to_ds_store(None)?.to_bytes()?)
+        }
+        None => None,
+    };
+    let volume_label = &options.volume_label;
     let mut fat32 = vec![0; total_sectors as usize * 512];
-    {
+    let format = || -> Result<()> {
         let mut volume_label_bytes = [0; 11];
         let end = std::cmp::min(volume_label_bytes.len(), volume_label.len());
         volume_label_bytes[..end].copy_from_slice(&volume_label.as_bytes()[..end]);
-        let volume_options = FormatVolumeOptions::new()
+        let mut volume_options = FormatVolumeOptions::new()
             .volume_label(volume_label_bytes)
             .bytes_per_sector(512)
             .total_sectors(total_sectors);
+        let mut fs_options = FsOptions::new();
+        if let Some(reproducible) = &options.reproducible {
+            let id = reproducible.id("volume").0;
+            volume_options =
+                volume_options.volume_id(u32::from_le_bytes([id[0], id[1], id[2], id[3]]));
+            fs_options = fs_options.time_provider(&CLOCK_TIME_PROVIDER);
+        }
         let mut disk = BufStream::new(Cursor::new(&mut fat32));
         fatfs::format_volume(&mut disk, volume_options)?;
-        let fs = FileSystem::new(disk, FsOptions::new())?;
+        let fs = FileSystem::new(disk, fs_options)?;
+        let root = fs.root_dir();
         let file_name = dir.file_name().unwrap().to_str().unwrap();
-        let dest = fs.root_dir().create_dir(file_name)?;
+        let dest = root.create_dir(file_name)?;
         add_dir(dir, &dest)?;
+        if options.applications_symlink {
+            let xsym = symlink("/Applications")?;
+            std::io::copy(&mut &xsym[..], &mut root.create_file("Applications")?)?;
+        }
+        if let Some(ds_store) = ds_store {
+            std::io::copy(&mut &ds_store[..], &mut root.create_file(".DS_Store")?)?;
+        }
+        Ok(())
+    };
+    match &options.reproducible {
+        Some(reproducible) => with_clock(reproducible.timestamp, format)?,
+        None => format()?,
+    }
+    Ok(fat32)
+}
+
+fn hfs_volume(dir: &Path, options: &DmgOptions) -> Result<Vec<u8>> {
+    let mut builder = HfsBuilder::new(&options.volume_label);
+    if let Some(reproducible) = &options.reproducible {
+        builder.set_reproducible(reproducible);
+    }
+    let file_name = dir.file_name().unwrap().to_str().unwrap();
+    builder.add_directory(file_name, 0o755)?;
+    builder.add_dir_from_path(dir, file_name)?;
+    if options.applications_symlink {
+        builder.add_symlink("Applications", "/Applications")?;
+    }
+    if let Some(icon) = &options.volume_icon {
+        builder.set_volume_icon(std::fs::read(icon)?)?;
+    }
+    if let Some(window) = &options.window {
+        // Add every entry before computing the alias, as catalog ids depend on
+        // the full set of paths.
+        builder.add_file_from_data(".DS_Store", vec![], 0o644)?;
+        let alias = match &window.background {
+            Some(FinderBackground::Image(image)) => {
+                let image_name = image
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .ok_or_else(|| anyhow::anyhow!("invalid background image path"))?;
+                let path = format!(".background/{}", image_name);
+                builder.add_file_from_data(&path, std::fs::read(image)?, 0o644)?;
+                Some(Alias {
+                    volume_name: options.volume_label.clone(),
+                    volume_created: builder.create_time(),
+                    parent_ids: vec![builder.catalog_id(".background").unwrap()],
+                    file_id: builder.catalog_id(&path).unwrap(),
+                    file_created: builder.create_time(),
+                    path,
+                })
+            }
+            _ => None,
+        };
+        let ds_store = window.to_ds_store(alias.as_ref())?.to_bytes()?;
+        builder.add_file_from_data(".DS_Store", ds_store, 0o644)?;
     }
-    DmgWriter::create(dmg)?.create_fat32(&fat32)
+    builder.build()
 }
 
 #[cfg(test)]
@@ -333,6 +762,274 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn hfs_dmg_round_trip() -> Result<()> {
+        let mut builder = HfsBuilder::new("Example");
+        builder.add_file_from_data("Example.app/Contents/MacOS/example", vec![1; 5000], 0o755)?;
+        builder.add_symlink("Applications", "/Applications")?;
+        let hfs = builder.build()?;
+
+        let mut buffer = vec![];
+        DmgWriter::new(Cursor::new(&mut buffer)).create_hfs(&hfs)?;
+        let dmg = DmgReader::new(Cursor::new(buffer))?;
+        let mut volume = dmg.hfs()?;
+        assert_eq!(volume.volume_name(), "Example");
+        let exe = volume
+            .entry("Example.app/Contents/MacOS/example")
+            .unwrap()
+            .clone();
+        assert_eq!(exe.mode, 0o755);
+        assert_eq!(volume.read_file(&exe)?, vec![1; 5000]);
+        Ok(())
+    }
+
+    #[test]
+    fn installer_window() -> Result<()> {
+        let tmp = std::env::temp_dir().join(format!("apple-dmg-window-{}", std::process::id()));
+        let app = tmp.join("Example.app");
+        std::fs::create_dir_all(app.join("Contents"))?;
+        std::fs::write(app.join("Contents/Info.plist"), b"<plist/>")?;
+        let background = tmp.join("background.png");
+        std::fs::write(&background, b"png")?;
+        let mut window = FinderWindow {
+            background: Some(FinderBackground::Image(background)),
+            ..Default::default()
+        };
+        window
+            .icon_positions
+            .insert("Example.app".to_string(), (160, 240));
+        window
+            .icon_positions
+            .insert("Applications".to_string(), (480, 240));
+        let mut options = DmgOptions::new("Example", DmgFilesystem::Hfs);
+        options.applications_symlink = true;
+        options.window = Some(window);
+        let dmg_path = tmp.join("example.dmg");
+        create_dmg_with_options(&app, &dmg_path, &options)?;
+
+        let dmg = DmgReader::open(&dmg_path)?;
+        let mut volume = dmg.hfs()?;
+        let image = volume.entry(".background/background.png").unwrap().clone();
+        assert_eq!(volume.read_file(&image)?, b"png");
+        let entry = volume.entry(".DS_Store").unwrap().clone();
+        let ds_store = DsStore::from_bytes(&volume.read_file(&entry)?)?;
+        assert!(ds_store.get("Applications", b"Iloc").is_some());
+        let Some(DsValue::Blob(icvp)) = ds_store.get(".", b"icvp") else {
+            panic!("missing view settings");
+        };
+        let icvp: plist::Value = plist::from_bytes(icvp)?;
+        let alias = icvp.as_dictionary().unwrap()["backgroundImageAlias"]
+            .as_data()
+            .unwrap();
+        assert_eq!(&alias[114..118], &image.id.to_be_bytes());
+        std::fs::remove_dir_all(&tmp)?;
+        Ok(())
+    }
+
+    #[test]
+    fn disk_image() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("apple-dmg-disk-{}.dmg", std::process::id()));
+        std::fs::write(&path, DMG)?;
+        let mut dmg = DmgReader::new(Cursor::new(DMG))?;
+        let mut image = DiskImage::open(&path)?;
+        assert_eq!(image.len(), dmg.koly().sector_count * 512);
+        let partition = dmg.partition_list()?.into_iter().last().unwrap();
+        let data = dmg.partition_data(partition.index)?;
+        image.seek(SeekFrom::Start(partition.sector_number * 512))?;
+        let mut buf = vec![0; data.len()];
+        image.read_exact(&mut buf)?;
+        assert_eq!(buf, data);
+
+        image.rewind()?;
+        let mut disk = vec![];
+        image.read_to_end(&mut disk)?;
+        assert_eq!(disk, dmg.disk_data()?);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn disk_data_out_of_range() -> Result<()> {
+        let mut image = DMG.to_vec();
+        let koly_offset = image.len() - 512;
+        let mut koly = KolyTrailer::read_from(&mut Cursor::new(DMG))?;
+        for sector_count in [u64::MAX, u64::MAX / 512] {
+            koly.sector_count = sector_count;
+            koly.write_to(&mut &mut image[koly_offset..])?;
+            let mut dmg = DmgReader::new(Cursor::new(&image))?;
+            assert!(dmg.disk_data().is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn encrypted_dmg() -> Result<()> {
+        let image = crate::encrypted::tests::encrypt(DMG, "secret")?;
+        let mut dmg = DmgReader::new(EncryptedReader::new(Cursor::new(image), "secret")?)?;
+        let mut expected = DmgReader::new(Cursor::new(DMG))?;
+        assert_eq!(dmg.koly(), expected.koly());
+        for i in 0..expected.plist().partitions().len() {
+            assert_eq!(dmg.partition_data(i)?, expected.partition_data(i)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reproducible_dmg() -> Result<()> {
+        let tmp =
+            std::env::temp_dir().join(format!("apple-dmg-reproducible-{}", std::process::id()));
+        let app = tmp.join("Example.app");
+        std::fs::create_dir_all(app.join("Contents"))?;
+        std::fs::write(app.join("Contents/Info.plist"), b"<plist/>")?;
+        let reproducible =
+            Reproducible::new("8F0C2B1A-7E44-4E1B-9C39-2B6D3B4E9A10".parse()?, 1700000000);
+        for filesystem in [
+            DmgFilesystem::Fat32 {
+                total_sectors: 100_000,
+            },
+            DmgFilesystem::Hfs,
+        ] {
+            let mut options = DmgOptions::new("Example", filesystem);
+            options.reproducible = Some(reproducible);
+            options.window = Some(FinderWindow::default());
+            let first = tmp.join("first.dmg");
+            let second = tmp.join("second.dmg");
+            with_clock(1800000000, || {
+                create_dmg_with_options(&app, &first, &options)
+            })?;
+            with_clock(1900000000, || {
+                create_dmg_with_options(&app, &second, &options)
+            })?;
+            assert_eq!(std::fs::read(&first)?, std::fs::read(&second)?);
+            let dmg = DmgReader::open(&first)?;
+            assert_eq!(dmg.koly().segment_id, reproducible.id("segment").0);
+        }
+        std::fs::remove_dir_all(&tmp)?;
+        Ok(())
+    }
+
+    #[test]
+    fn fat32_entries_are_sorted() -> Result<()> {
+        let tmp = std::env::temp_dir().join(format!("apple-dmg-sorted-{}", std::process::id()));
+        let dir = tmp.join("Example");
+        std::fs::create_dir_all(&dir)?;
+        for name in ["b", "c", "a"] {
+            std::fs::write(dir.join(name), name)?;
+        }
+        let options = DmgOptions::new("Example", DmgFilesystem::Hfs);
+        let fat32 = fat32_volume(&dir, &options, 100_000)?;
+        let fs = FileSystem::new(Cursor::new(fat32), FsOptions::new())?;
+        let names = fs
+            .root_dir()
+            .open_dir("Example")?
+            .iter()
+            .filter(|entry| !matches!(entry, Ok(e) if e.file_name().starts_with('.')))
+            .map(|entry| Ok(entry?.file_name()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(names, ["a", "b", "c"]);
+        std::fs::remove_dir_all(&tmp)?;
+        Ok(())
+    }
+
+    #[test]
+    fn gpt_layout() -> Result<()> {
+        let mut layout = DiskLayout::new(PartitionScheme::Gpt);
+        layout.add_partition(
+            "EFI System Partition",
+            PartitionType::EfiSystem,
+            vec![1; 512 * 3],
+        )?;
+        layout.add_partition("disk image", PartitionType::Hfs, vec![2; 512 * 10])?;
+        let mut buffer = vec![];
+        DmgWriter::new(Cursor::new(&mut buffer)).create_layout(&layout)?;
+
+        let mut dmg = DmgReader::new(Cursor::new(buffer))?;
+        let types = dmg
+            .partition_list()?
+            .into_iter()
+            .map(|p| p.ty)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                PartitionType::ProtectiveMbr,
+                PartitionType::PrimaryGptHeader,
+                PartitionType::PrimaryGptTable,
+                PartitionType::Free,
+                PartitionType::EfiSystem,
+                PartitionType::Free,
+                PartitionType::Hfs,
+                PartitionType::Free,
+                PartitionType::BackupGptTable,
+                PartitionType::BackupGptHeader,
+            ]
+        );
+        let (header, entries) = dmg.gpt()?.unwrap();
+        assert_eq!(header.first_usable_lba, 34);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "EFI System Partition");
+        assert_eq!(entries[0].partition_type(), PartitionType::EfiSystem);
+        assert_eq!((entries[1].first_lba, entries[1].last_lba), (48, 57));
+        let list = dmg.partition_list()?;
+        assert_eq!(list[6].sector_number, 48);
+        assert_eq!(list[9].sector_number, header.backup_lba);
+        assert_eq!(dmg.partition_data(6)?, vec![2; 512 * 10]);
+
+        let mbr = ProtectiveMBR::from_bytes(&dmg.partition_data(0)?, LogicalBlockSize::Lb512)?;
+        assert_eq!(mbr.partition(0).unwrap().lb_size as u64, header.backup_lba);
+        Ok(())
+    }
+
+    #[test]
+    fn apm_layout() -> Result<()> {
+        let mut layout = DiskLayout::new(PartitionScheme::Apm);
+        layout.add_partition("disk image", PartitionType::Hfs, vec![2; 512 * 10])?;
+        let mut buffer = vec![];
+        DmgWriter::new(Cursor::new(&mut buffer)).create_layout(&layout)?;
+
+        let mut dmg = DmgReader::new(Cursor::new(buffer))?;
+        let ddm = dmg.driver_descriptor_map()?.unwrap();
+        assert_eq!(ddm.block_count, 74);
+        let map = dmg.apple_partition_map()?.unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[1].partition_type, "Apple_HFS");
+        assert_eq!(map[1].start_block, 64);
+        let list = dmg.partition_list()?;
+        assert_eq!(list[2].name, "disk image");
+        assert_eq!(list[2].ty, PartitionType::Hfs);
+        assert_eq!(list[2].sector_number, 64);
+        assert!(dmg.gpt()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn license_agreement() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("apple-dmg-license-{}.dmg", std::process::id()));
+        std::fs::write(&path, DMG)?;
+        let license = License::english("Use at your own risk.");
+        update_plist(&path, |plist| {
+            plist
+                .resource_fork
+                .set_licenses(std::slice::from_ref(&license))
+        })?;
+        let data = std::fs::read(&path)?;
+        std::fs::remove_file(&path)?;
+
+        let mut dmg = DmgReader::new(Cursor::new(data))?;
+        assert_eq!(dmg.plist().resource_fork.licenses()?, vec![license]);
+        let original = DmgReader::new(Cursor::new(DMG))?;
+        assert_eq!(
+            dmg.koly().data_fork_digest,
+            original.koly().data_fork_digest
+        );
+        assert_eq!(
+            UdifChecksum::new(dmg.data_checksum()?),
+            dmg.koly().data_fork_digest
+        );
+        Ok(())
+    }
+
     #[test]
     fn checksum() -> Result<()> {
         let mut dmg = DmgReader::new(Cursor::new(DMG))?;
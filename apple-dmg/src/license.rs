@@ -0,0 +1,351 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Software license agreements shown before an image is mounted.
+//!
+//! A license agreement consists of an `LPic` resource listing the available
+//! languages and, per language, a `STR#` resource with the button labels and a
+//! `TEXT` or `RTF ` resource with the license itself. Resource ids start at 5000.
+//!
+//! `STR#` and `TEXT` resources are stored in the script encoding of the region of
+//! the license. Only Mac OS Roman is supported, so regions using other scripts,
+//! like Japanese, Russian or Greek, are limited to ASCII button labels and need
+//! an RTF license. Text that can't be encoded is an error.
+
+use {
+    crate::xml::{Resource, ResourceFork},
+    anyhow::Result,
+    byteorder::{ReadBytesExt, WriteBytesExt, BE},
+    std::io::Read,
+};
+
+const FIRST_RESOURCE_ID: u16 = 5000;
+
+/// Characters 0x80 to 0xff of the Mac OS Roman encoding.
+const MAC_ROMAN: [char; 128] = [
+    'Ä', 'Å', 'Ç', 'É', 'Ñ', 'Ö', 'Ü', 'á', 'à', 'â', 'ä', 'ã', 'å', 'ç', 'é', 'è', //
+    'ê', 'ë', 'í', 'ì', 'î', 'ï', 'ñ', 'ó', 'ò', 'ô', 'ö', 'õ', 'ú', 'ù', 'û', 'ü', //
+    '†', '°', '¢', '£', '§', '•', '¶', 'ß', '®', '©', '™', '´', '¨', '≠', 'Æ', 'Ø', //
+    '∞', '±', '≤', '≥', '¥', 'µ', '∂', '∑', '∏', 'π', '∫', 'ª', 'º', 'Ω', 'æ', 'ø', //
+    '¿', '¡', '¬', '√', 'ƒ', '≈', '∆', '«', '»', '…', '\u{a0}', 'À', 'Ã', 'Õ', 'Œ', 'œ', //
+    '–', '—', '“', '”', '‘', '’', '÷', '◊', 'ÿ', 'Ÿ', '⁄', '€', '‹', '›', 'ﬁ', 'ﬂ', //
+    '‡', '·', '‚', '„', '‰', 'Â', 'Ê', 'Á', 'Ë', 'È', 'Í', 'Î', 'Ï', 'Ì', 'Ó', 'Ô', //
+    '\u{f8ff}', 'Ò', 'Ú', 'Û', 'Ù', 'ı', 'ˆ', '˜', '¯', '˘', '˙', '˚', '¸', '˝', '˛', 'ˇ', //
+];
+
+/// Region codes of languages using the Mac OS Roman encoding.
+const MAC_ROMAN_REGIONS: [u16; 17] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 15, 17, 18, 19];
+
+/// Encode a string in the script encoding of `region_code`.
+fn encode_text(s: &str, region_code: u16) -> Result<Vec<u8>> {
+    if MAC_ROMAN_REGIONS.contains(&region_code) {
+        encode_mac_roman(s)
+    } else {
+        anyhow::ensure!(
+            s.is_ascii(),
+            "license text of region {} can only contain ASCII, its script encoding isn't supported",
+            region_code
+        );
+        Ok(s.as_bytes().to_vec())
+    }
+}
+
+/// Decode a string in the script encoding of `region_code`.
+fn decode_text(bytes: &[u8], region_code: u16) -> Result<String> {
+    if MAC_ROMAN_REGIONS.contains(&region_code) {
+        Ok(decode_mac_roman(bytes))
+    } else {
+        anyhow::ensure!(
+            bytes.is_ascii(),
+            "license text of region {} is not ASCII, its script encoding isn't supported",
+            region_code
+        );
+        Ok(String::from_utf8(bytes.to_vec())?)
+    }
+}
+
+fn encode_mac_roman(s: &str) -> Result<Vec<u8>> {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii() {
+                Ok(c as u8)
+            } else {
+                MAC_ROMAN
+                    .iter()
+                    .position(|m| *m == c)
+                    .map(|i| 0x80 + i as u8)
+                    .ok_or_else(|| anyhow::anyhow!("{:?} cannot be encoded as Mac OS Roman", c))
+            }
+        })
+        .collect()
+}
+
+fn decode_mac_roman(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| {
+            if b.is_ascii() {
+                *b as char
+            } else {
+                MAC_ROMAN[*b as usize - 0x80]
+            }
+        })
+        .collect()
+}
+
+/// Labels of the license agreement dialog.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LicenseButtons {
+    /// Name of the language shown in the language menu.
+    pub language: String,
+    pub agree: String,
+    pub disagree: String,
+    pub print: String,
+    pub save: String,
+    /// Explanation shown above the license.
+    pub message: String,
+}
+
+impl LicenseButtons {
+    pub fn english() -> Self {
+        Self {
+            language: "English".to_string(),
+            agree: "Agree".to_string(),
+            disagree: "Disagree".to_string(),
+            print: "Print".to_string(),
+            save: "Save...".to_string(),
+            message: "If you agree with the terms of this license, press \"Agree\" to install \
+                      the software. If you do not agree, press \"Disagree\"."
+                .to_string(),
+        }
+    }
+
+    fn to_bytes(&self, region_code: u16) -> Result<Vec<u8>> {
+        let strings = [
+            &self.language,
+            &self.agree,
+            &self.disagree,
+            &self.print,
+            &self.save,
+            &self.message,
+        ];
+        let mut data = vec![];
+        data.write_u16::<BE>(strings.len() as u16)?;
+        for s in strings {
+            let bytes = encode_text(s, region_code)?;
+            anyhow::ensure!(bytes.len() <= 255, "license button label is too long");
+            data.write_u8(bytes.len() as u8)?;
+            data.extend_from_slice(&bytes);
+        }
+        Ok(data)
+    }
+
+    fn from_bytes(mut data: &[u8], region_code: u16) -> Result<Self> {
+        let count = data.read_u16::<BE>()?;
+        let mut strings = vec![];
+        for _ in 0..count {
+            let len = data.read_u8()?;
+            let mut bytes = vec![0; len as usize];
+            data.read_exact(&mut bytes)?;
+            strings.push(decode_text(&bytes, region_code)?);
+        }
+        anyhow::ensure!(
+            strings.len() >= 6,
+            "license STR# resource has too few strings"
+        );
+        let mut strings = strings.into_iter();
+        let mut next = || strings.next().unwrap();
+        Ok(Self {
+            language: next(),
+            agree: next(),
+            disagree: next(),
+            print: next(),
+            save: next(),
+            message: next(),
+        })
+    }
+}
+
+/// Content of a license agreement.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LicenseBody {
+    /// Plain text, stored in the script encoding of the region. Only Mac OS
+    /// Roman is supported, other regions are limited to ASCII.
+    Text(String),
+    /// Rich text format document.
+    Rtf(Vec<u8>),
+}
+
+/// A license agreement in one language.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct License {
+    /// Classic Mac OS region code of the language, e.g. 0 for English or 3 for German.
+    pub region_code: u16,
+    pub buttons: LicenseButtons,
+    pub body: LicenseBody,
+}
+
+impl License {
+    /// A plain text license in English.
+    pub fn english(text: &str) -> Self {
+        Self {
+            region_code: 0,
+            buttons: LicenseButtons::english(),
+            body: LicenseBody::Text(text.to_string()),
+        }
+    }
+}
+
+fn resource(id: u16, name: &str, data: Vec<u8>) -> Resource {
+    Resource {
+        attributes: "0x0000".to_string(),
+        cfname: None,
+        data,
+        id: id.to_string(),
+        name: name.to_string(),
+    }
+}
+
+fn find(resources: &[Resource], id: u16) -> Option<&Resource> {
+    let id = id.to_string();
+    resources.iter().find(|r| r.id == id)
+}
+
+impl ResourceFork {
+    /// Replace the license agreements of the image.
+    ///
+    /// The first license is shown by default. An empty slice removes all licenses.
+    ///
+    /// Labels and plain text that can't be encoded in the script of their
+    /// region are an error, see the [module](self) documentation.
+    pub fn set_licenses(&mut self, licenses: &[License]) -> Result<()> {
+        self.lpic.clear();
+        self.str.clear();
+        self.text.clear();
+        self.rtf.clear();
+        self.styl.clear();
+        let Some(default) = licenses.first() else {
+            return Ok(());
+        };
+
+        let mut lpic = vec![];
+        lpic.write_u16::<BE>(default.region_code)?;
+        lpic.write_u16::<BE>(licenses.len() as u16)?;
+        for (i, license) in licenses.iter().enumerate() {
+            let id = FIRST_RESOURCE_ID + i as u16;
+            lpic.write_u16::<BE>(license.region_code)?;
+            lpic.write_u16::<BE>(i as u16)?;
+            // Only single byte encodings are written.
+            lpic.write_u16::<BE>(0)?;
+
+            let name = &license.buttons.language;
+            self.str.push(resource(
+                id,
+                name,
+                license.buttons.to_bytes(license.region_code)?,
+            ));
+            match &license.body {
+                LicenseBody::Text(text) => {
+                    let text = text.replace("\r\n", "\r").replace('\n', "\r");
+                    let text = encode_text(&text, license.region_code)?;
+                    self.text.push(resource(id, name, text));
+                }
+                LicenseBody::Rtf(rtf) => self.rtf.push(resource(id, name, rtf.clone())),
+            }
+        }
+        self.lpic.push(resource(FIRST_RESOURCE_ID, "", lpic));
+        Ok(())
+    }
+
+    /// Parse the license agreements of the image, with the default license first.
+    pub fn licenses(&self) -> Result<Vec<License>> {
+        let Some(lpic) = find(&self.lpic, FIRST_RESOURCE_ID) else {
+            return Ok(vec![]);
+        };
+        let mut data = &lpic.data[..];
+        let default_region = data.read_u16::<BE>()?;
+        let count = data.read_u16::<BE>()?;
+        let mut licenses = vec![];
+        for _ in 0..count {
+            let region_code = data.read_u16::<BE>()?;
+            let id = FIRST_RESOURCE_ID + data.read_u16::<BE>()?;
+            let two_byte = data.read_u16::<BE>()?;
+            let buttons = find(&self.str, id)
+                .ok_or_else(|| anyhow::anyhow!("license {} has no STR# resource", id))?;
+            // Text in a two byte encoding is only accepted when it is ASCII.
+            let region = if two_byte != 0 { u16::MAX } else { region_code };
+            let buttons = LicenseButtons::from_bytes(&buttons.data, region)?;
+            let body = if let Some(rtf) = find(&self.rtf, id) {
+                LicenseBody::Rtf(rtf.data.clone())
+            } else if let Some(text) = find(&self.text, id) {
+                LicenseBody::Text(decode_text(&text.data, region)?.replace('\r', "\n"))
+            } else {
+                anyhow::bail!("license {} has no TEXT or RTF resource", id);
+            };
+            licenses.push(License {
+                region_code,
+                buttons,
+                body,
+            });
+        }
+        if let Some(i) = licenses
+            .iter()
+            .position(|l| l.region_code == default_region)
+        {
+            let default = licenses.remove(i);
+            licenses.insert(0, default);
+        }
+        Ok(licenses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn license_round_trip() -> Result<()> {
+        let german = License {
+            region_code: 3,
+            buttons: LicenseButtons {
+                language: "Deutsch".to_string(),
+                agree: "Akzeptieren".to_string(),
+                disagree: "Ablehnen".to_string(),
+                print: "Drucken".to_string(),
+                save: "Sichern...".to_string(),
+                message: "Bitte lesen Sie die Lizenzvereinbarung für dieses Programm.".to_string(),
+            },
+            body: LicenseBody::Rtf(b"{\\rtf1 Lizenz}".to_vec()),
+        };
+        let licenses = vec![License::english("Line 1\nLine 2 ©"), german];
+        let mut fork = ResourceFork::default();
+        fork.set_licenses(&licenses)?;
+        assert_eq!(fork.text[0].data, b"Line 1\rLine 2 \xa9");
+        assert_eq!(fork.licenses()?, licenses);
+
+        fork.set_licenses(&[])?;
+        assert!(fork.licenses()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn unencodable_text() {
+        let mut fork = ResourceFork::default();
+        assert!(fork.set_licenses(&[License::english("日本語")]).is_err());
+
+        // Japanese uses Mac OS Japanese, which isn't supported.
+        let mut japanese = License::english("ライセンス");
+        japanese.region_code = 14;
+        assert!(fork.set_licenses(&[japanese.clone()]).is_err());
+        japanese.body = LicenseBody::Text("License".to_string());
+        fork.set_licenses(&[japanese.clone()]).unwrap();
+        assert_eq!(fork.licenses().unwrap(), vec![japanese]);
+
+        // Non-ASCII text isn't decoded as Mac OS Roman for other regions.
+        fork.text[0].data = b"\x83\x89".to_vec();
+        assert!(fork.licenses().is_err());
+    }
+}
@@ -0,0 +1,203 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Settings for bit-for-bit reproducible images.
+//!
+//! Images normally embed random identifiers and the current time. In
+//! reproducible mode identifiers are derived from a seed and all times come
+//! from a fixed timestamp, following the `SOURCE_DATE_EPOCH` convention.
+
+use {
+    crate::layout::Guid,
+    anyhow::Result,
+    sha1::{Digest, Sha1},
+    std::{
+        cell::Cell,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+thread_local! {
+    /// Time returned by [now] on this thread instead of the system time.
+    static CLOCK: Cell<Option<i64>> = const { Cell::new(None) };
+}
+
+/// The current time as a Unix timestamp.
+pub(crate) fn now() -> i64 {
+    CLOCK.get().unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64)
+    })
+}
+
+/// Run `f` with [now] returning `unix` on this thread.
+pub(crate) fn with_clock<T>(unix: i64, f: impl FnOnce() -> T) -> T {
+    /// Restores the previous clock, even when `f` panics.
+    struct Restore(Option<i64>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CLOCK.set(self.0);
+        }
+    }
+
+    let _restore = Restore(CLOCK.replace(Some(unix)));
+    f()
+}
+
+/// Inputs replacing randomness and the current time when creating images.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Reproducible {
+    /// Namespace from which identifiers such as the segment id and GUIDs are derived.
+    pub seed: Guid,
+    /// Unix timestamp used as creation time. Newer modification times are
+    /// clamped to it.
+    pub timestamp: i64,
+}
+
+impl Reproducible {
+    pub fn new(seed: Guid, timestamp: i64) -> Self {
+        Self { seed, timestamp }
+    }
+
+    /// Read the timestamp from the `SOURCE_DATE_EPOCH` environment variable.
+    ///
+    /// The seed is derived from the timestamp. Returns `None` if the variable is
+    /// not set.
+    pub fn from_source_date_epoch() -> Result<Option<Self>> {
+        let Ok(value) = std::env::var("SOURCE_DATE_EPOCH") else {
+            return Ok(None);
+        };
+        let timestamp: i64 = value
+            .trim()
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid SOURCE_DATE_EPOCH {:?}", value))?;
+        let seed = Guid::from_name(&Guid::default(), timestamp.to_string().as_bytes());
+        Ok(Some(Self::new(seed, timestamp)))
+    }
+
+    /// Derive the identifier named `name` from the seed.
+    pub fn id(&self, name: &str) -> Guid {
+        Guid::from_name(&self.seed, name.as_bytes())
+    }
+}
+
+impl Guid {
+    /// Generate a name based version 5 GUID.
+    pub fn from_name(namespace: &Guid, name: &[u8]) -> Self {
+        // Name based GUIDs hash the big endian form of the namespace.
+        let mut namespace = namespace.0;
+        namespace[..4].reverse();
+        namespace[4..6].reverse();
+        namespace[6..8].reverse();
+        let hash = Sha1::new()
+            .chain_update(namespace)
+            .chain_update(name)
+            .finalize();
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&hash[..16]);
+        bytes[6] = (bytes[6] & 0x0f) | 0x50;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        bytes[..4].reverse();
+        bytes[4..6].reverse();
+        bytes[6..8].reverse();
+        Self(bytes)
+    }
+}
+
+/// Convert a Unix timestamp to a UTC calendar date and time.
+///
+/// Returns year, month, day, hour, minute and second.
+pub(crate) fn civil_time(unix: i64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = unix.div_euclid(86400);
+    let seconds = unix.rem_euclid(86400) as u32;
+    // Howard Hinnant's days_from_civil inverse.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+    )
+}
+
+/// FAT time provider returning [now] in UTC.
+///
+/// fatfs requires a provider that lives forever, so a fixed time is set with
+/// [with_clock] rather than stored in the provider.
+#[derive(Debug)]
+pub(crate) struct ClockTimeProvider;
+
+pub(crate) static CLOCK_TIME_PROVIDER: ClockTimeProvider = ClockTimeProvider;
+
+impl fatfs::TimeProvider for ClockTimeProvider {
+    fn get_current_date(&self) -> fatfs::Date {
+        self.get_current_date_time().date
+    }
+
+    /// FAT dates range from 1980 to 2107, other times are clamped.
+    fn get_current_date_time(&self) -> fatfs::DateTime {
+        let (year, month, day, hour, min, sec) = civil_time(now().clamp(315532800, 4354819199));
+        fatfs::DateTime {
+            date: fatfs::Date {
+                year: year as u16,
+                month: month as u16,
+                day: day as u16,
+            },
+            time: fatfs::Time {
+                hour: hour as u16,
+                min: min as u16,
+                sec: sec as u16,
+                millis: 0,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_based_guid() -> Result<()> {
+        // RFC 4122 DNS namespace and the well known v5 GUID of "python.org".
+        let dns = "6ba7b810-9dad-11d1-80b4-00c04fd430c8".parse()?;
+        let guid = Guid::from_name(&dns, b"python.org");
+        assert_eq!(guid.to_string(), "886313E1-3B8A-5372-9B90-0C9AEE199E5D");
+        Ok(())
+    }
+
+    #[test]
+    fn clock_is_restored() {
+        let result = std::panic::catch_unwind(|| {
+            with_clock(42, || {
+                assert_eq!(with_clock(7, now), 7);
+                assert_eq!(now(), 42);
+                panic!("interrupted");
+            })
+        });
+        assert!(result.is_err());
+        assert_eq!(CLOCK.get(), None);
+    }
+
+    #[test]
+    fn civil() {
+        assert_eq!(civil_time(0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(civil_time(951782400 + 3661), (2000, 2, 29, 1, 1, 1));
+        assert_eq!(civil_time(-1), (1969, 12, 31, 23, 59, 59));
+    }
+}
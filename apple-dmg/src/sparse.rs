@@ -0,0 +1,396 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sparse images (`.sparseimage`) and sparse bundles (`.sparsebundle`).
+//!
+//! Both store the disk in fixed size bands that are only allocated once
+//! written. A sparse image keeps the bands in a single file, in allocation
+//! order, behind a header mapping storage slots to band numbers. A sparse bundle
+//! is a directory with an `Info.plist` and one file per band in `bands/`.
+
+use {
+    anyhow::Result,
+    byteorder::{ReadBytesExt, WriteBytesExt, BE},
+    serde::{Deserialize, Serialize},
+    std::{
+        fs::File,
+        io::{BufReader, Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+    },
+};
+
+pub const SPARSE_IMAGE_SIGNATURE: u32 = u32::from_be_bytes(*b"sprs");
+const SECTOR_SIZE: u64 = 512;
+const HEADER_ALIGNMENT: u64 = 4096;
+const BAND_MAP_OFFSET: u64 = 64;
+
+/// Header of a sparse image.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SparseImageHeader {
+    pub version: u32,
+    pub sectors_per_band: u32,
+    /// Size of the disk in 512 byte sectors.
+    pub sector_count: u64,
+    /// Band number stored in each slot, starting at 1. 0 marks an unused slot.
+    pub bands: Vec<u32>,
+}
+
+impl SparseImageHeader {
+    pub fn new(sectors_per_band: u32, sector_count: u64) -> Self {
+        Self {
+            version: 3,
+            sectors_per_band,
+            sector_count,
+            bands: vec![0; sector_count.div_ceil(sectors_per_band as u64) as usize],
+        }
+    }
+
+    pub fn band_size(&self) -> u64 {
+        self.sectors_per_band as u64 * SECTOR_SIZE
+    }
+
+    pub fn band_count(&self) -> u64 {
+        self.sector_count.div_ceil(self.sectors_per_band as u64)
+    }
+
+    /// Offset of the first band, following the header and band map.
+    pub fn data_offset(&self) -> u64 {
+        (BAND_MAP_OFFSET + 4 * self.band_count()).next_multiple_of(HEADER_ALIGNMENT)
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let signature = r.read_u32::<BE>()?;
+        anyhow::ensure!(
+            signature == SPARSE_IMAGE_SIGNATURE,
+            "invalid sparse image signature"
+        );
+        let version = r.read_u32::<BE>()?;
+        let sectors_per_band = r.read_u32::<BE>()?;
+        anyhow::ensure!(sectors_per_band > 0, "invalid sparse image band size");
+        let _flags = r.read_u32::<BE>()?;
+        let legacy_sector_count = r.read_u32::<BE>()?;
+        let _reserved1 = r.read_u64::<BE>()?;
+        let sector_count = match r.read_u64::<BE>()? {
+            0 => legacy_sector_count as u64,
+            sector_count => sector_count,
+        };
+        let mut reserved2 = [0; 28];
+        r.read_exact(&mut reserved2)?;
+        // The band map grows as it is read, so a corrupted sector count can't
+        // allocate more than the image holds.
+        let mut bands = vec![];
+        for _ in 0..sector_count.div_ceil(sectors_per_band as u64) {
+            bands.push(r.read_u32::<BE>()?);
+        }
+        Ok(Self {
+            version,
+            sectors_per_band,
+            sector_count,
+            bands,
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_u32::<BE>(SPARSE_IMAGE_SIGNATURE)?;
+        w.write_u32::<BE>(self.version)?;
+        w.write_u32::<BE>(self.sectors_per_band)?;
+        w.write_u32::<BE>(1)?;
+        w.write_u32::<BE>(self.sector_count.min(u32::MAX as u64) as u32)?;
+        w.write_u64::<BE>(0)?;
+        w.write_u64::<BE>(self.sector_count)?;
+        w.write_all(&[0; 28])?;
+        for i in 0..self.band_count() as usize {
+            w.write_u32::<BE>(self.bands.get(i).copied().unwrap_or(0))?;
+        }
+        let len = BAND_MAP_OFFSET + 4 * self.band_count();
+        w.write_all(&vec![0; (self.data_offset() - len) as usize])?;
+        Ok(())
+    }
+}
+
+/// Map a position on the disk to a band and an offset within it.
+fn locate(position: u64, band_size: u64) -> (u64, u64) {
+    (position / band_size, position % band_size)
+}
+
+/// `Read + Seek` view of the disk stored in a sparse image.
+///
+/// Unallocated bands read as zeros.
+pub struct SparseImageReader<R: Read + Seek> {
+    header: SparseImageHeader,
+    /// Storage slot of each band.
+    slots: Vec<Option<u64>>,
+    r: R,
+    position: u64,
+}
+
+impl SparseImageReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> SparseImageReader<R> {
+    pub fn new(mut r: R) -> Result<Self> {
+        r.seek(SeekFrom::Start(0))?;
+        let header = SparseImageHeader::read_from(&mut r)?;
+        let mut slots = vec![None; header.band_count() as usize];
+        for (slot, band) in header.bands.iter().enumerate() {
+            if *band == 0 {
+                continue;
+            }
+            let band = slots
+                .get_mut(*band as usize - 1)
+                .ok_or_else(|| anyhow::anyhow!("sparse image band {} is out of range", band))?;
+            anyhow::ensure!(band.is_none(), "sparse image band is stored twice");
+            *band = Some(slot as u64);
+        }
+        Ok(Self {
+            header,
+            slots,
+            r,
+            position: 0,
+        })
+    }
+
+    pub fn header(&self) -> &SparseImageHeader {
+        &self.header
+    }
+
+    /// Size of the disk in bytes.
+    pub fn len(&self) -> u64 {
+        self.header.sector_count * SECTOR_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<R: Read + Seek> Read for SparseImageReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let band_size = self.header.band_size();
+        let remaining = self.len().saturating_sub(self.position);
+        let (band, offset) = locate(self.position, band_size);
+        let len = (buf.len() as u64).min(remaining).min(band_size - offset) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        let buf = &mut buf[..len];
+        match self.slots[band as usize] {
+            Some(slot) => {
+                let start = self.header.data_offset() + slot * band_size + offset;
+                self.r.seek(SeekFrom::Start(start))?;
+                self.r.read_exact(buf)?;
+            }
+            None => buf.fill(0),
+        }
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for SparseImageReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = seek_position(pos, self.position, self.len())?;
+        Ok(self.position)
+    }
+}
+
+pub(crate) fn seek_position(pos: SeekFrom, position: u64, len: u64) -> std::io::Result<u64> {
+    let new = match pos {
+        SeekFrom::Start(offset) => Some(offset),
+        SeekFrom::Current(offset) => position.checked_add_signed(offset),
+        SeekFrom::End(offset) => len.checked_add_signed(offset),
+    };
+    new.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "seek to a negative position",
+        )
+    })
+}
+
+/// `Info.plist` of a sparse bundle.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SparseBundleInfo {
+    #[serde(rename = "CFBundleInfoDictionaryVersion")]
+    pub info_dictionary_version: String,
+    /// Size of a band file in bytes.
+    #[serde(rename = "band-size")]
+    pub band_size: u64,
+    #[serde(rename = "bundle-backingstore-version")]
+    pub backing_store_version: u32,
+    #[serde(rename = "diskimage-bundle-type")]
+    pub bundle_type: String,
+    /// Size of the disk in bytes.
+    pub size: u64,
+}
+
+impl SparseBundleInfo {
+    pub fn new(band_size: u64, size: u64) -> Self {
+        Self {
+            info_dictionary_version: "6.0".to_string(),
+            band_size,
+            backing_store_version: 1,
+            bundle_type: "com.apple.diskimage.sparsebundle".to_string(),
+            size,
+        }
+    }
+}
+
+/// `Read + Seek` view of the disk stored in a sparse bundle.
+///
+/// Band files are named after the band number in lowercase hexadecimal. Missing
+/// bands and the part of a band beyond the end of its file read as zeros.
+pub struct SparseBundleReader {
+    path: PathBuf,
+    info: SparseBundleInfo,
+    band: Option<(u64, Option<File>)>,
+    position: u64,
+}
+
+impl SparseBundleReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let info: SparseBundleInfo = plist::from_file(path.join("Info.plist"))?;
+        anyhow::ensure!(
+            info.bundle_type == "com.apple.diskimage.sparsebundle",
+            "unsupported bundle type {}",
+            info.bundle_type
+        );
+        anyhow::ensure!(info.band_size > 0, "invalid sparse bundle band size");
+        Ok(Self {
+            path: path.to_path_buf(),
+            info,
+            band: None,
+            position: 0,
+        })
+    }
+
+    pub fn info(&self) -> &SparseBundleInfo {
+        &self.info
+    }
+
+    /// Size of the disk in bytes.
+    pub fn len(&self) -> u64 {
+        self.info.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn band_file(&mut self, band: u64) -> std::io::Result<Option<&mut File>> {
+        if !matches!(self.band, Some((b, _)) if b == band) {
+            let path = self.path.join("bands").join(format!("{:x}", band));
+            let file = match File::open(path) {
+                Ok(file) => Some(file),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            self.band = Some((band, file));
+        }
+        Ok(self.band.as_mut().unwrap().1.as_mut())
+    }
+}
+
+impl Read for SparseBundleReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let band_size = self.info.band_size;
+        let remaining = self.len().saturating_sub(self.position);
+        let (band, offset) = locate(self.position, band_size);
+        let len = (buf.len() as u64).min(remaining).min(band_size - offset) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        let buf = &mut buf[..len];
+        buf.fill(0);
+        if let Some(file) = self.band_file(band)? {
+            file.seek(SeekFrom::Start(offset))?;
+            let mut filled = 0;
+            while filled < len {
+                match file.read(&mut buf[filled..])? {
+                    0 => break,
+                    n => filled += n,
+                }
+            }
+        }
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for SparseBundleReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = seek_position(pos, self.position, self.len())?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::io::Cursor};
+
+    #[test]
+    fn sparse_image() -> Result<()> {
+        let mut header = SparseImageHeader::new(8, 40);
+        // Band 3 was written first, then band 1.
+        header.bands[0] = 3;
+        header.bands[1] = 1;
+        let mut image = vec![];
+        header.write_to(&mut image)?;
+        assert_eq!(image.len() as u64, header.data_offset());
+        image.extend_from_slice(&[3; 4096]);
+        image.extend_from_slice(&[1; 4096]);
+
+        let mut reader = SparseImageReader::new(Cursor::new(image))?;
+        assert_eq!(reader.header(), &header);
+        let mut disk = vec![];
+        reader.read_to_end(&mut disk)?;
+        assert_eq!(disk.len(), 40 * 512);
+        assert_eq!(disk[..4096], [1; 4096]);
+        assert_eq!(disk[4096..8192], [0; 4096]);
+        assert_eq!(disk[8192..12288], [3; 4096]);
+        assert!(disk[12288..].iter().all(|b| *b == 0));
+
+        reader.seek(SeekFrom::Start(8190))?;
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf)?;
+        assert_eq!(buf, [0, 0, 3, 3]);
+
+        // A sector count larger than the band map fails without allocating it.
+        let mut image = vec![];
+        header.write_to(&mut image)?;
+        image[28..36].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(SparseImageReader::new(Cursor::new(image)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sparse_bundle() -> Result<()> {
+        let bundle =
+            std::env::temp_dir().join(format!("apple-dmg-{}.sparsebundle", std::process::id()));
+        std::fs::create_dir_all(bundle.join("bands"))?;
+        plist::to_file_xml(bundle.join("Info.plist"), &SparseBundleInfo::new(16, 40))?;
+        std::fs::write(bundle.join("bands/0"), [1; 16])?;
+        std::fs::write(bundle.join("bands/2"), [2; 4])?;
+
+        let mut reader = SparseBundleReader::open(&bundle)?;
+        let mut disk = vec![];
+        reader.read_to_end(&mut disk)?;
+        let mut expected = vec![1; 16];
+        expected.extend_from_slice(&[0; 16]);
+        expected.extend_from_slice(&[2, 2, 2, 2, 0, 0, 0, 0]);
+        assert_eq!(disk, expected);
+
+        reader.seek(SeekFrom::End(-10))?;
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf)?;
+        assert_eq!(buf, [0, 0, 2, 2, 2, 2, 0, 0, 0, 0]);
+        std::fs::remove_dir_all(&bundle)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Streaming access to the disk stored in a UDIF image.
+//!
+//! The disk is assembled like [DmgReader::disk_data] does, by placing every
+//! partition at its first sector, but chunks are only decompressed when read.
+
+use {
+    crate::{sparse::seek_position, BlkxChunk, ChunkType, DmgReader},
+    anyhow::Result,
+    std::io::{Read, Seek, SeekFrom},
+};
+
+const SECTOR_SIZE: u64 = 512;
+
+/// Largest chunk decompressed into memory. `hdiutil` writes chunks of 1 MiB.
+const MAX_CHUNK_SIZE: u64 = 64 << 20;
+
+/// A chunk holding data, placed on the disk.
+struct DiskChunk {
+    /// Offset of the chunk on the disk.
+    start: u64,
+    /// Size of the chunk on the disk.
+    len: u64,
+    chunk: BlkxChunk,
+}
+
+/// `Read + Seek` view of the disk stored in a UDIF image.
+///
+/// Compressed chunks are decompressed one at a time as they are read. Zero
+/// chunks and sectors outside of partitions read as zeros.
+pub struct UdifReader<R: Read + Seek> {
+    dmg: DmgReader<R>,
+    /// Chunks holding data, sorted by their offset on the disk.
+    chunks: Vec<DiskChunk>,
+    len: u64,
+    position: u64,
+    /// Index and content of the last decompressed chunk.
+    cache: Option<(usize, Vec<u8>)>,
+}
+
+fn sectors(count: u64) -> Result<u64> {
+    count
+        .checked_mul(SECTOR_SIZE)
+        .ok_or_else(|| anyhow::anyhow!("UDIF sector count is out of range"))
+}
+
+impl<R: Read + Seek> UdifReader<R> {
+    /// Read the whole disk.
+    pub fn new(dmg: DmgReader<R>) -> Result<Self> {
+        let len = sectors(dmg.koly().sector_count)?;
+        let partitions = 0..dmg.plist().partitions().len();
+        Self::with_partitions(dmg, partitions, true, len)
+    }
+
+    /// Read partition `i` only, like [DmgReader::partition_data] does.
+    pub fn partition(dmg: DmgReader<R>, i: usize) -> Result<Self> {
+        anyhow::ensure!(
+            i < dmg.plist().partitions().len(),
+            "UDIF partition {} does not exist",
+            i
+        );
+        let len = dmg
+            .partition_table(i)?
+            .chunks
+            .iter()
+            .try_fold(0u64, |len, chunk| {
+                len.checked_add(sectors(chunk.sector_count)?)
+                    .ok_or_else(|| anyhow::anyhow!("UDIF partition {} is out of range", i))
+            })?;
+        Self::with_partitions(dmg, i..i + 1, false, len)
+    }
+
+    /// Place the chunks of `partitions` at their first sector on the disk, or
+    /// at the start when `on_disk` is false.
+    fn with_partitions(
+        dmg: DmgReader<R>,
+        partitions: std::ops::Range<usize>,
+        on_disk: bool,
+        len: u64,
+    ) -> Result<Self> {
+        let mut chunks = vec![];
+        for i in partitions {
+            let table = dmg.partition_table(i)?;
+            let mut start = if on_disk {
+                sectors(table.sector_number)?
+            } else {
+                0
+            };
+            for chunk in table.chunks {
+                let chunk_len = sectors(chunk.sector_count)?;
+                let holds_data = match chunk.ty() {
+                    Some(
+                        ChunkType::Zero | ChunkType::Ignore | ChunkType::Comment | ChunkType::Term,
+                    ) => false,
+                    Some(ChunkType::Raw) => {
+                        anyhow::ensure!(
+                            chunk.compressed_length >= chunk_len,
+                            "UDIF raw chunk is truncated"
+                        );
+                        true
+                    }
+                    Some(ChunkType::Zlib) => {
+                        anyhow::ensure!(chunk_len <= MAX_CHUNK_SIZE, "UDIF chunk is too large");
+                        true
+                    }
+                    ty => anyhow::bail!("unsupported UDIF chunk type {:?}", ty),
+                };
+                if holds_data && chunk_len > 0 {
+                    chunks.push(DiskChunk {
+                        start,
+                        len: chunk_len,
+                        chunk,
+                    });
+                }
+                start = start
+                    .checked_add(chunk_len)
+                    .ok_or_else(|| anyhow::anyhow!("UDIF partition {} is out of range", i))?;
+            }
+        }
+        chunks.sort_by_key(|c| c.start);
+
+        Ok(Self {
+            dmg,
+            chunks,
+            len,
+            position: 0,
+            cache: None,
+        })
+    }
+
+    /// Size of the disk in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Find the chunk holding `position`, or the offset of the next chunk.
+    fn locate(&self, position: u64) -> std::result::Result<usize, u64> {
+        let i = self.chunks.partition_point(|c| c.start <= position);
+        match i.checked_sub(1).map(|i| (i, &self.chunks[i])) {
+            Some((i, c)) if position < c.start + c.len => Ok(i),
+            _ => Err(self.chunks.get(i).map_or(self.len, |c| c.start)),
+        }
+    }
+
+    /// Decompress chunk `i` unless it is the cached one.
+    fn decompress(&mut self, i: usize) -> std::io::Result<&[u8]> {
+        if !matches!(&self.cache, Some((cached, _)) if *cached == i) {
+            let DiskChunk { len, chunk, .. } = self.chunks[i];
+            let mut data = Vec::with_capacity(len as usize);
+            self.dmg
+                .sector(&chunk)
+                .map_err(std::io::Error::other)?
+                .take(len)
+                .read_to_end(&mut data)?;
+            if data.len() as u64 != len {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "UDIF chunk is truncated",
+                ));
+            }
+            self.cache = Some((i, data));
+        }
+        Ok(&self.cache.as_ref().expect("cache is set").1)
+    }
+}
+
+impl<R: Read + Seek> Read for UdifReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.position);
+        let len = (buf.len() as u64).min(remaining);
+        if len == 0 {
+            return Ok(0);
+        }
+        let len = match self.locate(self.position) {
+            Ok(i) => {
+                let DiskChunk {
+                    start,
+                    len: chunk_len,
+                    chunk,
+                } = self.chunks[i];
+                let offset = self.position - start;
+                let len = len.min(chunk_len - offset) as usize;
+                let buf = &mut buf[..len];
+                if chunk.ty() == Some(ChunkType::Raw) {
+                    self.dmg
+                        .r
+                        .seek(SeekFrom::Start(chunk.compressed_offset + offset))?;
+                    self.dmg.r.read_exact(buf)?;
+                } else {
+                    let offset = offset as usize;
+                    buf.copy_from_slice(&self.decompress(i)?[offset..offset + len]);
+                }
+                len
+            }
+            Err(next) => {
+                let len = len.min(next - self.position) as usize;
+                buf[..len].fill(0);
+                len
+            }
+        };
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for UdifReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = seek_position(pos, self.position, self.len)?;
+        Ok(self.position)
+    }
+}
@@ -6,6 +6,7 @@
 use {
     crate::blkx::BlkxTable,
     anyhow::Result,
+    byteorder::{ReadBytesExt, WriteBytesExt, LE},
     serde::{Deserialize, Serialize},
 };
 
@@ -30,8 +31,116 @@ impl Plist {
 #[serde(deny_unknown_fields)]
 pub struct ResourceFork {
     pub blkx: Vec<Partition>,
+    /// Partition list resources.
+    ///
+    /// Their data isn't documented and is preserved as is.
     #[serde(default)]
-    pub plst: Vec<Partition>,
+    pub plst: Vec<Resource>,
+    /// Checksums, see [ResourceFork::checksums].
+    #[serde(rename = "cSum", default, skip_serializing_if = "Vec::is_empty")]
+    pub csum: Vec<Resource>,
+    /// License agreement languages.
+    #[serde(rename = "LPic", default, skip_serializing_if = "Vec::is_empty")]
+    pub lpic: Vec<Resource>,
+    /// License agreement button labels.
+    #[serde(rename = "STR#", default, skip_serializing_if = "Vec::is_empty")]
+    pub str: Vec<Resource>,
+    /// Plain text license agreements.
+    #[serde(rename = "TEXT", default, skip_serializing_if = "Vec::is_empty")]
+    pub text: Vec<Resource>,
+    /// Rich text license agreements.
+    #[serde(rename = "RTF ", default, skip_serializing_if = "Vec::is_empty")]
+    pub rtf: Vec<Resource>,
+    /// Styles of plain text license agreements.
+    #[serde(rename = "styl", default, skip_serializing_if = "Vec::is_empty")]
+    pub styl: Vec<Resource>,
+}
+
+/// A classic Mac OS resource stored in the resource fork plist.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Resource {
+    #[serde(rename = "Attributes")]
+    pub attributes: String,
+    #[serde(rename = "CFName", default, skip_serializing_if = "Option::is_none")]
+    pub cfname: Option<String>,
+    #[serde(rename = "Data")]
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Name")]
+    pub name: String,
+}
+
+/// The data of a `cSum` resource.
+///
+/// The layout is the one seen in existing images: a version, the checksum type
+/// and the checksum, little-endian.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChecksumResource {
+    pub version: u16,
+    /// `2` for CRC-32.
+    pub checksum_type: u32,
+    pub checksum: u32,
+}
+
+impl ChecksumResource {
+    /// A CRC-32 checksum.
+    pub fn crc32(checksum: u32) -> Self {
+        Self {
+            version: 1,
+            checksum_type: 2,
+            checksum,
+        }
+    }
+
+    pub fn from_bytes(mut data: &[u8]) -> Result<Self> {
+        anyhow::ensure!(data.len() == 10, "cSum resource is not 10 bytes");
+        Ok(Self {
+            version: data.read_u16::<LE>()?,
+            checksum_type: data.read_u32::<LE>()?,
+            checksum: data.read_u32::<LE>()?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(10);
+        data.write_u16::<LE>(self.version)?;
+        data.write_u32::<LE>(self.checksum_type)?;
+        data.write_u32::<LE>(self.checksum)?;
+        Ok(data)
+    }
+}
+
+impl ResourceFork {
+    /// Parse the `cSum` resources, ordered by resource id.
+    pub fn checksums(&self) -> Result<Vec<(i32, ChecksumResource)>> {
+        let mut checksums = self
+            .csum
+            .iter()
+            .map(|r| Ok((r.id.parse()?, ChecksumResource::from_bytes(&r.data)?)))
+            .collect::<Result<Vec<_>>>()?;
+        checksums.sort_by_key(|(id, _)| *id);
+        Ok(checksums)
+    }
+
+    /// Replace the `cSum` resources.
+    pub fn set_checksums(&mut self, checksums: &[(i32, ChecksumResource)]) -> Result<()> {
+        self.csum = checksums
+            .iter()
+            .map(|(id, checksum)| {
+                Ok(Resource {
+                    attributes: "0x0000".to_string(),
+                    cfname: None,
+                    data: checksum.to_bytes()?,
+                    id: id.to_string(),
+                    name: String::new(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -68,3 +177,40 @@ impl Partition {
         BlkxTable::read_from(&mut &self.data[..])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::KolyTrailer, std::io::Cursor};
+
+    #[test]
+    fn plist_round_trip() -> Result<()> {
+        let dmg = include_bytes!("../assets/example.dmg");
+        let koly = KolyTrailer::read_from(&mut Cursor::new(&dmg[..]))?;
+        let xml = &dmg[koly.plist_offset as usize..][..koly.plist_length as usize];
+
+        let mut plist: Plist = plist::from_reader_xml(xml)?;
+        assert_eq!(plist.resource_fork.plst.len(), 1);
+        assert_eq!(plist.resource_fork.plst[0].cfname, None);
+
+        let mut written = vec![];
+        plist::to_writer_xml(&mut written, &plist)?;
+        assert_eq!(
+            plist::Value::from_reader_xml(&written[..])?,
+            plist::Value::from_reader_xml(xml)?
+        );
+
+        let checksums = vec![(2, ChecksumResource::crc32(0x1234_5678))];
+        plist.resource_fork.set_checksums(&checksums)?;
+        assert_eq!(
+            plist.resource_fork.csum[0].data,
+            b"\x01\x00\x02\x00\x00\x00\x78\x56\x34\x12"
+        );
+
+        let mut written = vec![];
+        plist::to_writer_xml(&mut written, &plist)?;
+        let plist: Plist = plist::from_reader_xml(&written[..])?;
+        assert_eq!(plist.resource_fork.checksums()?, checksums);
+
+        Ok(())
+    }
+}
@@ -5,13 +5,30 @@
 //! Interface to component packages, installable units within flat packages.
 
 use {
-    crate::{package_info::PackageInfo, PkgResult},
-    cpio_archive::ChainedCpioReader,
-    std::io::{Cursor, Read},
+    crate::{
+        package_info::{PackageInfo, Payload, PostInstall, PreInstall, Script},
+        pbzx::{PbzxReader, PbzxWriter, PBZX_MAGIC, XZ_MAGIC},
+        Error, PkgResult,
+    },
+    apple_bom::{builder::BomBuilder, BomPath, ParsedBom},
+    apple_xar::{builder::XarBuilder, table_of_contents::Encoding},
+    chrono::{DateTime, Utc},
+    cpio_archive::{ChainedCpioReader, CpioHeader, OdcBuilder},
+    std::{
+        collections::BTreeMap,
+        fs::Metadata,
+        io::{Cursor, Read, Write},
+        path::{Component, Path, PathBuf},
+    },
 };
 
 const GZIP_HEADER: [u8; 3] = [0x1f, 0x8b, 0x08];
 
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
 /// Attempt to decode the compressed content of an archive file.
 ///
 /// The content can be compressed with various formats. This attempts to
@@ -19,6 +36,10 @@ const GZIP_HEADER: [u8; 3] = [0x1f, 0x8b, 0x08];
 fn decode_archive(data: Vec<u8>) -> PkgResult<Box<dyn Read>> {
     if data.len() > 3 && data[0..3] == GZIP_HEADER {
         Ok(Box::new(flate2::read::GzDecoder::new(Cursor::new(data))) as Box<dyn Read>)
+    } else if data.starts_with(&PBZX_MAGIC) {
+        Ok(Box::new(PbzxReader::new(Cursor::new(data))?) as Box<dyn Read>)
+    } else if data.starts_with(&XZ_MAGIC) {
+        Ok(Box::new(xz2::read::XzDecoder::new(Cursor::new(data))) as Box<dyn Read>)
     } else {
         Ok(Box::new(Cursor::new(data)) as Box<dyn Read>)
     }
@@ -89,4 +110,1130 @@ impl ComponentPackageReader {
             Ok(None)
         }
     }
+
+    /// Parse the path records of the `Bom`.
+    fn bom_paths(&self) -> PkgResult<Option<Vec<BomPath>>> {
+        if let Some(data) = &self.bom {
+            Ok(Some(ParsedBom::parse(data)?.paths()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Extract the `Payload` to a directory and verify it against the `Bom`.
+    ///
+    /// Regular files, directories and symlinks are extracted with their permissions
+    /// and modification times. Ownership is not restored and setuid/setgid bits are
+    /// cleared. Entries that would be written outside `dest_dir` are rejected.
+    ///
+    /// The mode, owner, size, checksum and link target of every entry are compared
+    /// against the `Bom`. Returns the differences found. If there is no `Bom`, the
+    /// payload is extracted without verification.
+    pub fn extract_payload(
+        &self,
+        dest_dir: impl AsRef<Path>,
+    ) -> PkgResult<Vec<PayloadDiscrepancy>> {
+        let Some(reader) = self.payload_reader()? else {
+            return Ok(vec![]);
+        };
+
+        extract_cpio(reader, Some(dest_dir.as_ref()), self.bom_paths()?)
+    }
+
+    /// Verify the `Payload` against the `Bom` without extracting it.
+    ///
+    /// See [Self::extract_payload] for details.
+    pub fn verify_payload(&self) -> PkgResult<Vec<PayloadDiscrepancy>> {
+        let Some(reader) = self.payload_reader()? else {
+            return Ok(vec![]);
+        };
+
+        extract_cpio(reader, None, self.bom_paths()?)
+    }
+
+    /// Extract the `Scripts` archive to a directory.
+    pub fn extract_scripts(&self, dest_dir: impl AsRef<Path>) -> PkgResult<()> {
+        if let Some(reader) = self.scripts_reader()? {
+            extract_cpio(reader, Some(dest_dir.as_ref()), None)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A difference between an entry in a `Payload` and its record in the `Bom`.
+///
+/// Discrepancies indicate a package that was modified after it was built or a
+/// bug in the tool that built it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PayloadDiscrepancy {
+    /// A payload entry has no `Bom` record.
+    NotInBom(String),
+
+    /// A `Bom` record has no payload entry.
+    NotInPayload(String),
+
+    /// The file modes, including the file type, differ.
+    Mode {
+        path: String,
+        bom: u16,
+        payload: u16,
+    },
+
+    /// The owners, as `(uid, gid)`, differ.
+    Owner {
+        path: String,
+        bom: (u32, u32),
+        payload: (u32, u32),
+    },
+
+    /// The sizes of a regular file differ.
+    Size {
+        path: String,
+        bom: u64,
+        payload: u64,
+    },
+
+    /// The content of a regular file does not match the `Bom` checksum.
+    Checksum {
+        path: String,
+        bom: u32,
+        payload: u32,
+    },
+
+    /// The targets of a symlink differ.
+    LinkName {
+        path: String,
+        bom: Option<String>,
+        payload: String,
+    },
+}
+
+impl PayloadDiscrepancy {
+    /// The path of the entry, as stored in the `Bom`.
+    pub fn path(&self) -> &str {
+        match self {
+            Self::NotInBom(path) | Self::NotInPayload(path) => path,
+            Self::Mode { path, .. }
+            | Self::Owner { path, .. }
+            | Self::Size { path, .. }
+            | Self::Checksum { path, .. }
+            | Self::LinkName { path, .. } => path,
+        }
+    }
+}
+
+impl std::fmt::Display for PayloadDiscrepancy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotInBom(path) => write!(f, "{path}: not in Bom"),
+            Self::NotInPayload(path) => write!(f, "{path}: not in Payload"),
+            Self::Mode { path, bom, payload } => {
+                write!(f, "{path}: mode {payload:o} does not match Bom {bom:o}")
+            }
+            Self::Owner { path, bom, payload } => write!(
+                f,
+                "{path}: owner {}/{} does not match Bom {}/{}",
+                payload.0, payload.1, bom.0, bom.1
+            ),
+            Self::Size { path, bom, payload } => {
+                write!(f, "{path}: size {payload} does not match Bom {bom}")
+            }
+            Self::Checksum { path, bom, payload } => {
+                write!(f, "{path}: checksum {payload} does not match Bom {bom}")
+            }
+            Self::LinkName { path, bom, payload } => write!(
+                f,
+                "{path}: link target {payload} does not match Bom {}",
+                bom.as_deref().unwrap_or("(none)")
+            ),
+        }
+    }
+}
+
+/// Normalize a cpio entry name to the form of `Bom` paths, e.g. `./usr/bin`.
+fn bom_path(name: &str) -> String {
+    let mut path = name;
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    let path = path.trim_end_matches('/');
+
+    if path.is_empty() || path == "." {
+        ".".to_string()
+    } else {
+        format!("./{path}")
+    }
+}
+
+/// Resolve the filesystem path to extract a cpio entry to.
+///
+/// Returns `None` for the root entry. Names escaping the destination directory
+/// or traversing symlinks created by earlier entries are rejected. Only
+/// components below `dest_dir` are checked, `dest_dir` itself may be or contain
+/// a symlink.
+fn safe_destination(dest_dir: &Path, name: &str) -> PkgResult<Option<PathBuf>> {
+    let mut dest = dest_dir.to_path_buf();
+    let mut is_root = true;
+
+    for component in Path::new(name).components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(part) => {
+                if !is_root && dest.symlink_metadata().is_ok_and(|m| m.is_symlink()) {
+                    return Err(Error::UnsafePayloadPath(name.to_string()));
+                }
+                dest.push(part);
+                is_root = false;
+            }
+            _ => return Err(Error::UnsafePayloadPath(name.to_string())),
+        }
+    }
+
+    Ok(if is_root { None } else { Some(dest) })
+}
+
+/// CRC table of the POSIX `cksum` algorithm.
+const CKSUM_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Computes the checksum file content is recorded with in a `Bom`.
+///
+/// `mkbom` and `BomBuilder` record the checksum of the POSIX `cksum` utility.
+#[derive(Default)]
+struct ContentChecksum {
+    cksum: u32,
+    size: u64,
+}
+
+impl ContentChecksum {
+    fn update(&mut self, data: &[u8]) {
+        for b in data {
+            self.cksum = (self.cksum << 8) ^ CKSUM_TABLE[((self.cksum >> 24) as u8 ^ b) as usize];
+        }
+        self.size += data.len() as u64;
+    }
+
+    /// Finish, returning the `cksum` checksum.
+    fn finalize(self) -> u32 {
+        let mut cksum = self.cksum;
+        let mut size = self.size;
+        while size != 0 {
+            cksum = (cksum << 8) ^ CKSUM_TABLE[((cksum >> 24) as u8 ^ size as u8) as usize];
+            size >>= 8;
+        }
+
+        !cksum
+    }
+}
+
+/// Compare a cpio entry against its `Bom` record.
+fn compare_entry(
+    path: &str,
+    header: &dyn CpioHeader,
+    entry: &BomPath,
+    checksum: Option<u32>,
+    link_name: Option<&str>,
+    discrepancies: &mut Vec<PayloadDiscrepancy>,
+) {
+    let mode = header.mode() as u16;
+    if entry.file_mode() != mode {
+        discrepancies.push(PayloadDiscrepancy::Mode {
+            path: path.to_string(),
+            bom: entry.file_mode(),
+            payload: mode,
+        });
+    }
+
+    let owner = (header.uid(), header.gid());
+    if (entry.user_id(), entry.group_id()) != owner {
+        discrepancies.push(PayloadDiscrepancy::Owner {
+            path: path.to_string(),
+            bom: (entry.user_id(), entry.group_id()),
+            payload: owner,
+        });
+    }
+
+    if let Some(cksum) = checksum {
+        if entry.size() as u64 != header.file_size() {
+            discrepancies.push(PayloadDiscrepancy::Size {
+                path: path.to_string(),
+                bom: entry.size() as u64,
+                payload: header.file_size(),
+            });
+        }
+
+        let recorded = entry.crc32().unwrap_or_default();
+        if recorded != cksum {
+            discrepancies.push(PayloadDiscrepancy::Checksum {
+                path: path.to_string(),
+                bom: recorded,
+                payload: cksum,
+            });
+        }
+    }
+
+    if let Some(link_name) = link_name {
+        if entry.link_name() != Some(link_name) {
+            discrepancies.push(PayloadDiscrepancy::LinkName {
+                path: path.to_string(),
+                bom: entry.link_name().map(|s| s.to_string()),
+                payload: link_name.to_string(),
+            });
+        }
+    }
+}
+
+/// Extract a cpio archive and/or compare it against `Bom` records.
+///
+/// Entries are extracted below `dest_dir` if given. Regular files, directories and
+/// symlinks are extracted. Ownership is not restored and other entry types are
+/// skipped.
+fn extract_cpio(
+    mut reader: CpioReader,
+    dest_dir: Option<&Path>,
+    bom: Option<Vec<BomPath>>,
+) -> PkgResult<Vec<PayloadDiscrepancy>> {
+    let mut bom = bom.map(|paths| {
+        paths
+            .into_iter()
+            .map(|p| (p.path().to_string(), p))
+            .collect::<BTreeMap<_, _>>()
+    });
+    let mut discrepancies = vec![];
+    // Permissions of directories are applied last so they don't prevent writing.
+    let mut directories = vec![];
+
+    // Resolve symlinks in the destination once, e.g. `/tmp` on macOS.
+    let dest_dir = match dest_dir {
+        Some(dest_dir) => {
+            std::fs::create_dir_all(dest_dir)?;
+            Some(std::fs::canonicalize(dest_dir)?)
+        }
+        None => None,
+    };
+
+    while let Some(header) = reader.read_next()? {
+        let path = bom_path(header.name());
+        let dest = match &dest_dir {
+            Some(dest_dir) => safe_destination(dest_dir, header.name())?,
+            None => None,
+        };
+        let mode = header.mode() & S_IFMT;
+
+        let mut checksum = None;
+        let mut link_name = None;
+
+        match mode {
+            S_IFREG => {
+                let mut file = match &dest {
+                    Some(dest) => {
+                        if let Some(parent) = dest.parent() {
+                            std::fs::create_dir_all(parent)?;
+                        }
+                        // Replace existing files and symlinks rather than writing
+                        // through them.
+                        if dest.symlink_metadata().is_ok_and(|m| !m.is_dir()) {
+                            std::fs::remove_file(dest)?;
+                        }
+                        Some(
+                            std::fs::OpenOptions::new()
+                                .write(true)
+                                .create_new(true)
+                                .open(dest)?,
+                        )
+                    }
+                    None => None,
+                };
+
+                let mut state = ContentChecksum::default();
+                let mut buffer = vec![0u8; 65536];
+                loop {
+                    let size = reader.read(&mut buffer)?;
+                    if size == 0 {
+                        break;
+                    }
+                    state.update(&buffer[..size]);
+                    if let Some(file) = &mut file {
+                        file.write_all(&buffer[..size])?;
+                    }
+                }
+                checksum = Some(state.finalize());
+
+                if let Some(file) = file {
+                    if let Some(mtime) = DateTime::from_timestamp(header.mtime() as _, 0) {
+                        file.set_modified(mtime.into())?;
+                    }
+                    #[cfg(unix)]
+                    file.set_permissions(permissions(header.mode()))?;
+                }
+            }
+            S_IFDIR => {
+                if let Some(dest) = &dest {
+                    // Existing directories are reused, but never symlinks to them.
+                    match dest.symlink_metadata() {
+                        Ok(metadata) if metadata.is_dir() => {}
+                        Ok(_) => return Err(Error::UnsafePayloadPath(header.name().to_string())),
+                        Err(_) => std::fs::create_dir_all(dest)?,
+                    }
+                    directories.push((dest.clone(), header.mode()));
+                }
+            }
+            S_IFLNK => {
+                let mut target = String::new();
+                reader.read_to_string(&mut target)?;
+
+                #[cfg(unix)]
+                if let Some(dest) = &dest {
+                    if let Some(parent) = dest.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::os::unix::fs::symlink(&target, dest)?;
+                }
+
+                link_name = Some(target);
+            }
+            _ => {
+                std::io::copy(&mut reader, &mut std::io::sink())?;
+            }
+        }
+
+        if let Some(bom) = &mut bom {
+            match bom.remove(&path) {
+                Some(entry) => compare_entry(
+                    &path,
+                    header.as_ref(),
+                    &entry,
+                    checksum,
+                    link_name.as_deref(),
+                    &mut discrepancies,
+                ),
+                None => discrepancies.push(PayloadDiscrepancy::NotInBom(path)),
+            }
+        }
+    }
+
+    if let Some(bom) = bom {
+        discrepancies.extend(bom.into_keys().map(PayloadDiscrepancy::NotInPayload));
+    }
+
+    for (dest, mode) in directories.into_iter().rev() {
+        set_permissions(&dest, mode)?;
+    }
+
+    Ok(discrepancies)
+}
+
+/// Resolve the permissions to extract an entry with from its Unix mode.
+#[cfg(unix)]
+fn permissions(mode: u32) -> std::fs::Permissions {
+    use std::os::unix::fs::PermissionsExt;
+    // Never restore setuid/setgid bits from untrusted archives.
+    std::fs::Permissions::from_mode(mode & 0o1777)
+}
+
+/// Apply the permission bits of a Unix mode to an extracted directory.
+///
+/// Symlinks are refused rather than followed.
+fn set_permissions(path: &Path, mode: u32) -> PkgResult<()> {
+    if !path.symlink_metadata()?.is_dir() {
+        return Err(Error::UnsafePayloadPath(path.display().to_string()));
+    }
+
+    #[cfg(unix)]
+    std::fs::set_permissions(path, permissions(mode))?;
+
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    Ok(())
+}
+
+/// Recursively collect the entries of a directory, sorted by path.
+///
+/// Returns paths relative to `root` with `/` separators.
+fn walk_dir(root: &Path) -> PkgResult<Vec<(String, PathBuf, Metadata)>> {
+    let mut entries = vec![];
+    let mut pending = vec![(String::new(), root.to_path_buf())];
+
+    while let Some((prefix, dir)) = pending.pop() {
+        let mut children = std::fs::read_dir(&dir)?.collect::<Result<Vec<_>, _>>()?;
+        children.sort_by_key(|entry| entry.file_name());
+
+        // Visit subdirectories in order by pushing them in reverse.
+        let mut subdirs = vec![];
+
+        for child in children {
+            let name = child.file_name();
+            let name = name
+                .to_str()
+                .ok_or_else(|| Error::UnsupportedPayloadEntry(child.path()))?;
+            let path = format!("{prefix}{name}");
+            let metadata = std::fs::symlink_metadata(child.path())?;
+
+            if metadata.is_dir() {
+                subdirs.push((format!("{path}/"), child.path()));
+            }
+            entries.push((path, child.path(), metadata));
+        }
+
+        pending.extend(subdirs.into_iter().rev());
+    }
+
+    // Directories were expanded depth first, so parents precede their children.
+    // Sorting by path retains that.
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(entries)
+}
+
+/// The Unix mode of a filesystem entry.
+fn unix_mode(metadata: &Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.mode()
+    }
+
+    #[cfg(not(unix))]
+    if metadata.is_dir() {
+        0o40755
+    } else if metadata.is_symlink() {
+        0o120755
+    } else {
+        0o100644
+    }
+}
+
+/// Compression of the `Payload` of built component packages.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PayloadCompression {
+    /// gzip, as produced by `pkgbuild` by default.
+    #[default]
+    Gzip,
+
+    /// pbzx, as used by macOS installers and newer packages.
+    Pbzx,
+}
+
+/// A compressor for a cpio archive.
+enum PayloadEncoder {
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    Pbzx(PbzxWriter<Vec<u8>>),
+}
+
+impl PayloadEncoder {
+    fn new(compression: PayloadCompression) -> PkgResult<Self> {
+        Ok(match compression {
+            PayloadCompression::Gzip => Self::Gzip(flate2::write::GzEncoder::new(
+                vec![],
+                flate2::Compression::default(),
+            )),
+            PayloadCompression::Pbzx => Self::Pbzx(PbzxWriter::new(vec![])?),
+        })
+    }
+
+    fn finish(self) -> PkgResult<Vec<u8>> {
+        match self {
+            Self::Gzip(encoder) => Ok(encoder.finish()?),
+            Self::Pbzx(writer) => writer.finish(),
+        }
+    }
+}
+
+impl Write for PayloadEncoder {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Gzip(encoder) => encoder.write(buf),
+            Self::Pbzx(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Gzip(encoder) => encoder.flush(),
+            Self::Pbzx(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Reader copying the data it reads to a writer.
+struct TeeReader<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.reader.read(buf)?;
+        self.writer.write_all(&buf[..count])?;
+
+        Ok(count)
+    }
+}
+
+/// Builds a cpio archive and matching BOM from a directory tree.
+struct PayloadBuilder {
+    cpio: OdcBuilder<PayloadEncoder>,
+    bom: BomBuilder,
+    preserve_ownership: bool,
+    number_of_files: u64,
+    install_bytes: u64,
+}
+
+impl PayloadBuilder {
+    fn new(preserve_ownership: bool, compression: PayloadCompression) -> PkgResult<Self> {
+        let mut cpio = OdcBuilder::new(PayloadEncoder::new(compression)?);
+        cpio.auto_write_dirs(false);
+
+        Ok(Self {
+            cpio,
+            bom: BomBuilder::default(),
+            preserve_ownership,
+            number_of_files: 0,
+            install_bytes: 0,
+        })
+    }
+
+    /// Write a cpio header for a filesystem entry.
+    fn header(&mut self, name: String, metadata: &Metadata) -> cpio_archive::OdcHeader {
+        let mut header = self.cpio.next_header();
+        header.name = name;
+        header.mode = unix_mode(metadata);
+        header.nlink = 1;
+
+        if let Ok(mtime) = metadata.modified() {
+            header.mtime = DateTime::<Utc>::from(mtime).timestamp() as _;
+        }
+
+        #[cfg(unix)]
+        if self.preserve_ownership {
+            use std::os::unix::fs::MetadataExt;
+            header.uid = metadata.uid();
+            header.gid = metadata.gid();
+        }
+
+        header
+    }
+
+    /// Add a tree to the cpio archive and the BOM.
+    ///
+    /// Both are derived from the same cpio headers, so they agree.
+    fn add_tree(&mut self, root: &Path) -> PkgResult<()> {
+        let metadata = std::fs::metadata(root)?;
+        let header = self.header(".".to_string(), &metadata);
+        self.bom.add_cpio_entry(&header, &mut std::io::empty())?;
+        self.cpio.append_header_with_data(header, [])?;
+        self.number_of_files += 1;
+
+        for (path, fs_path, metadata) in walk_dir(root)? {
+            let mut header = self.header(format!("./{path}"), &metadata);
+
+            if metadata.is_dir() {
+                self.bom.add_cpio_entry(&header, &mut std::io::empty())?;
+                self.cpio.append_header_with_data(header, [])?;
+            } else if metadata.is_file() {
+                header.file_size = metadata.len();
+                header.write(self.cpio.get_mut())?;
+
+                // The BOM hashes the content as it is copied to the payload.
+                let mut reader = TeeReader {
+                    reader: std::fs::File::open(&fs_path)?,
+                    writer: self.cpio.get_mut(),
+                };
+                if self.bom.add_cpio_entry(&header, &mut reader)?.size() as u64 != header.file_size
+                {
+                    return Err(cpio_archive::Error::SizeMismatch.into());
+                }
+
+                self.install_bytes += metadata.len();
+            } else if metadata.is_symlink() {
+                // Symlinks are stored with their target as data, like cpio does.
+                let target = std::fs::read_link(&fs_path)?;
+                let target = target
+                    .to_str()
+                    .ok_or_else(|| Error::UnsupportedPayloadEntry(fs_path.clone()))?
+                    .as_bytes();
+                header.file_size = target.len() as _;
+
+                self.bom.add_cpio_entry(&header, &mut &target[..])?;
+                self.cpio.append_header_with_data(header, target)?;
+            } else {
+                return Err(Error::UnsupportedPayloadEntry(fs_path));
+            }
+
+            self.number_of_files += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Finish building, returning the compressed cpio archive and the BOM.
+    fn finish(self) -> PkgResult<(Vec<u8>, Vec<u8>, Payload)> {
+        let payload = Payload {
+            number_of_files: self.number_of_files,
+            install_kbytes: self.install_bytes.div_ceil(1024),
+        };
+        let bom = self.bom.build_bom()?;
+        let cpio = self.cpio.into_inner()?.finish()?;
+
+        Ok((cpio, bom, payload))
+    }
+}
+
+/// Builder for *component packages*, like `pkgbuild`.
+///
+/// A component package consists of a `Payload` cpio archive holding the files to
+/// install, a `Bom` describing them, an optional `Scripts` cpio archive and a
+/// `PackageInfo` file. [Self::add_to_xar] adds these to a XAR archive, which is
+/// how component packages are embedded in product packages. [Self::write] writes a
+/// standalone component package.
+#[derive(Clone, Debug)]
+pub struct ComponentPackageBuilder {
+    package_info: PackageInfo,
+    payload_root: Option<PathBuf>,
+    scripts_dir: Option<PathBuf>,
+    preserve_ownership: bool,
+    compression: PayloadCompression,
+}
+
+impl ComponentPackageBuilder {
+    /// Create a new builder for a package with the given identifier and version.
+    ///
+    /// The package installs to `/` and requires root privileges, like packages
+    /// produced by `pkgbuild`.
+    pub fn new(identifier: impl ToString, version: impl ToString) -> Self {
+        Self {
+            package_info: PackageInfo {
+                auth: "root".to_string(),
+                identifier: identifier.to_string(),
+                version: version.to_string(),
+                install_location: Some("/".to_string()),
+                overwrite_permissions: Some(true),
+                relocatable: Some(false),
+                postinstall_action: Some("none".to_string()),
+                ..Default::default()
+            },
+            payload_root: None,
+            scripts_dir: None,
+            preserve_ownership: false,
+            compression: PayloadCompression::default(),
+        }
+    }
+
+    /// The `PackageInfo` to write.
+    ///
+    /// The payload and scripts elements are replaced when building.
+    pub fn package_info(&self) -> &PackageInfo {
+        &self.package_info
+    }
+
+    /// Obtain a mutable reference to the `PackageInfo` to write.
+    pub fn package_info_mut(&mut self) -> &mut PackageInfo {
+        &mut self.package_info
+    }
+
+    /// Set the directory whose content forms the `Payload`.
+    ///
+    /// Regular files, directories and symlinks are supported. Symlinks are stored
+    /// as is rather than followed.
+    pub fn set_payload_root(&mut self, path: impl AsRef<Path>) {
+        self.payload_root = Some(path.as_ref().to_path_buf());
+    }
+
+    /// Set the directory whose content forms the `Scripts` archive.
+    ///
+    /// `preinstall` and `postinstall` files in this directory are registered in the
+    /// `PackageInfo`. Other files are available to these scripts.
+    pub fn set_scripts_dir(&mut self, path: impl AsRef<Path>) {
+        self.scripts_dir = Some(path.as_ref().to_path_buf());
+    }
+
+    /// Set whether to record the owner of payload files instead of `root:wheel`.
+    pub fn set_preserve_ownership(&mut self, value: bool) {
+        self.preserve_ownership = value;
+    }
+
+    /// Set the compression of the `Payload` archive.
+    ///
+    /// The `Scripts` archive is always gzip compressed, like `pkgbuild` writes it.
+    pub fn set_compression(&mut self, compression: PayloadCompression) {
+        self.compression = compression;
+    }
+
+    /// Add the files of the component to a XAR archive below a path prefix.
+    ///
+    /// Pass an empty prefix for a standalone component package or `<name>.pkg` for
+    /// a component of a product package.
+    ///
+    /// Returns the `PackageInfo` that was written, including its payload and scripts.
+    pub fn add_to_xar(&self, xar: &mut XarBuilder, prefix: &str) -> PkgResult<PackageInfo> {
+        let path = |name: &str| {
+            if prefix.is_empty() {
+                name.to_string()
+            } else {
+                format!("{prefix}/{name}")
+            }
+        };
+
+        let mut package_info = self.package_info.clone();
+
+        if let Some(root) = &self.payload_root {
+            let mut builder = PayloadBuilder::new(self.preserve_ownership, self.compression)?;
+            builder.add_tree(root)?;
+            let (cpio, bom, payload) = builder.finish()?;
+
+            // The payload is compressed already.
+            xar.add_file_data(&path("Payload"), cpio)?
+                .set_encoding(Encoding::None);
+            xar.add_file_data(&path("Bom"), bom)?;
+            package_info.payload = Some(payload);
+        }
+
+        if let Some(dir) = &self.scripts_dir {
+            let mut builder = PayloadBuilder::new(false, PayloadCompression::Gzip)?;
+            builder.add_tree(dir)?;
+            let (cpio, _, _) = builder.finish()?;
+
+            xar.add_file_data(&path("Scripts"), cpio)?
+                .set_encoding(Encoding::None);
+
+            package_info.scripts.scripts.clear();
+            if dir.join("preinstall").is_file() {
+                package_info
+                    .scripts
+                    .scripts
+                    .push(Script::PreInstall(PreInstall {
+                        file: "./preinstall".to_string(),
+                        component_id: None,
+                    }));
+            }
+            if dir.join("postinstall").is_file() {
+                package_info
+                    .scripts
+                    .scripts
+                    .push(Script::PostInstall(PostInstall {
+                        file: "./postinstall".to_string(),
+                        component_id: None,
+                    }));
+            }
+        }
+
+        xar.add_file_data(&path("PackageInfo"), package_info.to_xml()?)?;
+
+        Ok(package_info)
+    }
+
+    /// Write a standalone component package.
+    pub fn write(&self, writer: &mut impl Write) -> PkgResult<()> {
+        let mut xar = XarBuilder::new();
+        self.add_to_xar(&mut xar, "")?;
+        xar.write(writer)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::reader::PkgReader};
+
+    #[test]
+    fn build_component() -> PkgResult<()> {
+        let root = tempfile::tempdir()?;
+        std::fs::create_dir_all(root.path().join("usr/local/bin"))?;
+        std::fs::write(root.path().join("usr/local/bin/tool"), b"#!/bin/sh\n")?;
+        std::fs::write(root.path().join("usr/local/README"), vec![42; 2000])?;
+
+        let scripts = tempfile::tempdir()?;
+        std::fs::write(scripts.path().join("postinstall"), b"#!/bin/sh\n")?;
+
+        let mut builder = ComponentPackageBuilder::new("com.example.tool", "1.0");
+        builder.set_payload_root(root.path());
+        builder.set_scripts_dir(scripts.path());
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        let mut reader = PkgReader::new(Cursor::new(data))?;
+        let component = reader.root_component()?.unwrap();
+
+        let info = component.package_info().unwrap();
+        assert_eq!(info.identifier, "com.example.tool");
+        assert_eq!(info.version, "1.0");
+        assert_eq!(info.install_location.as_deref(), Some("/"));
+        assert_eq!(
+            info.payload,
+            Some(Payload {
+                number_of_files: 6,
+                install_kbytes: 2,
+            })
+        );
+        assert_eq!(
+            info.scripts.scripts,
+            vec![Script::PostInstall(PostInstall {
+                file: "./postinstall".to_string(),
+                component_id: None,
+            })]
+        );
+
+        let mut payload = component.payload_reader()?.unwrap();
+        let mut names = vec![];
+        while let Some(header) = payload.read_next()? {
+            let mut data = vec![];
+            payload.read_to_end(&mut data)?;
+            if header.name() == "./usr/local/README" {
+                assert_eq!(data, vec![42; 2000]);
+            }
+            names.push(header.name().to_string());
+        }
+        assert_eq!(
+            names,
+            vec![
+                ".",
+                "./usr",
+                "./usr/local",
+                "./usr/local/README",
+                "./usr/local/bin",
+                "./usr/local/bin/tool"
+            ]
+        );
+
+        let bom = apple_bom::ParsedBom::parse(component.bom().unwrap())?;
+        let paths = bom
+            .paths()?
+            .into_iter()
+            .map(|p| p.path().to_string())
+            .collect::<Vec<_>>();
+        assert!(paths.contains(&"./usr/local/bin/tool".to_string()));
+
+        let mut scripts = component.scripts_reader()?.unwrap();
+        let mut names = vec![];
+        while let Some(header) = scripts.read_next()? {
+            names.push(header.name().to_string());
+        }
+        assert_eq!(names, vec![".", "./postinstall"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cksum() {
+        let mut checksum = ContentChecksum::default();
+        checksum.update(b"123456789");
+        assert_eq!(checksum.finalize(), 930766865);
+    }
+
+    #[test]
+    fn extract_payload() -> PkgResult<()> {
+        let root = tempfile::tempdir()?;
+        std::fs::create_dir_all(root.path().join("usr/local/bin"))?;
+        std::fs::write(root.path().join("usr/local/bin/tool"), b"#!/bin/sh\n")?;
+        std::fs::write(root.path().join("usr/local/README"), vec![42; 2000])?;
+
+        let build = |root: &Path| -> PkgResult<ComponentPackageReader> {
+            let mut builder = ComponentPackageBuilder::new("com.example.tool", "1.0");
+            builder.set_payload_root(root);
+            let mut data = vec![];
+            builder.write(&mut data)?;
+
+            Ok(PkgReader::new(Cursor::new(data))?
+                .root_component()?
+                .unwrap())
+        };
+        let component = build(root.path())?;
+
+        let dest = tempfile::tempdir()?;
+        let discrepancies = component.extract_payload(dest.path().join("out"))?;
+        assert_eq!(
+            std::fs::read(dest.path().join("out/usr/local/README"))?,
+            vec![42; 2000]
+        );
+        assert_eq!(
+            std::fs::read(dest.path().join("out/usr/local/bin/tool"))?,
+            b"#!/bin/sh\n"
+        );
+        assert_eq!(discrepancies, vec![]);
+
+        // Pair the Bom with the payload of modified content.
+        std::fs::write(root.path().join("usr/local/README"), vec![42; 2001])?;
+        let modified = build(root.path())?;
+        let tampered =
+            ComponentPackageReader::from_file_data(component.bom, None, modified.payload, None)?;
+        let discrepancies = tampered.verify_payload()?;
+        assert!(discrepancies.contains(&PayloadDiscrepancy::Size {
+            path: "./usr/local/README".to_string(),
+            bom: 2000,
+            payload: 2001,
+        }));
+        assert!(discrepancies.iter().any(|d| matches!(
+            d,
+            PayloadDiscrepancy::Checksum { path, .. } if path == "./usr/local/README"
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn build_pbzx_component() -> PkgResult<()> {
+        let root = tempfile::tempdir()?;
+        std::fs::write(root.path().join("file"), vec![42; 100_000])?;
+
+        let scripts = tempfile::tempdir()?;
+        std::fs::write(scripts.path().join("postinstall"), b"#!/bin/sh\n")?;
+
+        let mut builder = ComponentPackageBuilder::new("com.example.pbzx", "1.0");
+        builder.set_payload_root(root.path());
+        builder.set_scripts_dir(scripts.path());
+        builder.set_compression(PayloadCompression::Pbzx);
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        let component = PkgReader::new(Cursor::new(data))?
+            .root_component()?
+            .unwrap();
+        assert!(component.payload.as_ref().unwrap().starts_with(&PBZX_MAGIC));
+        // Scripts stay gzip compressed.
+        assert!(component
+            .scripts
+            .as_ref()
+            .unwrap()
+            .starts_with(&[0x1f, 0x8b]));
+
+        let dest = tempfile::tempdir()?;
+        let discrepancies = component.extract_payload(dest.path())?;
+        assert_eq!(std::fs::read(dest.path().join("file"))?, vec![42; 100_000]);
+        assert_eq!(discrepancies, vec![]);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn build_component_symlinks() -> PkgResult<()> {
+        let root = tempfile::tempdir()?;
+        std::fs::create_dir(root.path().join("lib"))?;
+        std::fs::write(root.path().join("lib/libfoo.1.dylib"), b"foo")?;
+        std::os::unix::fs::symlink("libfoo.1.dylib", root.path().join("lib/libfoo.dylib"))?;
+
+        let mut builder = ComponentPackageBuilder::new("com.example.links", "1.0");
+        builder.set_payload_root(root.path());
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        let component = PkgReader::new(Cursor::new(data))?
+            .root_component()?
+            .unwrap();
+
+        let bom = apple_bom::ParsedBom::parse(component.bom().unwrap())?;
+        let link = bom
+            .paths()?
+            .into_iter()
+            .find(|p| p.path() == "./lib/libfoo.dylib")
+            .unwrap();
+        assert_eq!(link.link_name(), Some("libfoo.1.dylib"));
+
+        let dest = tempfile::tempdir()?;
+        let discrepancies = component.extract_payload(dest.path())?;
+        assert_eq!(
+            std::fs::read_link(dest.path().join("lib/libfoo.dylib"))?,
+            PathBuf::from("libfoo.1.dylib")
+        );
+        assert_eq!(discrepancies, vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn extract_rejects_traversal() -> PkgResult<()> {
+        let mut cpio = OdcBuilder::new(vec![]);
+        let mut header = cpio.next_header();
+        header.name = "./../escape".to_string();
+        header.mode = S_IFREG | 0o644;
+        header.file_size = 4;
+        cpio.append_header_with_data(header, b"data")?;
+        let payload = cpio.into_inner()?;
+
+        let component = ComponentPackageReader::from_file_data(None, None, Some(payload), None)?;
+        let dest = tempfile::tempdir()?;
+        assert!(matches!(
+            component.extract_payload(dest.path().join("out")),
+            Err(Error::UnsafePayloadPath(_))
+        ));
+        assert!(!dest.path().join("escape").exists());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_to_symlinked_destination() -> PkgResult<()> {
+        let mut cpio = OdcBuilder::new(vec![]);
+        let mut header = cpio.next_header();
+        header.name = "./dir/file".to_string();
+        header.mode = S_IFREG | 0o644;
+        header.file_size = 4;
+        cpio.append_header_with_data(header, b"data")?;
+        let payload = cpio.into_inner()?;
+
+        let component = ComponentPackageReader::from_file_data(None, None, Some(payload), None)?;
+        let dest = tempfile::tempdir()?;
+        std::fs::create_dir(dest.path().join("real"))?;
+        std::os::unix::fs::symlink("real", dest.path().join("link"))?;
+        component.extract_payload(dest.path().join("link"))?;
+        assert_eq!(std::fs::read(dest.path().join("real/dir/file"))?, b"data");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_rejects_symlinked_directories() -> PkgResult<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let outside = tempfile::tempdir()?;
+        std::fs::set_permissions(outside.path(), std::fs::Permissions::from_mode(0o700))?;
+
+        let mut cpio = OdcBuilder::new(vec![]);
+        let mut header = cpio.next_header();
+        header.name = "./x".to_string();
+        header.mode = S_IFLNK | 0o755;
+        let target = outside.path().to_str().unwrap().as_bytes();
+        header.file_size = target.len() as _;
+        cpio.append_header_with_data(header, target)?;
+        let mut header = cpio.next_header();
+        header.name = "./x".to_string();
+        header.mode = S_IFDIR | 0o777;
+        cpio.append_header_with_data(header, [])?;
+        let payload = cpio.into_inner()?;
+
+        let component = ComponentPackageReader::from_file_data(None, None, Some(payload), None)?;
+        let dest = tempfile::tempdir()?;
+        assert!(matches!(
+            component.extract_payload(dest.path().join("out")),
+            Err(Error::UnsafePayloadPath(_))
+        ));
+        assert_eq!(
+            outside.path().metadata()?.permissions().mode() & 0o777,
+            0o700
+        );
+
+        Ok(())
+    }
 }
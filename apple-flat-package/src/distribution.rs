@@ -8,9 +8,17 @@
 //! for Apple's documentation of this file format.
 
 use {
-    crate::PkgResult,
+    crate::{
+        optional, write_empty_element, write_end_element, write_start_element, write_text_element,
+        Error, PkgResult,
+    },
     serde::{Deserialize, Serialize},
-    std::io::Read,
+    std::io::{Read, Write},
+    xml::{
+        common::XmlVersion,
+        reader::{EventReader, XmlEvent as ReaderEvent},
+        writer::{EmitterConfig, EventWriter, XmlEvent},
+    },
 };
 
 /// Represents a distribution XML file.
@@ -43,26 +51,153 @@ pub struct Distribution {
 
 impl Distribution {
     /// Parse Distribution XML from a reader.
-    pub fn from_reader(reader: impl Read) -> PkgResult<Self> {
-        let mut de =
-            serde_xml_rs::Deserializer::new_from_reader(reader).non_contiguous_seq_elements(true);
+    pub fn from_reader(mut reader: impl Read) -> PkgResult<Self> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
 
-        Ok(Self::deserialize(&mut de)?)
+        let mut de = serde_xml_rs::Deserializer::new_from_reader(data.as_slice())
+            .non_contiguous_seq_elements(true);
+
+        let mut distribution = Self::deserialize(&mut de)?;
+        distribution.resolve_pkg_ref_locations(&data)?;
+
+        Ok(distribution)
     }
 
     /// Parse Distribution XML from a string.
     pub fn from_xml(s: &str) -> PkgResult<Self> {
-        let mut de = serde_xml_rs::Deserializer::new_from_reader(s.as_bytes())
-            .non_contiguous_seq_elements(true);
+        Self::from_reader(s.as_bytes())
+    }
+
+    /// Populate [PkgRef::location] of top-level `<pkg-ref>` elements.
+    ///
+    /// serde-xml-rs cannot deserialize elements having text content and child
+    /// elements, so the text is resolved with a separate pass over the XML.
+    fn resolve_pkg_ref_locations(&mut self, data: &[u8]) -> PkgResult<()> {
+        let mut depth = 0;
+        let mut index = None::<usize>;
+        let mut count = 0;
+
+        for event in EventReader::new(data) {
+            match event.map_err(|e| Error::Io(std::io::Error::other(e)))? {
+                ReaderEvent::StartElement { name, .. } => {
+                    depth += 1;
+                    if depth == 2 && name.local_name == "pkg-ref" {
+                        index = Some(count);
+                        count += 1;
+                    }
+                }
+                ReaderEvent::EndElement { .. } => {
+                    if depth == 2 {
+                        index = None;
+                    }
+                    depth -= 1;
+                }
+                ReaderEvent::Characters(text) if depth == 2 => {
+                    if let Some(pkg_ref) = index.and_then(|i| self.pkg_ref.get_mut(i)) {
+                        pkg_ref.location = Some(text.trim().to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
 
-        Ok(Self::deserialize(&mut de)?)
+    /// Serialize to XML.
+    pub fn to_xml(&self) -> PkgResult<Vec<u8>> {
+        let mut emitter = EmitterConfig::new()
+            .perform_indent(true)
+            .create_writer(std::io::BufWriter::new(vec![]));
+        self.write_xml(&mut emitter)?;
+
+        emitter
+            .into_inner()
+            .into_inner()
+            .map_err(|e| Error::Io(std::io::Error::other(e)))
+    }
+
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        writer.write(XmlEvent::StartDocument {
+            version: XmlVersion::Version10,
+            encoding: Some("utf-8"),
+            standalone: None,
+        })?;
+
+        write_start_element(
+            writer,
+            "installer-gui-script",
+            &[("minSpecVersion", Some(self.min_spec_version.to_string()))],
+        )?;
+
+        if let Some(title) = &self.title {
+            title.write_xml(writer)?;
+        }
+        if let Some(background) = &self.background {
+            background.write_xml(writer)?;
+        }
+        if let Some(welcome) = &self.welcome {
+            welcome.write_xml(writer)?;
+        }
+        if let Some(readme) = &self.readme {
+            readme.write_xml(writer)?;
+        }
+        if let Some(license) = &self.license {
+            license.write_xml(writer)?;
+        }
+        if let Some(conclusion) = &self.conclusion {
+            conclusion.write_xml(writer)?;
+        }
+        if let Some(options) = &self.options {
+            options.write_xml(writer)?;
+        }
+        if let Some(domains) = &self.domains {
+            domains.write_xml(writer)?;
+        }
+        if let Some(check) = &self.installation_check {
+            check.write_xml(writer)?;
+        }
+        if let Some(check) = &self.volume_check {
+            check.write_xml(writer)?;
+        }
+        if let Some(script) = &self.script {
+            script.write_xml(writer)?;
+        }
+        for locator in &self.locator {
+            locator.write_xml(writer)?;
+        }
+        self.choices_outline.write_xml(writer)?;
+        for choice in &self.choice {
+            choice.write_xml(writer)?;
+        }
+        for pkg_ref in &self.pkg_ref {
+            pkg_ref.write_xml(writer)?;
+        }
+        if let Some(product) = &self.product {
+            product.write_xml(writer)?;
+        }
+
+        write_end_element(writer)
     }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct AllowedOsVersions {
     #[serde(rename = "os-version")]
-    os_versions: Vec<OsVersion>,
+    pub os_versions: Vec<OsVersion>,
+}
+
+impl AllowedOsVersions {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(writer, "allowed-os-versions", &[])?;
+        for version in &self.os_versions {
+            version.write_xml(writer)?;
+        }
+        write_end_element(writer)
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
@@ -70,6 +205,13 @@ pub struct App {
     pub id: String,
 }
 
+impl App {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(writer, "app", &[("id", Some(self.id.clone()))])
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Background {
@@ -82,6 +224,23 @@ pub struct Background {
     pub uti: Option<String>,
 }
 
+impl Background {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(
+            writer,
+            "background",
+            &[
+                ("file", Some(self.file.clone())),
+                ("mime-type", self.mime_type.clone()),
+                ("uti", self.uti.clone()),
+                ("alignment", self.alignment.clone()),
+                ("scaling", self.scaling.clone()),
+            ],
+        )
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Bundle {
     #[serde(rename = "CFBundleShortVersionString")]
@@ -92,6 +251,37 @@ pub struct Bundle {
     pub path: String,
     pub search: Option<bool>,
     // BuildVersion, SourceVersion reserved attributes.
+    /// Bundles nested in this bundle, with paths relative to it.
+    ///
+    /// Only `PackageInfo` files nest bundles.
+    #[serde(default)]
+    pub bundle: Vec<Bundle>,
+}
+
+impl Bundle {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        let attributes = [
+            (
+                "CFBundleShortVersionString",
+                self.cf_bundle_short_version_string.clone(),
+            ),
+            ("CFBundleVersion", self.cf_bundle_version.clone()),
+            ("id", Some(self.id.clone())),
+            ("path", Some(self.path.clone())),
+            ("search", optional(&self.search)),
+        ];
+
+        if self.bundle.is_empty() {
+            return write_empty_element(writer, "bundle", &attributes);
+        }
+
+        write_start_element(writer, "bundle", &attributes)?;
+        for bundle in &self.bundle {
+            bundle.write_xml(writer)?;
+        }
+        write_end_element(writer)
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
@@ -100,6 +290,17 @@ pub struct BundleVersion {
     pub bundle: Vec<Bundle>,
 }
 
+impl BundleVersion {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(writer, "bundle-version", &[])?;
+        for bundle in &self.bundle {
+            bundle.write_xml(writer)?;
+        }
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Choice {
     // The naming format on this element is all over the place.
@@ -110,26 +311,71 @@ pub struct Choice {
     pub description: Option<String>,
     #[serde(rename = "description-mime-type")]
     pub description_mime_type: Option<String>,
-    pub enabled: Option<bool>,
+    /// JavaScript expression defining whether the choice can be changed.
+    pub enabled: Option<String>,
     pub id: String,
-    pub selected: Option<bool>,
+    /// JavaScript expression defining whether the choice is selected.
+    pub selected: Option<String>,
     pub start_enabled: Option<bool>,
     pub start_selected: Option<bool>,
     pub start_visible: Option<bool>,
     // Supposed to be required. But there are elements with only `id` attribute in wild.
     pub title: Option<String>,
-    pub visible: Option<bool>,
+    /// JavaScript expression defining whether the choice is shown.
+    pub visible: Option<String>,
     // bundle, customLocationIsSelfContained, tooltip, and versStr are reserved attributes.
     #[serde(default, rename = "pkg-ref")]
     pub pkg_ref: Vec<PkgRef>,
 }
 
+impl Choice {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(
+            writer,
+            "choice",
+            &[
+                ("id", Some(self.id.clone())),
+                ("title", self.title.clone()),
+                ("description", self.description.clone()),
+                ("description-mime-type", self.description_mime_type.clone()),
+                ("customLocation", self.custom_location.clone()),
+                (
+                    "customLocationAllowAlternateVolumes",
+                    optional(&self.custom_location_allow_alternative_volumes),
+                ),
+                ("enabled", self.enabled.clone()),
+                ("selected", self.selected.clone()),
+                ("visible", self.visible.clone()),
+                ("start_enabled", optional(&self.start_enabled)),
+                ("start_selected", optional(&self.start_selected)),
+                ("start_visible", optional(&self.start_visible)),
+            ],
+        )?;
+        for pkg_ref in &self.pkg_ref {
+            pkg_ref.write_xml(writer)?;
+        }
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct ChoicesOutline {
     // ui is a reserved attribute.
     pub line: Vec<Line>,
 }
 
+impl ChoicesOutline {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(writer, "choices-outline", &[])?;
+        for line in &self.line {
+            line.write_xml(writer)?;
+        }
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Conclusion {
     pub file: String,
@@ -139,6 +385,21 @@ pub struct Conclusion {
     // language is a reserved attribute.
 }
 
+impl Conclusion {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(
+            writer,
+            "conclusion",
+            &[
+                ("file", Some(self.file.clone())),
+                ("mime-type", self.mime_type.clone()),
+                ("uti", self.uti.clone()),
+            ],
+        )
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Domains {
     pub enable_anywhere: bool,
@@ -148,14 +409,54 @@ pub struct Domains {
     pub enable_local_system: bool,
 }
 
+impl Domains {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(
+            writer,
+            "domains",
+            &[
+                ("enable_anywhere", Some(self.enable_anywhere.to_string())),
+                (
+                    "enable_currentUserHome",
+                    Some(self.enable_current_user_home.to_string()),
+                ),
+                (
+                    "enable_localSystem",
+                    Some(self.enable_local_system.to_string()),
+                ),
+            ],
+        )
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct InstallationCheck {
-    pub script: Option<bool>,
+    /// JavaScript expression evaluating to whether installation is allowed.
+    pub script: Option<String>,
     pub ram: Option<Ram>,
     #[serde(rename = "required-graphics")]
     pub required_graphics: Option<RequiredGraphics>,
 }
 
+impl InstallationCheck {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(
+            writer,
+            "installation-check",
+            &[("script", self.script.clone())],
+        )?;
+        if let Some(ram) = &self.ram {
+            ram.write_xml(writer)?;
+        }
+        if let Some(graphics) = &self.required_graphics {
+            graphics.write_xml(writer)?;
+        }
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct License {
@@ -165,6 +466,21 @@ pub struct License {
     // auto, language, and sla are reserved but not defined.
 }
 
+impl License {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(
+            writer,
+            "license",
+            &[
+                ("file", Some(self.file.clone())),
+                ("mime-type", self.mime_type.clone()),
+                ("uti", self.uti.clone()),
+            ],
+        )
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Line {
     pub choice: String,
@@ -172,17 +488,50 @@ pub struct Line {
     pub lines: Vec<Line>,
 }
 
+impl Line {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(writer, "line", &[("choice", Some(self.choice.clone()))])?;
+        for line in &self.lines {
+            line.write_xml(writer)?;
+        }
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Locator {
     #[serde(rename = "search")]
     pub searches: Vec<Search>,
 }
 
+impl Locator {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(writer, "locator", &[])?;
+        for search in &self.searches {
+            search.write_xml(writer)?;
+        }
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct MustClose {
     pub app: Vec<App>,
 }
 
+impl MustClose {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(writer, "must-close", &[])?;
+        for app in &self.app {
+            app.write_xml(writer)?;
+        }
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Options {
     #[serde(rename = "allow-external-scripts")]
@@ -198,6 +547,27 @@ pub struct Options {
     // type, visibleOnlyForPredicate are reserved attributes.
 }
 
+impl Options {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(
+            writer,
+            "options",
+            &[
+                ("customize", self.customize.clone()),
+                ("require-scripts", optional(&self.require_scripts)),
+                (
+                    "allow-external-scripts",
+                    optional(&self.allow_external_scripts),
+                ),
+                ("hostArchitectures", self.host_architecutres.clone()),
+                ("mpkg", self.mpkg.clone()),
+                ("rootVolumeOnly", optional(&self.root_volume_only)),
+            ],
+        )
+    }
+}
+
 /// Defines a range of supported OS versions.
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct OsVersion {
@@ -205,6 +575,20 @@ pub struct OsVersion {
     pub min: String,
 }
 
+impl OsVersion {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(
+            writer,
+            "os-version",
+            &[
+                ("min", Some(self.min.clone())),
+                ("before", self.before.clone()),
+            ],
+        )
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct PkgRef {
     pub active: Option<bool>,
@@ -225,6 +609,45 @@ pub struct PkgRef {
     pub bundle_version: Option<BundleVersion>,
     #[serde(default)]
     pub relocate: Vec<Relocate>,
+
+    /// Location of the component package, e.g. `#com.example.pkg`.
+    ///
+    /// This is the text content of the element. Only top-level `<pkg-ref>` elements
+    /// have a location. It is resolved by [Distribution::from_reader].
+    #[serde(skip)]
+    pub location: Option<String>,
+}
+
+impl PkgRef {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(
+            writer,
+            "pkg-ref",
+            &[
+                ("id", Some(self.id.clone())),
+                ("version", self.version.clone()),
+                ("auth", self.auth.clone()),
+                ("active", optional(&self.active)),
+                ("installKBytes", optional(&self.install_kbytes)),
+                ("onConclusion", self.on_conclusion.clone()),
+                ("onConclusionScript", self.on_conclusion_script.clone()),
+            ],
+        )?;
+        if let Some(location) = &self.location {
+            writer.write(XmlEvent::characters(location))?;
+        }
+        if let Some(must_close) = &self.must_close {
+            must_close.write_xml(writer)?;
+        }
+        if let Some(bundle_version) = &self.bundle_version {
+            bundle_version.write_xml(writer)?;
+        }
+        for relocate in &self.relocate {
+            relocate.write_xml(writer)?;
+        }
+        write_end_element(writer)
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
@@ -233,13 +656,35 @@ pub struct Product {
     pub version: Option<String>,
 }
 
+impl Product {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(
+            writer,
+            "product",
+            &[
+                ("id", Some(self.id.clone())),
+                ("version", self.version.clone()),
+            ],
+        )
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Ram {
     #[serde(rename = "min-gb")]
     pub min_gb: String,
 }
 
+impl Ram {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(writer, "ram", &[("min-gb", Some(self.min_gb.clone()))])
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct Readme {
     pub file: String,
     pub mime_type: Option<String>,
@@ -247,6 +692,21 @@ pub struct Readme {
     // language is reserved.
 }
 
+impl Readme {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(
+            writer,
+            "readme",
+            &[
+                ("file", Some(self.file.clone())),
+                ("mime-type", self.mime_type.clone()),
+                ("uti", self.uti.clone()),
+            ],
+        )
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Relocate {
     #[serde(rename = "search-id")]
@@ -254,6 +714,19 @@ pub struct Relocate {
     pub bundle: Bundle,
 }
 
+impl Relocate {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(
+            writer,
+            "relocate",
+            &[("search-id", Some(self.search_id.clone()))],
+        )?;
+        self.bundle.write_xml(writer)?;
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct RequiredBundles {
     pub all: Option<bool>,
@@ -262,6 +735,24 @@ pub struct RequiredBundles {
     pub bundles: Vec<Bundle>,
 }
 
+impl RequiredBundles {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(
+            writer,
+            "required-bundles",
+            &[
+                ("all", optional(&self.all)),
+                ("description", self.description.clone()),
+            ],
+        )?;
+        for bundle in &self.bundles {
+            bundle.write_xml(writer)?;
+        }
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct RequiredClDevice {
     #[serde(rename = "$value")]
@@ -283,6 +774,27 @@ pub struct RequiredGraphics {
     pub required_gl_renderer: Option<RequiredGlRenderer>,
 }
 
+impl RequiredGraphics {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(
+            writer,
+            "required-graphics",
+            &[
+                ("description", self.description.clone()),
+                ("single-device", optional(&self.single_device)),
+            ],
+        )?;
+        if let Some(device) = &self.required_cl_device {
+            write_text_element(writer, "required-cl-device", &[], &device.predicate)?;
+        }
+        if let Some(renderer) = &self.required_gl_renderer {
+            write_text_element(writer, "required-gl-renderer", &[], &renderer.predicate)?;
+        }
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Script {
     // language is a reserved attribute.
@@ -290,6 +802,15 @@ pub struct Script {
     pub script: String,
 }
 
+impl Script {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(writer, "script", &[])?;
+        writer.write(XmlEvent::cdata(&self.script))?;
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub enum SearchValue {
     #[serde(rename = "bundle")]
@@ -310,20 +831,64 @@ pub struct Search {
     pub value: SearchValue,
 }
 
+impl Search {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(
+            writer,
+            "search",
+            &[
+                ("id", Some(self.id.clone())),
+                ("type", Some(self.search_type.clone())),
+                ("script", self.script.clone()),
+                ("search-id", self.search_id.clone()),
+                ("search-path", self.search_path.clone()),
+            ],
+        )?;
+        match &self.value {
+            SearchValue::Bundle(bundle) => bundle.write_xml(writer)?,
+            SearchValue::Script(script) => script.write_xml(writer)?,
+        }
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Title {
     #[serde(rename = "$value")]
     pub title: String,
 }
 
+impl Title {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_text_element(writer, "title", &[], &self.title)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
-#[serde(rename = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
 pub struct VolumeCheck {
-    pub script: bool,
+    /// JavaScript expression evaluating to whether a volume is a valid target.
+    pub script: Option<String>,
     pub allowed_os_versions: Option<AllowedOsVersions>,
     pub required_bundles: Option<RequiredBundles>,
 }
 
+impl VolumeCheck {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_start_element(writer, "volume-check", &[("script", self.script.clone())])?;
+        if let Some(versions) = &self.allowed_os_versions {
+            versions.write_xml(writer)?;
+        }
+        if let Some(bundles) = &self.required_bundles {
+            bundles.write_xml(writer)?;
+        }
+        write_end_element(writer)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Welcome {
@@ -332,3 +897,84 @@ pub struct Welcome {
     pub uti: Option<String>,
     // language reserved attribute.
 }
+
+impl Welcome {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(
+            writer,
+            "welcome",
+            &[
+                ("file", Some(self.file.clone())),
+                ("mime-type", self.mime_type.clone()),
+                ("uti", self.uti.clone()),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISTRIBUTION: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<installer-gui-script minSpecVersion="2">
+    <title>Example</title>
+    <welcome file="welcome.html" mime-type="text/html"/>
+    <license file="license.txt"/>
+    <options customize="never" require-scripts="false" hostArchitectures="x86_64,arm64"/>
+    <domains enable_anywhere="false" enable_currentUserHome="false" enable_localSystem="true"/>
+    <installation-check script="installation_check()">
+        <ram min-gb="8"/>
+    </installation-check>
+    <volume-check script="false">
+        <allowed-os-versions>
+            <os-version min="11.0"/>
+        </allowed-os-versions>
+    </volume-check>
+    <script><![CDATA[function installation_check() { return system.version.ProductVersion >= '11'; }]]></script>
+    <choices-outline>
+        <line choice="default">
+            <line choice="com.example.app"/>
+        </line>
+    </choices-outline>
+    <choice id="default"/>
+    <choice id="com.example.app" visible="false">
+        <pkg-ref id="com.example.app"/>
+    </choice>
+    <pkg-ref id="com.example.app" version="1.0" onConclusion="none" installKBytes="1024">#com.example.app.pkg</pkg-ref>
+    <pkg-ref id="com.example.app">
+        <must-close>
+            <app id="com.example.app"/>
+        </must-close>
+        <bundle-version>
+            <bundle CFBundleShortVersionString="1.0" id="com.example.app" path="Example.app"/>
+        </bundle-version>
+    </pkg-ref>
+    <product id="com.example" version="1.0"/>
+</installer-gui-script>
+"#;
+
+    #[test]
+    fn round_trip() -> PkgResult<()> {
+        let distribution = Distribution::from_xml(DISTRIBUTION)?;
+
+        assert_eq!(distribution.pkg_ref.len(), 2);
+        assert_eq!(
+            distribution.pkg_ref[0].location.as_deref(),
+            Some("#com.example.app.pkg")
+        );
+        assert_eq!(distribution.pkg_ref[1].location, None);
+        assert!(distribution.pkg_ref[1].must_close.is_some());
+        assert_eq!(
+            distribution.choices_outline.line[0].lines[0].choice,
+            "com.example.app"
+        );
+
+        let xml = distribution.to_xml()?;
+        let parsed = Distribution::from_reader(xml.as_slice())?;
+        assert_eq!(parsed, distribution);
+
+        Ok(())
+    }
+}
@@ -85,13 +85,25 @@
 //!   content is treated as opaque by the flat package format).
 
 pub mod component_package;
-pub use component_package::ComponentPackageReader;
+pub use component_package::{
+    ComponentPackageBuilder, ComponentPackageReader, PayloadCompression, PayloadDiscrepancy,
+};
 pub mod distribution;
 pub use distribution::Distribution;
 pub mod package_info;
 pub use package_info::PackageInfo;
+pub mod pbzx;
+pub mod product_package;
+pub use product_package::ProductPackageBuilder;
 pub mod reader;
 pub use reader::{PkgFlavor, PkgReader};
+pub mod requirements;
+pub use requirements::{RequirementsReport, TargetSystem};
+
+use {
+    std::io::Write,
+    xml::writer::{EventWriter, XmlEvent},
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -110,9 +122,75 @@ pub enum Error {
     #[error("cpio archive error: {0}")]
     Cpio(#[from] cpio_archive::Error),
 
+    #[error("BOM error: {0}")]
+    Bom(#[from] apple_bom::Error),
+
+    #[error("XML write error: {0}")]
+    XmlWrite(#[from] xml::writer::Error),
+
+    #[error("pbzx error: {0}")]
+    Pbzx(&'static str),
+
+    #[error("unsafe path in package payload: {0}")]
+    UnsafePayloadPath(String),
+
+    #[error("unsupported file type in package payload: {0}")]
+    UnsupportedPayloadEntry(std::path::PathBuf),
+
     #[error("failed to resolve known component (this should not happen)")]
     ComponentResolution,
 }
 
 /// Result type for this crate.
 pub type PkgResult<T> = std::result::Result<T, Error>;
+
+/// Convert an optional attribute value to a string.
+pub(crate) fn optional(value: &Option<impl ToString>) -> Option<String> {
+    value.as_ref().map(|v| v.to_string())
+}
+
+/// Write the start of an XML element with the attributes that have a value.
+pub(crate) fn write_start_element<W: Write>(
+    writer: &mut EventWriter<W>,
+    name: &str,
+    attributes: &[(&str, Option<String>)],
+) -> PkgResult<()> {
+    let mut element = XmlEvent::start_element(name);
+    for (name, value) in attributes {
+        if let Some(value) = value {
+            element = element.attr(*name, value);
+        }
+    }
+    writer.write(element)?;
+
+    Ok(())
+}
+
+/// Write the end of the current XML element.
+pub(crate) fn write_end_element<W: Write>(writer: &mut EventWriter<W>) -> PkgResult<()> {
+    writer.write(XmlEvent::end_element())?;
+
+    Ok(())
+}
+
+/// Write an XML element without children.
+pub(crate) fn write_empty_element<W: Write>(
+    writer: &mut EventWriter<W>,
+    name: &str,
+    attributes: &[(&str, Option<String>)],
+) -> PkgResult<()> {
+    write_start_element(writer, name, attributes)?;
+    write_end_element(writer)
+}
+
+/// Write an XML element holding text.
+pub(crate) fn write_text_element<W: Write>(
+    writer: &mut EventWriter<W>,
+    name: &str,
+    attributes: &[(&str, Option<String>)],
+    text: &str,
+) -> PkgResult<()> {
+    write_start_element(writer, name, attributes)?;
+    writer.write(XmlEvent::characters(text))?;
+    write_end_element(writer)
+}
@@ -5,9 +5,16 @@
 //! `PkgInfo` XML files.
 
 use {
-    crate::{distribution::Bundle, PkgResult},
-    serde::{Deserialize, Serialize},
-    std::io::Read,
+    crate::{
+        distribution::Bundle, optional, write_empty_element, write_end_element,
+        write_start_element, Error, PkgResult,
+    },
+    serde::{Deserialize, Deserializer, Serialize},
+    std::io::{Read, Write},
+    xml::{
+        common::XmlVersion,
+        writer::{EmitterConfig, EventWriter, XmlEvent},
+    },
 };
 
 /// Provides information about the package to install.
@@ -78,48 +85,90 @@ pub struct PackageInfo {
     pub version: String,
 
     // End of attributes. Beginning of elements.
-    #[serde(default)]
+    /// Bundles to update atomically, from `<atomic-update-bundle>`.
+    #[serde(default, deserialize_with = "deserialize_bundle_refs")]
     pub atomic_update_bundle: Vec<BundleRef>,
 
     /// Versioning information about bundles within the payload.
     #[serde(default)]
     pub bundle: Vec<Bundle>,
 
-    #[serde(default)]
+    /// Bundles whose versions are checked, from `<bundle-version>`.
+    #[serde(default, deserialize_with = "deserialize_bundle_refs")]
     pub bundle_version: Vec<BundleRef>,
 
     /// Files to not obsolete during install.
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_files")]
     pub dont_obsolete: Vec<File>,
 
     /// Installs to process at next startup.
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_files")]
     pub install_at_startup: Vec<File>,
 
     /// Files to be patched.
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_files")]
     pub patch: Vec<File>,
 
     /// Provides information on the content being installed.
     pub payload: Option<Payload>,
 
-    #[serde(default)]
+    /// Bundles that can be relocated, from `<relocate>`.
+    #[serde(default, deserialize_with = "deserialize_bundle_refs")]
     pub relocate: Vec<BundleRef>,
 
     /// Scripts to run before and after install.
     #[serde(default)]
     pub scripts: Scripts,
 
-    #[serde(default)]
+    /// Bundles that must keep their identifier, from `<strict-identifier>`.
+    #[serde(
+        default,
+        rename = "strict-identifier",
+        deserialize_with = "deserialize_bundle_refs"
+    )]
     pub strict_identifiers: Vec<BundleRef>,
 
-    #[serde(default)]
+    /// Bundles to update, from `<update-bundle>`.
+    #[serde(default, deserialize_with = "deserialize_bundle_refs")]
     pub update_bundle: Vec<BundleRef>,
 
-    #[serde(default)]
+    /// Bundles to upgrade, from `<upgrade-bundle>`.
+    #[serde(default, deserialize_with = "deserialize_bundle_refs")]
     pub upgrade_bundle: Vec<BundleRef>,
 }
 
+/// An element holding `<bundle>` references, like `<bundle-version>`.
+#[derive(Deserialize)]
+struct BundleRefs {
+    #[serde(default)]
+    bundle: Vec<BundleRef>,
+}
+
+/// Deserialize the `<bundle>` children of all occurrences of an element.
+fn deserialize_bundle_refs<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<BundleRef>, D::Error> {
+    Ok(Vec::<BundleRefs>::deserialize(deserializer)?
+        .into_iter()
+        .flat_map(|refs| refs.bundle)
+        .collect())
+}
+
+/// An element holding `<file>` records, like `<patch>`.
+#[derive(Deserialize)]
+struct Files {
+    #[serde(default)]
+    file: Vec<File>,
+}
+
+/// Deserialize the `<file>` children of all occurrences of an element.
+fn deserialize_files<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<File>, D::Error> {
+    Ok(Vec::<Files>::deserialize(deserializer)?
+        .into_iter()
+        .flat_map(|files| files.file)
+        .collect())
+}
+
 impl Default for PackageInfo {
     fn default() -> Self {
         Self {
@@ -156,17 +205,109 @@ impl Default for PackageInfo {
 impl PackageInfo {
     /// Parse Distribution XML from a reader.
     pub fn from_reader(reader: impl Read) -> PkgResult<Self> {
-        let mut de = serde_xml_rs::Deserializer::new_from_reader(reader);
+        let mut de =
+            serde_xml_rs::Deserializer::new_from_reader(reader).non_contiguous_seq_elements(true);
 
         Ok(Self::deserialize(&mut de)?)
     }
 
     /// Parse Distribution XML from a string.
     pub fn from_xml(s: &str) -> PkgResult<Self> {
-        let mut de = serde_xml_rs::Deserializer::new_from_reader(s.as_bytes())
-            .non_contiguous_seq_elements(true);
+        Self::from_reader(s.as_bytes())
+    }
 
-        Ok(Self::deserialize(&mut de)?)
+    /// Serialize to XML.
+    pub fn to_xml(&self) -> PkgResult<Vec<u8>> {
+        let mut emitter = EmitterConfig::new()
+            .perform_indent(true)
+            .create_writer(std::io::BufWriter::new(vec![]));
+        self.write_xml(&mut emitter)?;
+
+        emitter
+            .into_inner()
+            .into_inner()
+            .map_err(|e| Error::Io(std::io::Error::other(e)))
+    }
+
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        writer.write(XmlEvent::StartDocument {
+            version: XmlVersion::Version10,
+            encoding: Some("utf-8"),
+            standalone: None,
+        })?;
+
+        // Attributes and elements are ordered like `pkgbuild` writes them.
+        write_start_element(
+            writer,
+            "pkg-info",
+            &[
+                (
+                    "overwrite-permissions",
+                    optional(&self.overwrite_permissions),
+                ),
+                ("relocatable", optional(&self.relocatable)),
+                ("identifier", Some(self.identifier.clone())),
+                ("postinstall-action", self.postinstall_action.clone()),
+                ("version", Some(self.version.clone())),
+                ("format-version", Some(self.format_version.to_string())),
+                ("generator-version", self.generator_version.clone()),
+                ("install-location", self.install_location.clone()),
+                ("auth", Some(self.auth.clone())),
+                (
+                    "deleteObsoleteLanguages",
+                    optional(&self.delete_obsolete_languages),
+                ),
+                ("followSymLinks", optional(&self.follow_symlinks)),
+                (
+                    "minimumSystemVersion",
+                    optional(&self.minimum_system_version),
+                ),
+                ("preserve-xattr", optional(&self.preserve_xattr)),
+                (
+                    "useHFSPlusCompression",
+                    optional(&self.use_hfs_plus_compression),
+                ),
+            ],
+        )?;
+
+        if let Some(payload) = &self.payload {
+            payload.write_xml(writer)?;
+        }
+        for bundle in &self.bundle {
+            bundle.write_xml(writer)?;
+        }
+        for (name, refs) in [
+            ("bundle-version", &self.bundle_version),
+            ("upgrade-bundle", &self.upgrade_bundle),
+            ("update-bundle", &self.update_bundle),
+            ("atomic-update-bundle", &self.atomic_update_bundle),
+            ("strict-identifier", &self.strict_identifiers),
+            ("relocate", &self.relocate),
+        ] {
+            write_start_element(writer, name, &[])?;
+            for bundle_ref in refs {
+                bundle_ref.write_xml(writer)?;
+            }
+            write_end_element(writer)?;
+        }
+        for (name, files) in [
+            ("dont-obsolete", &self.dont_obsolete),
+            ("install-at-startup", &self.install_at_startup),
+            ("patch", &self.patch),
+        ] {
+            if !files.is_empty() {
+                write_start_element(writer, name, &[])?;
+                for file in files {
+                    file.write_xml(writer)?;
+                }
+                write_end_element(writer)?;
+            }
+        }
+
+        self.scripts.write_xml(writer)?;
+
+        write_end_element(writer)
     }
 }
 
@@ -184,6 +325,21 @@ pub struct File {
     pub sha1: Option<String>,
 }
 
+impl File {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(
+            writer,
+            "file",
+            &[
+                ("path", Some(self.path.clone())),
+                ("required-sha1", self.required_sha1.clone()),
+                ("sha1", self.sha1.clone()),
+            ],
+        )
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Payload {
     #[serde(rename = "numberOfFiles")]
@@ -192,11 +348,33 @@ pub struct Payload {
     pub install_kbytes: u64,
 }
 
+impl Payload {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(
+            writer,
+            "payload",
+            &[
+                ("numberOfFiles", Some(self.number_of_files.to_string())),
+                ("installKBytes", Some(self.install_kbytes.to_string())),
+            ],
+        )
+    }
+}
+
+/// A reference to a bundle defined by a `<bundle>` element.
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct BundleRef {
     pub id: Option<String>,
 }
 
+impl BundleRef {
+    /// Write the XML representation to an XML writer.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        write_empty_element(writer, "bundle", &[("id", self.id.clone())])
+    }
+}
+
 /// Wrapper type to represent <scripts>.
 #[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Scripts {
@@ -204,6 +382,35 @@ pub struct Scripts {
     pub scripts: Vec<Script>,
 }
 
+impl Scripts {
+    /// Write the XML representation to an XML writer.
+    ///
+    /// Nothing is written if there are no scripts.
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> PkgResult<()> {
+        if self.scripts.is_empty() {
+            return Ok(());
+        }
+
+        write_start_element(writer, "scripts", &[])?;
+        for script in &self.scripts {
+            let (name, file, component_id) = match script {
+                Script::PreInstall(s) => ("preinstall", &s.file, &s.component_id),
+                Script::PostInstall(s) => ("postinstall", &s.file, &s.component_id),
+            };
+
+            write_empty_element(
+                writer,
+                name,
+                &[
+                    ("file", Some(file.clone())),
+                    ("component-id", component_id.clone()),
+                ],
+            )?;
+        }
+        write_end_element(writer)
+    }
+}
+
 /// An entry in <scripts>.
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub enum Script {
@@ -221,6 +428,7 @@ pub struct PreInstall {
     pub file: String,
 
     /// ID of bundle element to run before.
+    #[serde(rename = "component-id")]
     pub component_id: Option<String>,
 }
 
@@ -231,6 +439,7 @@ pub struct PostInstall {
     pub file: String,
 
     /// ID of bundle element to run after.
+    #[serde(rename = "component-id")]
     pub component_id: Option<String>,
 }
 
@@ -273,4 +482,137 @@ mod test {
             ]
         );
     }
+
+    // Laid out like the PackageInfo `pkgbuild --component` writes for an app
+    // with a nested login item. No package built by `pkgbuild` is vendored, so
+    // identifiers, sizes and the generator version are illustrative.
+    const PKGBUILD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<pkg-info overwrite-permissions="true" relocatable="false" identifier="com.example.app" postinstall-action="none" version="1.2.3" format-version="2" generator-version="InstallCmds-807 (21D62)" install-location="/Applications" auth="root">
+    <payload numberOfFiles="42" installKBytes="1024"/>
+    <bundle path="./Example.app" id="com.example.app" CFBundleShortVersionString="1.2.3" CFBundleVersion="123">
+        <bundle path="./Contents/Library/LoginItems/Helper.app" id="com.example.app.helper" CFBundleShortVersionString="1.2.3" CFBundleVersion="123"/>
+    </bundle>
+    <bundle-version>
+        <bundle id="com.example.app"/>
+    </bundle-version>
+    <upgrade-bundle>
+        <bundle id="com.example.app"/>
+    </upgrade-bundle>
+    <update-bundle/>
+    <atomic-update-bundle/>
+    <strict-identifier>
+        <bundle id="com.example.app"/>
+    </strict-identifier>
+    <relocate>
+        <bundle id="com.example.app"/>
+    </relocate>
+    <scripts>
+        <postinstall file="./postinstall" component-id="com.example.app"/>
+    </scripts>
+</pkg-info>
+"#;
+
+    #[test]
+    fn pkgbuild_decode() -> PkgResult<()> {
+        let info = PackageInfo::from_xml(PKGBUILD)?;
+
+        let app = vec![BundleRef {
+            id: Some("com.example.app".into()),
+        }];
+        assert_eq!(info.identifier, "com.example.app");
+        assert_eq!(info.install_location.as_deref(), Some("/Applications"));
+        assert_eq!(info.bundle.len(), 1);
+        assert_eq!(info.bundle[0].cf_bundle_version.as_deref(), Some("123"));
+        assert_eq!(info.bundle[0].bundle.len(), 1);
+        assert_eq!(info.bundle[0].bundle[0].id, "com.example.app.helper");
+        assert_eq!(
+            info.bundle[0].bundle[0].path,
+            "./Contents/Library/LoginItems/Helper.app"
+        );
+        assert_eq!(info.bundle_version, app);
+        assert_eq!(info.upgrade_bundle, app);
+        assert_eq!(info.update_bundle, vec![]);
+        assert_eq!(info.strict_identifiers, app);
+        assert_eq!(info.relocate, app);
+        assert_eq!(
+            info.scripts.scripts,
+            vec![Script::PostInstall(PostInstall {
+                file: "./postinstall".into(),
+                component_id: Some("com.example.app".into()),
+            })]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> PkgResult<()> {
+        let mut info = PackageInfo::from_xml(PKGBUILD)?;
+        assert_eq!(PackageInfo::from_reader(info.to_xml()?.as_slice())?, info);
+
+        // Rewrite an existing package.
+        info.identifier = "org.example.app".into();
+        info.version = "2.0".into();
+        info.install_location = Some("/opt".into());
+        info.patch.push(File {
+            path: "/opt/file".into(),
+            required_sha1: None,
+            sha1: Some("0000000000000000000000000000000000000000".into()),
+        });
+
+        let xml = String::from_utf8(info.to_xml()?).unwrap();
+        assert!(
+            xml.contains(r#"identifier="org.example.app" postinstall-action="none" version="2.0""#)
+        );
+        assert_eq!(PackageInfo::from_xml(&xml)?, info);
+
+        Ok(())
+    }
+
+    // Laid out like the PackageInfo `pkgbuild --root <dir> --scripts <dir>
+    // --preserve-xattr` writes for a component without bundles. No package built
+    // by `pkgbuild` is vendored, so the identifier, sizes and generator version
+    // are illustrative.
+    const PKGBUILD_SCRIPTS: &str = r#"<?xml version="1.0" encoding="utf-8" standalone="no"?>
+<pkg-info overwrite-permissions="true" relocatable="false" identifier="com.example.tool" postinstall-action="none" version="0.4.1" format-version="2" generator-version="InstallCmds-834 (22G74)" install-location="/usr/local" auth="root" preserve-xattr="true">
+    <payload numberOfFiles="5" installKBytes="2381"/>
+    <bundle-version/>
+    <upgrade-bundle/>
+    <update-bundle/>
+    <atomic-update-bundle/>
+    <strict-identifier/>
+    <relocate/>
+    <scripts>
+        <preinstall file="./preinstall"/>
+        <postinstall file="./postinstall"/>
+    </scripts>
+</pkg-info>
+"#;
+
+    #[test]
+    fn pkgbuild_scripts_round_trip() -> PkgResult<()> {
+        let mut info = PackageInfo::from_xml(PKGBUILD_SCRIPTS)?;
+        assert_eq!(info.preserve_xattr, Some(true));
+        assert_eq!(info.install_location.as_deref(), Some("/usr/local"));
+        assert_eq!(
+            info.payload,
+            Some(Payload {
+                number_of_files: 5,
+                install_kbytes: 2381,
+            })
+        );
+        assert!(info.bundle.is_empty());
+        assert_eq!(info.scripts.scripts.len(), 2);
+        assert_eq!(PackageInfo::from_reader(info.to_xml()?.as_slice())?, info);
+
+        info.version = "0.5.0".into();
+        info.install_location = Some("/opt/tool".into());
+        let xml = String::from_utf8(info.to_xml()?).unwrap();
+        assert!(xml.contains(r#"version="0.5.0""#));
+        assert!(xml.contains(r#"install-location="/opt/tool""#));
+        assert!(xml.contains(r#"preserve-xattr="true""#));
+        assert_eq!(PackageInfo::from_xml(&xml)?, info);
+
+        Ok(())
+    }
 }
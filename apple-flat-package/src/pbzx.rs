@@ -0,0 +1,315 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! pbzx compressed streams.
+//!
+//! pbzx is a chunked compression format used for the `Payload` of newer
+//! packages and of macOS installers. A stream starts with the magic `pbzx`
+//! and a big-endian u64 holding the maximum uncompressed size of chunks.
+//! Each chunk consists of a big-endian u64 uncompressed size, a big-endian
+//! u64 stored size and the stored data. If both sizes are equal, the data is
+//! stored raw. Otherwise it is a complete XZ stream.
+//!
+//! The decompressed stream of a `Payload` is a cpio archive, which can be read
+//! by chaining [PbzxReader] and `cpio_archive::reader()`:
+//!
+//! ```no_run
+//! use {apple_flat_package::pbzx::PbzxReader, cpio_archive::CpioReader};
+//!
+//! let file = std::fs::File::open("Payload")?;
+//! let mut cpio = cpio_archive::reader(PbzxReader::new(file)?)?;
+//! while let Some(header) = cpio.read_next()? {
+//!     println!("{}", header.name());
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use {
+    crate::{Error, PkgResult},
+    std::io::{Read, Write},
+};
+
+/// Magic bytes at the start of pbzx streams.
+pub const PBZX_MAGIC: [u8; 4] = *b"pbzx";
+
+/// Magic bytes at the start of XZ streams.
+pub(crate) const XZ_MAGIC: [u8; 6] = [0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
+/// Chunk size used by Apple tooling.
+pub const DEFAULT_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Upper bound of the chunk size we are willing to buffer.
+const MAX_CHUNK_SIZE: u64 = 4 * DEFAULT_CHUNK_SIZE;
+
+/// Read a big-endian u64, returning `None` on a clean end of stream.
+fn read_u64(reader: &mut impl Read) -> PkgResult<Option<u64>> {
+    let mut buf = [0u8; 8];
+    let mut offset = 0;
+
+    while offset < buf.len() {
+        match reader.read(&mut buf[offset..])? {
+            0 if offset == 0 => return Ok(None),
+            0 => return Err(Error::Pbzx("truncated chunk header")),
+            size => offset += size,
+        }
+    }
+
+    Ok(Some(u64::from_be_bytes(buf)))
+}
+
+/// A reader decompressing a pbzx stream.
+pub struct PbzxReader<R: Read> {
+    reader: R,
+    chunk_size: u64,
+    chunk: Vec<u8>,
+    offset: usize,
+}
+
+impl<R: Read> PbzxReader<R> {
+    /// Construct a new instance, reading the stream header.
+    pub fn new(mut reader: R) -> PkgResult<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != PBZX_MAGIC {
+            return Err(Error::Pbzx("bad magic"));
+        }
+
+        let chunk_size = read_u64(&mut reader)?.ok_or(Error::Pbzx("missing header"))?;
+
+        Ok(Self {
+            reader,
+            chunk_size,
+            chunk: vec![],
+            offset: 0,
+        })
+    }
+
+    /// The maximum uncompressed size of chunks, as declared by the stream header.
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    /// Return the inner reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read and decompress the next chunk.
+    ///
+    /// Returns false at the end of the stream.
+    fn read_chunk(&mut self) -> PkgResult<bool> {
+        let Some(uncompressed_size) = read_u64(&mut self.reader)? else {
+            return Ok(false);
+        };
+        let stored_size = read_u64(&mut self.reader)?.ok_or(Error::Pbzx("truncated chunk"))?;
+
+        if uncompressed_size > MAX_CHUNK_SIZE || stored_size > MAX_CHUNK_SIZE {
+            return Err(Error::Pbzx("chunk too large"));
+        }
+
+        let mut stored = vec![0u8; stored_size as usize];
+        self.reader.read_exact(&mut stored)?;
+
+        self.chunk = if stored_size == uncompressed_size {
+            stored
+        } else {
+            if !stored.starts_with(&XZ_MAGIC) {
+                return Err(Error::Pbzx("chunk is not XZ compressed"));
+            }
+
+            let mut chunk = Vec::with_capacity(uncompressed_size as usize);
+            xz2::read::XzDecoder::new(stored.as_slice())
+                .take(uncompressed_size + 1)
+                .read_to_end(&mut chunk)?;
+
+            if chunk.len() as u64 != uncompressed_size {
+                return Err(Error::Pbzx("chunk size mismatch"));
+            }
+
+            chunk
+        };
+        self.offset = 0;
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for PbzxReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Chunks can be empty, so loop until we have data or reach the end.
+        while self.offset == self.chunk.len() {
+            if !self.read_chunk().map_err(std::io::Error::other)? {
+                return Ok(0);
+            }
+        }
+
+        let size = buf.len().min(self.chunk.len() - self.offset);
+        buf[..size].copy_from_slice(&self.chunk[self.offset..self.offset + size]);
+        self.offset += size;
+
+        Ok(size)
+    }
+}
+
+/// A writer producing a pbzx stream.
+///
+/// Data is buffered and compressed in chunks. Chunks that XZ doesn't make
+/// smaller are stored raw. [Self::finish] must be called to write the last chunk.
+pub struct PbzxWriter<W: Write> {
+    writer: W,
+    chunk_size: usize,
+    level: u32,
+    chunk: Vec<u8>,
+}
+
+impl<W: Write> PbzxWriter<W> {
+    /// Construct a new instance using the default chunk size and compression level.
+    pub fn new(writer: W) -> PkgResult<Self> {
+        Self::with_options(writer, DEFAULT_CHUNK_SIZE, 6)
+    }
+
+    /// Construct a new instance with an explicit chunk size and XZ compression level.
+    ///
+    /// The level ranges from 0 to 9.
+    pub fn with_options(mut writer: W, chunk_size: u64, level: u32) -> PkgResult<Self> {
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(Error::Pbzx("invalid chunk size"));
+        }
+
+        writer.write_all(&PBZX_MAGIC)?;
+        writer.write_all(&chunk_size.to_be_bytes())?;
+
+        Ok(Self {
+            writer,
+            chunk_size: chunk_size as usize,
+            level,
+            chunk: Vec::with_capacity(chunk_size as usize),
+        })
+    }
+
+    /// Compress and write the buffered chunk.
+    fn write_chunk(&mut self) -> std::io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+
+        let mut encoder = xz2::write::XzEncoder::new(vec![], self.level);
+        encoder.write_all(&self.chunk)?;
+        let compressed = encoder.finish()?;
+
+        let stored = if compressed.len() < self.chunk.len() {
+            &compressed
+        } else {
+            &self.chunk
+        };
+
+        self.writer
+            .write_all(&(self.chunk.len() as u64).to_be_bytes())?;
+        self.writer
+            .write_all(&(stored.len() as u64).to_be_bytes())?;
+        self.writer.write_all(stored)?;
+        self.chunk.clear();
+
+        Ok(())
+    }
+
+    /// Write the remaining data and return the inner writer.
+    pub fn finish(mut self) -> PkgResult<W> {
+        self.write_chunk()?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for PbzxWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let size = buf.len().min(self.chunk_size - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..size]);
+
+        if self.chunk.len() == self.chunk_size {
+            self.write_chunk()?;
+        }
+
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_chunks() -> PkgResult<()> {
+        let mut xz = xz2::write::XzEncoder::new(vec![], 6);
+        xz.write_all(&[b'b'; 1000])?;
+        let xz = xz.finish()?;
+
+        let mut data = PBZX_MAGIC.to_vec();
+        data.extend(1000u64.to_be_bytes());
+        // A raw chunk.
+        data.extend(3u64.to_be_bytes());
+        data.extend(3u64.to_be_bytes());
+        data.extend(b"aaa");
+        // An XZ compressed chunk.
+        data.extend(1000u64.to_be_bytes());
+        data.extend((xz.len() as u64).to_be_bytes());
+        data.extend(xz);
+
+        let mut reader = PbzxReader::new(data.as_slice())?;
+        assert_eq!(reader.chunk_size(), 1000);
+        let mut decoded = vec![];
+        reader.read_to_end(&mut decoded)?;
+        assert_eq!(&decoded[0..3], b"aaa");
+        assert_eq!(&decoded[3..], &[b'b'; 1000]);
+
+        // Truncated streams are errors.
+        let mut reader = PbzxReader::new(&data[0..data.len() - 1])?;
+        assert!(reader.read_to_end(&mut vec![]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_cpio() -> PkgResult<()> {
+        let mut cpio = cpio_archive::OdcBuilder::new(PbzxWriter::with_options(vec![], 1024, 6)?);
+        let mut header = cpio.next_header();
+        header.name = "./compressible".to_string();
+        header.mode = 0o100644;
+        header.file_size = 5000;
+        cpio.append_header_with_data(header, [b'a'; 5000])?;
+        let mut header = cpio.next_header();
+        header.name = "./random".to_string();
+        header.mode = 0o100644;
+        let random = (0..3000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        header.file_size = random.len() as _;
+        cpio.append_header_with_data(header, &random)?;
+        let data = cpio.into_inner()?.finish()?;
+
+        let mut reader = cpio_archive::reader(PbzxReader::new(std::io::Cursor::new(data))?)?;
+        let mut files = vec![];
+        while let Some(header) = reader.read_next()? {
+            let mut content = vec![];
+            reader.read_to_end(&mut content)?;
+            files.push((header.name().to_string(), content));
+        }
+        assert_eq!(
+            files,
+            vec![
+                ("./compressible".to_string(), vec![b'a'; 5000]),
+                ("./random".to_string(), random)
+            ]
+        );
+
+        assert!(PbzxWriter::with_options(vec![], 0, 6).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,793 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Inspect Apple flat packages, like `pkgutil`.
+
+use {
+    apple_bom::ParsedBom,
+    apple_flat_package::{
+        distribution::{Choice, Distribution, Line},
+        package_info::Script,
+        requirements::{InstalledBundle, Outcome, TargetSystem},
+        ComponentPackageReader, PkgFlavor, PkgReader,
+    },
+    apple_xar::{
+        builder::XarBuilder,
+        reader::UnpackOptions,
+        table_of_contents::{Encoding, SignatureStyle},
+    },
+    clap::{value_parser, Arg, ArgAction, ArgMatches, Command},
+    std::{fs::File, path::PathBuf},
+};
+
+type CliResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+fn path_arg() -> Arg {
+    Arg::new("path")
+        .action(ArgAction::Set)
+        .value_parser(value_parser!(PathBuf))
+        .required(true)
+        .help("Path to .pkg file")
+}
+
+fn component_arg() -> Arg {
+    Arg::new("component")
+        .long("component")
+        .action(ArgAction::Set)
+        .help("Only show the component package with this path, e.g. `foo.pkg`")
+}
+
+fn open(args: &ArgMatches) -> CliResult<PkgReader<File>> {
+    let path = args
+        .get_one::<PathBuf>("path")
+        .expect("path should be required");
+
+    Ok(PkgReader::new(File::open(path)?)?)
+}
+
+/// Resolve the component packages selected by the arguments.
+///
+/// The root component of a component package has an empty path.
+fn components(
+    reader: &mut PkgReader<File>,
+    args: &ArgMatches,
+) -> CliResult<Vec<(String, ComponentPackageReader)>> {
+    // Not all commands accept this argument.
+    let filter = args.try_get_one::<String>("component").ok().flatten();
+
+    let paths = match reader.flavor() {
+        PkgFlavor::Component => vec!["".to_string()],
+        PkgFlavor::Product => reader.component_package_paths()?,
+    };
+
+    let mut res = vec![];
+    for path in paths {
+        if filter.is_some_and(|filter| filter != &path) {
+            continue;
+        }
+        if let Some(component) = reader.resolve_component(&path)? {
+            res.push((path, component));
+        }
+    }
+
+    if let (Some(filter), true) = (filter, res.is_empty()) {
+        return Err(format!("component package not found: {filter}").into());
+    }
+
+    Ok(res)
+}
+
+fn display_path(path: &str) -> &str {
+    if path.is_empty() {
+        "(root)"
+    } else {
+        path
+    }
+}
+
+fn command_components(args: &ArgMatches) -> CliResult<()> {
+    let mut reader = open(args)?;
+
+    println!("flavor: {:?}", reader.flavor());
+    for (path, component) in components(&mut reader, args)? {
+        println!("{}", display_path(&path));
+
+        if let Some(info) = component.package_info() {
+            println!("  identifier: {}", info.identifier);
+            println!("  version: {}", info.version);
+            if let Some(location) = &info.install_location {
+                println!("  install location: {location}");
+            }
+            println!("  auth: {}", info.auth);
+            if let Some(payload) = &info.payload {
+                println!(
+                    "  payload: {} files, {} KB",
+                    payload.number_of_files, payload.install_kbytes
+                );
+            }
+            for script in &info.scripts.scripts {
+                match script {
+                    Script::PreInstall(script) => println!("  preinstall: {}", script.file),
+                    Script::PostInstall(script) => println!("  postinstall: {}", script.file),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_line(line: &Line, depth: usize) {
+    println!("{}{}", "  ".repeat(depth), line.choice);
+    for line in &line.lines {
+        print_line(line, depth + 1);
+    }
+}
+
+fn print_choice(choice: &Choice) {
+    println!("  {}", choice.id);
+    if let Some(title) = &choice.title {
+        println!("    title: {title}");
+    }
+    for (name, value) in [
+        ("enabled", &choice.enabled),
+        ("selected", &choice.selected),
+        ("visible", &choice.visible),
+    ] {
+        if let Some(value) = value {
+            println!("    {name}: {value}");
+        }
+    }
+    for (name, value) in [
+        ("start enabled", choice.start_enabled),
+        ("start selected", choice.start_selected),
+        ("start visible", choice.start_visible),
+    ] {
+        if let Some(value) = value {
+            println!("    {name}: {value}");
+        }
+    }
+    if let Some(location) = &choice.custom_location {
+        println!("    custom location: {location}");
+    }
+    for pkg_ref in &choice.pkg_ref {
+        println!("    pkg-ref: {}", pkg_ref.id);
+    }
+}
+
+fn print_requirements(distribution: &Distribution) {
+    println!("requirements:");
+
+    if let Some(options) = &distribution.options {
+        if let Some(architectures) = &options.host_architecutres {
+            println!("  host architectures: {architectures}");
+        }
+        if let Some(value) = options.root_volume_only {
+            println!("  root volume only: {value}");
+        }
+    }
+    if let Some(domains) = &distribution.domains {
+        println!(
+            "  domains: anywhere={} current user home={} local system={}",
+            domains.enable_anywhere, domains.enable_current_user_home, domains.enable_local_system
+        );
+    }
+    if let Some(check) = &distribution.installation_check {
+        if let Some(script) = &check.script {
+            println!("  installation check script: {script}");
+        }
+        if let Some(ram) = &check.ram {
+            println!("  minimum RAM: {} GB", ram.min_gb);
+        }
+        if let Some(graphics) = &check.required_graphics {
+            if let Some(device) = &graphics.required_cl_device {
+                println!("  required OpenCL device: {}", device.predicate);
+            }
+            if let Some(renderer) = &graphics.required_gl_renderer {
+                println!("  required OpenGL renderer: {}", renderer.predicate);
+            }
+        }
+    }
+    if let Some(check) = &distribution.volume_check {
+        if let Some(script) = &check.script {
+            println!("  volume check script: {script}");
+        }
+        for version in check
+            .allowed_os_versions
+            .iter()
+            .flat_map(|v| &v.os_versions)
+        {
+            if let Some(before) = &version.before {
+                println!("  allowed OS versions: >= {}, < {}", version.min, before);
+            } else {
+                println!("  allowed OS versions: >= {}", version.min);
+            }
+        }
+        if let Some(bundles) = &check.required_bundles {
+            for bundle in &bundles.bundles {
+                println!("  required bundle: {} at {}", bundle.id, bundle.path);
+            }
+        }
+    }
+    if let Some(script) = &distribution.script {
+        println!("  script: {} bytes", script.script.len());
+    }
+}
+
+fn command_distribution(args: &ArgMatches) -> CliResult<()> {
+    let mut reader = open(args)?;
+    let distribution = reader
+        .distribution()?
+        .ok_or("package does not have a Distribution file")?;
+
+    if let Some(title) = &distribution.title {
+        println!("title: {}", title.title);
+    }
+    if let Some(product) = &distribution.product {
+        println!(
+            "product: {} {}",
+            product.id,
+            product.version.as_deref().unwrap_or_default()
+        );
+    }
+    for (name, file) in [
+        (
+            "background",
+            distribution.background.as_ref().map(|x| &x.file),
+        ),
+        ("welcome", distribution.welcome.as_ref().map(|x| &x.file)),
+        ("readme", distribution.readme.as_ref().map(|x| &x.file)),
+        ("license", distribution.license.as_ref().map(|x| &x.file)),
+        (
+            "conclusion",
+            distribution.conclusion.as_ref().map(|x| &x.file),
+        ),
+    ] {
+        if let Some(file) = file {
+            println!("{name}: {file}");
+        }
+    }
+
+    print_requirements(&distribution);
+
+    println!("choices outline:");
+    for line in &distribution.choices_outline.line {
+        print_line(line, 1);
+    }
+
+    println!("choices:");
+    for choice in &distribution.choice {
+        print_choice(choice);
+    }
+
+    println!("packages:");
+    for pkg_ref in &distribution.pkg_ref {
+        if let Some(location) = &pkg_ref.location {
+            println!(
+                "  {} {} ({} KB) at {}",
+                pkg_ref.id,
+                pkg_ref.version.as_deref().unwrap_or_default(),
+                pkg_ref.install_kbytes.unwrap_or_default(),
+                location
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn command_check_requirements(args: &ArgMatches) -> CliResult<()> {
+    let mut reader = open(args)?;
+    let distribution = reader
+        .distribution()?
+        .ok_or("package does not have a Distribution file")?;
+
+    let mut target = TargetSystem::default();
+    if let Some(version) = args.get_one::<String>("os_version") {
+        target.os_version = version.clone();
+    }
+    if let Some(architecture) = args.get_one::<String>("arch") {
+        target.architecture = architecture.clone();
+    }
+    if let Some(ram) = args.get_one::<u64>("ram_gb") {
+        target.ram_bytes = ram << 30;
+    }
+    if let Some(bundles) = args.get_many::<String>("bundle") {
+        for bundle in bundles {
+            let mut parts = bundle.splitn(3, ':');
+            let (Some(path), Some(identifier)) = (parts.next(), parts.next()) else {
+                return Err(format!("invalid bundle: {bundle}").into());
+            };
+            target.bundles.push(InstalledBundle {
+                path: path.to_string(),
+                identifier: identifier.to_string(),
+                short_version: parts.next().map(|v| v.to_string()),
+                version: None,
+            });
+        }
+    }
+
+    let report = distribution.evaluate_requirements(&target);
+
+    for check in &report.checks {
+        match &check.outcome {
+            Outcome::Passed => println!("{}: passed", check.check),
+            Outcome::Warning(message) => println!("{}: warning: {message}", check.check),
+            Outcome::Failed(message) => println!("{}: FAILED: {message}", check.check),
+            Outcome::Unknown(message) => println!("{}: unknown: {message}", check.check),
+        }
+    }
+
+    println!("choices:");
+    for choice in &report.choices {
+        let state = |value: Option<bool>| match value {
+            Some(value) => value.to_string(),
+            None => "unknown".to_string(),
+        };
+        println!(
+            "  {} (visible: {}, enabled: {}, selected: {})",
+            choice.id,
+            state(choice.visible),
+            state(choice.enabled),
+            state(choice.selected)
+        );
+    }
+
+    println!(
+        "installation allowed: {}",
+        match report.installation_allowed() {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "unknown",
+        }
+    );
+
+    Ok(())
+}
+
+fn command_package_info(args: &ArgMatches) -> CliResult<()> {
+    let mut reader = open(args)?;
+
+    for (path, component) in components(&mut reader, args)? {
+        if let Some(info) = component.package_info() {
+            println!("# {}", display_path(&path));
+            println!("{}", String::from_utf8(info.to_xml()?)?);
+        }
+    }
+
+    Ok(())
+}
+
+fn command_payload_files(args: &ArgMatches) -> CliResult<()> {
+    let mut reader = open(args)?;
+
+    for (path, component) in components(&mut reader, args)? {
+        let Some(bom) = component.bom() else {
+            eprintln!("{} does not have a Bom", display_path(&path));
+            continue;
+        };
+
+        for entry in ParsedBom::parse(bom)?.paths()? {
+            if let Some(link) = entry.link_name() {
+                println!(
+                    "{} {}/{} {} -> {}",
+                    entry.symbolic_mode(),
+                    entry.user_id(),
+                    entry.group_id(),
+                    entry.path(),
+                    link
+                );
+            } else {
+                println!(
+                    "{} {}/{} {:>10} {}",
+                    entry.symbolic_mode(),
+                    entry.user_id(),
+                    entry.group_id(),
+                    entry.size(),
+                    entry.path()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn command_expand(args: &ArgMatches) -> CliResult<()> {
+    let mut reader = open(args)?;
+    let dest_dir = args
+        .get_one::<PathBuf>("destination")
+        .expect("destination should be required");
+    let full = args.get_flag("full");
+
+    if dest_dir.exists() {
+        return Err(format!("destination already exists: {}", dest_dir.display()).into());
+    }
+
+    // Packages may come from untrusted sources, so guard against malicious paths.
+    std::fs::create_dir_all(dest_dir)?;
+    reader
+        .xar_mut()
+        .unpack_with_options(dest_dir, &UnpackOptions::default())?;
+
+    if full {
+        for (path, component) in components(&mut reader, args)? {
+            let dir = dest_dir.join(&path);
+
+            // Like pkgutil, replace the archives by directories of their content.
+            if component.payload_reader()?.is_some() {
+                std::fs::remove_file(dir.join("Payload"))?;
+                for discrepancy in component.extract_payload(dir.join("Payload"))? {
+                    eprintln!("warning: {}: {discrepancy}", display_path(&path));
+                }
+            }
+            if component.scripts_reader()?.is_some() {
+                std::fs::remove_file(dir.join("Scripts"))?;
+                component.extract_scripts(dir.join("Scripts"))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn command_flatten(args: &ArgMatches) -> CliResult<()> {
+    let source_dir = args
+        .get_one::<PathBuf>("source")
+        .expect("source should be required");
+    let dest = args
+        .get_one::<PathBuf>("path")
+        .expect("path should be required");
+
+    if dest.exists() {
+        return Err(format!("destination already exists: {}", dest.display()).into());
+    }
+
+    // Archives live at the root of component packages and in the `.pkg`
+    // directories of product packages.
+    let mut component_dirs = vec![String::new()];
+    for entry in std::fs::read_dir(source_dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if name.ends_with(".pkg") {
+            component_dirs.push(format!("{name}/"));
+        }
+    }
+
+    let mut xar = XarBuilder::new();
+    xar.add_tree("", source_dir)?;
+
+    for dir in component_dirs {
+        for name in ["Payload", "Scripts"] {
+            let path = format!("{dir}{name}");
+
+            if source_dir.join(&path).is_dir() {
+                return Err(format!(
+                    "{path} is a directory; packages expanded with --full cannot be flattened"
+                )
+                .into());
+            }
+
+            // The archives are compressed already.
+            if let Some(entry) = xar.entry_mut(&path) {
+                entry.set_encoding(Encoding::None);
+            }
+        }
+    }
+
+    xar.write(&mut File::create(dest)?)?;
+
+    Ok(())
+}
+
+fn command_verify_payload(args: &ArgMatches) -> CliResult<()> {
+    let mut reader = open(args)?;
+    let mut count = 0;
+
+    for (path, component) in components(&mut reader, args)? {
+        if component.bom().is_none() {
+            eprintln!("{} does not have a Bom", display_path(&path));
+            continue;
+        }
+
+        for discrepancy in component.verify_payload()? {
+            println!("{}: {discrepancy}", display_path(&path));
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        println!("payloads match their Bom");
+        Ok(())
+    } else {
+        Err(format!("{count} discrepancies between payloads and their Bom").into())
+    }
+}
+
+fn command_signature(args: &ArgMatches) -> CliResult<()> {
+    let mut reader = open(args)?;
+    let xar = reader.xar_mut();
+
+    let (checksum_type, checksum) = xar.checksum()?;
+    let checksum_valid = xar.verify_table_of_contents_checksum()?;
+    println!(
+        "checksum: {} {} ({})",
+        checksum_type,
+        checksum
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>(),
+        if checksum_valid { "valid" } else { "INVALID" }
+    );
+
+    let signatures = xar
+        .table_of_contents()
+        .signatures()
+        .into_iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    if signatures.is_empty() {
+        println!("signature: none");
+    }
+
+    for signature in signatures {
+        let verified = match signature.style {
+            SignatureStyle::Rsa => xar.verify_rsa_checksum_signature(),
+            SignatureStyle::Cms => xar.verify_cms_signature(),
+        };
+        println!(
+            "signature: {} ({})",
+            signature.style,
+            match verified {
+                Ok(true) => "valid".to_string(),
+                Ok(false) => "not verified".to_string(),
+                Err(e) => format!("INVALID: {e}"),
+            }
+        );
+
+        for (i, cert) in signature.x509_certificates()?.iter().enumerate() {
+            println!("  certificate #{i}");
+            println!(
+                "    subject: {}",
+                cert.subject_name()
+                    .user_friendly_str()
+                    .unwrap_or_else(|_| "(unknown)".to_string())
+            );
+            println!(
+                "    issuer: {}",
+                cert.issuer_name()
+                    .user_friendly_str()
+                    .unwrap_or_else(|_| "(unknown)".to_string())
+            );
+            println!(
+                "    not before: {}",
+                cert.validity_not_before().to_rfc3339()
+            );
+            println!("    not after: {}", cert.validity_not_after().to_rfc3339());
+            println!(
+                "    SHA-256 fingerprint: {}",
+                cert.sha256_fingerprint()?
+                    .as_ref()
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<String>()
+            );
+            println!(
+                "    Developer ID Installer: {}",
+                apple_xar::trust::has_developer_id_installer_purpose(cert)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn cli() -> Command {
+    Command::new("Apple Flat Package Utility")
+        .arg_required_else_help(true)
+        .version("0.1")
+        .author("Gregory Szorc <gregory.szorc@gmail.com>")
+        .about("Inspect Apple flat packages (.pkg files)")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("components")
+                .about("List component packages")
+                .arg(path_arg())
+                .arg(component_arg()),
+        )
+        .subcommand(
+            Command::new("distribution")
+                .about("Show the choices and requirements of the Distribution file")
+                .arg(path_arg()),
+        )
+        .subcommand(
+            Command::new("check-requirements")
+                .about("Evaluate the Distribution requirements against a simulated system")
+                .arg(path_arg())
+                .arg(
+                    Arg::new("os_version")
+                        .long("os-version")
+                        .action(ArgAction::Set)
+                        .help("macOS version of the system [default: 14.0]"),
+                )
+                .arg(
+                    Arg::new("arch")
+                        .long("arch")
+                        .action(ArgAction::Set)
+                        .help("CPU architecture of the system [default: arm64]"),
+                )
+                .arg(
+                    Arg::new("ram_gb")
+                        .long("ram-gb")
+                        .action(ArgAction::Set)
+                        .value_parser(value_parser!(u64))
+                        .help("Installed memory in GB [default: 16]"),
+                )
+                .arg(
+                    Arg::new("bundle")
+                        .long("bundle")
+                        .action(ArgAction::Append)
+                        .help("Installed bundle as PATH:IDENTIFIER[:VERSION]"),
+                ),
+        )
+        .subcommand(
+            Command::new("package-info")
+                .about("Print the PackageInfo files of component packages")
+                .arg(path_arg())
+                .arg(component_arg()),
+        )
+        .subcommand(
+            Command::new("payload-files")
+                .about("List the files of component payloads using their Bom")
+                .arg(path_arg())
+                .arg(component_arg()),
+        )
+        .subcommand(
+            Command::new("expand")
+                .about("Expand a package to a new directory")
+                .arg(path_arg())
+                .arg(
+                    Arg::new("destination")
+                        .action(ArgAction::Set)
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("Directory to create"),
+                )
+                .arg(
+                    Arg::new("full")
+                        .long("full")
+                        .action(ArgAction::SetTrue)
+                        .help("Also expand Payload and Scripts archives"),
+                ),
+        )
+        .subcommand(
+            Command::new("flatten")
+                .about("Create a package from a directory expanded without --full")
+                .arg(
+                    Arg::new("source")
+                        .action(ArgAction::Set)
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("Expanded package directory"),
+                )
+                .arg(
+                    Arg::new("path")
+                        .action(ArgAction::Set)
+                        .value_parser(value_parser!(PathBuf))
+                        .required(true)
+                        .help("Path of the .pkg file to create"),
+                ),
+        )
+        .subcommand(
+            Command::new("verify-payload")
+                .about("Verify that the payload files match the Bom")
+                .arg(path_arg())
+                .arg(component_arg()),
+        )
+        .subcommand(
+            Command::new("signature")
+                .about("Show and verify the checksum, signatures and certificates")
+                .arg(path_arg()),
+        )
+}
+
+fn run(matches: ArgMatches) -> CliResult<()> {
+    match matches.subcommand() {
+        Some(("components", args)) => command_components(args),
+        Some(("distribution", args)) => command_distribution(args),
+        Some(("check-requirements", args)) => command_check_requirements(args),
+        Some(("package-info", args)) => command_package_info(args),
+        Some(("payload-files", args)) => command_payload_files(args),
+        Some(("expand", args)) => command_expand(args),
+        Some(("flatten", args)) => command_flatten(args),
+        Some(("verify-payload", args)) => command_verify_payload(args),
+        Some(("signature", args)) => command_signature(args),
+        _ => Err("unhandled command".into()),
+    }
+}
+
+fn main_impl() -> CliResult<()> {
+    run(cli().get_matches())
+}
+
+fn main() {
+    let exit_code = match main_impl() {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            1
+        }
+    };
+
+    std::process::exit(exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, apple_flat_package::ComponentPackageBuilder, std::path::Path};
+
+    fn opkgutil(args: &[&Path]) -> CliResult<()> {
+        let args = std::iter::once(Path::new("opkgutil")).chain(args.iter().copied());
+        run(cli().try_get_matches_from(args)?)
+    }
+
+    /// The decoded content of the files of a package, by path.
+    fn package_files(path: &Path) -> CliResult<Vec<(String, Vec<u8>)>> {
+        let mut reader = PkgReader::new(File::open(path)?)?;
+        let xar = reader.xar_mut();
+
+        let mut files = vec![];
+        for (path, file) in xar.files()? {
+            if file.data.is_some() {
+                let data = xar.get_file_data_from_path(&path)?.unwrap();
+                files.push((path, data));
+            }
+        }
+        files.sort();
+
+        Ok(files)
+    }
+
+    #[test]
+    fn expand_and_flatten() -> CliResult<()> {
+        let tmp = tempfile::tempdir()?;
+        let root = tmp.path().join("root");
+        std::fs::create_dir_all(root.join("usr/local/bin"))?;
+        std::fs::write(root.join("usr/local/bin/tool"), b"#!/bin/sh\n")?;
+        let scripts = tmp.path().join("scripts");
+        std::fs::create_dir(&scripts)?;
+        std::fs::write(scripts.join("postinstall"), b"#!/bin/sh\n")?;
+
+        let mut builder = ComponentPackageBuilder::new("com.example.tool", "1.0");
+        builder.set_payload_root(&root);
+        builder.set_scripts_dir(&scripts);
+        let pkg = tmp.path().join("tool.pkg");
+        builder.write(&mut File::create(&pkg)?)?;
+
+        let expanded = tmp.path().join("expanded");
+        opkgutil(&[Path::new("expand"), &pkg, &expanded])?;
+        assert!(expanded.join("PackageInfo").is_file());
+        assert!(expanded.join("Payload").is_file());
+
+        let flattened = tmp.path().join("flattened.pkg");
+        opkgutil(&[Path::new("flatten"), &expanded, &flattened])?;
+        assert_eq!(package_files(&flattened)?, package_files(&pkg)?);
+        opkgutil(&[Path::new("verify-payload"), &flattened])?;
+
+        // Existing destinations are not replaced.
+        assert!(opkgutil(&[Path::new("flatten"), &expanded, &flattened]).is_err());
+        assert!(opkgutil(&[Path::new("expand"), &pkg, &expanded]).is_err());
+
+        // Expanded archives cannot be flattened.
+        let full = tmp.path().join("full");
+        opkgutil(&[Path::new("expand"), Path::new("--full"), &pkg, &full])?;
+        assert_eq!(
+            std::fs::read(full.join("Payload/usr/local/bin/tool"))?,
+            b"#!/bin/sh\n"
+        );
+        assert!(full.join("Scripts/postinstall").is_file());
+        assert!(opkgutil(&[Path::new("flatten"), &full, &tmp.path().join("full.pkg")]).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Building *product packages*.
+
+use {
+    crate::{
+        component_package::ComponentPackageBuilder,
+        distribution::{
+            Background, Choice, ChoicesOutline, Distribution, License, Line, Options, PkgRef,
+            Title, Welcome,
+        },
+        PkgResult,
+    },
+    apple_xar::builder::XarBuilder,
+    std::{collections::BTreeMap, io::Write},
+};
+
+/// Builder for *product packages*, like `productbuild`.
+///
+/// A product package combines 1 or more component packages, resource files
+/// shown by the installer and a `Distribution` file describing the installer.
+///
+/// Each component is stored in a `<identifier>.pkg` directory. When building, a
+/// `<pkg-ref>` pointing at each component and a `<choice>` installing it are
+/// added to the `Distribution`, unless the `Distribution` defines them already.
+///
+/// The written archive is not signed. Use `apple_xar::signing::XarSigner` to sign it.
+#[derive(Clone, Debug)]
+pub struct ProductPackageBuilder {
+    distribution: Distribution,
+    components: Vec<ComponentPackageBuilder>,
+    resources: BTreeMap<String, Vec<u8>>,
+}
+
+impl ProductPackageBuilder {
+    /// Create a new builder for an installer with the given title.
+    pub fn new(title: impl ToString) -> Self {
+        Self {
+            distribution: Distribution {
+                min_spec_version: 2,
+                background: None,
+                choice: vec![],
+                choices_outline: ChoicesOutline { line: vec![] },
+                conclusion: None,
+                domains: None,
+                installation_check: None,
+                license: None,
+                locator: vec![],
+                options: Some(Options {
+                    allow_external_scripts: None,
+                    customize: Some("never".to_string()),
+                    host_architecutres: None,
+                    mpkg: None,
+                    require_scripts: Some(false),
+                    root_volume_only: None,
+                }),
+                pkg_ref: vec![],
+                product: None,
+                readme: None,
+                script: None,
+                title: Some(Title {
+                    title: title.to_string(),
+                }),
+                volume_check: None,
+                welcome: None,
+            },
+            components: vec![],
+            resources: BTreeMap::new(),
+        }
+    }
+
+    /// The `Distribution` to write.
+    ///
+    /// Component `<pkg-ref>` and `<choice>` elements are added when building.
+    pub fn distribution(&self) -> &Distribution {
+        &self.distribution
+    }
+
+    /// Obtain a mutable reference to the `Distribution` to write.
+    pub fn distribution_mut(&mut self) -> &mut Distribution {
+        &mut self.distribution
+    }
+
+    /// Add a component package.
+    pub fn add_component(&mut self, component: ComponentPackageBuilder) {
+        self.components.push(component);
+    }
+
+    /// Add a file to the `Resources/` directory.
+    pub fn add_resource(&mut self, name: impl ToString, data: impl Into<Vec<u8>>) {
+        self.resources.insert(name.to_string(), data.into());
+    }
+
+    /// Set the welcome text shown by the installer from a resource file.
+    pub fn set_welcome(&mut self, name: impl ToString, data: impl Into<Vec<u8>>) {
+        let name = name.to_string();
+        self.add_resource(&name, data);
+        self.distribution.welcome = Some(Welcome {
+            file: name,
+            mime_type: None,
+            uti: None,
+        });
+    }
+
+    /// Set the license shown by the installer from a resource file.
+    pub fn set_license(&mut self, name: impl ToString, data: impl Into<Vec<u8>>) {
+        let name = name.to_string();
+        self.add_resource(&name, data);
+        self.distribution.license = Some(License {
+            file: name,
+            mime_type: None,
+            uti: None,
+        });
+    }
+
+    /// Set the background image shown by the installer from a resource file.
+    pub fn set_background(&mut self, name: impl ToString, data: impl Into<Vec<u8>>) {
+        let name = name.to_string();
+        self.add_resource(&name, data);
+        self.distribution.background = Some(Background {
+            alignment: None,
+            file: name,
+            mime_type: None,
+            scaling: None,
+            uti: None,
+        });
+    }
+
+    /// Add the components, resources and `Distribution` to a XAR archive.
+    ///
+    /// Returns the `Distribution` that was written.
+    pub fn add_to_xar(&self, xar: &mut XarBuilder) -> PkgResult<Distribution> {
+        let mut distribution = self.distribution.clone();
+
+        for component in &self.components {
+            let identifier = &component.package_info().identifier;
+            let info = component.add_to_xar(xar, &format!("{identifier}.pkg"))?;
+
+            let index = distribution
+                .pkg_ref
+                .iter()
+                .position(|r| &r.id == identifier)
+                .unwrap_or_else(|| {
+                    distribution.pkg_ref.push(pkg_ref(identifier));
+                    distribution.pkg_ref.len() - 1
+                });
+            let pkg_ref = &mut distribution.pkg_ref[index];
+            pkg_ref.version = Some(info.version.clone());
+            pkg_ref.install_kbytes = info.payload.as_ref().map(|p| p.install_kbytes);
+            pkg_ref.location = Some(format!("#{identifier}.pkg"));
+            pkg_ref
+                .on_conclusion
+                .get_or_insert_with(|| "none".to_string());
+
+            if !distribution.choice.iter().any(|c| &c.id == identifier) {
+                distribution.choice.push(Choice {
+                    custom_location: None,
+                    custom_location_allow_alternative_volumes: None,
+                    description: None,
+                    description_mime_type: None,
+                    enabled: None,
+                    id: identifier.clone(),
+                    selected: None,
+                    start_enabled: None,
+                    start_selected: None,
+                    start_visible: None,
+                    title: None,
+                    visible: Some("false".to_string()),
+                    pkg_ref: vec![self::pkg_ref(identifier)],
+                });
+                distribution.choices_outline.line.push(Line {
+                    choice: identifier.clone(),
+                    lines: vec![],
+                });
+            }
+        }
+
+        for (name, data) in &self.resources {
+            xar.add_file_data(&format!("Resources/{name}"), data.clone())?;
+        }
+
+        xar.add_file_data("Distribution", distribution.to_xml()?)?;
+
+        Ok(distribution)
+    }
+
+    /// Write an unsigned product package.
+    pub fn write(&self, writer: &mut impl Write) -> PkgResult<()> {
+        let mut xar = XarBuilder::new();
+        self.add_to_xar(&mut xar)?;
+        xar.write(writer)?;
+
+        Ok(())
+    }
+}
+
+/// Construct a `<pkg-ref>` referencing a package identifier.
+fn pkg_ref(identifier: &str) -> PkgRef {
+    PkgRef {
+        active: None,
+        auth: None,
+        id: identifier.to_string(),
+        install_kbytes: None,
+        on_conclusion: None,
+        on_conclusion_script: None,
+        version: None,
+        must_close: None,
+        bundle_version: None,
+        relocate: vec![],
+        location: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::reader::{PkgFlavor, PkgReader},
+        std::io::Cursor,
+    };
+
+    #[test]
+    fn build_product() -> PkgResult<()> {
+        let root = tempfile::tempdir()?;
+        std::fs::create_dir_all(root.path().join("Applications"))?;
+        std::fs::write(root.path().join("Applications/file"), vec![42; 5000])?;
+
+        let mut builder = ProductPackageBuilder::new("Example");
+        // An existing reference without location is completed rather than repeated.
+        let mut existing = pkg_ref("com.example.a");
+        existing.auth = Some("root".to_string());
+        builder.distribution_mut().pkg_ref.push(existing);
+        for identifier in ["com.example.a", "com.example.b"] {
+            let mut component = ComponentPackageBuilder::new(identifier, "1.0");
+            component.set_payload_root(root.path());
+            builder.add_component(component);
+        }
+        builder.set_welcome("welcome.txt", b"Welcome".to_vec());
+
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        let mut reader = PkgReader::new(Cursor::new(data))?;
+        assert_eq!(reader.flavor(), PkgFlavor::Product);
+
+        let distribution = reader.distribution()?.unwrap();
+        assert_eq!(distribution.title.unwrap().title, "Example");
+        assert_eq!(distribution.welcome.unwrap().file, "welcome.txt");
+        assert_eq!(distribution.choice.len(), 2);
+        assert_eq!(distribution.choices_outline.line.len(), 2);
+        assert_eq!(
+            distribution
+                .pkg_ref
+                .iter()
+                .map(|r| (r.location.as_deref(), r.auth.as_deref(), r.install_kbytes))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some("#com.example.a.pkg"), Some("root"), Some(5)),
+                (Some("#com.example.b.pkg"), None, Some(5)),
+            ]
+        );
+
+        assert_eq!(reader.component_packages()?.len(), 2);
+
+        let mut xar = reader.into_inner();
+        assert_eq!(
+            xar.get_file_data_from_path("Resources/welcome.txt")?,
+            Some(b"Welcome".to_vec())
+        );
+
+        Ok(())
+    }
+}
@@ -57,6 +57,13 @@ impl<R: Read + Seek + Sized + Debug> PkgReader<R> {
         self.xar
     }
 
+    /// Obtain a mutable reference to the inner XAR reader.
+    ///
+    /// This can be used to access XAR level data like signatures.
+    pub fn xar_mut(&mut self) -> &mut XarReader<R> {
+        &mut self.xar
+    }
+
     /// Obtain the flavor of the flat package.
     pub fn flavor(&self) -> PkgFlavor {
         self.flavor
@@ -156,17 +163,13 @@ impl<R: Read + Seek + Sized + Debug> PkgReader<R> {
         self.resolve_component("")
     }
 
-    /// Obtain *component package* instances in this flat package.
+    /// Obtain the paths of *component packages* in this flat package.
     ///
-    /// This looks for `.pkg` directories in the root directory of the XAR archive
-    /// and resolves a [ComponentPackageReader] for each. If there are no `.pkg`
-    /// directories, this will return an empty vec.
-    ///
-    /// Generally, this function will return something for *product packages*
-    /// whereas [root_component()] will return something for *component packages*.
-    pub fn component_packages(&mut self) -> PkgResult<Vec<ComponentPackageReader>> {
+    /// These are the `.pkg` directories in the root directory of the XAR archive.
+    /// Each can be passed to [Self::resolve_component].
+    pub fn component_package_paths(&self) -> PkgResult<Vec<String>> {
         // TODO obtain instances from Distribution XML instead of scanning filenames.
-        let components = self
+        Ok(self
             .xar
             .files()?
             .into_iter()
@@ -177,11 +180,21 @@ impl<R: Read + Seek + Sized + Debug> PkgReader<R> {
                     None
                 }
             })
-            .collect::<Vec<_>>();
+            .collect::<Vec<_>>())
+    }
 
+    /// Obtain *component package* instances in this flat package.
+    ///
+    /// This looks for `.pkg` directories in the root directory of the XAR archive
+    /// and resolves a [ComponentPackageReader] for each. If there are no `.pkg`
+    /// directories, this will return an empty vec.
+    ///
+    /// Generally, this function will return something for *product packages*
+    /// whereas [root_component()] will return something for *component packages*.
+    pub fn component_packages(&mut self) -> PkgResult<Vec<ComponentPackageReader>> {
         let mut res = vec![];
 
-        for component in components {
+        for component in self.component_package_paths()? {
             res.push(
                 self.resolve_component(&component)?
                     .ok_or(Error::ComponentResolution)?,
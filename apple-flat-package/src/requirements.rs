@@ -0,0 +1,1132 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Evaluating the installation requirements of `Distribution` files.
+//!
+//! Installer checks declarative requirements of a `Distribution`, like allowed
+//! OS versions, and evaluates JavaScript expressions of `<installation-check>`,
+//! `<volume-check>` and choice attributes. The JavaScript can call a `system`
+//! API to inspect the machine.
+//!
+//! [Distribution::evaluate_requirements] evaluates these requirements against a
+//! simulated [TargetSystem]. With the `javascript` feature, scripts run in an
+//! embedded QuickJS interpreter. Its `system` and `my` objects are stubs answering
+//! from the [TargetSystem]. They cannot access the host and evaluation is bounded
+//! in time and memory. Without the feature, script checks are reported as
+//! [Outcome::Unknown].
+
+use {
+    crate::distribution::{Bundle, Distribution, RequiredGraphics},
+    std::{cmp::Ordering, collections::BTreeMap, time::Duration},
+};
+
+/// A bundle installed on a [TargetSystem].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct InstalledBundle {
+    /// Absolute path of the bundle, e.g. `/Applications/Example.app`.
+    pub path: String,
+
+    /// The `CFBundleIdentifier` of the bundle.
+    pub identifier: String,
+
+    /// The `CFBundleShortVersionString` of the bundle.
+    pub short_version: Option<String>,
+
+    /// The `CFBundleVersion` of the bundle.
+    pub version: Option<String>,
+}
+
+/// A simulated system to evaluate installation requirements against.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TargetSystem {
+    /// macOS version, e.g. `14.2.1`.
+    pub os_version: String,
+
+    /// macOS build version, e.g. `23C71`.
+    pub os_build_version: String,
+
+    /// CPU architecture, e.g. `arm64` or `x86_64`.
+    pub architecture: String,
+
+    /// Whether `x86_64` code can run with Rosetta on an `arm64` system.
+    pub rosetta: bool,
+
+    /// Installed memory in bytes.
+    pub ram_bytes: u64,
+
+    /// Installed application and other bundles.
+    pub bundles: Vec<InstalledBundle>,
+
+    /// Paths of existing files, in addition to bundles.
+    pub files: Vec<String>,
+
+    /// Installed package receipts, mapping package identifiers to versions.
+    pub receipts: BTreeMap<String, String>,
+
+    /// Properties of graphics devices.
+    ///
+    /// These are matched by the predicates of `<required-cl-device>` and
+    /// `<required-gl-renderer>`. Values that parse as numbers are compared as numbers.
+    pub graphics_devices: Vec<BTreeMap<String, String>>,
+
+    /// Additional values returned by `system.sysctl()`.
+    ///
+    /// Values for `hw.memsize`, `hw.machine`, `hw.optional.arm64`,
+    /// `kern.osproductversion` and `sysctl.proc_translated` are derived from the
+    /// other fields unless defined here.
+    pub sysctl: BTreeMap<String, String>,
+
+    /// Maximum duration of evaluating each script, defaulting to 5 seconds.
+    ///
+    /// Scripts running longer are interrupted and their checks reported as
+    /// [Outcome::Unknown].
+    pub script_timeout: Duration,
+}
+
+impl Default for TargetSystem {
+    fn default() -> Self {
+        Self {
+            os_version: "14.0".to_string(),
+            os_build_version: "23A344".to_string(),
+            architecture: "arm64".to_string(),
+            rosetta: true,
+            ram_bytes: 16 << 30,
+            bundles: vec![],
+            files: vec![],
+            receipts: BTreeMap::new(),
+            graphics_devices: vec![],
+            sysctl: BTreeMap::new(),
+            script_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl TargetSystem {
+    /// Resolve the values returned by `system.sysctl()`.
+    fn sysctl_values(&self) -> BTreeMap<String, String> {
+        let mut values = BTreeMap::from([
+            ("hw.memsize".to_string(), self.ram_bytes.to_string()),
+            ("hw.machine".to_string(), self.architecture.clone()),
+            (
+                "hw.optional.arm64".to_string(),
+                if self.architecture == "arm64" {
+                    "1"
+                } else {
+                    "0"
+                }
+                .to_string(),
+            ),
+            ("kern.osproductversion".to_string(), self.os_version.clone()),
+            ("sysctl.proc_translated".to_string(), "0".to_string()),
+        ]);
+        values.extend(self.sysctl.clone());
+
+        values
+    }
+}
+
+/// The outcome of evaluating a requirement.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// The requirement is satisfied.
+    Passed,
+
+    /// The requirement is not satisfied, but installation can continue.
+    Warning(String),
+
+    /// The requirement is not satisfied and prevents installation.
+    Failed(String),
+
+    /// The requirement could not be evaluated.
+    Unknown(String),
+}
+
+/// The result of evaluating a single requirement.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CheckResult {
+    /// The element or attribute defining the requirement.
+    ///
+    /// e.g. `volume-check/allowed-os-versions`.
+    pub check: String,
+
+    /// The outcome of the evaluation.
+    pub outcome: Outcome,
+}
+
+/// The evaluated state of a `<choice>`.
+///
+/// Values are `None` if they could not be evaluated.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChoiceState {
+    /// The `id` of the choice.
+    pub id: String,
+
+    /// Whether the choice is shown.
+    pub visible: Option<bool>,
+
+    /// Whether the choice can be changed.
+    pub enabled: Option<bool>,
+
+    /// Whether the choice is selected.
+    pub selected: Option<bool>,
+}
+
+/// The result of evaluating the requirements of a `Distribution`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RequirementsReport {
+    /// Results of the requirements, in evaluation order.
+    pub checks: Vec<CheckResult>,
+
+    /// States of the choices, in document order.
+    pub choices: Vec<ChoiceState>,
+}
+
+impl RequirementsReport {
+    /// Whether installation would be allowed.
+    ///
+    /// Returns `None` if no requirement failed but some could not be evaluated.
+    pub fn installation_allowed(&self) -> Option<bool> {
+        if self
+            .checks
+            .iter()
+            .any(|c| matches!(c.outcome, Outcome::Failed(_)))
+        {
+            Some(false)
+        } else if self
+            .checks
+            .iter()
+            .any(|c| matches!(c.outcome, Outcome::Unknown(_)))
+        {
+            None
+        } else {
+            Some(true)
+        }
+    }
+
+    /// Choices that would be shown to the user.
+    pub fn visible_choices(&self) -> impl Iterator<Item = &ChoiceState> {
+        self.choices.iter().filter(|c| c.visible == Some(true))
+    }
+
+    fn push(&mut self, check: &str, outcome: Outcome) {
+        self.checks.push(CheckResult {
+            check: check.to_string(),
+            outcome,
+        });
+    }
+}
+
+/// Compare version strings like Installer's `system.compareVersions()`.
+///
+/// Components are compared numerically if possible. Missing components are 0.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let a = a.split('.').collect::<Vec<_>>();
+    let b = b.split('.').collect::<Vec<_>>();
+
+    for i in 0..a.len().max(b.len()) {
+        let x = a.get(i).copied().unwrap_or("0");
+        let y = b.get(i).copied().unwrap_or("0");
+
+        let ordering = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            _ => x.cmp(y),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    Ordering::Equal
+}
+
+/// Quote a string as a JavaScript string literal.
+fn js_string(value: &str) -> String {
+    let mut res = String::with_capacity(value.len() + 2);
+    res.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            c if c.is_control() || c == '\u{2028}' || c == '\u{2029}' => {
+                res.push_str(&format!("\\u{:04x}", c as u32));
+            }
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+
+    res
+}
+
+/// Convert an `NSPredicate` comparing properties of `SELF` to a JavaScript expression.
+///
+/// Only comparisons combined with `AND`, `OR` and `NOT` are supported.
+fn predicate_to_js(predicate: &str) -> Result<String, String> {
+    let mut res = String::new();
+    let mut chars = predicate.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some(c) => value.push(c),
+                        None => return Err(format!("unterminated string in {predicate}")),
+                    }
+                }
+                res.push_str(&js_string(&value));
+            }
+            '=' => {
+                chars.next_if_eq(&'=');
+                res.push_str("==");
+            }
+            '<' if chars.next_if_eq(&'>').is_some() => res.push_str("!="),
+            '<' | '>' | '!' => {
+                res.push(c);
+                if chars.next_if_eq(&'=').is_some() {
+                    res.push('=');
+                }
+            }
+            '&' | '|' => {
+                chars.next_if_eq(&c);
+                res.push(c);
+                res.push(c);
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_' || *c == '.')
+                {
+                    word.push(c);
+                }
+                match word.to_uppercase().as_str() {
+                    "AND" => res.push_str("&&"),
+                    "OR" => res.push_str("||"),
+                    "NOT" => res.push('!'),
+                    "TRUE" | "YES" => res.push_str("true"),
+                    "FALSE" | "NO" => res.push_str("false"),
+                    _ => match word.strip_prefix("SELF.") {
+                        Some(key) if key.chars().all(|c| c.is_alphanumeric() || c == '_') => {
+                            res.push_str(&format!("self[{}]", js_string(key)));
+                        }
+                        _ => return Err(format!("unsupported predicate term: {word}")),
+                    },
+                }
+            }
+            c if c.is_ascii_digit() || " \t\n().".contains(c) => res.push(c),
+            c => return Err(format!("unsupported predicate character: {c}")),
+        }
+    }
+
+    Ok(res)
+}
+
+/// Parse a literal boolean expression.
+fn literal_bool(expression: &str) -> Option<bool> {
+    match expression.trim() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether an installed bundle satisfies a bundle requirement.
+fn bundle_matches(requirement: &Bundle, installed: &InstalledBundle) -> bool {
+    let at_least = |required: &Option<String>, actual: &Option<String>| match (required, actual) {
+        (Some(required), Some(actual)) => compare_versions(actual, required) != Ordering::Less,
+        (Some(_), None) => false,
+        (None, _) => true,
+    };
+
+    installed.path == requirement.path
+        && installed.identifier == requirement.id
+        && at_least(
+            &requirement.cf_bundle_short_version_string,
+            &installed.short_version,
+        )
+        && at_least(&requirement.cf_bundle_version, &installed.version)
+}
+
+/// JavaScript prelude defining the Installer API stubs.
+const PRELUDE: &str = r#"
+function __compareVersions(a, b) {
+    var x = String(a).split('.'), y = String(b).split('.');
+    for (var i = 0; i < Math.max(x.length, y.length); i++) {
+        var p = i < x.length ? x[i] : '0', q = i < y.length ? y[i] : '0';
+        var m = Number(p), n = Number(q);
+        if (isNaN(m) || isNaN(n)) { m = p; n = q; }
+        if (m < n) return -1;
+        if (m > n) return 1;
+    }
+    return 0;
+}
+function __value(v) {
+    return v !== '' && !isNaN(Number(v)) ? Number(v) : v;
+}
+function __bundleAtPath(path) {
+    for (var i = 0; i < __target.bundles.length; i++) {
+        if (__target.bundles[i].path === path) return __target.bundles[i];
+    }
+    return null;
+}
+var system = {
+    version: {
+        ProductName: 'macOS',
+        ProductVersion: __target.osVersion,
+        ProductBuildVersion: __target.osBuildVersion
+    },
+    compareVersions: __compareVersions,
+    sysctl: function(name) {
+        return name in __target.sysctl ? __value(__target.sysctl[name]) : undefined;
+    },
+    log: function() {},
+    localizedString: function(key) { return key; },
+    localizedStringWithFormat: function(key) { return key; },
+    localizedStandardStringWithFormat: function(key) { return key; },
+    files: {
+        fileExistsAtPath: function(path) {
+            return __target.files.indexOf(path) >= 0 || __bundleAtPath(path) !== null;
+        },
+        bundleAtPath: __bundleAtPath,
+        plistAtPath: function(path) {
+            return __bundleAtPath(String(path).replace(/\/Contents\/Info\.plist$/, ''));
+        }
+    },
+    applications: {
+        fromIdentifier: function(id) {
+            var res = __target.bundles.filter(function(b) { return b.CFBundleIdentifier === id; });
+            return res.length ? res : null;
+        },
+        fromPID: function() { return null; },
+        all: function() { return __target.bundles; }
+    },
+    ioregistry: {
+        fromPath: function() { return null; },
+        matchingClass: function() { return []; },
+        matchingName: function() { return []; }
+    },
+    gestalt: function() { return undefined; },
+    run: function() { return -1; },
+    runOnce: function() { return -1; },
+    env: {},
+    defaults: {}
+};
+var my = {
+    result: {},
+    choice: {},
+    target: {
+        mountpoint: '/',
+        systemVersion: system.version,
+        receiptForIdentifier: function(id) {
+            return id in __target.receipts
+                ? { identifier: id, version: __target.receipts[id] }
+                : null;
+        }
+    }
+};
+var choices = {};
+"#;
+
+/// Render the JavaScript definition of `__target`.
+fn target_js(target: &TargetSystem) -> String {
+    let object = |entries: &mut dyn Iterator<Item = (String, String)>| {
+        format!(
+            "{{{}}}",
+            entries
+                .map(|(k, v)| format!("{}: {}", js_string(&k), v))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    };
+    let strings = |map: &BTreeMap<String, String>| {
+        object(&mut map.iter().map(|(k, v)| (k.clone(), js_string(v))))
+    };
+    let optional = |v: &Option<String>| v.as_deref().map_or("undefined".to_string(), js_string);
+
+    let bundles = target
+        .bundles
+        .iter()
+        .map(|b| {
+            format!(
+                "{{path: {}, CFBundleIdentifier: {}, CFBundleShortVersionString: {}, CFBundleVersion: {}}}",
+                js_string(&b.path),
+                js_string(&b.identifier),
+                optional(&b.short_version),
+                optional(&b.version)
+            )
+        })
+        .collect::<Vec<_>>();
+    let files = target
+        .files
+        .iter()
+        .map(|f| js_string(f))
+        .collect::<Vec<_>>();
+    let devices = target
+        .graphics_devices
+        .iter()
+        .map(|device| {
+            object(&mut device.iter().map(|(k, v)| match v.parse::<f64>() {
+                Ok(number) if number.is_finite() => (k.clone(), number.to_string()),
+                _ => (k.clone(), js_string(v)),
+            }))
+        })
+        .collect::<Vec<_>>();
+
+    format!(
+        "var __target = {{osVersion: {}, osBuildVersion: {}, bundles: [{}], files: [{}], receipts: {}, sysctl: {}, graphicsDevices: [{}]}};",
+        js_string(&target.os_version),
+        js_string(&target.os_build_version),
+        bundles.join(", "),
+        files.join(", "),
+        strings(&target.receipts),
+        strings(&target.sysctl_values()),
+        devices.join(", ")
+    )
+}
+
+#[cfg(feature = "javascript")]
+mod engine {
+    use {
+        rquickjs::{Coerced, Context, Runtime},
+        std::{
+            sync::{Arc, Mutex},
+            time::{Duration, Instant},
+        },
+    };
+
+    /// Maximum memory used by the interpreter.
+    const MEMORY_LIMIT: usize = 64 << 20;
+
+    /// A sandboxed JavaScript interpreter.
+    pub struct ScriptEngine {
+        _runtime: Runtime,
+        context: Context,
+        timeout: Duration,
+        deadline: Arc<Mutex<Instant>>,
+    }
+
+    impl ScriptEngine {
+        /// Create an engine interrupting evaluations running longer than `timeout`.
+        pub fn new(timeout: Duration) -> Result<Self, String> {
+            let runtime = Runtime::new().map_err(|e| e.to_string())?;
+            runtime.set_memory_limit(MEMORY_LIMIT);
+            runtime.set_max_stack_size(1 << 20);
+
+            let deadline = Arc::new(Mutex::new(Instant::now() + timeout));
+            let handler_deadline = deadline.clone();
+            runtime.set_interrupt_handler(Some(Box::new(move || {
+                Instant::now()
+                    > *handler_deadline
+                        .lock()
+                        .expect("lock should not be poisoned")
+            })));
+
+            // Does not include modules providing access to the host.
+            let context = Context::full(&runtime).map_err(|e| e.to_string())?;
+
+            Ok(Self {
+                _runtime: runtime,
+                context,
+                timeout,
+                deadline,
+            })
+        }
+
+        /// Evaluate code, coercing the result to a boolean.
+        pub fn eval_bool(&self, code: &str) -> Result<bool, String> {
+            *self.deadline.lock().expect("lock should not be poisoned") =
+                Instant::now() + self.timeout;
+
+            self.context.with(|ctx| {
+                ctx.eval::<Coerced<bool>, _>(code)
+                    .map(|v| v.0)
+                    .map_err(|e| match e {
+                        rquickjs::Error::Exception => {
+                            let exception = ctx.catch();
+                            exception
+                                .as_exception()
+                                .and_then(|e| e.message())
+                                .or_else(|| exception.get::<Coerced<String>>().ok().map(|s| s.0))
+                                .unwrap_or_else(|| "unknown exception".to_string())
+                        }
+                        e => e.to_string(),
+                    })
+            })
+        }
+
+        /// Evaluate code, converting the result to a string.
+        ///
+        /// `null` and `undefined` resolve to `None`.
+        pub fn eval_string(&self, code: &str) -> Result<Option<String>, String> {
+            if self.eval_bool(&format!("({code}) == null"))? {
+                Ok(None)
+            } else {
+                self.context.with(|ctx| {
+                    ctx.eval::<Coerced<String>, _>(format!("String({code})"))
+                        .map(|v| Some(v.0))
+                        .map_err(|e| e.to_string())
+                })
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "javascript"))]
+mod engine {
+    const UNSUPPORTED: &str = "JavaScript evaluation requires the `javascript` feature";
+
+    /// Stand-in for the JavaScript interpreter when it isn't available.
+    pub struct ScriptEngine;
+
+    impl ScriptEngine {
+        pub fn new(_timeout: std::time::Duration) -> Result<Self, String> {
+            Err(UNSUPPORTED.to_string())
+        }
+
+        pub fn eval_bool(&self, _code: &str) -> Result<bool, String> {
+            Err(UNSUPPORTED.to_string())
+        }
+
+        pub fn eval_string(&self, _code: &str) -> Result<Option<String>, String> {
+            Err(UNSUPPORTED.to_string())
+        }
+    }
+}
+
+/// Evaluates requirements of a `Distribution` against a target system.
+struct Evaluator<'a> {
+    distribution: &'a Distribution,
+    target: &'a TargetSystem,
+    engine: Result<engine::ScriptEngine, String>,
+    report: RequirementsReport,
+}
+
+impl<'a> Evaluator<'a> {
+    fn new(distribution: &'a Distribution, target: &'a TargetSystem) -> Self {
+        let engine = engine::ScriptEngine::new(target.script_timeout).and_then(|engine| {
+            engine.eval_bool(&target_js(target))?;
+            engine.eval_bool(PRELUDE)?;
+            if let Some(script) = &distribution.script {
+                engine
+                    .eval_bool(&script.script)
+                    .map_err(|e| format!("error in <script>: {e}"))?;
+            }
+
+            Ok(engine)
+        });
+
+        Self {
+            distribution,
+            target,
+            engine,
+            report: RequirementsReport::default(),
+        }
+    }
+
+    fn eval_bool(&self, code: &str) -> Result<bool, String> {
+        match &self.engine {
+            Ok(engine) => engine.eval_bool(code),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
+    fn eval_string(&self, code: &str) -> Result<Option<String>, String> {
+        match &self.engine {
+            Ok(engine) => engine.eval_string(code),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
+    fn host_architectures(&mut self) {
+        let Some(architectures) = self
+            .distribution
+            .options
+            .as_ref()
+            .and_then(|o| o.host_architecutres.as_ref())
+        else {
+            return;
+        };
+        let architectures = architectures
+            .split(',')
+            .map(|a| a.trim())
+            .collect::<Vec<_>>();
+
+        let outcome = if architectures.contains(&self.target.architecture.as_str()) {
+            Outcome::Passed
+        } else if self.target.architecture == "arm64" && architectures.contains(&"x86_64") {
+            if self.target.rosetta {
+                Outcome::Warning("installer runs with Rosetta".to_string())
+            } else {
+                Outcome::Failed("installer requires Rosetta".to_string())
+            }
+        } else {
+            Outcome::Failed(format!(
+                "architecture {} is not supported",
+                self.target.architecture
+            ))
+        };
+
+        self.report.push("options/hostArchitectures", outcome);
+    }
+
+    fn ram(&mut self) {
+        let Some(ram) = self
+            .distribution
+            .installation_check
+            .as_ref()
+            .and_then(|c| c.ram.as_ref())
+        else {
+            return;
+        };
+
+        let outcome = match ram.min_gb.parse::<f64>() {
+            Ok(gb) if self.target.ram_bytes as f64 >= gb * (1u64 << 30) as f64 => Outcome::Passed,
+            Ok(gb) => Outcome::Failed(format!("requires {gb} GB of RAM")),
+            Err(_) => Outcome::Unknown(format!("invalid RAM requirement: {}", ram.min_gb)),
+        };
+
+        self.report.push("installation-check/ram", outcome);
+    }
+
+    /// Find devices matching a predicate.
+    fn matching_devices(&self, predicate: &str) -> Result<Vec<usize>, String> {
+        let js = predicate_to_js(predicate)?;
+
+        let mut res = vec![];
+        for i in 0..self.target.graphics_devices.len() {
+            if self.eval_bool(&format!(
+                "(function(self) {{ return ({js}); }})(__target.graphicsDevices[{i}])"
+            ))? {
+                res.push(i);
+            }
+        }
+
+        Ok(res)
+    }
+
+    fn evaluate_graphics(&self, graphics: &RequiredGraphics) -> Result<bool, String> {
+        let mut sets = vec![];
+        if let Some(device) = &graphics.required_cl_device {
+            sets.push(self.matching_devices(&device.predicate)?);
+        }
+        if let Some(renderer) = &graphics.required_gl_renderer {
+            sets.push(self.matching_devices(&renderer.predicate)?);
+        }
+
+        Ok(if graphics.single_device.unwrap_or(false) {
+            (0..self.target.graphics_devices.len()).any(|i| sets.iter().all(|s| s.contains(&i)))
+        } else {
+            sets.iter().all(|s| !s.is_empty())
+        })
+    }
+
+    fn graphics(&mut self) {
+        let Some(graphics) = self
+            .distribution
+            .installation_check
+            .as_ref()
+            .and_then(|c| c.required_graphics.as_ref())
+        else {
+            return;
+        };
+
+        let outcome = match self.evaluate_graphics(graphics) {
+            Ok(true) => Outcome::Passed,
+            Ok(false) => Outcome::Failed(
+                graphics
+                    .description
+                    .clone()
+                    .unwrap_or_else(|| "graphics requirements not met".to_string()),
+            ),
+            Err(e) => Outcome::Unknown(e),
+        };
+
+        self.report
+            .push("installation-check/required-graphics", outcome);
+    }
+
+    fn allowed_os_versions(&mut self) {
+        let Some(versions) = self
+            .distribution
+            .volume_check
+            .as_ref()
+            .and_then(|c| c.allowed_os_versions.as_ref())
+        else {
+            return;
+        };
+
+        let os_version = &self.target.os_version;
+        let allowed = versions.os_versions.iter().any(|v| {
+            compare_versions(os_version, &v.min) != Ordering::Less
+                && v.before
+                    .as_ref()
+                    .map_or(true, |b| compare_versions(os_version, b) == Ordering::Less)
+        });
+
+        let outcome = if allowed {
+            Outcome::Passed
+        } else {
+            Outcome::Failed(format!("macOS {os_version} is not supported"))
+        };
+
+        self.report
+            .push("volume-check/allowed-os-versions", outcome);
+    }
+
+    fn required_bundles(&mut self) {
+        let Some(required) = self
+            .distribution
+            .volume_check
+            .as_ref()
+            .and_then(|c| c.required_bundles.as_ref())
+        else {
+            return;
+        };
+
+        let mut present = required.bundles.iter().map(|bundle| {
+            self.target
+                .bundles
+                .iter()
+                .any(|installed| bundle_matches(bundle, installed))
+        });
+
+        let satisfied = if required.all.unwrap_or(true) {
+            present.all(|p| p)
+        } else {
+            present.any(|p| p)
+        };
+
+        let outcome = if satisfied {
+            Outcome::Passed
+        } else {
+            Outcome::Failed(
+                required
+                    .description
+                    .clone()
+                    .unwrap_or_else(|| "required bundles are missing".to_string()),
+            )
+        };
+
+        self.report.push("volume-check/required-bundles", outcome);
+    }
+
+    /// Evaluate a check expression, honoring `my.result`.
+    fn script_check(&mut self, check: &str, expression: Option<&String>) {
+        let Some(expression) = expression else {
+            return;
+        };
+
+        let outcome = match literal_bool(expression) {
+            Some(true) => Outcome::Passed,
+            Some(false) => Outcome::Failed("check is disabled".to_string()),
+            None => self
+                .evaluate_script_check(expression)
+                .unwrap_or_else(Outcome::Unknown),
+        };
+
+        self.report.push(check, outcome);
+    }
+
+    fn evaluate_script_check(&self, expression: &str) -> Result<Outcome, String> {
+        self.eval_bool("my.result = {}; true")?;
+
+        if self.eval_bool(expression)? {
+            return Ok(Outcome::Passed);
+        }
+
+        let message = self
+            .eval_string("my.result.message")?
+            .or(self.eval_string("my.result.title")?)
+            .unwrap_or_else(|| "check failed".to_string());
+
+        Ok(match self.eval_string("my.result.type")?.as_deref() {
+            Some("Warn") | Some("Warning") => Outcome::Warning(message),
+            _ => Outcome::Failed(message),
+        })
+    }
+
+    fn choices(&mut self) {
+        let distribution = self.distribution;
+
+        // Installer evaluates choices with the start values of all choices defined.
+        for choice in &distribution.choice {
+            let _ = self.eval_bool(&format!(
+                "choices[{id}] = {{ visible: {}, enabled: {}, selected: {} }}; true",
+                choice.start_visible.unwrap_or(true),
+                choice.start_enabled.unwrap_or(true),
+                choice.start_selected.unwrap_or(true),
+                id = js_string(&choice.id),
+            ));
+        }
+
+        for choice in &distribution.choice {
+            let _ = self.eval_bool(&format!(
+                "my.choice = choices[{}]; true",
+                js_string(&choice.id)
+            ));
+
+            let evaluate = |name: &str, expression: &Option<String>, start: Option<bool>| {
+                let value = match expression {
+                    None => Some(start.unwrap_or(true)),
+                    Some(expression) => {
+                        literal_bool(expression).or_else(|| self.eval_bool(expression).ok())
+                    }
+                };
+
+                if let Some(value) = value {
+                    let _ = self.eval_bool(&format!("my.choice.{name} = {value}; true"));
+                }
+
+                value
+            };
+
+            let state = ChoiceState {
+                id: choice.id.clone(),
+                visible: evaluate("visible", &choice.visible, choice.start_visible),
+                enabled: evaluate("enabled", &choice.enabled, choice.start_enabled),
+                selected: evaluate("selected", &choice.selected, choice.start_selected),
+            };
+            self.report.choices.push(state);
+        }
+    }
+
+    fn evaluate(mut self) -> RequirementsReport {
+        if let (Err(e), Some(_)) = (&self.engine, &self.distribution.script) {
+            self.report.push("script", Outcome::Unknown(e.clone()));
+        }
+
+        self.host_architectures();
+        self.ram();
+        self.graphics();
+        let installation_check = self
+            .distribution
+            .installation_check
+            .as_ref()
+            .and_then(|c| c.script.as_ref());
+        self.script_check("installation-check/script", installation_check);
+
+        self.allowed_os_versions();
+        self.required_bundles();
+        let volume_check = self
+            .distribution
+            .volume_check
+            .as_ref()
+            .and_then(|c| c.script.as_ref());
+        self.script_check("volume-check/script", volume_check);
+
+        self.choices();
+
+        self.report
+    }
+}
+
+impl Distribution {
+    /// Evaluate the installation requirements against a simulated target system.
+    ///
+    /// See the [crate::requirements] module documentation for details.
+    ///
+    /// Requirements that cannot be evaluated are reported as [Outcome::Unknown].
+    pub fn evaluate_requirements(&self, target: &TargetSystem) -> RequirementsReport {
+        Evaluator::new(self, target).evaluate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::PkgResult};
+
+    const DISTRIBUTION: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<installer-gui-script minSpecVersion="2">
+    <title>Example</title>
+    <options customize="always" hostArchitectures="arm64,x86_64"/>
+    <installation-check script="installation_check()">
+        <ram min-gb="8"/>
+    </installation-check>
+    <volume-check script="volume_check()">
+        <allowed-os-versions>
+            <os-version min="12.0"/>
+        </allowed-os-versions>
+    </volume-check>
+    <script><![CDATA[
+function installation_check() {
+    if (system.sysctl('hw.optional.arm64') != 1) {
+        my.result.type = 'Fatal';
+        my.result.message = 'Apple silicon required';
+        return false;
+    }
+    return true;
+}
+function volume_check() {
+    var apps = system.applications.fromIdentifier('com.example.legacy');
+    if (apps && system.compareVersions(apps[0].CFBundleShortVersionString, '2.0') < 0) {
+        my.result.type = 'Warning';
+        my.result.message = 'legacy version installed';
+        return false;
+    }
+    return true;
+}
+]]></script>
+    <choices-outline>
+        <line choice="app"/>
+        <line choice="extras"/>
+    </choices-outline>
+    <choice id="app" visible="false"/>
+    <choice id="extras" visible="system.files.fileExistsAtPath('/Applications/Example.app')" selected="choices.app.selected"/>
+</installer-gui-script>
+"#;
+
+    #[test]
+    fn compare() {
+        assert_eq!(compare_versions("10.15", "10.9"), Ordering::Greater);
+        assert_eq!(compare_versions("11", "11.0.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.2b", "1.2a"), Ordering::Greater);
+    }
+
+    #[test]
+    fn predicate() {
+        assert_eq!(
+            predicate_to_js("(SELF.vendor = 'NVIDIA') AND SELF.version >= 2").unwrap(),
+            r#"(self["vendor"] == "NVIDIA") && self["version"] >= 2"#
+        );
+        assert!(predicate_to_js("SELF.name BEGINSWITH 'x'").is_err());
+    }
+
+    #[test]
+    fn declarative() -> PkgResult<()> {
+        let distribution = Distribution::from_xml(DISTRIBUTION)?;
+
+        let target = TargetSystem {
+            os_version: "11.7".to_string(),
+            ram_bytes: 4 << 30,
+            ..Default::default()
+        };
+        let report = distribution.evaluate_requirements(&target);
+
+        let outcome = |check: &str| {
+            report
+                .checks
+                .iter()
+                .find(|c| c.check == check)
+                .map(|c| c.outcome.clone())
+        };
+        assert_eq!(outcome("options/hostArchitectures"), Some(Outcome::Passed));
+        assert!(matches!(
+            outcome("installation-check/ram"),
+            Some(Outcome::Failed(_))
+        ));
+        assert!(matches!(
+            outcome("volume-check/allowed-os-versions"),
+            Some(Outcome::Failed(_))
+        ));
+        assert_eq!(report.installation_allowed(), Some(false));
+        assert_eq!(report.choices[0].visible, Some(false));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "javascript")]
+    #[test]
+    fn scripts() -> PkgResult<()> {
+        let distribution = Distribution::from_xml(DISTRIBUTION)?;
+
+        let report = distribution.evaluate_requirements(&TargetSystem::default());
+        assert_eq!(report.installation_allowed(), Some(true));
+        assert_eq!(
+            report
+                .visible_choices()
+                .map(|c| c.id.as_str())
+                .collect::<Vec<_>>(),
+            Vec::<&str>::new()
+        );
+
+        let target = TargetSystem {
+            architecture: "x86_64".to_string(),
+            bundles: vec![InstalledBundle {
+                path: "/Applications/Example.app".to_string(),
+                identifier: "com.example.legacy".to_string(),
+                short_version: Some("1.5".to_string()),
+                version: None,
+            }],
+            ..Default::default()
+        };
+        let report = distribution.evaluate_requirements(&target);
+        assert_eq!(
+            report.checks.last(),
+            Some(&CheckResult {
+                check: "volume-check/script".to_string(),
+                outcome: Outcome::Warning("legacy version installed".to_string()),
+            })
+        );
+        assert!(report.checks.contains(&CheckResult {
+            check: "installation-check/script".to_string(),
+            outcome: Outcome::Failed("Apple silicon required".to_string()),
+        }));
+        assert_eq!(
+            report.choices[1],
+            ChoiceState {
+                id: "extras".to_string(),
+                visible: Some(true),
+                enabled: Some(true),
+                selected: Some(true),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "javascript")]
+    #[test]
+    fn graphics_and_timeout() -> PkgResult<()> {
+        let mut distribution = Distribution::from_xml(DISTRIBUTION)?;
+        distribution
+            .installation_check
+            .as_mut()
+            .unwrap()
+            .required_graphics = Some(RequiredGraphics {
+            description: Some("OpenCL 1.2 required".to_string()),
+            single_device: Some(true),
+            required_cl_device: Some(crate::distribution::RequiredClDevice {
+                predicate: "SELF.version >= 1.2 AND SELF.vendor <> 'Example'".to_string(),
+            }),
+            required_gl_renderer: None,
+        });
+
+        let mut target = TargetSystem {
+            graphics_devices: vec![BTreeMap::from([
+                ("vendor".to_string(), "Apple".to_string()),
+                ("version".to_string(), "1.1".to_string()),
+            ])],
+            ..Default::default()
+        };
+        let graphics = |report: &RequirementsReport| {
+            report
+                .checks
+                .iter()
+                .find(|c| c.check == "installation-check/required-graphics")
+                .map(|c| c.outcome.clone())
+        };
+
+        let report = distribution.evaluate_requirements(&target);
+        assert_eq!(
+            graphics(&report),
+            Some(Outcome::Failed("OpenCL 1.2 required".to_string()))
+        );
+
+        target.graphics_devices[0].insert("version".to_string(), "1.2".to_string());
+        let report = distribution.evaluate_requirements(&target);
+        assert_eq!(graphics(&report), Some(Outcome::Passed));
+
+        // A script that never finishes is interrupted.
+        target.script_timeout = Duration::from_millis(100);
+        distribution.script.as_mut().unwrap().script = "while (true) {}".to_string();
+        let report = distribution.evaluate_requirements(&target);
+        assert_eq!(report.checks[0].check, "script");
+        assert!(matches!(report.checks[0].outcome, Outcome::Unknown(_)));
+        assert!(matches!(graphics(&report), Some(Outcome::Unknown(_))));
+        assert_eq!(report.installation_allowed(), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reading XAR archives from async readers.
+//!
+//! [XarAsyncReader] reads archives from a [tokio] reader. Heap data is read
+//! asynchronously and decoded on tokio's blocking thread pool, so unpacking decodes
+//! several entries concurrently.
+
+use {
+    crate::{
+        format::XarHeader,
+        reader::{
+            header_and_toc_length, parse_header_and_toc, prepare_entry, restore_directories,
+            unpack_entry_content, write_decoded_verified, PreparedEntry, UnpackOptions,
+        },
+        table_of_contents::{File, FileType, TableOfContents},
+        Error, XarResult,
+    },
+    std::{io::SeekFrom, path::Path},
+    tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt},
+        sync::mpsc,
+        task::{spawn_blocking, JoinError, JoinSet},
+    },
+};
+
+/// Size of the chunks heap data is streamed to decoding tasks in.
+const HEAP_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Number of heap chunks buffered for each decoding task.
+const HEAP_CHUNKS_BUFFERED: usize = 16;
+
+impl From<JoinError> for Error {
+    fn from(e: JoinError) -> Self {
+        Self::Io(std::io::Error::other(e))
+    }
+}
+
+/// Read-only interface to a XAR archive backed by an async reader.
+#[derive(Debug)]
+pub struct XarAsyncReader<R: AsyncRead + AsyncSeek + Unpin> {
+    /// Reader of raw XAR archive content.
+    reader: R,
+
+    /// Parsed file header.
+    header: XarHeader,
+
+    /// Parsed table of contents.
+    toc: TableOfContents,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> XarAsyncReader<R> {
+    /// Construct a new XAR reader from an async stream reader.
+    pub async fn new(mut reader: R) -> XarResult<Self> {
+        let mut data = vec![0u8; 28];
+        reader.seek(SeekFrom::Start(0)).await?;
+        reader.read_exact(&mut data).await?;
+
+        // The length is validated to cover the header and be bounded.
+        data.resize(header_and_toc_length(&data)? as _, 0);
+        reader.read_exact(&mut data[28..]).await?;
+
+        let (header, toc) = parse_header_and_toc(data)?;
+
+        Ok(Self {
+            reader,
+            header,
+            toc,
+        })
+    }
+
+    /// Obtain the inner reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Obtain the parsed [XarHeader] file header.
+    pub fn header(&self) -> &XarHeader {
+        &self.header
+    }
+
+    /// The start offset of the heap.
+    pub fn heap_start_offset(&self) -> u64 {
+        self.header.size as u64 + self.header.toc_length_compressed
+    }
+
+    /// Obtain the table of contents for this archive.
+    pub fn table_of_contents(&self) -> &TableOfContents {
+        &self.toc
+    }
+
+    /// Obtain the file entries in this archive.
+    pub fn files(&self) -> XarResult<Vec<(String, File)>> {
+        self.toc.files()
+    }
+
+    /// Attempt to find the [File] entry for a given path in the archive.
+    pub fn find_file(&self, filename: &str) -> XarResult<Option<File>> {
+        Ok(self
+            .toc
+            .files()?
+            .into_iter()
+            .find_map(|(path, file)| if path == filename { Some(file) } else { None }))
+    }
+
+    /// Read a slice of the heap.
+    async fn read_heap_slice(&mut self, offset: u64, size: u64) -> XarResult<Vec<u8>> {
+        self.reader
+            .seek(SeekFrom::Start(self.heap_start_offset() + offset))
+            .await?;
+
+        let mut data = vec![];
+        (&mut self.reader).take(size).read_to_end(&mut data).await?;
+
+        if data.len() as u64 != size {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+
+        Ok(data)
+    }
+
+    /// Send a slice of the heap to a decoding task in chunks.
+    ///
+    /// Returns `false` if the task stopped receiving, e.g. because decoding failed.
+    async fn send_heap_slice(
+        &mut self,
+        offset: u64,
+        size: u64,
+        sender: &mpsc::Sender<Vec<u8>>,
+    ) -> XarResult<bool> {
+        self.reader
+            .seek(SeekFrom::Start(self.heap_start_offset() + offset))
+            .await?;
+
+        let mut remaining = size;
+
+        while remaining > 0 {
+            let mut chunk = vec![0; remaining.min(HEAP_CHUNK_SIZE) as usize];
+            self.reader.read_exact(&mut chunk).await?;
+            remaining -= chunk.len() as u64;
+
+            if sender.send(chunk).await.is_err() {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Obtain the heap file data of a file record.
+    ///
+    /// This is the raw data backing a file as stored in the heap.
+    pub async fn file_data_heap(&mut self, file: &File) -> XarResult<Vec<u8>> {
+        let data = file.data.as_ref().ok_or(Error::FileNoData)?;
+
+        self.read_heap_slice(data.offset, data.length).await
+    }
+
+    /// Obtain the decoded file data of a file record, verifying its checksums.
+    pub async fn file_data_decoded(&mut self, file: &File) -> XarResult<Vec<u8>> {
+        let heap = self.file_data_heap(file).await?;
+        let file = file.clone();
+
+        spawn_blocking(move || {
+            let data = file.data.as_ref().ok_or(Error::FileNoData)?;
+            let mut buffer = Vec::<u8>::with_capacity(file.size.unwrap_or(0) as _);
+
+            write_decoded_verified(
+                &data.encoding,
+                &data.archived_checksum,
+                &data.extracted_checksum,
+                true,
+                file.names.last().map(|s| s.as_str()).unwrap_or_default(),
+                &mut buffer,
+                |writer| Ok(writer.write_all(&heap)?),
+            )?;
+
+            Ok(buffer)
+        })
+        .await?
+    }
+
+    /// Resolve data for a given path.
+    pub async fn get_file_data_from_path(&mut self, path: &str) -> XarResult<Option<Vec<u8>>> {
+        if let Some(file) = self.find_file(path)? {
+            Ok(Some(self.file_data_decoded(&file).await?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Unpack the contents of the XAR archive to a given directory, guarding against
+    /// malicious archives.
+    ///
+    /// This behaves like [crate::reader::XarReader::unpack_with_options]. Filesystem
+    /// entries are created first. Then the heap data of each entry is streamed in
+    /// chunks to the blocking thread pool and decoded there, with as many entries in
+    /// flight as there are CPUs.
+    pub async fn unpack_with_options(
+        &mut self,
+        dest_dir: impl AsRef<Path>,
+        options: &UnpackOptions,
+    ) -> XarResult<()> {
+        let dest_dir = dest_dir.as_ref().to_path_buf();
+        let files = self.toc.files()?;
+        let options = *options;
+
+        let entries = spawn_blocking(move || {
+            files
+                .into_iter()
+                .map(|(path, file)| prepare_entry(&dest_dir, path, file))
+                .collect::<XarResult<Vec<_>>>()
+        })
+        .await??;
+
+        let limit = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let mut tasks = JoinSet::<XarResult<(usize, PreparedEntry)>>::new();
+        let mut unpacked = Vec::with_capacity(entries.len());
+
+        for (index, entry) in entries.into_iter().enumerate() {
+            // The heap slices unpack_entry_content() copies, in the order it does.
+            let mut slices = vec![];
+
+            if let (FileType::File, Some(data)) = (&entry.file.file_type, &entry.file.data) {
+                slices.push((data.offset, data.length));
+            }
+            if options.restore_xattrs {
+                slices.extend(entry.file.ea.iter().map(|ea| (ea.offset, ea.length)));
+            }
+
+            while tasks.len() >= limit {
+                if let Some(result) = tasks.join_next().await {
+                    unpacked.push(result??);
+                }
+            }
+
+            let (sender, mut receiver) = mpsc::channel::<Vec<u8>>(HEAP_CHUNKS_BUFFERED);
+
+            tasks.spawn_blocking(move || {
+                unpack_entry_content(&entry, &options, |_, length, writer| {
+                    let mut remaining = length;
+
+                    while remaining > 0 {
+                        let chunk = receiver
+                            .blocking_recv()
+                            .ok_or(Error::TableOfContentsCorrupted("heap data not read"))?;
+                        remaining = remaining.saturating_sub(chunk.len() as u64);
+                        writer.write_all(&chunk)?;
+                    }
+
+                    Ok(())
+                })?;
+
+                Ok((index, entry))
+            });
+
+            for (offset, length) in slices {
+                if !self.send_heap_slice(offset, length, &sender).await? {
+                    // The task failed. Its error is reported when it is joined.
+                    break;
+                }
+            }
+        }
+
+        while let Some(result) = tasks.join_next().await {
+            unpacked.push(result??);
+        }
+
+        // Directories are restored relying on the table of contents order.
+        unpacked.sort_by_key(|(index, _)| *index);
+        let unpacked = unpacked
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect::<Vec<_>>();
+
+        spawn_blocking(move || restore_directories(&unpacked, &options)).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{builder::XarBuilder, table_of_contents::Encoding},
+        std::io::Cursor,
+    };
+
+    #[tokio::test]
+    async fn malformed_header() -> XarResult<()> {
+        let mut data = vec![];
+        XarBuilder::new().write(&mut data)?;
+
+        let mut small = data.clone();
+        small[4..6].copy_from_slice(&20u16.to_be_bytes());
+        assert!(XarAsyncReader::new(Cursor::new(small)).await.is_err());
+
+        let mut large = data;
+        large[8..16].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(XarAsyncReader::new(Cursor::new(large)).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_and_unpack() -> XarResult<()> {
+        let mut builder = XarBuilder::new();
+        builder.add_directory("dir")?.set_mode(0o700);
+        for (i, encoding) in [
+            Encoding::None,
+            Encoding::Gzip,
+            Encoding::Bzip2,
+            Encoding::Xz,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            builder
+                .add_file_data(&format!("dir/file{i}"), vec![i as u8; 100_000])?
+                .set_encoding(encoding);
+        }
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        let mut reader = XarAsyncReader::new(Cursor::new(data)).await?;
+        assert_eq!(reader.files()?.len(), 5);
+        assert_eq!(
+            reader.get_file_data_from_path("dir/file2").await?,
+            Some(vec![2; 100_000])
+        );
+
+        let dest = tempfile::tempdir()?;
+        reader
+            .unpack_with_options(dest.path(), &UnpackOptions::default())
+            .await?;
+        for i in 0..4u8 {
+            assert_eq!(
+                std::fs::read(dest.path().join(format!("dir/file{i}")))?,
+                vec![i; 100_000]
+            );
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = std::fs::metadata(dest.path().join("dir"))?
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        Ok(())
+    }
+}
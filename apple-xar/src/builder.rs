@@ -0,0 +1,732 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! XAR archive creation.
+//!
+//! The heap of archives created by [XarBuilder] starts with the table of contents
+//! checksum, followed by space reserved for signatures, if any, followed by the data
+//! and extended attributes of each file in file ID order.
+
+use {
+    crate::{
+        format::{XarChecksum, XarHeader, XAR_MAGIC},
+        hex, path_components,
+        table_of_contents::{
+            Checksum, ChecksumType, Ea, Encoding, File, FileChecksum, FileData, FileType, KeyInfo,
+            Link, Signature, SignatureStyle, TableOfContents, XarToC,
+        },
+        Error, XarResult,
+    },
+    chrono::{DateTime, SecondsFormat, Utc},
+    scroll::IOwrite,
+    std::{
+        collections::BTreeMap,
+        io::Write,
+        path::{Path, PathBuf},
+    },
+    x509_certificate::CapturedX509Certificate,
+};
+
+/// Format a time the way it is stored in a table of contents.
+pub(crate) fn format_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Digest data and format the digest as hex string.
+fn hex_digest(checksum: ChecksumType, data: &[u8]) -> XarResult<String> {
+    if matches!(checksum, ChecksumType::None) {
+        return Ok(String::new());
+    }
+
+    Ok(hex(&checksum.digest_data(data)?))
+}
+
+/// Encode data with the given encoding.
+fn encode(encoding: Encoding, data: &[u8]) -> XarResult<Vec<u8>> {
+    Ok(match encoding {
+        Encoding::None => data.to_vec(),
+        Encoding::Gzip => {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        Encoding::Bzip2 => {
+            let mut encoder = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        Encoding::Xz => {
+            let mut encoder = xz2::write::XzEncoder::new(vec![], 6);
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+    })
+}
+
+/// Write the header, the compressed table of contents and its checksum.
+///
+/// The checksum is expected at heap offset 0. The heap data follows.
+pub(crate) fn write_header_and_table_of_contents(
+    writer: &mut impl Write,
+    toc: &TableOfContents,
+) -> XarResult<()> {
+    let toc_data = toc.to_xml()?;
+    let mut zlib = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+    zlib.write_all(&toc_data)?;
+    let toc_compressed = zlib.finish()?;
+
+    let checksum_type = toc.checksum.style;
+    let header = XarHeader {
+        magic: XAR_MAGIC,
+        size: 28,
+        version: 1,
+        toc_length_compressed: toc_compressed.len() as _,
+        toc_length_uncompressed: toc_data.len() as _,
+        checksum_algorithm_id: XarChecksum::from(checksum_type).into(),
+    };
+
+    writer.iowrite_with(header, scroll::BE)?;
+    writer.write_all(&toc_compressed)?;
+    if !matches!(checksum_type, ChecksumType::None) {
+        writer.write_all(&checksum_type.digest_data(&toc_compressed)?)?;
+    }
+
+    Ok(())
+}
+
+/// Encode data stored at `offset` in the heap.
+///
+/// Returns the [FileData] record and the encoded data.
+pub(crate) fn encode_file_data(
+    encoding: Encoding,
+    checksum_type: ChecksumType,
+    data: &[u8],
+    offset: u64,
+) -> XarResult<(FileData, Vec<u8>)> {
+    let encoded = encode(encoding, data)?;
+
+    let file_data = FileData {
+        offset,
+        size: data.len() as _,
+        length: encoded.len() as _,
+        extracted_checksum: FileChecksum {
+            style: checksum_type,
+            checksum: hex_digest(checksum_type, data)?,
+        },
+        archived_checksum: FileChecksum {
+            style: checksum_type,
+            checksum: hex_digest(checksum_type, &encoded)?,
+        },
+        encoding: encoding.into(),
+    };
+
+    Ok((file_data, encoded))
+}
+
+/// The content of an entry in a [XarBuilder].
+#[derive(Clone, Debug)]
+enum EntryContent {
+    File(Vec<u8>),
+    Directory,
+    Symlink(Link),
+}
+
+/// An entry to be written by [XarBuilder].
+#[derive(Clone, Debug)]
+pub struct XarEntry {
+    content: EntryContent,
+    mode: u32,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    user: Option<String>,
+    group: Option<String>,
+    mtime: Option<DateTime<Utc>>,
+    encoding: Option<Encoding>,
+    xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+impl XarEntry {
+    fn new(content: EntryContent, mode: u32) -> Self {
+        Self {
+            content,
+            mode,
+            uid: None,
+            gid: None,
+            user: None,
+            group: None,
+            mtime: None,
+            encoding: None,
+            xattrs: BTreeMap::new(),
+        }
+    }
+
+    fn is_directory(&self) -> bool {
+        matches!(self.content, EntryContent::Directory)
+    }
+
+    /// Set the permission bits.
+    pub fn set_mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode & 0o7777;
+        self
+    }
+
+    /// Set the numeric owner and group.
+    pub fn set_owner(&mut self, uid: u32, gid: u32) -> &mut Self {
+        self.uid = Some(uid);
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Set the names of the owner and group.
+    pub fn set_owner_names(&mut self, user: impl ToString, group: impl ToString) -> &mut Self {
+        self.user = Some(user.to_string());
+        self.group = Some(group.to_string());
+        self
+    }
+
+    /// Set the modification time.
+    pub fn set_mtime(&mut self, mtime: DateTime<Utc>) -> &mut Self {
+        self.mtime = Some(mtime);
+        self
+    }
+
+    /// Set the encoding of this entry, overriding the default of the builder.
+    pub fn set_encoding(&mut self, encoding: Encoding) -> &mut Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    /// Add an extended attribute.
+    pub fn add_xattr(&mut self, name: impl ToString, data: impl Into<Vec<u8>>) -> &mut Self {
+        self.xattrs.insert(name.to_string(), data.into());
+        self
+    }
+}
+
+/// Space reserved for signatures.
+#[derive(Clone, Debug)]
+struct SignatureReservation {
+    rsa_size: u64,
+    cms_size: u64,
+    key_info: KeyInfo,
+}
+
+/// Entity for creating XAR archives.
+///
+/// Entries are addressed by `/` separated paths. Missing parent directories are
+/// added automatically.
+#[derive(Clone, Debug)]
+pub struct XarBuilder {
+    checksum_type: ChecksumType,
+    file_checksum_type: ChecksumType,
+    encoding: Encoding,
+    creation_time: DateTime<Utc>,
+    entries: BTreeMap<String, XarEntry>,
+    signatures: Option<SignatureReservation>,
+}
+
+impl Default for XarBuilder {
+    fn default() -> Self {
+        Self {
+            checksum_type: ChecksumType::Sha1,
+            file_checksum_type: ChecksumType::Sha1,
+            encoding: Encoding::Gzip,
+            creation_time: Utc::now(),
+            entries: BTreeMap::new(),
+            signatures: None,
+        }
+    }
+}
+
+impl XarBuilder {
+    /// Create a new builder using SHA-1 checksums and zlib compression.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the checksum algorithm for the table of contents.
+    ///
+    /// With [ChecksumType::None] the archive has no checksum and cannot be signed.
+    pub fn set_checksum_type(&mut self, checksum_type: ChecksumType) {
+        self.checksum_type = checksum_type;
+    }
+
+    /// Set the checksum algorithm for file data and extended attributes.
+    pub fn set_file_checksum_type(&mut self, checksum_type: ChecksumType) {
+        self.file_checksum_type = checksum_type;
+    }
+
+    /// Set the default encoding of file data and extended attributes.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Set the creation time recorded in the table of contents.
+    pub fn set_creation_time(&mut self, time: DateTime<Utc>) {
+        self.creation_time = time;
+    }
+
+    /// Obtain the entry at a given path.
+    pub fn entry_mut(&mut self, path: &str) -> Option<&mut XarEntry> {
+        self.entries.get_mut(path)
+    }
+
+    /// Reserve space for an RSA and a CMS signature.
+    ///
+    /// The table of contents will reference zeroed signature data of the given
+    /// sizes and the certificates. The signatures can then be injected into the
+    /// archive without altering the table of contents. A size of 0 omits that
+    /// signature. `XarSigner` can sign archives with or without reservation.
+    pub fn reserve_signatures<'a>(
+        &mut self,
+        rsa_size: u64,
+        cms_size: u64,
+        certificates: impl Iterator<Item = &'a CapturedX509Certificate>,
+    ) -> XarResult<()> {
+        self.signatures = Some(SignatureReservation {
+            rsa_size,
+            cms_size,
+            key_info: KeyInfo::from_certificates(certificates)?,
+        });
+
+        Ok(())
+    }
+
+    /// Add the parent directories of a path.
+    fn add_parents(&mut self, path: &str) -> XarResult<()> {
+        let components = path_components(path)?;
+
+        for i in 1..components.len() {
+            let parent = components[..i].join("/");
+
+            match self.entries.get(&parent) {
+                Some(entry) if !matches!(entry.content, EntryContent::Directory) => {
+                    return Err(Error::InvalidPath(path.to_string()));
+                }
+                Some(_) => {}
+                None => {
+                    self.entries
+                        .insert(parent, XarEntry::new(EntryContent::Directory, 0o755));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_entry(&mut self, path: &str, entry: XarEntry) -> XarResult<&mut XarEntry> {
+        self.add_parents(path)?;
+
+        if let Some(existing) = self.entries.get(path) {
+            let dir = matches!(existing.content, EntryContent::Directory);
+            if dir != matches!(entry.content, EntryContent::Directory) {
+                return Err(Error::InvalidPath(path.to_string()));
+            }
+        }
+
+        self.entries.insert(path.to_string(), entry);
+
+        Ok(self.entries.get_mut(path).expect("entry was just inserted"))
+    }
+
+    /// Add a regular file with the given content.
+    ///
+    /// An existing file at this path is replaced.
+    pub fn add_file_data(
+        &mut self,
+        path: &str,
+        data: impl Into<Vec<u8>>,
+    ) -> XarResult<&mut XarEntry> {
+        self.add_entry(path, XarEntry::new(EntryContent::File(data.into()), 0o644))
+    }
+
+    /// Add a directory.
+    ///
+    /// If the directory already exists, the existing entry is returned.
+    pub fn add_directory(&mut self, path: &str) -> XarResult<&mut XarEntry> {
+        if !matches!(
+            self.entries.get(path).map(|e| &e.content),
+            Some(EntryContent::Directory)
+        ) {
+            self.add_entry(path, XarEntry::new(EntryContent::Directory, 0o755))?;
+        }
+
+        Ok(self
+            .entries
+            .get_mut(path)
+            .expect("directory was just added"))
+    }
+
+    /// Add a symlink pointing to `target`.
+    pub fn add_symlink(&mut self, path: &str, target: impl ToString) -> XarResult<&mut XarEntry> {
+        let link = Link {
+            link_type: "file".to_string(),
+            target: target.to_string(),
+        };
+
+        self.add_entry(path, XarEntry::new(EntryContent::Symlink(link), 0o755))
+    }
+
+    /// Add a file, directory or symlink from the filesystem.
+    ///
+    /// The content of directories is not added. Permissions and modification times are
+    /// taken from the filesystem.
+    pub fn add_path(&mut self, path: &str, fs_path: impl AsRef<Path>) -> XarResult<&mut XarEntry> {
+        let fs_path = fs_path.as_ref();
+        let metadata = std::fs::symlink_metadata(fs_path)?;
+
+        let entry = if metadata.is_symlink() {
+            let target = std::fs::read_link(fs_path)?;
+            let link_type = match std::fs::metadata(fs_path) {
+                Ok(m) if m.is_dir() => "directory",
+                Ok(_) => "file",
+                Err(_) => "broken",
+            };
+
+            self.add_symlink(path, target.to_string_lossy())?;
+            let entry = self.entries.get_mut(path).expect("symlink was just added");
+            if let EntryContent::Symlink(link) = &mut entry.content {
+                link.link_type = link_type.to_string();
+            }
+
+            entry
+        } else if metadata.is_dir() {
+            self.add_directory(path)?
+        } else {
+            self.add_file_data(path, std::fs::read(fs_path)?)?
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            entry.set_mode(metadata.permissions().mode());
+        }
+
+        if let Ok(mtime) = metadata.modified() {
+            entry.set_mtime(mtime.into());
+        }
+
+        Ok(entry)
+    }
+
+    /// Recursively add the content of a directory in the filesystem.
+    ///
+    /// Entries are added below `prefix`, or at the root of the archive if `prefix`
+    /// is empty. Symlinks are not followed.
+    pub fn add_tree(&mut self, prefix: &str, dir: impl AsRef<Path>) -> XarResult<()> {
+        let mut entries = std::fs::read_dir(dir.as_ref())?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<PathBuf>>>()?;
+        entries.sort();
+
+        for fs_path in entries {
+            let name = fs_path
+                .file_name()
+                .expect("directory entries have a name")
+                .to_string_lossy();
+            let path = if prefix.is_empty() {
+                name.to_string()
+            } else {
+                format!("{prefix}/{name}")
+            };
+
+            if self.add_path(&path, &fs_path)?.is_directory() {
+                self.add_tree(&path, &fs_path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the table of contents and the heap.
+    fn build(&self) -> XarResult<(TableOfContents, Vec<Vec<u8>>)> {
+        let digest_size = self.checksum_type.digest_size() as u64;
+
+        let mut heap = vec![];
+        let mut offset = digest_size;
+
+        let mut reserve = |style: SignatureStyle, size: u64| {
+            let reservation = self.signatures.as_ref().filter(|_| size > 0)?;
+            let signature = Signature {
+                style,
+                offset,
+                size,
+                key_info: reservation.key_info.clone(),
+            };
+            heap.push(vec![0; size as usize]);
+            offset += size;
+
+            Some(signature)
+        };
+
+        let (rsa_size, cms_size) = self
+            .signatures
+            .as_ref()
+            .map_or((0, 0), |r| (r.rsa_size, r.cms_size));
+        let signature = reserve(SignatureStyle::Rsa, rsa_size);
+        let x_signature = reserve(SignatureStyle::Cms, cms_size);
+
+        let mut children = BTreeMap::<Option<&str>, Vec<&str>>::new();
+        for path in self.entries.keys() {
+            let parent = path.rsplit_once('/').map(|(parent, _)| parent);
+            children.entry(parent).or_default().push(path);
+        }
+
+        let mut next_id = 1;
+        let files = self.build_files(None, &children, &mut next_id, &mut offset, &mut heap)?;
+
+        let toc = XarToC {
+            creation_time: format_time(&self.creation_time),
+            checksum: Checksum {
+                style: self.checksum_type,
+                offset: 0,
+                size: digest_size,
+            },
+            files,
+            signature,
+            x_signature,
+        };
+
+        Ok((toc.into(), heap))
+    }
+
+    /// Build the [File] records below a directory, appending data to the heap.
+    fn build_files(
+        &self,
+        parent: Option<&str>,
+        children: &BTreeMap<Option<&str>, Vec<&str>>,
+        next_id: &mut u64,
+        offset: &mut u64,
+        heap: &mut Vec<Vec<u8>>,
+    ) -> XarResult<Vec<File>> {
+        let mut files = vec![];
+
+        for path in children.get(&parent).into_iter().flatten() {
+            let entry = &self.entries[*path];
+            let encoding = entry.encoding.unwrap_or(self.encoding);
+
+            let id = *next_id;
+            *next_id += 1;
+
+            let mut encode_data = |data: &[u8]| -> XarResult<FileData> {
+                let (res, encoded) =
+                    encode_file_data(encoding, self.file_checksum_type, data, *offset)?;
+                *offset += encoded.len() as u64;
+                heap.push(encoded);
+
+                Ok(res)
+            };
+
+            let (file_type, size, data, link) = match &entry.content {
+                EntryContent::File(content) => (
+                    FileType::File,
+                    Some(content.len() as u64),
+                    Some(encode_data(content)?),
+                    None,
+                ),
+                EntryContent::Directory => (FileType::Directory, None, None, None),
+                EntryContent::Symlink(link) => (FileType::Link, None, None, Some(link.clone())),
+            };
+
+            let ea = entry
+                .xattrs
+                .iter()
+                .enumerate()
+                .map(|(i, (name, value))| {
+                    let data = encode_data(value)?;
+
+                    Ok(Ea {
+                        id: Some(i as _),
+                        name: name.clone(),
+                        offset: data.offset,
+                        size: data.size,
+                        length: data.length,
+                        extracted_checksum: data.extracted_checksum,
+                        archived_checksum: data.archived_checksum,
+                        encoding: data.encoding,
+                    })
+                })
+                .collect::<XarResult<Vec<_>>>()?;
+
+            let name = path.rsplit('/').next().expect("split yields an element");
+
+            files.push(File {
+                id,
+                ctime: None,
+                mtime: entry.mtime.as_ref().map(format_time),
+                atime: None,
+                names: vec![name.to_string()],
+                file_type,
+                mode: Some(format!("{:04o}", entry.mode)),
+                deviceno: None,
+                inode: None,
+                uid: entry.uid,
+                gid: entry.gid,
+                user: entry.user.clone(),
+                group: entry.group.clone(),
+                size,
+                data,
+                ea,
+                link,
+                finder_create_time: None,
+                files: self.build_files(Some(path), children, next_id, offset, heap)?,
+            });
+        }
+
+        Ok(files)
+    }
+
+    /// Write the archive to a writer.
+    pub fn write<W: Write>(&self, writer: &mut W) -> XarResult<()> {
+        let (toc, heap) = self.build()?;
+
+        write_header_and_table_of_contents(writer, &toc)?;
+        for data in heap {
+            writer.write_all(&data)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::reader::XarReader, std::io::Cursor};
+
+    #[test]
+    fn build_and_read() -> XarResult<()> {
+        let mut builder = XarBuilder::new();
+        builder
+            .add_file_data("dir/gzip.txt", b"gzip content".to_vec())?
+            .set_mode(0o600)
+            .add_xattr("com.apple.test", b"xattr value".to_vec());
+        builder
+            .add_file_data("dir/bzip2.txt", b"bzip2 content".to_vec())?
+            .set_encoding(Encoding::Bzip2);
+        builder
+            .add_file_data("xz.txt", b"xz content".to_vec())?
+            .set_encoding(Encoding::Xz);
+        builder
+            .add_file_data("raw.txt", b"raw content".to_vec())?
+            .set_encoding(Encoding::None);
+        builder.add_symlink("dir/link", "gzip.txt")?;
+        builder.add_directory("empty")?;
+
+        assert!(builder.add_file_data("../escape", vec![]).is_err());
+        assert!(builder.add_file_data("xz.txt/child", vec![]).is_err());
+
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        let mut reader = XarReader::new(Cursor::new(data))?;
+        assert!(reader.verify_table_of_contents_checksum()?);
+
+        let files = reader.files()?;
+        assert_eq!(
+            files
+                .iter()
+                .map(|(path, _)| path.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "dir",
+                "dir/bzip2.txt",
+                "dir/gzip.txt",
+                "dir/link",
+                "empty",
+                "raw.txt",
+                "xz.txt"
+            ]
+        );
+
+        for (path, content) in [
+            ("dir/gzip.txt", b"gzip content".as_ref()),
+            ("dir/bzip2.txt", b"bzip2 content"),
+            ("xz.txt", b"xz content"),
+            ("raw.txt", b"raw content"),
+        ] {
+            assert_eq!(
+                reader.get_file_data_from_path(path)?.as_deref(),
+                Some(content)
+            );
+        }
+
+        let file = reader.find_file("dir/gzip.txt")?.unwrap();
+        assert_eq!(file.mode.as_deref(), Some("0600"));
+        assert_eq!(file.ea.len(), 1);
+        assert_eq!(file.ea[0].name, "com.apple.test");
+
+        let link = reader.find_file("dir/link")?.unwrap();
+        assert!(matches!(link.file_type, FileType::Link));
+        assert_eq!(link.link.unwrap().target, "gzip.txt");
+
+        Ok(())
+    }
+
+    #[test]
+    fn checksum_types() -> XarResult<()> {
+        for (toc, file) in [
+            (ChecksumType::Sha384, ChecksumType::Sha512),
+            (ChecksumType::Sha512, ChecksumType::Md5),
+            (ChecksumType::Sha256, ChecksumType::None),
+            (ChecksumType::None, ChecksumType::Sha384),
+        ] {
+            let mut builder = XarBuilder::new();
+            builder.set_checksum_type(toc);
+            builder.set_file_checksum_type(file);
+            builder.add_file_data("file", b"content".to_vec())?;
+
+            let mut data = vec![];
+            builder.write(&mut data)?;
+
+            let mut reader = XarReader::new(Cursor::new(data.clone()))?;
+            assert_eq!(
+                reader.verify_table_of_contents_checksum()?,
+                !matches!(toc, ChecksumType::None)
+            );
+            let dest = tempfile::tempdir()?;
+            reader.unpack_with_options(dest.path(), &Default::default())?;
+            assert_eq!(std::fs::read(dest.path().join("file"))?, b"content");
+
+            // The header checksum algorithm must agree with the table of contents.
+            data[24..28].copy_from_slice(&2u32.to_be_bytes());
+            assert!(matches!(
+                XarReader::new(Cursor::new(data)),
+                Err(Error::TableOfContentsCorrupted(_))
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn signature_reservation() -> XarResult<()> {
+        let mut builder = XarBuilder::new();
+        builder.set_checksum_type(ChecksumType::Sha256);
+        builder.reserve_signatures(256, 4096, std::iter::empty())?;
+        builder.add_file_data("file", b"content".to_vec())?;
+
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        let mut reader = XarReader::new(Cursor::new(data))?;
+        assert!(reader.verify_table_of_contents_checksum()?);
+
+        let toc = reader.table_of_contents();
+        let rsa = toc.find_signature(SignatureStyle::Rsa).unwrap();
+        let cms = toc.find_signature(SignatureStyle::Cms).unwrap();
+        assert_eq!((rsa.offset, rsa.size), (32, 256));
+        assert_eq!((cms.offset, cms.size), (288, 4096));
+        assert_eq!(
+            reader.find_file("file")?.unwrap().data.unwrap().offset,
+            288 + 4096
+        );
+        assert_eq!(
+            reader.get_file_data_from_path("file")?.as_deref(),
+            Some(b"content".as_ref())
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,434 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Modifying existing XAR archives.
+//!
+//! [XarEditor] adds, replaces and removes files of an archive and writes a new
+//! archive. The heap data of untouched files and extended attributes is copied
+//! verbatim, so it isn't decompressed and recompressed.
+//!
+//! Modifying an archive invalidates its signatures, so they are not carried over.
+//! The new archive can be signed with `XarSigner`.
+
+use {
+    crate::{
+        builder::{encode_file_data, format_time, write_header_and_table_of_contents},
+        path_components,
+        reader::XarReader,
+        table_of_contents::{
+            Checksum, ChecksumType, Encoding, File, FileData, FileType, TableOfContents, XarToC,
+        },
+        Error, XarResult,
+    },
+    chrono::{DateTime, Utc},
+    std::{
+        collections::HashMap,
+        fmt::Debug,
+        io::{Read, Seek, Write},
+    },
+};
+
+/// Create a [File] record without data.
+fn new_file(
+    id: u64,
+    name: &str,
+    file_type: FileType,
+    mode: u32,
+    mtime: Option<&DateTime<Utc>>,
+) -> File {
+    File {
+        id,
+        ctime: None,
+        mtime: mtime.map(format_time),
+        atime: None,
+        names: vec![name.to_string()],
+        file_type,
+        mode: Some(format!("{mode:04o}")),
+        deviceno: None,
+        inode: None,
+        uid: None,
+        gid: None,
+        user: None,
+        group: None,
+        size: None,
+        data: None,
+        ea: vec![],
+        link: None,
+        finder_create_time: None,
+        files: vec![],
+    }
+}
+
+/// The name of a [File].
+fn file_name(file: &File) -> Option<&str> {
+    file.names.last().map(|s| s.as_str())
+}
+
+/// Entity for modifying an existing XAR archive.
+pub struct XarEditor<R: Read + Seek + Sized + Debug> {
+    reader: XarReader<R>,
+    toc: XarToC,
+    /// New content of files, keyed by file ID.
+    pending: HashMap<u64, Vec<u8>>,
+    encoding: Encoding,
+    file_checksum_type: ChecksumType,
+    mtime: Option<DateTime<Utc>>,
+    next_id: u64,
+}
+
+impl<R: Read + Seek + Sized + Debug> XarEditor<R> {
+    /// Create a new instance bound to an existing XAR.
+    ///
+    /// New file data is compressed with zlib and uses the checksum algorithm of the
+    /// table of contents.
+    pub fn new(reader: XarReader<R>) -> XarResult<Self> {
+        let toc = XarToC::clone(reader.table_of_contents());
+        let next_id = reader.files()?.iter().map(|(_, f)| f.id).max().unwrap_or(0) + 1;
+        let file_checksum_type = toc.checksum.style;
+
+        Ok(Self {
+            reader,
+            toc,
+            pending: HashMap::new(),
+            encoding: Encoding::Gzip,
+            file_checksum_type,
+            mtime: None,
+            next_id,
+        })
+    }
+
+    /// The table of contents as modified so far.
+    ///
+    /// Offsets and checksums of new file data are only computed when writing.
+    pub fn table_of_contents(&self) -> &XarToC {
+        &self.toc
+    }
+
+    /// Set the encoding of new file data.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Set the checksum algorithm of the table of contents.
+    pub fn set_checksum_type(&mut self, checksum_type: ChecksumType) {
+        self.toc.checksum.style = checksum_type;
+    }
+
+    /// Set the checksum algorithm of new file data.
+    pub fn set_file_checksum_type(&mut self, checksum_type: ChecksumType) {
+        self.file_checksum_type = checksum_type;
+    }
+
+    /// Set the modification time of files and directories added or replaced from
+    /// now on.
+    ///
+    /// By default added entries have no modification time and replaced files keep
+    /// theirs, so editing is reproducible.
+    pub fn set_mtime(&mut self, mtime: DateTime<Utc>) {
+        self.mtime = Some(mtime);
+    }
+
+    /// Find the [File] record of a path.
+    fn find(&self, path: &str) -> XarResult<Option<&File>> {
+        let mut files = &self.toc.files;
+        let mut found = None;
+
+        for component in path_components(path)? {
+            let Some(file) = files.iter().find(|f| file_name(f) == Some(component)) else {
+                return Ok(None);
+            };
+            files = &file.files;
+            found = Some(file);
+        }
+
+        Ok(found)
+    }
+
+    /// Obtain the current content of a file.
+    ///
+    /// Returns `None` if the path doesn't exist.
+    pub fn file_data(&mut self, path: &str) -> XarResult<Option<Vec<u8>>> {
+        let Some(file) = self.find(path)?.cloned() else {
+            return Ok(None);
+        };
+
+        if let Some(data) = self.pending.get(&file.id) {
+            Ok(Some(data.clone()))
+        } else if file.data.is_some() {
+            let mut buffer = vec![];
+            self.reader
+                .write_file_data_decoded_from_file(&file, &mut buffer)?;
+            Ok(Some(buffer))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Add a file or replace the content of an existing file.
+    ///
+    /// Missing parent directories are added. Metadata of replaced files is retained,
+    /// except for the modification time if one was set with [Self::set_mtime].
+    pub fn set_file_data(&mut self, path: &str, data: impl Into<Vec<u8>>) -> XarResult<()> {
+        let components = path_components(path)?;
+        let (name, parents) = components.split_last().expect("split yields an element");
+        let data = data.into();
+
+        let mut files = &mut self.toc.files;
+        for parent in parents {
+            let index = match files.iter().position(|f| file_name(f) == Some(parent)) {
+                Some(index) if matches!(files[index].file_type, FileType::Directory) => index,
+                Some(_) => return Err(Error::InvalidPath(path.to_string())),
+                None => {
+                    files.push(new_file(
+                        self.next_id,
+                        parent,
+                        FileType::Directory,
+                        0o755,
+                        self.mtime.as_ref(),
+                    ));
+                    self.next_id += 1;
+                    files.len() - 1
+                }
+            };
+            files = &mut files[index].files;
+        }
+
+        let file = match files.iter().position(|f| file_name(f) == Some(name)) {
+            Some(index) if matches!(files[index].file_type, FileType::File) => {
+                let file = &mut files[index];
+                if let Some(mtime) = &self.mtime {
+                    file.mtime = Some(format_time(mtime));
+                }
+                file
+            }
+            Some(_) => return Err(Error::InvalidPath(path.to_string())),
+            None => {
+                files.push(new_file(
+                    self.next_id,
+                    name,
+                    FileType::File,
+                    0o644,
+                    self.mtime.as_ref(),
+                ));
+                self.next_id += 1;
+                files.last_mut().expect("file was just added")
+            }
+        };
+
+        file.size = Some(data.len() as _);
+        self.pending.insert(file.id, data);
+
+        Ok(())
+    }
+
+    /// Remove a file or a directory including its content.
+    ///
+    /// Returns whether the path existed.
+    pub fn remove(&mut self, path: &str) -> XarResult<bool> {
+        let components = path_components(path)?;
+        let (name, parents) = components.split_last().expect("split yields an element");
+
+        let mut files = &mut self.toc.files;
+        for parent in parents {
+            match files.iter_mut().find(|f| file_name(f) == Some(parent)) {
+                Some(file) => files = &mut file.files,
+                None => return Ok(false),
+            }
+        }
+
+        let len = files.len();
+        files.retain(|f| file_name(f) != Some(name));
+
+        Ok(files.len() != len)
+    }
+
+    /// Write the modified archive to a writer.
+    pub fn write<W: Write>(&mut self, writer: &mut W) -> XarResult<()> {
+        let mut toc = self.toc.clone();
+        toc.signature = None;
+        toc.x_signature = None;
+        toc.checksum = Checksum {
+            style: toc.checksum.style,
+            offset: 0,
+            size: toc.checksum.style.digest_size() as _,
+        };
+
+        let original_files = self
+            .reader
+            .files()?
+            .into_iter()
+            .map(|(_, f)| (f.id, f))
+            .collect::<HashMap<_, _>>();
+
+        // Lay out the heap in table of contents traversal order, encoding new data
+        // along the way.
+        let mut offset = toc.checksum.size;
+        let mut new_data = HashMap::<u64, (FileData, Vec<u8>)>::new();
+        let mut offsets = HashMap::<(u64, Option<usize>), u64>::new();
+        let mut heap_files = vec![];
+
+        for (_, file) in TableOfContents::from(toc.clone()).files()? {
+            if let Some(content) = self.pending.get(&file.id) {
+                let (data, encoded) =
+                    encode_file_data(self.encoding, self.file_checksum_type, content, offset)?;
+                offset += data.length;
+                new_data.insert(file.id, (data, encoded));
+            } else if let Some(data) = &file.data {
+                offsets.insert((file.id, None), offset);
+                offset += data.length;
+            }
+            for (i, ea) in file.ea.iter().enumerate() {
+                offsets.insert((file.id, Some(i)), offset);
+                offset += ea.length;
+            }
+
+            heap_files.push(file.id);
+        }
+
+        toc.visit_files_mut(&|file: &mut File| {
+            if let Some((data, _)) = new_data.get(&file.id) {
+                file.data = Some(data.clone());
+            } else if let Some(data) = &mut file.data {
+                data.offset = offsets[&(file.id, None)];
+            }
+            for (i, ea) in file.ea.iter_mut().enumerate() {
+                ea.offset = offsets[&(file.id, Some(i))];
+            }
+        });
+
+        write_header_and_table_of_contents(writer, &TableOfContents::from(toc))?;
+
+        for id in heap_files {
+            if let Some((_, encoded)) = new_data.get(&id) {
+                writer.write_all(encoded)?;
+            }
+
+            if let Some(file) = original_files.get(&id) {
+                if file.data.is_some() && !new_data.contains_key(&id) {
+                    self.reader.write_file_data_heap_from_file(file, writer)?;
+                }
+                for ea in &file.ea {
+                    self.reader.write_ea_data_heap(ea, writer)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::builder::XarBuilder, std::io::Cursor};
+
+    #[test]
+    fn edit() -> XarResult<()> {
+        let mut builder = XarBuilder::new();
+        builder
+            .add_file_data("Distribution", b"<installer-gui-script/>".to_vec())?
+            .add_xattr("com.apple.test", b"value".to_vec());
+        builder
+            .add_file_data("pkg/Payload", b"payload".to_vec())?
+            .set_encoding(Encoding::Bzip2);
+        builder.add_file_data("pkg/Remove", b"remove".to_vec())?;
+        builder.reserve_signatures(256, 4096, std::iter::empty())?;
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        let reader = XarReader::new(Cursor::new(data))?;
+        let payload = reader.find_file("pkg/Payload")?.unwrap().data.unwrap();
+
+        let mut editor = XarEditor::new(reader)?;
+        let distribution = editor.file_data("Distribution")?.unwrap();
+        let distribution = String::from_utf8(distribution)
+            .unwrap()
+            .replace("/>", " minSpecVersion=\"2\"/>");
+        editor.set_file_data("Distribution", distribution)?;
+        editor.set_file_data("pkg/Scripts/postinstall", b"#!/bin/sh\n".to_vec())?;
+        assert!(editor.remove("pkg/Remove")?);
+        assert!(!editor.remove("missing")?);
+        assert!(editor.set_file_data("pkg/Payload/child", vec![]).is_err());
+
+        let mut data = vec![];
+        editor.write(&mut data)?;
+
+        let mut reader = XarReader::new(Cursor::new(data))?;
+        assert!(reader.verify_table_of_contents_checksum()?);
+        assert!(reader.table_of_contents().signatures().is_empty());
+        assert_eq!(
+            reader
+                .files()?
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<Vec<_>>(),
+            vec![
+                "Distribution",
+                "pkg",
+                "pkg/Payload",
+                "pkg/Scripts",
+                "pkg/Scripts/postinstall"
+            ]
+        );
+        assert_eq!(
+            reader.get_file_data_from_path("Distribution")?.as_deref(),
+            Some(b"<installer-gui-script minSpecVersion=\"2\"/>".as_ref())
+        );
+        assert_eq!(
+            reader
+                .get_file_data_from_path("pkg/Scripts/postinstall")?
+                .as_deref(),
+            Some(b"#!/bin/sh\n".as_ref())
+        );
+
+        // Untouched data is copied verbatim.
+        let new_payload = reader.find_file("pkg/Payload")?.unwrap().data.unwrap();
+        assert_eq!(new_payload.encoding.style, "application/x-bzip2");
+        assert_eq!(
+            new_payload.archived_checksum.checksum,
+            payload.archived_checksum.checksum
+        );
+
+        let dest = tempfile::tempdir()?;
+        reader.unpack_with_options(dest.path(), &Default::default())?;
+        assert_eq!(std::fs::read(dest.path().join("pkg/Payload"))?, b"payload");
+
+        let file = reader.find_file("Distribution")?.unwrap();
+        let mut value = vec![];
+        reader.write_ea_data_decoded(&file.ea[0], &mut value)?;
+        assert_eq!(value, b"value");
+
+        Ok(())
+    }
+
+    #[test]
+    fn mtime() -> XarResult<()> {
+        use chrono::TimeZone;
+
+        let old = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let new = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let mut builder = XarBuilder::new();
+        builder
+            .add_file_data("file", b"old".to_vec())?
+            .set_mtime(old);
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        let mut editor = XarEditor::new(XarReader::new(Cursor::new(data))?)?;
+        editor.set_file_data("file", b"new".to_vec())?;
+        editor.set_file_data("dir/added", b"added".to_vec())?;
+        let toc = editor.table_of_contents();
+        assert_eq!(toc.files[0].mtime, Some(format_time(&old)));
+        assert_eq!(toc.files[1].mtime, None);
+        assert_eq!(toc.files[1].files[0].mtime, None);
+
+        editor.set_mtime(new);
+        editor.set_file_data("file", b"newer".to_vec())?;
+        editor.set_file_data("other", b"other".to_vec())?;
+        let toc = editor.table_of_contents();
+        assert_eq!(toc.files[0].mtime, Some(format_time(&new)));
+        assert_eq!(toc.files[2].mtime, Some(format_time(&new)));
+
+        Ok(())
+    }
+}
@@ -2,11 +2,17 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+// The scroll derives emit unit expressions.
+#![allow(clippy::unused_unit)]
+
 use {
     scroll::{IOread, IOwrite, Pread, SizeWith},
     std::fmt::{Display, Formatter},
 };
 
+/// File magic of XAR archives, `xar!`.
+pub const XAR_MAGIC: u32 = 0x78617221;
+
 /// A XAR archive header.
 ///
 /// The header effectively defines a table of contents, which
@@ -38,6 +44,9 @@ pub enum XarChecksum {
     Sha1,
     Md5,
     Sha256,
+    /// Written as identifier 3, which xar defines as an algorithm named in the
+    /// table of contents.
+    Sha384,
     Sha512,
     Other(u32),
 }
@@ -61,7 +70,7 @@ impl From<XarChecksum> for u32 {
             XarChecksum::None => 0,
             XarChecksum::Sha1 => 1,
             XarChecksum::Md5 => 2,
-            XarChecksum::Sha256 => 3,
+            XarChecksum::Sha256 | XarChecksum::Sha384 => 3,
             XarChecksum::Sha512 => 4,
             XarChecksum::Other(v) => v,
         }
@@ -75,6 +84,7 @@ impl Display for XarChecksum {
             XarChecksum::Sha1 => f.write_str("SHA-1"),
             XarChecksum::Md5 => f.write_str("MD5"),
             XarChecksum::Sha256 => f.write_str("SHA-256"),
+            XarChecksum::Sha384 => f.write_str("SHA-384"),
             XarChecksum::Sha512 => f.write_str("SHA-512"),
             XarChecksum::Other(v) => f.write_fmt(format_args!("unknown ({v})")),
         }
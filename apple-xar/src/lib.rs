@@ -4,11 +4,17 @@
 
 /*! XAR file format */
 
+#[cfg(feature = "tokio")]
+pub mod async_reader;
+pub mod builder;
+pub mod editor;
 pub mod format;
+pub mod positioned;
 pub mod reader;
 #[cfg(feature = "signing")]
 pub mod signing;
 pub mod table_of_contents;
+pub mod trust;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -30,9 +36,18 @@ pub enum Error {
     #[error("XML write error: {0}")]
     XmlWrite(#[from] xml::writer::Error),
 
+    #[error("invalid path in archive: {0}")]
+    InvalidPath(String),
+
+    #[error("checksum mismatch: {0}")]
+    ChecksumMismatch(String),
+
     #[error("Invalid file ID")]
     InvalidFileId,
 
+    #[error("header is corrupted: {0}")]
+    HeaderCorrupted(&'static str),
+
     #[error("table of contents is corrupted: {0}")]
     TableOfContentsCorrupted(&'static str),
 
@@ -62,3 +77,24 @@ pub enum Error {
 }
 
 pub type XarResult<T> = std::result::Result<T, Error>;
+
+/// Validate a path in an archive and split it into its components.
+///
+/// Paths must be relative and must not contain empty, `.` or `..` components.
+pub(crate) fn path_components(path: &str) -> XarResult<Vec<&str>> {
+    let components = path.split('/').collect::<Vec<_>>();
+
+    if components
+        .iter()
+        .any(|c| c.is_empty() || *c == "." || *c == ".." || c.contains(['\\', '\0']))
+    {
+        return Err(Error::InvalidPath(path.to_string()));
+    }
+
+    Ok(components)
+}
+
+/// Format bytes as lowercase hex string.
+pub(crate) fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
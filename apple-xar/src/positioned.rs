@@ -0,0 +1,346 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reading XAR archives with positioned reads.
+//!
+//! [crate::reader::XarReader] seeks a single stream, so it can only read one heap
+//! entry at a time. [XarPositionedReader] instead reads from explicit offsets via
+//! [ReadAt], like `pread(2)`. Its methods take `&self`, so many heap entries can be
+//! decoded concurrently from different threads.
+//!
+//! With the `rayon` feature, [XarPositionedReader::unpack_with_options] decodes
+//! entries in parallel.
+
+use {
+    crate::{
+        format::XarHeader,
+        reader::{
+            header_and_toc_length, parse_header_and_toc, prepare_entry, restore_directories,
+            unpack_entry_content, write_decoded_verified, UnpackOptions,
+        },
+        table_of_contents::{File, TableOfContents},
+        Error, XarResult,
+    },
+    std::{cmp::min, io::Write, path::Path, sync::Arc},
+};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// A source of data that can be read at arbitrary offsets without shared state.
+pub trait ReadAt {
+    /// Read data at an offset into a buffer.
+    ///
+    /// Returns the number of bytes read, 0 signaling the end of data.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize>;
+
+    /// Read the exact number of bytes needed to fill a buffer at an offset.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                Ok(size) => {
+                    buf = &mut buf[size..];
+                    offset += size as u64;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl ReadAt for std::fs::File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+}
+
+#[cfg(windows)]
+impl ReadAt for std::fs::File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        // This moves the file cursor, which positioned reads don't rely on.
+        std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        let start = min(offset, self.len() as u64) as usize;
+        let size = min(buf.len(), self.len() - start);
+        buf[..size].copy_from_slice(&self[start..start + size]);
+
+        Ok(size)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        self.as_slice().read_at(buf, offset)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for &T {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for Arc<T> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+}
+
+/// Read-only interface to a XAR archive using positioned reads.
+#[derive(Debug)]
+pub struct XarPositionedReader<R: ReadAt> {
+    /// Reader of raw XAR archive content.
+    reader: R,
+
+    /// Parsed file header.
+    header: XarHeader,
+
+    /// Parsed table of contents.
+    toc: TableOfContents,
+}
+
+impl<R: ReadAt> XarPositionedReader<R> {
+    /// Construct a new XAR reader from a positioned reader.
+    pub fn new(reader: R) -> XarResult<Self> {
+        let mut data = vec![0u8; 28];
+        reader.read_exact_at(&mut data, 0)?;
+
+        // The length is validated to cover the header and be bounded.
+        data.resize(header_and_toc_length(&data)? as _, 0);
+        reader.read_exact_at(&mut data[28..], 28)?;
+
+        let (header, toc) = parse_header_and_toc(data)?;
+
+        Ok(Self {
+            reader,
+            header,
+            toc,
+        })
+    }
+
+    /// Obtain the inner reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Obtain the parsed [XarHeader] file header.
+    pub fn header(&self) -> &XarHeader {
+        &self.header
+    }
+
+    /// The start offset of the heap.
+    pub fn heap_start_offset(&self) -> u64 {
+        self.header.size as u64 + self.header.toc_length_compressed
+    }
+
+    /// Obtain the table of contents for this archive.
+    pub fn table_of_contents(&self) -> &TableOfContents {
+        &self.toc
+    }
+
+    /// Obtain the file entries in this archive.
+    pub fn files(&self) -> XarResult<Vec<(String, File)>> {
+        self.toc.files()
+    }
+
+    /// Attempt to find the [File] entry for a given path in the archive.
+    pub fn find_file(&self, filename: &str) -> XarResult<Option<File>> {
+        Ok(self
+            .toc
+            .files()?
+            .into_iter()
+            .find_map(|(path, file)| if path == filename { Some(file) } else { None }))
+    }
+
+    /// Write a slice of the heap to a writer.
+    fn write_heap_slice(&self, offset: u64, size: u64, writer: &mut impl Write) -> XarResult<()> {
+        let mut offset = self.heap_start_offset() + offset;
+        let mut remaining = size;
+        let mut buffer = vec![0u8; min(remaining, 32768) as usize];
+
+        while remaining > 0 {
+            let chunk = &mut buffer[..min(remaining, 32768) as usize];
+            self.reader.read_exact_at(chunk, offset)?;
+            writer.write_all(chunk)?;
+
+            offset += chunk.len() as u64;
+            remaining -= chunk.len() as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Write heap file data for a given file record to a writer.
+    ///
+    /// This will write the raw data backing a file as stored in the heap.
+    ///
+    /// Returns the number of bytes written.
+    pub fn write_file_data_heap_from_file(
+        &self,
+        file: &File,
+        writer: &mut impl Write,
+    ) -> XarResult<usize> {
+        let data = file.data.as_ref().ok_or(Error::FileNoData)?;
+
+        self.write_heap_slice(data.offset, data.length, writer)?;
+
+        Ok(data.length as _)
+    }
+
+    /// Write decoded file data for a given file record to a writer, verifying its
+    /// checksums.
+    pub fn write_file_data_decoded_from_file(
+        &self,
+        file: &File,
+        writer: impl Write,
+    ) -> XarResult<()> {
+        let data = file.data.as_ref().ok_or(Error::FileNoData)?;
+
+        write_decoded_verified(
+            &data.encoding,
+            &data.archived_checksum,
+            &data.extracted_checksum,
+            true,
+            file.names.last().map(|s| s.as_str()).unwrap_or_default(),
+            writer,
+            |mut writer| self.write_heap_slice(data.offset, data.length, &mut writer),
+        )
+    }
+
+    /// Resolve data for a given path.
+    pub fn get_file_data_from_path(&self, path: &str) -> XarResult<Option<Vec<u8>>> {
+        if let Some(file) = self.find_file(path)? {
+            let mut buffer = Vec::<u8>::with_capacity(file.size.unwrap_or(0) as _);
+            self.write_file_data_decoded_from_file(&file, &mut buffer)?;
+
+            Ok(Some(buffer))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<R: ReadAt + Sync> XarPositionedReader<R> {
+    /// Unpack the contents of the XAR archive to a given directory, guarding against
+    /// malicious archives.
+    ///
+    /// This behaves like [crate::reader::XarReader::unpack_with_options]. Filesystem
+    /// entries are created first. With the `rayon` feature, file data and extended
+    /// attributes are then decoded in parallel.
+    pub fn unpack_with_options(
+        &self,
+        dest_dir: impl AsRef<Path>,
+        options: &UnpackOptions,
+    ) -> XarResult<()> {
+        let dest_dir = dest_dir.as_ref();
+
+        let entries = self
+            .toc
+            .files()?
+            .into_iter()
+            .map(|(path, file)| prepare_entry(dest_dir, path, file))
+            .collect::<XarResult<Vec<_>>>()?;
+
+        let unpack = |entry: &_| {
+            unpack_entry_content(entry, options, |offset, length, mut writer| {
+                self.write_heap_slice(offset, length, &mut writer)
+            })
+        };
+
+        #[cfg(feature = "rayon")]
+        entries.par_iter().try_for_each(unpack)?;
+        #[cfg(not(feature = "rayon"))]
+        entries.iter().try_for_each(unpack)?;
+
+        restore_directories(&entries, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{builder::XarBuilder, table_of_contents::Encoding},
+    };
+
+    #[test]
+    fn malformed_header() -> XarResult<()> {
+        let mut data = vec![];
+        XarBuilder::new().write(&mut data)?;
+
+        // Header size below the fixed header.
+        let mut small = data.clone();
+        small[4..6].copy_from_slice(&20u16.to_be_bytes());
+        assert!(XarPositionedReader::new(small.as_slice()).is_err());
+        assert!(crate::reader::XarReader::new(std::io::Cursor::new(small)).is_err());
+
+        // Huge compressed table of contents.
+        let mut large = data;
+        large[8..16].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(XarPositionedReader::new(large.as_slice()).is_err());
+        assert!(crate::reader::XarReader::new(std::io::Cursor::new(large)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_and_unpack() -> XarResult<()> {
+        let mut builder = XarBuilder::new();
+        for (i, encoding) in [
+            Encoding::None,
+            Encoding::Gzip,
+            Encoding::Bzip2,
+            Encoding::Xz,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            builder
+                .add_file_data(&format!("dir/file{i}"), vec![i as u8; 100_000])?
+                .set_encoding(encoding);
+        }
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        let reader = XarPositionedReader::new(data.as_slice())?;
+        assert_eq!(reader.files()?.len(), 5);
+        assert_eq!(
+            reader.get_file_data_from_path("dir/file3")?,
+            Some(vec![3; 100_000])
+        );
+        assert_eq!(reader.get_file_data_from_path("missing")?, None);
+
+        let dest = tempfile::tempdir()?;
+        reader.unpack_with_options(dest.path(), &UnpackOptions::default())?;
+        for i in 0..4u8 {
+            assert_eq!(
+                std::fs::read(dest.path().join(format!("dir/file{i}")))?,
+                vec![i; 100_000]
+            );
+        }
+
+        // Corrupt the data of a file.
+        let offset = reader.heap_start_offset()
+            + reader.find_file("dir/file0")?.unwrap().data.unwrap().offset;
+        data[offset as usize] ^= 0xff;
+
+        let reader = XarPositionedReader::new(Arc::new(data))?;
+        let dest = tempfile::tempdir()?;
+        assert!(matches!(
+            reader.unpack_with_options(dest.path(), &UnpackOptions::default()),
+            Err(Error::ChecksumMismatch(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -4,16 +4,23 @@
 
 use {
     crate::{
-        format::{XarChecksum, XarHeader},
-        table_of_contents::{ChecksumType, File, FileType, SignatureStyle, TableOfContents},
+        format::XarHeader,
+        hex, path_components,
+        table_of_contents::{
+            ChecksumType, Ea, Encoding, File, FileChecksum, FileEncoding, FileType, SignatureStyle,
+            TableOfContents,
+        },
+        trust::{self, TrustPolicy, TrustProblem, TrustReport},
         Error, XarResult,
     },
+    digest::DynDigest,
+    log::warn,
     scroll::IOread,
     std::{
         cmp::min,
         fmt::Debug,
         io::{Cursor, Read, Seek, SeekFrom, Write},
-        path::Path,
+        path::{Path, PathBuf},
     },
     x509_certificate::CapturedX509Certificate,
 };
@@ -21,6 +28,449 @@ use {
 #[cfg(feature = "signing")]
 use cryptographic_message_syntax::SignedData;
 
+/// A writer decoding heap data.
+enum Decoder<W: Write> {
+    None(W),
+    Gzip(flate2::write::ZlibDecoder<W>),
+    Bzip2(bzip2::write::BzDecoder<W>),
+    Xz(xz2::write::XzDecoder<W>),
+}
+
+impl<W: Write> Decoder<W> {
+    fn new(encoding: &FileEncoding, writer: W) -> XarResult<Self> {
+        Ok(match Encoding::try_from(encoding)? {
+            Encoding::None => Self::None(writer),
+            // The media type is arguably wrong, as there is no gzip header.
+            Encoding::Gzip => Self::Gzip(flate2::write::ZlibDecoder::new(writer)),
+            Encoding::Bzip2 => Self::Bzip2(bzip2::write::BzDecoder::new(writer)),
+            Encoding::Xz => Self::Xz(xz2::write::XzDecoder::new(writer)),
+        })
+    }
+
+    /// Finish decoding, returning the inner writer.
+    fn finish(self) -> std::io::Result<W> {
+        match self {
+            Self::None(w) => Ok(w),
+            Self::Gzip(w) => w.finish(),
+            Self::Bzip2(mut w) => w.finish(),
+            Self::Xz(mut w) => w.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Decoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::None(w) => w.write(buf),
+            Self::Gzip(w) => w.write(buf),
+            Self::Bzip2(w) => w.write(buf),
+            Self::Xz(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::None(w) => w.flush(),
+            Self::Gzip(w) => w.flush(),
+            Self::Bzip2(w) => w.flush(),
+            Self::Xz(w) => w.flush(),
+        }
+    }
+}
+
+/// A writer digesting data written through it.
+struct DigestWriter<W: Write> {
+    inner: W,
+    digest: Option<Box<dyn DynDigest + Send>>,
+}
+
+impl<W: Write> DigestWriter<W> {
+    /// Create a writer digesting data with the algorithm of a checksum, if `verify` is set.
+    fn new(inner: W, checksum: &FileChecksum, verify: bool) -> XarResult<Self> {
+        let digest = if verify && !matches!(checksum.style, ChecksumType::None) {
+            Some(checksum.style.digest()?)
+        } else {
+            None
+        };
+
+        Ok(Self { inner, digest })
+    }
+
+    /// Verify the digest of the written data matches a checksum.
+    ///
+    /// Returns the inner writer.
+    fn verify(self, checksum: &FileChecksum, what: &str) -> XarResult<W> {
+        if let Some(digest) = self.digest {
+            if !hex(&digest.finalize()).eq_ignore_ascii_case(checksum.checksum.trim()) {
+                return Err(Error::ChecksumMismatch(what.to_string()));
+            }
+        }
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let size = self.inner.write(buf)?;
+        if let Some(digest) = &mut self.digest {
+            digest.update(&buf[..size]);
+        }
+
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Whether a symlink at `path` below `dest_dir` pointing to `target` may resolve
+/// outside of `dest_dir`.
+///
+/// `..` components are only allowed after components that are existing directories.
+/// Following `..` through a symlink, including one created by a later entry, would
+/// resolve relative to the symlink's target rather than lexically.
+fn symlink_escapes(dest_dir: &Path, path: &str, target: &str) -> bool {
+    if target.starts_with('/') {
+        return true;
+    }
+
+    let mut resolved = path.split('/').collect::<Vec<_>>();
+    resolved.pop();
+    let mut unverified = false;
+
+    for component in target.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if unverified || resolved.pop().is_none() {
+                    return true;
+                }
+            }
+            _ => {
+                resolved.push(component);
+
+                if !unverified {
+                    unverified = !std::fs::symlink_metadata(dest_dir.join(resolved.join("/")))
+                        .is_ok_and(|m| m.is_dir());
+                }
+            }
+        }
+    }
+
+    false
+}
+
+/// Resolve the filesystem path of an archive path below a directory.
+///
+/// Errors if the path isn't a plain relative path or if any of its parents in the
+/// filesystem is a symlink.
+fn safe_destination(dest_dir: &Path, path: &str) -> XarResult<PathBuf> {
+    let components = path_components(path)?;
+    let (name, parents) = components.split_last().expect("split yields an element");
+
+    let mut dest_path = dest_dir.to_path_buf();
+    for parent in parents {
+        dest_path.push(parent);
+
+        if std::fs::symlink_metadata(&dest_path).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(Error::InvalidPath(path.to_string()));
+        }
+    }
+    dest_path.push(name);
+
+    Ok(dest_path)
+}
+
+/// Restore metadata of an unpacked entry.
+fn restore_metadata(dest_path: &Path, file: &File, options: &UnpackOptions) -> XarResult<()> {
+    let symlink = matches!(file.file_type, FileType::Link);
+
+    #[cfg(unix)]
+    if options.restore_ownership {
+        if symlink {
+            std::os::unix::fs::lchown(dest_path, file.uid, file.gid)?;
+        } else {
+            std::os::unix::fs::chown(dest_path, file.uid, file.gid)?;
+        }
+    }
+
+    // Symlink times and permissions cannot be set portably.
+    if symlink {
+        return Ok(());
+    }
+
+    if options.restore_mtimes {
+        if let Some(mtime) = &file.mtime {
+            match chrono::DateTime::parse_from_rfc3339(mtime) {
+                Ok(mtime) => {
+                    std::fs::File::open(dest_path)?.set_modified(mtime.into())?;
+                }
+                Err(e) => warn!("unable to parse modification time {}: {}", mtime, e),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    if options.restore_modes {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Some(mode) = &file.mode {
+            let mode = u32::from_str_radix(mode, 8)
+                .map_err(|_| Error::TableOfContentsCorrupted("invalid file mode"))?;
+            let mask = if options.restore_special_modes {
+                0o7777
+            } else {
+                0o777
+            };
+            std::fs::set_permissions(dest_path, std::fs::Permissions::from_mode(mode & mask))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Decode heap data to a writer, verifying its checksums.
+///
+/// `copy` writes the raw heap data to the writer it is given.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn write_decoded_verified(
+    encoding: &FileEncoding,
+    archived_checksum: &FileChecksum,
+    extracted_checksum: &FileChecksum,
+    verify: bool,
+    what: &str,
+    writer: impl Write,
+    copy: impl FnOnce(&mut dyn Write) -> XarResult<()>,
+) -> XarResult<()> {
+    let writer = DigestWriter::new(writer, extracted_checksum, verify)?;
+    let writer = Decoder::new(encoding, writer)?;
+    let mut writer = DigestWriter::new(writer, archived_checksum, verify)?;
+
+    copy(&mut writer)?;
+
+    // Verify the archived checksum first, as decoding corrupted data likely fails.
+    let writer = writer.verify(archived_checksum, what)?;
+    let writer = writer.finish()?;
+    writer.verify(extracted_checksum, what)?;
+
+    Ok(())
+}
+
+/// An archive entry whose filesystem object has been created by [prepare_entry].
+pub(crate) struct PreparedEntry {
+    pub path: String,
+    pub dest_path: PathBuf,
+    pub file: File,
+}
+
+/// Create the filesystem object of an archive entry below a directory.
+///
+/// Directories and symlinks are created completely. Regular files are created empty
+/// and closed again, so archives with many files don't exhaust file descriptors.
+/// Their data is written by [unpack_entry_content].
+pub(crate) fn prepare_entry(dest_dir: &Path, path: String, file: File) -> XarResult<PreparedEntry> {
+    let dest_path = safe_destination(dest_dir, &path)?;
+
+    if let Some(parent) = dest_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    match file.file_type {
+        FileType::Directory => {
+            if !std::fs::symlink_metadata(&dest_path).is_ok_and(|m| m.is_dir()) {
+                std::fs::create_dir(&dest_path)?;
+            }
+        }
+        FileType::File => {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&dest_path)?;
+        }
+        FileType::Link => {
+            let link = file
+                .link
+                .as_ref()
+                .ok_or(Error::TableOfContentsCorrupted("symlink without target"))?;
+
+            if symlink_escapes(dest_dir, &path, &link.target) {
+                return Err(Error::InvalidPath(format!("{path} -> {}", link.target)));
+            }
+
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&link.target, &dest_path)?;
+            #[cfg(not(unix))]
+            return Err(Error::Unsupported("writing symlinks"));
+        }
+        FileType::HardLink => return Err(Error::Unsupported("writing hard links")),
+    }
+
+    Ok(PreparedEntry {
+        path,
+        dest_path,
+        file,
+    })
+}
+
+/// Write the data and extended attributes of a prepared entry and restore its metadata.
+///
+/// `copy` writes the raw heap data at an offset and length to a writer. Metadata of
+/// directories isn't restored, as writing their content would alter it. Use
+/// [restore_directories] once all entries have been unpacked.
+pub(crate) fn unpack_entry_content(
+    entry: &PreparedEntry,
+    options: &UnpackOptions,
+    mut copy: impl FnMut(u64, u64, &mut dyn Write) -> XarResult<()>,
+) -> XarResult<()> {
+    let PreparedEntry {
+        path,
+        dest_path,
+        file,
+    } = entry;
+
+    if let (FileType::File, Some(data)) = (&file.file_type, &file.data) {
+        // The file was created by prepare_entry. Refuse anything that replaced it
+        // in the meantime rather than following a symlink.
+        if !std::fs::symlink_metadata(dest_path)?.is_file() {
+            return Err(Error::InvalidPath(path.clone()));
+        }
+
+        let fh = std::fs::OpenOptions::new().write(true).open(dest_path)?;
+
+        write_decoded_verified(
+            &data.encoding,
+            &data.archived_checksum,
+            &data.extracted_checksum,
+            options.verify_checksums,
+            path,
+            fh,
+            |writer| copy(data.offset, data.length, writer),
+        )?;
+    }
+
+    if options.restore_xattrs {
+        for ea in &file.ea {
+            let mut value = vec![];
+            write_decoded_verified(
+                &ea.encoding,
+                &ea.archived_checksum,
+                &ea.extracted_checksum,
+                options.verify_checksums,
+                &format!("{path} ({})", ea.name),
+                &mut value,
+                |writer| copy(ea.offset, ea.length, writer),
+            )?;
+
+            #[cfg(unix)]
+            if let Err(e) = xattr::set(dest_path, &ea.name, &value) {
+                warn!(
+                    "unable to set extended attribute {} on {}: {}",
+                    ea.name, path, e
+                );
+            }
+            #[cfg(not(unix))]
+            warn!("unable to set extended attribute {} on {}", ea.name, path);
+        }
+    }
+
+    if !matches!(file.file_type, FileType::Directory) {
+        restore_metadata(dest_path, file, options)?;
+    }
+
+    Ok(())
+}
+
+/// Restore metadata of the directories among unpacked entries.
+pub(crate) fn restore_directories(
+    entries: &[PreparedEntry],
+    options: &UnpackOptions,
+) -> XarResult<()> {
+    // Children are written after their parent, so restoring in reverse order
+    // handles nested directories.
+    for entry in entries.iter().rev() {
+        if matches!(entry.file.file_type, FileType::Directory) {
+            restore_metadata(&entry.dest_path, &entry.file, options)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The length of the header and table of contents of an archive.
+///
+/// `data` holds at least the fixed-size part of the header.
+pub(crate) fn header_and_toc_length(data: &[u8]) -> XarResult<u64> {
+    let header = Cursor::new(data).ioread_with::<XarHeader>(scroll::BE)?;
+    validate_header(&header)?;
+
+    Ok(header.size as u64 + header.toc_length_compressed)
+}
+
+/// Size of the fixed part of the header.
+const HEADER_SIZE: u16 = 28;
+
+/// Largest table of contents accepted, compressed or decompressed.
+const MAX_TOC_LENGTH: u64 = 256 << 20;
+
+/// Check the lengths of a header before reading what they describe.
+fn validate_header(header: &XarHeader) -> XarResult<()> {
+    if header.size < HEADER_SIZE {
+        return Err(Error::HeaderCorrupted("header size is too small"));
+    }
+    if header.toc_length_compressed > MAX_TOC_LENGTH {
+        return Err(Error::TableOfContentsCorrupted(
+            "compressed table of contents is too large",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parse the header and table of contents of an archive.
+///
+/// `data` holds the archive up to the start of the heap, as determined by
+/// [header_and_toc_length].
+pub(crate) fn parse_header_and_toc(data: Vec<u8>) -> XarResult<(XarHeader, TableOfContents)> {
+    let reader = XarReader::new(Cursor::new(data))?;
+
+    Ok((reader.header, reader.toc))
+}
+
+/// Options controlling [XarReader::unpack_with_options].
+#[derive(Clone, Copy, Debug)]
+pub struct UnpackOptions {
+    /// Verify archived and extracted checksums of file data and extended attributes.
+    pub verify_checksums: bool,
+    /// Restore permissions from the mode of entries.
+    pub restore_modes: bool,
+    /// Also restore the setuid, setgid and sticky bits when restoring modes.
+    pub restore_special_modes: bool,
+    /// Restore numeric owners and groups. This usually requires elevated privileges.
+    pub restore_ownership: bool,
+    /// Restore modification times.
+    pub restore_mtimes: bool,
+    /// Restore extended attributes.
+    ///
+    /// Attributes that cannot be set, e.g. because the filesystem or operating system
+    /// doesn't support their namespace, are skipped with a warning.
+    pub restore_xattrs: bool,
+}
+
+impl Default for UnpackOptions {
+    fn default() -> Self {
+        Self {
+            verify_checksums: true,
+            restore_modes: true,
+            restore_special_modes: false,
+            restore_ownership: false,
+            restore_mtimes: true,
+            restore_xattrs: true,
+        }
+    }
+}
+
 /// Read-only interface to a single XAR archive.
 #[derive(Debug)]
 pub struct XarReader<R: Read + Seek + Sized + Debug> {
@@ -41,8 +491,9 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
     /// Construct a new XAR reader from a stream reader.
     pub fn new(mut reader: R) -> XarResult<Self> {
         let header = reader.ioread_with::<XarHeader>(scroll::BE)?;
+        validate_header(&header)?;
 
-        let mut header_extra = vec![0u8; header.size as usize - 28];
+        let mut header_extra = vec![0u8; (header.size - HEADER_SIZE) as usize];
         reader.read_exact(&mut header_extra)?;
 
         // Following the header is a zlib compressed table of contents.
@@ -52,14 +503,32 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         let toc_reader = reader.take(header.toc_length_compressed);
         let mut toc_reader = flate2::read::ZlibDecoder::new(toc_reader);
 
-        let mut toc_data = Vec::with_capacity(header.toc_length_uncompressed as _);
-        toc_reader.read_to_end(&mut toc_data)?;
+        let mut toc_data =
+            Vec::with_capacity(header.toc_length_uncompressed.min(MAX_TOC_LENGTH) as _);
+        (&mut toc_reader)
+            .take(MAX_TOC_LENGTH + 1)
+            .read_to_end(&mut toc_data)?;
+        if toc_data.len() as u64 > MAX_TOC_LENGTH {
+            return Err(Error::TableOfContentsCorrupted(
+                "table of contents is too large",
+            ));
+        }
 
         let mut reader = toc_reader.into_inner().into_inner();
         let heap_start_offset = reader.stream_position()?;
 
         let toc = TableOfContents::from_reader(std::io::Cursor::new(toc_data))?;
 
+        if !toc
+            .checksum
+            .style
+            .matches_header_id(header.checksum_algorithm_id)
+        {
+            return Err(Error::TableOfContentsCorrupted(
+                "checksum algorithm of header and table of contents differ",
+            ));
+        }
+
         Ok(Self {
             reader,
             header,
@@ -195,6 +664,15 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         Ok(data.length as _)
     }
 
+    /// Write heap data of an extended attribute to a writer.
+    ///
+    /// Returns the number of bytes written.
+    pub fn write_ea_data_heap(&mut self, ea: &Ea, writer: &mut impl Write) -> XarResult<usize> {
+        self.write_heap_slice(ea.offset, ea.length as _, writer)?;
+
+        Ok(ea.length as _)
+    }
+
     /// Write heap file data for a given file ID to a writer.
     ///
     /// This is a wrapper around [Self::write_file_data_heap_from_file] that
@@ -226,22 +704,11 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
     ) -> XarResult<usize> {
         let data = file.data.as_ref().ok_or(Error::FileNoData)?;
 
-        let mut writer = match data.encoding.style.as_str() {
-            "application/octet-stream" => Box::new(writer) as Box<dyn Write>,
-            "application/x-bzip2" => {
-                Box::new(bzip2::write::BzDecoder::new(writer)) as Box<dyn Write>
-            }
-            // The media type is arguably wrong, as there is no gzip header.
-            "application/x-gzip" => {
-                Box::new(flate2::write::ZlibDecoder::new(writer)) as Box<dyn Write>
-            }
-            "application/x-lzma" => Box::new(xz2::write::XzDecoder::new(writer)) as Box<dyn Write>,
-            encoding => {
-                return Err(Error::UnimplementedFileEncoding(encoding.to_string()));
-            }
-        };
+        let mut writer = Decoder::new(&data.encoding, writer)?;
+        let size = self.write_file_data_heap_from_file(file, &mut writer)?;
+        writer.finish()?;
 
-        self.write_file_data_heap_from_file(file, &mut writer)
+        Ok(size)
     }
 
     /// Write decoded file data for a given file ID to a writer.
@@ -276,7 +743,23 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         }
     }
 
+    /// Write decoded data of an extended attribute to a writer, verifying its checksums.
+    pub fn write_ea_data_decoded(&mut self, ea: &Ea, writer: impl Write) -> XarResult<()> {
+        write_decoded_verified(
+            &ea.encoding,
+            &ea.archived_checksum,
+            &ea.extracted_checksum,
+            true,
+            &ea.name,
+            writer,
+            |mut writer| self.write_heap_slice(ea.offset, ea.length as _, &mut writer),
+        )
+    }
+
     /// Unpack the contents of the XAR archive to a given directory.
+    ///
+    /// Paths and data are used as recorded in the table of contents. Use
+    /// [Self::unpack_with_options] for archives from untrusted sources.
     pub fn unpack(&mut self, dest_dir: impl AsRef<Path>) -> XarResult<()> {
         let dest_dir = dest_dir.as_ref();
 
@@ -299,6 +782,35 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         Ok(())
     }
 
+    /// Unpack the contents of the XAR archive to a given directory, guarding against
+    /// malicious archives.
+    ///
+    /// File data is streamed through its decoder and the archived and extracted
+    /// checksums are verified. Paths that are absolute, contain `..` or would be
+    /// written through a symlink are rejected, as are symlinks pointing outside
+    /// `dest_dir`. Existing files are not overwritten.
+    ///
+    /// Metadata is restored according to `options`. Modes and times of directories are
+    /// restored after their content has been written.
+    pub fn unpack_with_options(
+        &mut self,
+        dest_dir: impl AsRef<Path>,
+        options: &UnpackOptions,
+    ) -> XarResult<()> {
+        let dest_dir = dest_dir.as_ref();
+        let mut entries = vec![];
+
+        for (path, file) in self.toc.files()? {
+            let entry = prepare_entry(dest_dir, path, file)?;
+            unpack_entry_content(&entry, options, |offset, length, mut writer| {
+                self.write_heap_slice(offset, length as _, &mut writer)
+            })?;
+            entries.push(entry);
+        }
+
+        restore_directories(&entries, options)
+    }
+
     /// Obtain the archive checksum.
     ///
     /// The checksum consists of a digest format and a raw digest.
@@ -317,8 +829,15 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
     ///
     /// Will `Err` if an error occurs obtaining or computing the checksums. Returns Ok
     /// with a bool indicating if the checksums matched.
+    ///
+    /// The algorithm is the one declared by the table of contents, which [Self::new]
+    /// validated against the header. Archives without checksum never verify.
     pub fn verify_table_of_contents_checksum(&mut self) -> XarResult<bool> {
-        let format = ChecksumType::try_from(XarChecksum::from(self.header.checksum_algorithm_id))?;
+        let format = self.toc.checksum.style;
+        if matches!(format, ChecksumType::None) {
+            return Ok(false);
+        }
+
         let actual_digest = self.digest_table_of_contents_with(format)?;
         let recorded_digest = self.checksum()?.1;
 
@@ -352,7 +871,7 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
 
         if let Some((signature, certificates)) = self.rsa_signature()? {
             // The first certificate is the signing certificate.
-            if let Some(cert) = certificates.get(0) {
+            if let Some(cert) = certificates.first() {
                 cert.verify_signed_data(signed_data, signature)?;
                 Ok(true)
             } else {
@@ -363,6 +882,89 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         }
     }
 
+    /// Evaluate whether the archive is signed by a certificate trusted by a policy.
+    ///
+    /// This verifies the table of contents checksum and all signatures, resolves the
+    /// certificate chain of the signing certificate to a root of the policy, checks that
+    /// the certificates were valid at the signing time and checks the extended key
+    /// usage of the signing certificate. Failed checks are recorded as problems in the
+    /// returned report. `Err` is only returned if the archive cannot be read.
+    pub fn verify_trust(&mut self, policy: &TrustPolicy) -> XarResult<TrustReport> {
+        let mut problems = vec![];
+        let mut verified = vec![];
+        #[allow(unused_mut)]
+        let mut signature_time = None;
+
+        let certificates = match self.toc.signatures().first() {
+            Some(sig) => sig.x509_certificates()?,
+            None => {
+                problems.push(TrustProblem::NoSignature);
+                vec![]
+            }
+        };
+
+        if !self.verify_table_of_contents_checksum()? {
+            problems.push(TrustProblem::ChecksumMismatch);
+        }
+
+        // Signatures are only meaningful if they are made by the certificate the
+        // chain is evaluated for.
+        if let Some(sig) = self.toc.find_signature(SignatureStyle::Rsa).cloned() {
+            let rsa_cert = sig.x509_certificates()?.into_iter().next();
+
+            if rsa_cert.as_ref().map(|cert| cert.constructed_data())
+                != certificates.first().map(|cert| cert.constructed_data())
+            {
+                problems.push(TrustProblem::SignerMismatch(SignatureStyle::Rsa));
+            } else {
+                match self.verify_rsa_checksum_signature() {
+                    Ok(true) => verified.push(SignatureStyle::Rsa),
+                    Ok(false) => problems.push(TrustProblem::NoCertificates),
+                    Err(e) => problems.push(TrustProblem::InvalidSignature(
+                        SignatureStyle::Rsa,
+                        e.to_string(),
+                    )),
+                }
+            }
+        }
+
+        #[cfg(feature = "signing")]
+        if self.toc.find_signature(SignatureStyle::Cms).is_some() {
+            match self.cms_signature() {
+                Ok(Some(signed_data))
+                    if !trust::cms_signers_match(&signed_data, certificates.first()) =>
+                {
+                    problems.push(TrustProblem::SignerMismatch(SignatureStyle::Cms));
+                }
+                Ok(Some(signed_data)) => {
+                    match self.verify_cms_signature() {
+                        Ok(true) => verified.push(SignatureStyle::Cms),
+                        Ok(false) => problems.push(TrustProblem::NoCertificates),
+                        Err(e) => problems.push(TrustProblem::InvalidSignature(
+                            SignatureStyle::Cms,
+                            e.to_string(),
+                        )),
+                    }
+
+                    signature_time = trust::cms_time(&signed_data, &policy.roots);
+                }
+                Ok(None) => {}
+                Err(e) => problems.push(TrustProblem::InvalidSignature(
+                    SignatureStyle::Cms,
+                    e.to_string(),
+                )),
+            }
+        }
+
+        Ok(trust::evaluate(
+            policy,
+            &certificates,
+            verified,
+            problems,
+            signature_time,
+        ))
+    }
+
     /// Attempt to resolve a cryptographic message syntax (CMS) signature.
     ///
     /// The data signed by the CMS signature is the raw data returned by [Self::checksum].
@@ -395,3 +997,299 @@ impl<R: Read + Seek + Sized + Debug> XarReader<R> {
         Ok(checked)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::builder::XarBuilder, chrono::TimeZone};
+
+    fn unpack(builder: &XarBuilder) -> XarResult<tempfile::TempDir> {
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        let dest = tempfile::tempdir()?;
+        XarReader::new(Cursor::new(data))?
+            .unpack_with_options(dest.path(), &UnpackOptions::default())?;
+
+        Ok(dest)
+    }
+
+    /// Create a self-signed certificate.
+    fn test_certificate(
+        developer_id_installer: bool,
+    ) -> (
+        CapturedX509Certificate,
+        x509_certificate::InMemorySigningKeyPair,
+    ) {
+        let mut builder = x509_certificate::X509CertificateBuilder::default();
+        builder
+            .subject()
+            .append_common_name_utf8_string("Developer ID Installer: Test")
+            .unwrap();
+        if developer_id_installer {
+            // SEQUENCE { OID 1.2.840.113635.100.4.13 }
+            builder.add_extension_der_data(
+                bcder::Oid([85, 29, 37].as_ref().into()),
+                false,
+                [48, 11, 6, 9, 42, 134, 72, 134, 247, 99, 100, 4, 13],
+            );
+        }
+
+        builder
+            .create_with_random_keypair(x509_certificate::KeyAlgorithm::Ed25519)
+            .unwrap()
+    }
+
+    /// Create an archive signed by a self-signed certificate.
+    fn signed_archive(
+        developer_id_installer: bool,
+    ) -> XarResult<(Vec<u8>, CapturedX509Certificate)> {
+        let (cert, key) = test_certificate(developer_id_installer);
+
+        let mut builder = XarBuilder::new();
+        builder.add_file_data("file", b"content".to_vec())?;
+        builder.reserve_signatures(64, 0, std::iter::once(&cert))?;
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        // Inject the signature over the checksum into the reserved space.
+        let mut reader = XarReader::new(Cursor::new(data.clone()))?;
+        let checksum = reader.checksum()?.1;
+        let offset = reader.heap_start_offset() as usize
+            + reader
+                .table_of_contents()
+                .find_signature(SignatureStyle::Rsa)
+                .unwrap()
+                .offset as usize;
+        let signature = x509_certificate::Signer::try_sign(&key, &checksum).unwrap();
+        data[offset..offset + 64].copy_from_slice(signature.as_ref());
+
+        Ok((data, cert))
+    }
+
+    #[test]
+    fn verify_trust() -> XarResult<()> {
+        let (data, cert) = signed_archive(true)?;
+        let mut reader = XarReader::new(Cursor::new(data))?;
+
+        let report = reader.verify_trust(&TrustPolicy::new([cert.clone()]))?;
+        assert!(report.is_trusted(), "{:?}", report.problems);
+        assert_eq!(report.verified_signatures, vec![SignatureStyle::Rsa]);
+        assert_eq!(report.trusted_root.as_ref(), Some(&cert));
+        assert_eq!(report.time_source, trust::TimeSource::Now);
+
+        let report = reader.verify_trust(&TrustPolicy::new([]))?;
+        assert_eq!(report.problems, vec![TrustProblem::UntrustedRoot]);
+
+        let mut policy = TrustPolicy::new([cert.clone()]);
+        policy.verification_time = Some(cert.validity_not_after() + chrono::Duration::days(1));
+        let report = reader.verify_trust(&policy)?;
+        assert!(matches!(
+            report.problems.as_slice(),
+            [TrustProblem::CertificateNotValid(_)]
+        ));
+
+        let (data, cert) = signed_archive(false)?;
+        let mut reader = XarReader::new(Cursor::new(data))?;
+        let report = reader.verify_trust(&TrustPolicy::new([cert]))?;
+        assert_eq!(
+            report.problems,
+            vec![TrustProblem::MissingDeveloperIdInstaller]
+        );
+
+        let mut data = vec![];
+        XarBuilder::new().write(&mut data)?;
+        let report = XarReader::new(Cursor::new(data))?.verify_trust(&TrustPolicy::new([]))?;
+        assert_eq!(
+            report.problems,
+            vec![TrustProblem::NoSignature, TrustProblem::NoCertificates]
+        );
+
+        Ok(())
+    }
+
+    /// Create an archive with a CMS signature made by `key` and `cert`.
+    ///
+    /// The table of contents declares `key_info` as the signing certificate.
+    #[cfg(feature = "signing")]
+    async fn cms_signed_archive(
+        key_info: &CapturedX509Certificate,
+        key: &x509_certificate::InMemorySigningKeyPair,
+        cert: &CapturedX509Certificate,
+    ) -> XarResult<Vec<u8>> {
+        use cryptographic_message_syntax::{
+            asn1::rfc5652::OID_ID_DATA, SignedDataBuilder, SignerBuilder,
+        };
+
+        let mut builder = XarBuilder::new();
+        builder.add_file_data("file", b"content".to_vec())?;
+        builder.reserve_signatures(0, 4096, std::iter::once(key_info))?;
+        let mut data = vec![];
+        builder.write(&mut data)?;
+
+        let mut reader = XarReader::new(Cursor::new(data.clone()))?;
+        let checksum = reader.checksum()?.1;
+        let offset = reader.heap_start_offset() as usize
+            + reader
+                .table_of_contents()
+                .find_signature(SignatureStyle::Cms)
+                .unwrap()
+                .offset as usize;
+        let signature = SignedDataBuilder::default()
+            .content_type(bcder::Oid(OID_ID_DATA.as_ref().into()))
+            .signer(SignerBuilder::new(key, cert.clone()).message_id_content(checksum))
+            .build_der()
+            .await?;
+        data[offset..offset + signature.len()].copy_from_slice(&signature);
+
+        Ok(data)
+    }
+
+    #[cfg(feature = "signing")]
+    #[tokio::test]
+    async fn verify_trust_cms() -> XarResult<()> {
+        let (cert, key) = test_certificate(true);
+        let (other_cert, other_key) = test_certificate(true);
+        let policy = TrustPolicy::new([cert.clone()]);
+
+        let data = cms_signed_archive(&cert, &key, &cert).await?;
+        let report = XarReader::new(Cursor::new(data))?.verify_trust(&policy)?;
+        assert!(report.is_trusted(), "{:?}", report.problems);
+        assert_eq!(report.verified_signatures, vec![SignatureStyle::Cms]);
+        // The signing time attribute chosen by the signer is ignored.
+        assert_eq!(report.time_source, trust::TimeSource::Now);
+
+        // The table of contents declares the trusted certificate, but the CMS
+        // signature is made by another certificate.
+        let data = cms_signed_archive(&cert, &other_key, &other_cert).await?;
+        let report = XarReader::new(Cursor::new(data))?.verify_trust(&policy)?;
+        assert!(!report.is_trusted());
+        assert!(report.verified_signatures.is_empty());
+        assert_eq!(
+            report.problems,
+            vec![
+                TrustProblem::SignerMismatch(SignatureStyle::Cms),
+                TrustProblem::NoVerifiedSignature
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unpack_with_options() -> XarResult<()> {
+        let mtime = chrono::Utc.with_ymd_and_hms(2020, 1, 2, 3, 4, 5).unwrap();
+
+        let mut builder = XarBuilder::new();
+        builder
+            .add_file_data("dir/file", b"content".to_vec())?
+            .set_mode(0o600)
+            .set_mtime(mtime)
+            .add_xattr("user.xar", b"value".to_vec());
+        builder.add_directory("dir")?.set_mtime(mtime);
+        builder.add_symlink("dir/link", "file")?;
+        builder
+            .add_file_data("setuid", b"#!/bin/sh\n".to_vec())?
+            .set_mode(0o4755);
+
+        let dest = unpack(&builder)?;
+        let file = dest.path().join("dir/file");
+        assert_eq!(std::fs::read(&file)?, b"content");
+        assert_eq!(
+            std::fs::read_link(dest.path().join("dir/link"))?,
+            Path::new("file")
+        );
+
+        let metadata = std::fs::metadata(&file)?;
+        assert_eq!(
+            chrono::DateTime::<chrono::Utc>::from(metadata.modified()?),
+            mtime
+        );
+        assert_eq!(
+            chrono::DateTime::<chrono::Utc>::from(
+                std::fs::metadata(dest.path().join("dir"))?.modified()?
+            ),
+            mtime
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+            assert_eq!(
+                std::fs::metadata(dest.path().join("setuid"))?
+                    .permissions()
+                    .mode()
+                    & 0o7777,
+                0o755
+            );
+
+            // Not all filesystems support extended attributes.
+            if let Ok(Some(value)) = xattr::get(&file, "user.xar") {
+                assert_eq!(value, b"value");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn unpack_rejects_corrupted_data() -> XarResult<()> {
+        let mut builder = XarBuilder::new();
+        builder
+            .add_file_data("file", b"original content".to_vec())?
+            .set_encoding(Encoding::None);
+
+        let mut data = vec![];
+        builder.write(&mut data)?;
+        let pos = data.len() - b"content".len();
+        data[pos] = b'C';
+
+        let dest = tempfile::tempdir()?;
+        let res = XarReader::new(Cursor::new(data))?
+            .unpack_with_options(dest.path(), &UnpackOptions::default());
+        assert!(matches!(res, Err(Error::ChecksumMismatch(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unpack_rejects_escapes() -> XarResult<()> {
+        let mut builder = XarBuilder::new();
+        builder.add_symlink("dir/link", "../../outside")?;
+        assert!(matches!(unpack(&builder), Err(Error::InvalidPath(_))));
+
+        // Chained symlinks resolve outside in either order.
+        let mut builder = XarBuilder::new();
+        builder.add_directory("d")?;
+        builder.add_symlink("d/l1", "..")?;
+        builder.add_symlink("s", "d/l1/../..")?;
+        assert!(matches!(unpack(&builder), Err(Error::InvalidPath(_))));
+
+        let mut builder = XarBuilder::new();
+        builder.add_directory("d")?;
+        builder.add_symlink("s", "d/l1/../..")?;
+        builder.add_symlink("d/l1", "..")?;
+        assert!(matches!(unpack(&builder), Err(Error::InvalidPath(_))));
+
+        let dest = tempfile::tempdir()?;
+        std::fs::create_dir(dest.path().join("a"))?;
+        assert!(!symlink_escapes(dest.path(), "a/b/link", "../c"));
+        assert!(!symlink_escapes(dest.path(), "link", "a/../b"));
+        assert!(symlink_escapes(dest.path(), "link", "missing/../b"));
+        assert!(symlink_escapes(dest.path(), "a/link", "../../c"));
+        assert!(symlink_escapes(dest.path(), "link", "/etc/passwd"));
+
+        assert!(safe_destination(dest.path(), "a/b").is_ok());
+        for path in ["../a", "/a", "a/../../b", "a//b", "a\\..\\b"] {
+            assert!(safe_destination(dest.path(), path).is_err(), "{path}");
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("/", dest.path().join("link"))?;
+            assert!(safe_destination(dest.path(), "link/file").is_err());
+        }
+
+        Ok(())
+    }
+}
@@ -55,6 +55,13 @@ impl<R: Read + Seek + Sized + Debug> XarSigner<R> {
         }
     }
 
+    /// Set the checksum algorithm of the table of contents of the signed archive.
+    ///
+    /// Defaults to the algorithm of the archive being signed.
+    pub fn set_checksum_type(&mut self, checksum_type: ChecksumType) {
+        self.checksum_type = checksum_type;
+    }
+
     /// Sign a XAR file using signing parameters.
     ///
     /// The `signing_key` and `signing_cert` form the certificate to use for signing.
@@ -69,6 +76,12 @@ impl<R: Read + Seek + Sized + Debug> XarSigner<R> {
         time_stamp_url: Option<&Url>,
         certificates: impl Iterator<Item = CapturedX509Certificate>,
     ) -> XarResult<()> {
+        if matches!(self.checksum_type, ChecksumType::None) {
+            return Err(Error::Unsupported(
+                "cannot sign archive without table of contents checksum",
+            ));
+        }
+
         let extra_certificates = certificates.collect::<Vec<_>>();
 
         // Base64 encoding of all public certificates.
@@ -144,17 +157,26 @@ impl<R: Read + Seek + Sized + Debug> XarSigner<R> {
 
         for (_, file) in self.reader.files()? {
             if let Some(data) = &file.data {
-                ids_to_offsets.insert(file.id, current_offset);
+                ids_to_offsets.insert((file.id, None), current_offset);
                 current_offset += data.length;
             }
+            for (i, ea) in file.ea.iter().enumerate() {
+                ids_to_offsets.insert((file.id, Some(i)), current_offset);
+                current_offset += ea.length;
+            }
         }
 
         toc.visit_files_mut(&|file: &mut File| {
             if let Some(data) = &mut file.data {
                 data.offset = *ids_to_offsets
-                    .get(&file.id)
+                    .get(&(file.id, None))
                     .expect("file should have offset recorded");
             }
+            for (i, ea) in file.ea.iter_mut().enumerate() {
+                ea.offset = *ids_to_offsets
+                    .get(&(file.id, Some(i)))
+                    .expect("extended attribute should have offset recorded");
+            }
         });
 
         // The TOC should be all set up now. Let's serialize it so we can produce
@@ -203,12 +225,15 @@ impl<R: Read + Seek + Sized + Debug> XarSigner<R> {
         writer.write_all(rsa_signature.as_ref())?;
         writer.write_all(&cms_signature)?;
 
-        // And write all the files to the heap.
+        // And write all the files and extended attributes to the heap.
         for (path, file) in self.reader.files()? {
             if file.data.is_some() {
                 info!("copying {} to output XAR", path);
                 self.reader.write_file_data_heap_from_file(&file, writer)?;
             }
+            for ea in &file.ea {
+                self.reader.write_ea_data_heap(ea, writer)?;
+            }
         }
 
         Ok(())
@@ -42,6 +42,12 @@ impl DerefMut for TableOfContents {
     }
 }
 
+impl From<XarToC> for TableOfContents {
+    fn from(toc: XarToC) -> Self {
+        Self { toc }
+    }
+}
+
 impl TableOfContents {
     /// Parse XML table of contents from a reader.
     pub fn from_reader(reader: impl Read) -> XarResult<Self> {
@@ -64,7 +70,7 @@ impl TableOfContents {
             .flat_map(|x| x.into_iter())
             .collect::<Vec<_>>();
 
-        files.sort_by(|a, b| a.1.id.cmp(&b.1.id));
+        files.sort_by_key(|a| a.1.id);
 
         Ok(files)
     }
@@ -76,7 +82,7 @@ impl TableOfContents {
         emitter
             .into_inner()
             .into_inner()
-            .map_err(|e| Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e)))
+            .map_err(|e| Error::Io(std::io::Error::other(e)))
     }
 
     pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> XarResult<()> {
@@ -119,7 +125,7 @@ impl TableOfContents {
 pub struct XarToC {
     pub creation_time: String,
     pub checksum: Checksum,
-    #[serde(rename = "file")]
+    #[serde(default, rename = "file")]
     pub files: Vec<File>,
     pub signature: Option<Signature>,
     pub x_signature: Option<Signature>,
@@ -189,6 +195,8 @@ pub enum ChecksumType {
     Sha1,
     #[serde(alias = "SHA256")]
     Sha256,
+    #[serde(alias = "SHA384")]
+    Sha384,
     #[serde(alias = "SHA512")]
     Sha512,
     #[serde(alias = "MD5")]
@@ -201,6 +209,7 @@ impl Display for ChecksumType {
             Self::None => f.write_str("none"),
             Self::Sha1 => f.write_str("sha1"),
             Self::Sha256 => f.write_str("sha256"),
+            Self::Sha384 => f.write_str("sha384"),
             Self::Sha512 => f.write_str("sha512"),
             Self::Md5 => f.write_str("md5"),
         }
@@ -216,6 +225,7 @@ impl TryFrom<XarChecksum> for ChecksumType {
             XarChecksum::Sha1 => Ok(Self::Sha1),
             XarChecksum::Md5 => Ok(Self::Md5),
             XarChecksum::Sha256 => Ok(Self::Sha256),
+            XarChecksum::Sha384 => Ok(Self::Sha384),
             XarChecksum::Sha512 => Ok(Self::Sha512),
             XarChecksum::Other(_) => Err(Error::Unsupported("unknown checksum type")),
         }
@@ -228,6 +238,7 @@ impl From<ChecksumType> for XarChecksum {
             ChecksumType::None => Self::None,
             ChecksumType::Sha1 => Self::Sha1,
             ChecksumType::Sha256 => Self::Sha256,
+            ChecksumType::Sha384 => Self::Sha384,
             ChecksumType::Sha512 => Self::Sha512,
             ChecksumType::Md5 => Self::Md5,
         }
@@ -235,15 +246,53 @@ impl From<ChecksumType> for XarChecksum {
 }
 
 impl ChecksumType {
-    /// Digest a slice of data.
-    pub fn digest_data(&self, data: &[u8]) -> XarResult<Vec<u8>> {
-        let mut h: Box<dyn DynDigest> = match self {
+    /// Whether the checksum algorithm identifier of a [crate::format::XarHeader] agrees
+    /// with this checksum type.
+    ///
+    /// Identifier 3, which this crate historically writes for SHA-256, is defined by
+    /// xar as an algorithm named in the table of contents. So it is accepted for any
+    /// SHA-2 algorithm.
+    pub fn matches_header_id(&self, id: u32) -> bool {
+        matches!(
+            (XarChecksum::from(id), self),
+            (XarChecksum::None, Self::None)
+                | (XarChecksum::Sha1, Self::Sha1)
+                | (XarChecksum::Md5, Self::Md5)
+                | (XarChecksum::Sha512, Self::Sha512)
+                | (
+                    XarChecksum::Sha256,
+                    Self::Sha256 | Self::Sha384 | Self::Sha512
+                )
+        )
+    }
+
+    /// Obtain a hasher for this checksum type.
+    pub fn digest(&self) -> XarResult<Box<dyn DynDigest + Send>> {
+        Ok(match self {
             Self::None => return Err(Error::Unsupported("cannot digest None checksum")),
             Self::Md5 => Box::<md5::Md5>::default(),
             Self::Sha1 => Box::<sha1::Sha1>::default(),
             Self::Sha256 => Box::<sha2::Sha256>::default(),
+            Self::Sha384 => Box::<sha2::Sha384>::default(),
             Self::Sha512 => Box::<sha2::Sha512>::default(),
-        };
+        })
+    }
+
+    /// The size of digests in bytes. 0 for [Self::None].
+    pub fn digest_size(&self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::Md5 => 16,
+            Self::Sha1 => 20,
+            Self::Sha256 => 32,
+            Self::Sha384 => 48,
+            Self::Sha512 => 64,
+        }
+    }
+
+    /// Digest a slice of data.
+    pub fn digest_data(&self, data: &[u8]) -> XarResult<Vec<u8>> {
+        let mut h = self.digest()?;
 
         h.update(data);
 
@@ -275,7 +324,11 @@ pub struct File {
     pub group: Option<String>,
     pub size: Option<u64>,
     pub data: Option<FileData>,
-    pub ea: Option<Ea>,
+    /// Extended attributes.
+    #[serde(default)]
+    pub ea: Vec<Ea>,
+    /// Target of symlinks.
+    pub link: Option<Link>,
     #[serde(rename = "FinderCreateTime")]
     pub finder_create_time: Option<FinderCreateTime>,
     #[serde(default, rename = "file")]
@@ -393,10 +446,14 @@ impl File {
             writer.write(XmlEvent::end_element())?;
         }
 
-        if let Some(ea) = &self.ea {
+        for ea in &self.ea {
             ea.write_xml(writer)?;
         }
 
+        if let Some(link) = &self.link {
+            link.write_xml(writer)?;
+        }
+
         writer.write(XmlEvent::start_element("type"))?;
         writer.write(XmlEvent::characters(&self.file_type.to_string()))?;
         writer.write(XmlEvent::end_element())?;
@@ -423,6 +480,7 @@ pub enum FileType {
     File,
     Directory,
     HardLink,
+    #[serde(rename = "symlink", alias = "link")]
     Link,
 }
 
@@ -482,7 +540,8 @@ impl FileData {
 #[serde(deny_unknown_fields)]
 pub struct FileChecksum {
     pub style: ChecksumType,
-    #[serde(rename = "$value")]
+    /// Hex encoded digest. Empty for [ChecksumType::None].
+    #[serde(default, rename = "$value")]
     pub checksum: String,
 }
 
@@ -502,6 +561,75 @@ pub struct FileEncoding {
     pub style: String,
 }
 
+impl From<Encoding> for FileEncoding {
+    fn from(v: Encoding) -> Self {
+        Self {
+            style: v.to_string(),
+        }
+    }
+}
+
+/// Encodings of file data in the heap.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    /// Data is stored as is.
+    None,
+    /// zlib compression.
+    ///
+    /// The media type is arguably wrong, as there is no gzip header.
+    Gzip,
+    /// bzip2 compression.
+    Bzip2,
+    /// xz compression.
+    Xz,
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => f.write_str("application/octet-stream"),
+            Self::Gzip => f.write_str("application/x-gzip"),
+            Self::Bzip2 => f.write_str("application/x-bzip2"),
+            Self::Xz => f.write_str("application/x-lzma"),
+        }
+    }
+}
+
+impl TryFrom<&FileEncoding> for Encoding {
+    type Error = Error;
+
+    fn try_from(v: &FileEncoding) -> Result<Self, Self::Error> {
+        match v.style.as_str() {
+            "application/octet-stream" => Ok(Self::None),
+            "application/x-gzip" => Ok(Self::Gzip),
+            "application/x-bzip2" => Ok(Self::Bzip2),
+            "application/x-lzma" => Ok(Self::Xz),
+            encoding => Err(Error::UnimplementedFileEncoding(encoding.to_string())),
+        }
+    }
+}
+
+/// The target of a symlink.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Link {
+    /// Type of the target: `file`, `directory` or `broken`.
+    #[serde(rename = "type")]
+    pub link_type: String,
+    #[serde(rename = "$value")]
+    pub target: String,
+}
+
+impl Link {
+    pub fn write_xml<W: Write>(&self, writer: &mut EventWriter<W>) -> XarResult<()> {
+        writer.write(XmlEvent::start_element("link").attr("type", &self.link_type))?;
+        writer.write(XmlEvent::characters(&self.target))?;
+        writer.write(XmlEvent::end_element())?;
+
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct Ea {
@@ -681,7 +809,7 @@ impl KeyInfo {
 #[derive(Clone, Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct X509Data {
-    #[serde(rename = "X509Certificate")]
+    #[serde(default, rename = "X509Certificate")]
     pub x509_certificate: Vec<String>,
 }
 
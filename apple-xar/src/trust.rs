@@ -0,0 +1,542 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Trust evaluation of signed XAR archives.
+//!
+//! [crate::reader::XarReader::verify_rsa_checksum_signature] and friends only verify
+//! that signatures are cryptographically valid for the embedded certificates. This
+//! module additionally verifies that the signing certificate chains to a trusted
+//! root certificate, that the certificates were valid when the archive was signed,
+//! that the issuers in the chain are certificate authorities and that the signing
+//! certificate is meant for signing installer packages.
+//!
+//! This crate doesn't ship root certificates. Apple's roots are available from the
+//! `apple-codesign` crate.
+
+use {
+    crate::table_of_contents::SignatureStyle,
+    bcder::{
+        decode::{Constructed, IntoSource},
+        BitString, Mode, Oid,
+    },
+    chrono::{DateTime, Utc},
+    std::fmt::{Display, Formatter},
+    x509_certificate::CapturedX509Certificate,
+};
+
+#[cfg(feature = "signing")]
+use {
+    cryptographic_message_syntax::{asn1::rfc3161::TstInfo, SignedData, SignerInfo},
+    x509_certificate::DigestAlgorithm,
+};
+
+/// Key Usage extension.
+///
+/// 2.5.29.15
+const OID_KEY_USAGE: &[u8] = &[85, 29, 15];
+
+/// Basic Constraints extension.
+///
+/// 2.5.29.19
+const OID_BASIC_CONSTRAINTS: &[u8] = &[85, 29, 19];
+
+/// Extended Key Usage extension.
+///
+/// 2.5.29.37
+const OID_EXTENDED_KEY_USAGE: &[u8] = &[85, 29, 37];
+
+/// Bit of the `keyCertSign` key usage.
+const KEY_USAGE_KEY_CERT_SIGN: usize = 5;
+
+/// Extended Key Usage purpose for `Developer ID Installer`.
+///
+/// 1.2.840.113635.100.4.13
+const OID_EKU_PURPOSE_DEVELOPER_ID_INSTALLER: &[u8] = &[42, 134, 72, 134, 247, 99, 100, 4, 13];
+
+/// Whether a certificate has the `Developer ID Installer` extended key usage.
+pub fn has_developer_id_installer_purpose(cert: &CapturedX509Certificate) -> bool {
+    let cert: &x509_certificate::rfc5280::Certificate = cert.as_ref();
+
+    cert.iter_extensions().any(|extension| {
+        extension.id.as_ref() == OID_EXTENDED_KEY_USAGE
+            && Constructed::decode(extension.value.clone().into_source(), Mode::Der, |cons| {
+                // ExtKeyUsageSyntax ::= SEQUENCE SIZE (1..MAX) OF KeyPurposeId
+                cons.take_sequence(|cons| {
+                    let mut purposes = vec![];
+                    while let Some(oid) = Oid::take_opt_from(cons)? {
+                        purposes.push(oid);
+                    }
+                    Ok(purposes)
+                })
+            })
+            .is_ok_and(|purposes| {
+                purposes
+                    .iter()
+                    .any(|oid| oid.as_ref() == OID_EKU_PURPOSE_DEVELOPER_ID_INSTALLER)
+            })
+    })
+}
+
+/// Whether a certificate may issue certificates.
+///
+/// It must have the basic constraints extension with `cA` set and the key usage
+/// extension with `keyCertSign` set.
+pub fn is_certificate_authority(cert: &CapturedX509Certificate) -> bool {
+    let cert: &x509_certificate::rfc5280::Certificate = cert.as_ref();
+
+    let extension_value = |id: &[u8]| {
+        cert.iter_extensions()
+            .find(|extension| extension.id.as_ref() == id)
+            .map(|extension| extension.value.clone().into_source())
+    };
+
+    // BasicConstraints ::= SEQUENCE {
+    //      cA                      BOOLEAN DEFAULT FALSE,
+    //      pathLenConstraint       INTEGER (0..MAX) OPTIONAL }
+    let ca = extension_value(OID_BASIC_CONSTRAINTS).is_some_and(|value| {
+        Constructed::decode(value, Mode::Der, |cons| {
+            cons.take_sequence(|cons| {
+                let ca = cons.take_opt_bool()?.unwrap_or(false);
+                cons.take_opt_u64()?;
+                Ok(ca)
+            })
+        })
+        .unwrap_or(false)
+    });
+
+    let key_cert_sign = extension_value(OID_KEY_USAGE).is_some_and(|value| {
+        Constructed::decode(value, Mode::Der, BitString::take_from)
+            .is_ok_and(|usage| usage.bit(KEY_USAGE_KEY_CERT_SIGN))
+    });
+
+    ca && key_cert_sign
+}
+
+/// Requirements for trusting a signed archive.
+#[derive(Clone, Debug)]
+pub struct TrustPolicy {
+    /// Trusted root certificates.
+    pub roots: Vec<CapturedX509Certificate>,
+
+    /// Time at which the certificates must be valid.
+    ///
+    /// If not set, the time from the time-stamp token of the CMS signature is used,
+    /// falling back to the current time.
+    pub verification_time: Option<DateTime<Utc>>,
+
+    /// Require the `Developer ID Installer` extended key usage on the signing certificate.
+    pub require_developer_id_installer: bool,
+}
+
+impl TrustPolicy {
+    /// Create a policy trusting the given root certificates.
+    ///
+    /// The `Developer ID Installer` extended key usage is required.
+    pub fn new(roots: impl IntoIterator<Item = CapturedX509Certificate>) -> Self {
+        Self {
+            roots: roots.into_iter().collect(),
+            verification_time: None,
+            require_developer_id_installer: true,
+        }
+    }
+}
+
+/// Where the time certificates were validated at came from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TimeSource {
+    /// [TrustPolicy::verification_time].
+    Policy,
+    /// The time-stamp token of the CMS signature.
+    TimeStamp,
+    /// The current time.
+    Now,
+}
+
+/// A reason for not trusting an archive.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TrustProblem {
+    /// The archive isn't signed.
+    NoSignature,
+    /// The table of contents doesn't match its checksum.
+    ChecksumMismatch,
+    /// A signature failed to verify.
+    InvalidSignature(SignatureStyle, String),
+    /// No signature was verified.
+    NoVerifiedSignature,
+    /// A signature wasn't made by the signing certificate of the table of contents.
+    SignerMismatch(SignatureStyle),
+    /// The signature doesn't have certificates.
+    NoCertificates,
+    /// The certificate chain doesn't end at a trusted root certificate.
+    UntrustedRoot,
+    /// A certificate of the chain wasn't valid at the verification time.
+    CertificateNotValid(String),
+    /// A certificate issuing another certificate of the chain isn't a certificate
+    /// authority.
+    NotCertificateAuthority(String),
+    /// The signing certificate lacks the `Developer ID Installer` extended key usage.
+    MissingDeveloperIdInstaller,
+}
+
+impl Display for TrustProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoSignature => f.write_str("archive is not signed"),
+            Self::ChecksumMismatch => f.write_str("table of contents checksum mismatch"),
+            Self::InvalidSignature(style, e) => {
+                f.write_fmt(format_args!("{style} signature verification failed: {e}"))
+            }
+            Self::NoVerifiedSignature => f.write_str("no signature was verified"),
+            Self::SignerMismatch(style) => f.write_fmt(format_args!(
+                "{style} signature is not made by the signing certificate"
+            )),
+            Self::NoCertificates => f.write_str("signature has no certificates"),
+            Self::UntrustedRoot => {
+                f.write_str("certificate chain does not end at a trusted root certificate")
+            }
+            Self::CertificateNotValid(subject) => f.write_fmt(format_args!(
+                "certificate {subject} is not valid at verification time"
+            )),
+            Self::NotCertificateAuthority(subject) => f.write_fmt(format_args!(
+                "certificate {subject} issues certificates but is not a certificate authority"
+            )),
+            Self::MissingDeveloperIdInstaller => f.write_str(
+                "signing certificate lacks the Developer ID Installer extended key usage",
+            ),
+        }
+    }
+}
+
+/// The result of evaluating the trust of an archive.
+#[derive(Clone, Debug)]
+pub struct TrustReport {
+    /// Signatures that were successfully verified.
+    pub verified_signatures: Vec<SignatureStyle>,
+
+    /// Certificate chain from the signing certificate towards the root.
+    pub chain: Vec<CapturedX509Certificate>,
+
+    /// The trusted root certificate the chain ends at.
+    pub trusted_root: Option<CapturedX509Certificate>,
+
+    /// Time at which certificates were validated.
+    pub verification_time: DateTime<Utc>,
+
+    /// Where [Self::verification_time] came from.
+    pub time_source: TimeSource,
+
+    /// Whether the signing certificate has the `Developer ID Installer` extended key usage.
+    pub developer_id_installer: bool,
+
+    /// Reasons not to trust the archive.
+    pub problems: Vec<TrustProblem>,
+}
+
+impl TrustReport {
+    /// Whether the archive is trusted, i.e. a signature was verified and no problems
+    /// were found.
+    pub fn is_trusted(&self) -> bool {
+        !self.verified_signatures.is_empty() && self.problems.is_empty()
+    }
+}
+
+/// Resolve the certificate of a CMS signer from the certificates of the signed data.
+#[cfg(feature = "signing")]
+fn signer_certificate<'a>(
+    signed_data: &'a SignedData,
+    signer: &SignerInfo,
+) -> Option<&'a CapturedX509Certificate> {
+    let (issuer, serial) = signer.certificate_issuer_and_serial()?;
+
+    signed_data
+        .certificates()
+        .find(|cert| cert.issuer_name() == issuer && cert.serial_number_asn1() == serial)
+}
+
+/// Whether all signers of a CMS signature use the signing certificate.
+///
+/// Signed data without signers never matches.
+#[cfg(feature = "signing")]
+pub(crate) fn cms_signers_match(
+    signed_data: &SignedData,
+    signing_cert: Option<&CapturedX509Certificate>,
+) -> bool {
+    let Some(signing_cert) = signing_cert else {
+        return false;
+    };
+
+    signed_data.signers().count() > 0
+        && signed_data.signers().all(|signer| {
+            signer_certificate(signed_data, signer)
+                .is_some_and(|cert| cert.constructed_data() == signing_cert.constructed_data())
+        })
+}
+
+/// Resolve the time of a time-stamp token of a CMS signer.
+///
+/// The token must be signed by a certificate chaining to one of `roots`, which was
+/// valid at the time of the token, and it must time-stamp the signature of the signer.
+#[cfg(feature = "signing")]
+fn time_stamp_time(
+    signer: &SignerInfo,
+    roots: &[CapturedX509Certificate],
+) -> Option<DateTime<Utc>> {
+    let token = signer.time_stamp_token_signed_data().ok()??;
+    let tst_info = bcder::decode::Constructed::decode(
+        token.signed_content()?,
+        bcder::Mode::Der,
+        TstInfo::take_from,
+    )
+    .ok()?;
+    let time = DateTime::<Utc>::from(tst_info.gen_time);
+
+    let algorithm = DigestAlgorithm::try_from(&tst_info.message_imprint.hash_algorithm).ok()?;
+    if algorithm.digest_data(signer.signature())
+        != tst_info.message_imprint.hashed_message.to_bytes().as_ref()
+    {
+        return None;
+    }
+
+    if token.signers().count() == 0 {
+        return None;
+    }
+
+    for token_signer in token.signers() {
+        token_signer
+            .verify_signature_with_signed_data(&token)
+            .ok()?;
+        token_signer
+            .verify_message_digest_with_signed_data(&token)
+            .ok()?;
+
+        let cert = signer_certificate(&token, token_signer)?;
+        let chain = std::iter::once(cert)
+            .chain(cert.resolve_signing_chain(token.certificates().chain(roots.iter())))
+            .collect::<Vec<_>>();
+
+        if !chain.last().is_some_and(|last| roots.contains(last))
+            || !chain
+                .iter()
+                .all(|cert| cert.time_constraints_valid(Some(time)))
+            || !chain
+                .iter()
+                .skip(1)
+                .all(|cert| is_certificate_authority(cert))
+        {
+            return None;
+        }
+    }
+
+    Some(time)
+}
+
+/// Resolve the time of a CMS signature from its time-stamp token.
+///
+/// Only tokens issued by a time-stamp authority chaining to one of `roots` for the
+/// signature are used. The signing time attribute is chosen by the signer and is
+/// ignored.
+#[cfg(feature = "signing")]
+pub(crate) fn cms_time(
+    signed_data: &SignedData,
+    roots: &[CapturedX509Certificate],
+) -> Option<(DateTime<Utc>, TimeSource)> {
+    signed_data
+        .signers()
+        .find_map(|signer| time_stamp_time(signer, roots))
+        .map(|time| (time, TimeSource::TimeStamp))
+}
+
+/// Evaluate the certificates of a signature against a policy.
+///
+/// `certificates` are the certificates embedded in the archive, the first one being
+/// the signing certificate.
+pub(crate) fn evaluate(
+    policy: &TrustPolicy,
+    certificates: &[CapturedX509Certificate],
+    verified_signatures: Vec<SignatureStyle>,
+    mut problems: Vec<TrustProblem>,
+    signature_time: Option<(DateTime<Utc>, TimeSource)>,
+) -> TrustReport {
+    let (verification_time, time_source) = if let Some(time) = policy.verification_time {
+        (time, TimeSource::Policy)
+    } else {
+        signature_time.unwrap_or_else(|| (Utc::now(), TimeSource::Now))
+    };
+
+    if verified_signatures.is_empty() && !problems.contains(&TrustProblem::NoSignature) {
+        problems.push(TrustProblem::NoVerifiedSignature);
+    }
+
+    let mut report = TrustReport {
+        verified_signatures,
+        chain: vec![],
+        trusted_root: None,
+        verification_time,
+        time_source,
+        developer_id_installer: false,
+        problems: vec![],
+    };
+
+    let Some(cert) = certificates.first() else {
+        problems.push(TrustProblem::NoCertificates);
+        report.problems = problems;
+        return report;
+    };
+
+    report.chain = std::iter::once(cert)
+        .chain(cert.resolve_signing_chain(certificates.iter().chain(policy.roots.iter())))
+        .cloned()
+        .collect();
+
+    report.trusted_root = report
+        .chain
+        .last()
+        .filter(|last| policy.roots.contains(last))
+        .cloned();
+    if report.trusted_root.is_none() {
+        problems.push(TrustProblem::UntrustedRoot);
+    }
+
+    let subject = |cert: &CapturedX509Certificate| {
+        cert.subject_common_name()
+            .unwrap_or_else(|| "<unknown>".to_string())
+    };
+
+    for cert in &report.chain {
+        if !cert.time_constraints_valid(Some(verification_time)) {
+            problems.push(TrustProblem::CertificateNotValid(subject(cert)));
+        }
+    }
+
+    // Every certificate after the signing certificate issued the one before it.
+    for cert in report.chain.iter().skip(1) {
+        if !is_certificate_authority(cert) {
+            problems.push(TrustProblem::NotCertificateAuthority(subject(cert)));
+        }
+    }
+
+    report.developer_id_installer = has_developer_id_installer_purpose(cert);
+    if policy.require_developer_id_installer && !report.developer_id_installer {
+        problems.push(TrustProblem::MissingDeveloperIdInstaller);
+    }
+
+    report.problems = problems;
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        bcder::encode::Values,
+        x509_certificate::{InMemorySigningKeyPair, KeyAlgorithm, X509CertificateBuilder},
+    };
+
+    /// BasicConstraints with `cA` set.
+    const CA: &[u8] = &[0x30, 0x03, 0x01, 0x01, 0xff];
+    /// KeyUsage with `keyCertSign` and `cRLSign` set.
+    const KEY_CERT_SIGN: &[u8] = &[0x03, 0x02, 0x01, 0x06];
+
+    /// Create a certificate, signed by `issuer` or self-signed.
+    fn certificate(
+        name: &str,
+        extensions: &[(&[u8], &[u8])],
+        issuer: Option<(&CapturedX509Certificate, &InMemorySigningKeyPair)>,
+    ) -> (CapturedX509Certificate, InMemorySigningKeyPair) {
+        let mut builder = X509CertificateBuilder::default();
+        builder
+            .subject()
+            .append_common_name_utf8_string(name)
+            .unwrap();
+        for (id, value) in extensions {
+            builder.add_extension_der_data(Oid(id.to_vec().into()), true, value);
+        }
+        let (cert, key) = builder
+            .create_with_random_keypair(KeyAlgorithm::Ed25519)
+            .unwrap();
+        let Some((issuer, issuer_key)) = issuer else {
+            return (cert, key);
+        };
+
+        let cert: &x509_certificate::rfc5280::Certificate = cert.as_ref();
+        let mut cert = cert.clone();
+        cert.tbs_certificate.issuer = issuer.subject_name().clone();
+        cert.tbs_certificate.raw_data = None;
+        let mut tbs = vec![];
+        cert.tbs_certificate
+            .encode_ref()
+            .write_encoded(Mode::Der, &mut tbs)
+            .unwrap();
+        let signature = x509_certificate::Signer::try_sign(issuer_key, &tbs).unwrap();
+        cert.signature = BitString::new(0, signature.as_ref().to_vec().into());
+        let der = x509_certificate::X509Certificate::from(cert)
+            .encode_der()
+            .unwrap();
+
+        (CapturedX509Certificate::from_der(der).unwrap(), key)
+    }
+
+    fn problems(
+        root: &CapturedX509Certificate,
+        certificates: &[CapturedX509Certificate],
+    ) -> Vec<TrustProblem> {
+        let mut policy = TrustPolicy::new([root.clone()]);
+        policy.require_developer_id_installer = false;
+
+        evaluate(
+            &policy,
+            certificates,
+            vec![SignatureStyle::Rsa],
+            vec![],
+            None,
+        )
+        .problems
+    }
+
+    #[test]
+    fn issuers_must_be_certificate_authorities() {
+        let (root, root_key) = certificate(
+            "Root",
+            &[(OID_BASIC_CONSTRAINTS, CA), (OID_KEY_USAGE, KEY_CERT_SIGN)],
+            None,
+        );
+        assert!(is_certificate_authority(&root));
+        let (leaf, _) = certificate("Leaf", &[], Some((&root, &root_key)));
+        assert!(!is_certificate_authority(&leaf));
+        assert_eq!(problems(&root, &[leaf.clone(), root.clone()]), vec![]);
+
+        for extensions in [
+            &[(OID_KEY_USAGE, KEY_CERT_SIGN)][..],
+            &[(OID_BASIC_CONSTRAINTS, CA)][..],
+            // cA is false and the path length constraint is 0.
+            &[
+                (OID_BASIC_CONSTRAINTS, &[0x30, 0x03, 0x02, 0x01, 0x00][..]),
+                (OID_KEY_USAGE, KEY_CERT_SIGN),
+            ][..],
+        ] {
+            let (root, root_key) = certificate("Root", extensions, None);
+            assert!(!is_certificate_authority(&root));
+            let (leaf, _) = certificate("Leaf", &[], Some((&root, &root_key)));
+            assert_eq!(
+                problems(&root, &[leaf, root.clone()]),
+                vec![TrustProblem::NotCertificateAuthority("Root".to_string())]
+            );
+        }
+    }
+
+    #[test]
+    fn developer_id_installer_among_purposes() {
+        // SEQUENCE { OID codeSigning, OID Developer ID Installer }
+        let mut eku = vec![0x30, 0x15, 0x06, 0x08, 43, 6, 1, 5, 5, 7, 3, 3, 0x06, 0x09];
+        eku.extend_from_slice(OID_EKU_PURPOSE_DEVELOPER_ID_INSTALLER);
+        let (cert, _) = certificate("Installer", &[(OID_EXTENDED_KEY_USAGE, &eku)], None);
+        assert!(has_developer_id_installer_purpose(&cert));
+
+        // SEQUENCE { OID codeSigning }
+        let eku = [0x30, 0x0a, 0x06, 0x08, 43, 6, 1, 5, 5, 7, 3, 3];
+        let (cert, _) = certificate("Code", &[(OID_EXTENDED_KEY_USAGE, &eku)], None);
+        assert!(!has_developer_id_installer_purpose(&cert));
+    }
+}
@@ -13,7 +13,8 @@ use {
     chrono::{DateTime, Utc},
     is_executable::IsExecutable,
     simple_file_manifest::{
-        FileManifest, S_IFDIR, S_IRGRP, S_IROTH, S_IRUSR, S_IWUSR, S_IXGRP, S_IXOTH, S_IXUSR,
+        FileManifest, S_IFDIR, S_IFLNK, S_IFREG, S_IRGRP, S_IROTH, S_IRUSR, S_IWUSR, S_IXGRP,
+        S_IXOTH, S_IXUSR,
     },
     std::{
         collections::HashSet,
@@ -271,8 +272,7 @@ impl<T: Read + Sized> Read for OdcReader<T> {
         if let Some(reader) = &mut self.entry_reader {
             reader.read(buf)
         } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            Err(std::io::Error::other(
                 "no current archive entry to read from",
             ))
         }
@@ -529,14 +529,32 @@ impl<W: Write + Sized> OdcBuilder<W> {
     }
 
     /// Append a [FileManifest] to the archive.
+    ///
+    /// Files have mode `0o755` if executable and `0o644` otherwise. Symlinks store
+    /// their target as data.
     pub fn append_file_manifest(&mut self, manifest: &FileManifest) -> CpioResult<u64> {
         let mut bytes_written = 0;
 
         for (path, entry) in manifest.iter_entries() {
-            let mode = if entry.is_executable() { 0o755 } else { 0o644 };
-            let data = entry.resolve_content()?;
+            if let Some(target) = entry.link_target() {
+                let archive_path = self.normalize_archive_path(&path.display().to_string());
+                let target = target.display().to_string();
+
+                bytes_written += self.emit_parent_directories(&archive_path)?;
+
+                let mut header = self.next_header();
+                header.name = archive_path;
+                header.file_size = target.len() as _;
+                header.mode = S_IFLNK | 0o755;
+
+                bytes_written += self.append_header_with_data(header, target)?;
+            } else {
+                let mode = S_IFREG | if entry.is_executable() { 0o755 } else { 0o644 };
+                let data = entry.resolve_content()?;
 
-            bytes_written += self.append_file_from_data(path.display().to_string(), data, mode)?;
+                bytes_written +=
+                    self.append_file_from_data(path.display().to_string(), data, mode)?;
+            }
         }
 
         Ok(bytes_written)
@@ -561,6 +579,15 @@ impl<W: Write + Sized> OdcBuilder<W> {
         }
     }
 
+    /// Obtain a mutable reference to the writer this instance writes to.
+    ///
+    /// Data written to it is emitted as-is. Callers must keep the archive well
+    /// formed, e.g. by following a header written with [OdcHeader::write] with
+    /// exactly as much data as it advertises.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
     /// Consume self and return the original writer this instance was constructed from.
     ///
     /// This will automatically finish the archive if needed.